@@ -1,6 +1,6 @@
 #!/bin/sh -ex
 
-FEATURES="compiler serde"
+FEATURES="compiler serde tracing zeroize"
 
 # Use toolchain if explicitly specified
 if [ -n "$TOOLCHAIN" ]
@@ -19,7 +19,7 @@ extern crate miniscript;
 
 use bitcoin::Network;
 use miniscript::policy::{Concrete, Liftable};
-use miniscript::Descriptor;
+use miniscript::{Descriptor, Segwitv0};
 use std::str::FromStr;
 
 fn main() {
@@ -31,7 +31,7 @@ fn main() {
                                                   expiry = "4444"
     )).unwrap();
 
-    let htlc_descriptor = Descriptor::Wsh(htlc_policy.compile().unwrap());
+    let htlc_descriptor = Descriptor::Wsh(htlc_policy.compile::<Segwitv0>().unwrap());
 
     assert_eq!(
         format!("{}", htlc_descriptor),
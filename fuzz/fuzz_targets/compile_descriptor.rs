@@ -1,6 +1,6 @@
 extern crate miniscript;
 
-use miniscript::{policy, DummyKey, Miniscript};
+use miniscript::{policy, DummyKey, Miniscript, Segwitv0};
 use policy::Liftable;
 
 use std::str::FromStr;
@@ -12,7 +12,7 @@ fn do_test(data: &[u8]) {
     let data_str = String::from_utf8_lossy(data);
     if let Ok(pol) = DummyPolicy::from_str(&data_str) {
         // Compile
-        if let Ok(desc) = pol.compile() {
+        if let Ok(desc) = pol.compile::<Segwitv0>() {
             // Lift
             assert_eq!(desc.clone().lift(), pol.clone().lift());
             // Try to roundtrip the output of the compiler
@@ -0,0 +1,210 @@
+//! # Derived Key Cache
+//!
+//! Deriving a ranged descriptor at an index costs one EC tweak per step of
+//! every xpub's derivation path. Wallets scanning thousands of indices, or
+//! deriving the same addresses over and over, can keep the derived keys in
+//! a `DescriptorCache` instead, so that each (xpub, path) pair is only ever
+//! derived once and the steps up to the wildcard are shared by all indices.
+//!
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bitcoin::hashes::hash160;
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
+use bitcoin::{self, PublicKey};
+
+use descriptor::{DerivationError, Descriptor, DescriptorPublicKey};
+
+/// Cache of keys derived from the xpubs of descriptors, keyed by the xpub
+/// and the path derived from it
+#[derive(Clone, Debug, Default)]
+pub struct DescriptorCache {
+    xpubs: HashMap<(ExtendedPubKey, DerivationPath), ExtendedPubKey>,
+}
+
+impl DescriptorCache {
+    /// Creates an empty cache
+    pub fn new() -> DescriptorCache {
+        DescriptorCache::default()
+    }
+
+    /// Number of derived xpubs in the cache, including the intermediate
+    /// ones up to each wildcard
+    pub fn len(&self) -> usize {
+        self.xpubs.len()
+    }
+
+    /// Whether nothing has been derived into the cache yet
+    pub fn is_empty(&self) -> bool {
+        self.xpubs.is_empty()
+    }
+
+    /// Looks up the public key `key` stands for with its wildcard, if any,
+    /// at `index`, without deriving anything. Single keys are always found,
+    /// xpubs only once they were derived through this cache.
+    pub fn get(&self, key: &DescriptorPublicKey, index: u32) -> Option<PublicKey> {
+        match *key {
+            DescriptorPublicKey::PubKey(pk) => Some(pk),
+            DescriptorPublicKey::XPub(ref xpub) => {
+                let mut path = xpub.derivation_path.as_ref().to_vec();
+                if xpub.is_wildcard {
+                    if xpub.hardened_wildcard {
+                        return None;
+                    }
+                    path.push(ChildNumber::from_normal_idx(index).ok()?);
+                }
+                if path.is_empty() {
+                    return Some(xpub.xpub.public_key);
+                }
+                self.xpubs
+                    .get(&(xpub.xpub.clone(), DerivationPath::from(path)))
+                    .map(|derived| derived.public_key)
+            }
+        }
+    }
+
+    /// Derives the public key `key` stands for with its wildcard, if any,
+    /// at `index`, like `DescriptorPublicKey::try_derive` followed by
+    /// `to_public_key`, but reusing and filling the cache
+    pub fn derive_key<C: secp256k1::Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        key: &DescriptorPublicKey,
+        index: u32,
+    ) -> Result<PublicKey, DerivationError> {
+        match *key {
+            DescriptorPublicKey::PubKey(pk) => Ok(pk),
+            DescriptorPublicKey::XPub(ref xpub) => {
+                let mut path = xpub.derivation_path.as_ref().to_vec();
+                if xpub.is_wildcard {
+                    if xpub.hardened_wildcard {
+                        return Err(DerivationError::HardenedWildcard(key.clone()));
+                    }
+                    let child = ChildNumber::from_normal_idx(index)
+                        .map_err(|_| DerivationError::HardenedChild(ChildNumber::from(index)))?;
+                    path.push(child);
+                }
+                Ok(self.derive_xpub(secp, &xpub.xpub, &path).public_key)
+            }
+        }
+    }
+
+    /// Derives every key of `descriptor` at each index of `range` into the
+    /// cache, e.g. ahead of scanning a block for the wallet's outputs
+    pub fn populate<C: secp256k1::Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+        range: Range<u32>,
+    ) -> Result<(), DerivationError> {
+        for index in range {
+            descriptor.derive_index_cached(secp, index, self)?;
+        }
+        Ok(())
+    }
+
+    fn derive_xpub<C: secp256k1::Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        xpub: &ExtendedPubKey,
+        path: &[ChildNumber],
+    ) -> ExtendedPubKey {
+        let (child, parent_path) = match path.split_last() {
+            Some(split) => split,
+            None => return xpub.clone(),
+        };
+        let cache_key = (xpub.clone(), DerivationPath::from(path));
+        if let Some(derived) = self.xpubs.get(&cache_key) {
+            return derived.clone();
+        }
+
+        let derived = self
+            .derive_xpub(secp, xpub, parent_path)
+            .ckd_pub(secp, *child)
+            .expect("Shouldn't fail, only normal derivations");
+        self.xpubs.insert(cache_key, derived.clone());
+        derived
+    }
+}
+
+impl Descriptor<DescriptorPublicKey> {
+    /// Like `derive_index`, but takes derived keys from `cache` where
+    /// possible and adds the ones it has to derive
+    pub fn derive_index_cached<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        cache: &mut DescriptorCache,
+    ) -> Result<Descriptor<PublicKey>, DerivationError> {
+        self.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| cache.derive_key(secp, pk, index),
+            |pkh: &hash160::Hash| Ok(*pkh),
+        ))
+    }
+
+    /// The address of the descriptor at `index`, if it has one, see
+    /// `derive_index_cached` and `address`
+    pub fn address_cached<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        network: bitcoin::Network,
+        cache: &mut DescriptorCache,
+    ) -> Result<Option<bitcoin::Address>, DerivationError> {
+        let derived = self.derive_index_cached(secp, index, cache)?;
+        Ok(derived.address(network))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::Secp256k1;
+    use std::str::FromStr;
+
+    use super::*;
+    use ToPublicKey;
+
+    #[test]
+    fn cached_derivation() {
+        let secp = Secp256k1::verification_only();
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "wsh(multi(1,xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB/1/*,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))",
+        )
+        .unwrap();
+
+        let mut cache = DescriptorCache::new();
+        assert!(cache.is_empty());
+        for index in 0..3 {
+            assert_eq!(
+                desc.derive_index_cached(&secp, index, &mut cache),
+                desc.derive_index(index)
+            );
+        }
+        // The parent at `/1` plus one child per index
+        assert_eq!(cache.len(), 4);
+
+        let xpub = DescriptorPublicKey::from_str(
+            "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB/1/*",
+        )
+        .unwrap();
+        let child = ChildNumber::from_normal_idx(2).unwrap();
+        assert_eq!(
+            cache.get(&xpub, 2),
+            Some(xpub.derive(&[child]).to_public_key())
+        );
+        assert_eq!(cache.get(&xpub, 3), None);
+
+        cache.populate(&secp, &desc, 0..10).unwrap();
+        assert_eq!(cache.len(), 11);
+        assert!(cache.get(&xpub, 9).is_some());
+
+        let network = bitcoin::Network::Bitcoin;
+        assert_eq!(
+            desc.address_cached(&secp, 9, network, &mut cache).unwrap(),
+            desc.derive_index(9).unwrap().address(network)
+        );
+        assert_eq!(cache.len(), 11);
+    }
+}
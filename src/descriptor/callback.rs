@@ -0,0 +1,130 @@
+//! # Callback Signer
+//!
+//! Adaptor which implements `Satisfier` over a single signing callback, so
+//! that hardware signers or remote signing services can be used without
+//! implementing the whole `Satisfier` trait.
+//!
+
+use bitcoin::{self, secp256k1};
+
+use descriptor::Descriptor;
+use miniscript::satisfy::{BitcoinSig, Satisfier};
+use {ForEach, MiniscriptKey};
+
+/// Satisfier which asks a callback for the signature of each key. The
+/// callback is given the key, the sighash to sign and the sighash flag.
+///
+/// Since `Satisfier` is only called while a witness is being assembled,
+/// signers which need to batch or pre-authorize requests should first
+/// collect them using `CallbackSigner::requests`.
+pub struct CallbackSigner<F> {
+    sighash: secp256k1::Message,
+    sighash_type: bitcoin::SigHashType,
+    sign: F,
+}
+
+impl<F> CallbackSigner<F> {
+    /// Creates a new signer for the given sighash and flag
+    pub fn new(
+        sighash: secp256k1::Message,
+        sighash_type: bitcoin::SigHashType,
+        sign: F,
+    ) -> CallbackSigner<F> {
+        CallbackSigner {
+            sighash: sighash,
+            sighash_type: sighash_type,
+            sign: sign,
+        }
+    }
+
+    /// Lists every signature which satisfying `descriptor` may ask the
+    /// callback for, in the order the keys appear in the descriptor. Keys
+    /// only committed to by hash (`pk_h`) are not known and hence not listed.
+    pub fn requests<Pk: MiniscriptKey>(
+        &self,
+        descriptor: &Descriptor<Pk>,
+    ) -> Vec<(Pk, secp256k1::Message, bitcoin::SigHashType)> {
+        let mut ret: Vec<(Pk, secp256k1::Message, bitcoin::SigHashType)> = vec![];
+        descriptor.for_each_key(|key| {
+            if let ForEach::Key(pk) = key {
+                if !ret.iter().any(|&(ref k, _, _)| k == pk) {
+                    ret.push((pk.clone(), self.sighash, self.sighash_type));
+                }
+            }
+            true
+        });
+        ret
+    }
+}
+
+impl<Pk, F> Satisfier<Pk> for CallbackSigner<F>
+where
+    Pk: MiniscriptKey,
+    F: Fn(&Pk, &secp256k1::Message, bitcoin::SigHashType) -> Option<secp256k1::Signature>,
+{
+    fn lookup_sig(&self, pk: &Pk) -> Option<BitcoinSig> {
+        (self.sign)(pk, &self.sighash, self.sighash_type).map(|sig| (sig, self.sighash_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{self, secp256k1, PublicKey};
+    use std::cell::RefCell;
+    use std::str::FromStr;
+
+    use super::CallbackSigner;
+    use descriptor::Descriptor;
+
+    #[test]
+    fn callback_signer() {
+        let secp = secp256k1::Secp256k1::new();
+        let sks: Vec<_> = (1..4)
+            .map(|i| secp256k1::SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<_> = sks
+            .iter()
+            .map(|sk| PublicKey {
+                compressed: true,
+                key: secp256k1::PublicKey::from_secret_key(&secp, sk),
+            })
+            .collect();
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(multi(2,{},{},{}))",
+            pks[0], pks[1], pks[2]
+        ))
+        .unwrap();
+
+        // Only the first two keys are held by the signing service
+        let calls = RefCell::new(0);
+        let msg = secp256k1::Message::from_slice(&[0x42; 32]).unwrap();
+        let signer = CallbackSigner::new(
+            msg,
+            bitcoin::SigHashType::All,
+            |pk: &PublicKey, msg: &secp256k1::Message, _: bitcoin::SigHashType| {
+                *calls.borrow_mut() += 1;
+                let idx = pks.iter().position(|k| k == pk)?;
+                if idx < 2 {
+                    Some(secp.sign(msg, &sks[idx]))
+                } else {
+                    None
+                }
+            },
+        );
+
+        let requests = signer.requests(&desc);
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1], (pks[1], msg, bitcoin::SigHashType::All));
+        assert_eq!(*calls.borrow(), 0);
+
+        let mut txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        desc.satisfy(&mut txin, &signer).unwrap();
+        assert_eq!(txin.witness.len(), 4);
+        assert!(*calls.borrow() >= 3);
+    }
+}
@@ -0,0 +1,124 @@
+//! # Descriptor Checksums
+//!
+//! The 8-character checksum Bitcoin Core appends to descriptors after a `#`,
+//! as specified in BIP-380. It detects typos in descriptors which would
+//! otherwise parse fine but e.g. lead to a different address.
+//!
+
+use std::iter::FromIterator;
+
+use Error;
+
+const INPUT_CHARSET: &'static str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &'static [u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn poly_mod(mut c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    c = ((c & 0x7ffffffff) << 5) ^ val;
+    if c0 & 1 > 0 {
+        c ^= 0xf5dee51989;
+    }
+    if c0 & 2 > 0 {
+        c ^= 0xa9fdca3312;
+    }
+    if c0 & 4 > 0 {
+        c ^= 0x1bab10e32d;
+    }
+    if c0 & 8 > 0 {
+        c ^= 0x3706b1677a;
+    }
+    if c0 & 16 > 0 {
+        c ^= 0x644d626ffd;
+    }
+    c
+}
+
+/// Computes the checksum of a descriptor string, without the `#`
+pub fn desc_checksum(desc: &str) -> Result<String, Error> {
+    let mut c = 1;
+    let mut cls = 0;
+    let mut clscount = 0;
+    for ch in desc.chars() {
+        let pos = match INPUT_CHARSET.find(ch) {
+            Some(pos) => pos as u64,
+            None => return Err(Error::BadChecksum(format!("invalid character '{}'", ch))),
+        };
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = poly_mod(c, cls);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+
+    let chars = (0..8).map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char);
+    Ok(String::from_iter(chars))
+}
+
+/// Splits a trailing checksum off `s` and verifies it, if there is one.
+/// Returns the descriptor without the checksum.
+pub fn verify_checksum(s: &str) -> Result<&str, Error> {
+    let mut parts = s.splitn(2, '#');
+    let desc = parts.next().expect("splitn returns at least one part");
+    if let Some(checksum) = parts.next() {
+        if checksum.len() != 8 {
+            return Err(Error::BadChecksum(format!(
+                "checksum '{}' is not 8 characters",
+                checksum
+            )));
+        }
+        let expected = desc_checksum(desc)?;
+        if checksum != expected {
+            return Err(Error::BadChecksum(format!(
+                "got '{}', expected '{}'",
+                checksum, expected
+            )));
+        }
+    }
+    Ok(desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::PublicKey;
+    use std::str::FromStr;
+
+    use super::{desc_checksum, verify_checksum};
+    use descriptor::Descriptor;
+
+    #[test]
+    fn checksums() {
+        // Test vectors from BIP-380
+        assert_eq!(desc_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            verify_checksum("raw(deadbeef)#89f8spxm").unwrap(),
+            "raw(deadbeef)"
+        );
+        assert_eq!(verify_checksum("raw(deadbeef)").unwrap(), "raw(deadbeef)");
+
+        verify_checksum("raw(deadbeef)#").unwrap_err();
+        verify_checksum("raw(deadbeef)#89f8spxmx").unwrap_err();
+        verify_checksum("raw(deadbeef)#89f8spx").unwrap_err();
+        verify_checksum("raw(deedbeef)#89f8spxm").unwrap_err();
+        verify_checksum("raw(deadbeef)#9f8spxma").unwrap_err();
+        verify_checksum("raw(deadbeef)##9f8spxm").unwrap_err();
+        verify_checksum("raw(Ü)#00000000").unwrap_err();
+
+        let desc = Descriptor::<PublicKey>::from_str("raw(deadbeef)").unwrap();
+        assert_eq!(desc.to_string_with_checksum(), "raw(deadbeef)#89f8spxm");
+        assert_eq!(
+            Descriptor::<PublicKey>::from_str("raw(deadbeef)#89f8spxm").unwrap(),
+            desc
+        );
+        Descriptor::<PublicKey>::from_str("raw(deadbeef)#9f8spxma").unwrap_err();
+    }
+}
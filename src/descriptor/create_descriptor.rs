@@ -3,10 +3,13 @@
 //!
 
 use bitcoin::{self, Script};
+use std::collections::HashMap;
 
 use bitcoin::blockdata::opcodes;
 use bitcoin::blockdata::script::Instruction;
+use bitcoin::hashes::hash160;
 use descriptor::satisfied_constraints::Error as IntError;
+use descriptor::satisfied_constraints::{SatisfiedConstraint, SatisfiedConstraints};
 use descriptor::satisfied_constraints::{Stack, StackElement};
 use descriptor::Descriptor;
 use miniscript::Miniscript;
@@ -235,16 +238,70 @@ pub fn from_txin_with_witness_stack<'txin>(
     }
 }
 
+impl Descriptor<bitcoin::PublicKey> {
+    /// Infers the descriptor of an output from its scriptPubKey and the
+    /// scriptSig and witness which spent it. Handles p2pk, p2pkh, p2wpkh,
+    /// p2sh-p2wpkh, p2sh, p2wsh, p2sh-p2wsh and bare miniscripts, checking
+    /// every hash commitment on the way, see `from_txin_with_witness_stack`.
+    /// Standard legacy outputs need no special casing: bare
+    /// `OP_CHECKMULTISIG` is `multi`, and outside of segwit keys may be
+    /// uncompressed. Signatures are not checked.
+    pub fn from_spent_output(
+        script_pubkey: &bitcoin::Script,
+        script_sig: &bitcoin::Script,
+        witness: &[Vec<u8>],
+    ) -> Result<Descriptor<bitcoin::PublicKey>, Error> {
+        from_txin_with_witness_stack(script_pubkey, script_sig, witness).map(|(des, _)| des)
+    }
+}
+
+/// Reconstructs the descriptor spent by an input, with all keys inlined, as a
+/// string suitable for logging. Public keys which are only committed to by
+/// hash (`pk_h`) are inlined whenever the witness reveals them; otherwise the
+/// hash is printed. Signatures are not checked.
+pub fn descriptor_string_from_txin(
+    script_pubkey: &bitcoin::Script,
+    script_sig: &bitcoin::Script,
+    witness: &[Vec<u8>],
+) -> Result<String, Error> {
+    let (des, stack) = from_txin_with_witness_stack(script_pubkey, script_sig, witness)?;
+
+    let mut revealed = HashMap::new();
+    let assume_valid = |_: &bitcoin::PublicKey, _| true;
+    let iter = SatisfiedConstraints::from_descriptor(&des, stack, assume_valid, 0, 0);
+    for constraint in iter.assume_timelocks() {
+        match constraint {
+            Ok(SatisfiedConstraint::PublicKeyHash { keyhash, key, .. }) => {
+                revealed.insert(*keyhash, key);
+            }
+            Ok(_) => {}
+            // Whatever has been revealed so far is still useful
+            Err(_) => break,
+        }
+    }
+
+    let des = des.translate_pk(&mut (
+        |pk: &bitcoin::PublicKey| Ok::<String, ()>(pk.to_string()),
+        |pkh: &hash160::Hash| match revealed.get(pkh) {
+            Some(pk) => Ok(pk.to_string()),
+            None => Ok(pkh.to_string()),
+        },
+    ));
+    Ok(des.expect("translating to strings never fails").to_string())
+}
+
 #[cfg(test)]
 mod tests {
-    use bitcoin;
     use bitcoin::blockdata::{opcodes, script};
     use bitcoin::secp256k1::{self, Secp256k1, VerifyOnly};
-    use descriptor::create_descriptor::from_txin_with_witness_stack;
-    use descriptor::satisfied_constraints::{Stack, StackElement};
+    use bitcoin::{self, Script};
+    use descriptor::create_descriptor::{
+        descriptor_string_from_txin, from_txin_with_witness_stack,
+    };
+    use descriptor::satisfied_constraints::{SatisfiedConstraints, Stack, StackElement};
     use std::str::FromStr;
     use ToPublicKey;
-    use {Descriptor, Miniscript};
+    use {BitcoinSig, Descriptor, Error, Miniscript, MiniscriptKey};
 
     macro_rules! stack {
         ($($data:ident$(($pushdata:expr))*),*) => (
@@ -394,4 +451,96 @@ mod tests {
         assert_eq!(des_str!("sh(wpkh({}))", pks[2]), des);
         assert_eq!(stack, stack![Push(&sigs[2])]);
     }
+
+    #[test]
+    fn infer_from_spent_output() {
+        let (pks, sigs, _, _) = setup_keys_sigs(2);
+        let infer = Descriptor::<bitcoin::PublicKey>::from_spent_output;
+
+        //uncompressed p2pk
+        let pk = bitcoin::PublicKey {
+            compressed: false,
+            key: pks[0].key,
+        };
+        let script_pubkey = script::Builder::new()
+            .push_key(&pk)
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        let script_sig = script::Builder::new().push_slice(&sigs[0]).into_script();
+        let des = infer(&script_pubkey, &script_sig, &[]).unwrap();
+        assert_eq!(Descriptor::Pk(pk), des);
+
+        //p2sh-p2wsh
+        let ms = ms_str!("c:pk_k({})", pks[1]);
+        let script_pubkey =
+            bitcoin::Address::p2shwsh(&ms.encode(), bitcoin::Network::Bitcoin).script_pubkey();
+        let script_sig = script::Builder::new()
+            .push_slice(&ms.encode().to_v0_p2wsh().to_bytes())
+            .into_script();
+        let witness = vec![sigs[1].clone(), ms.encode().to_bytes()];
+        let des = infer(&script_pubkey, &script_sig, &witness).unwrap();
+        assert_eq!(Descriptor::ShWsh(ms.clone()), des);
+
+        //witness script not matching the output
+        let other = ms_str!("c:pk_k({})", pks[0]);
+        let witness = vec![sigs[1].clone(), other.encode().to_bytes()];
+        match infer(&script_pubkey, &script_sig, &witness) {
+            Err(Error::IncorrectScriptHash) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn legacy_multisig() {
+        let (pks, sigs, msg, secp) = setup_keys_sigs(2);
+        let uncompressed = bitcoin::PublicKey {
+            compressed: false,
+            key: pks[0].key,
+        };
+
+        //bare 1-of-2 CHECKMULTISIG, as found in old blocks
+        let script_pubkey = script::Builder::new()
+            .push_int(1)
+            .push_key(&uncompressed)
+            .push_key(&pks[1])
+            .push_int(2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let script_sig = script::Builder::new()
+            .push_int(0)
+            .push_slice(&sigs[0])
+            .into_script();
+        let (des, stack) = from_txin_with_witness_stack(&script_pubkey, &script_sig, &[])
+            .expect("Descriptor/Witness stack creation to succeed");
+        let ms = ms_str!("multi(1,{},{})", uncompressed, pks[1]);
+        assert_eq!(Descriptor::Bare(ms), des);
+        assert_eq!(des.script_pubkey(), script_pubkey);
+
+        let vfyfn = |pk: &bitcoin::PublicKey, (sig, _): BitcoinSig| {
+            secp.verify(&msg, &sig, &pk.key).is_ok()
+        };
+        let constraints: Result<Vec<_>, _> =
+            SatisfiedConstraints::from_descriptor(&des, stack, &vfyfn, 0, 0).collect();
+        assert_eq!(constraints.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reconstruct_descriptor_string() {
+        let (pks, sigs, _, _) = setup_keys_sigs(2);
+
+        let ms = ms_str!("and_v(vc:pk_k({}),older(144))", pks[0]);
+        let script_pubkey =
+            bitcoin::Address::p2wsh(&ms.encode(), bitcoin::Network::Bitcoin).script_pubkey();
+        let witness = vec![sigs[0].clone(), ms.encode().to_bytes()];
+        let des = descriptor_string_from_txin(&script_pubkey, &Script::new(), &witness).unwrap();
+        assert_eq!(des, format!("wsh(and_v(v:pk({}),older(144)))", pks[0]));
+
+        //pk_h keys are inlined once revealed
+        let ms = ms_str!("c:pk_h({})", pks[1].to_pubkeyhash());
+        let script_pubkey =
+            bitcoin::Address::p2wsh(&ms.encode(), bitcoin::Network::Bitcoin).script_pubkey();
+        let witness = vec![sigs[1].clone(), pks[1].to_bytes(), ms.encode().to_bytes()];
+        let des = descriptor_string_from_txin(&script_pubkey, &Script::new(), &witness).unwrap();
+        assert_eq!(des, format!("wsh(c:pk_h({}))", pks[1]));
+    }
 }
@@ -0,0 +1,119 @@
+//! # Descriptor Interning
+//!
+//! A pool of parsed descriptors, keyed by their string form, for servers
+//! which parse the same descriptors over and over again and would otherwise
+//! pay for parsing and type-checking them on every request.
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use descriptor::Descriptor;
+use {Error, MiniscriptKey};
+
+/// Thread-safe cache of parsed descriptors. Each descriptor is stored under
+/// the string it was requested with and under its canonical form, as
+/// produced by `Display`, so that spellings of the same descriptor share
+/// one entry once its canonical form has been seen.
+///
+/// The pool holds at most `capacity` entries; when full, it is emptied
+/// before the next descriptor is inserted. Callers holding an `Arc` to an
+/// evicted descriptor are unaffected.
+pub struct DescriptorInterner<Pk: MiniscriptKey> {
+    cache: Mutex<HashMap<String, Arc<Descriptor<Pk>>>>,
+    capacity: usize,
+}
+
+impl<Pk: MiniscriptKey> DescriptorInterner<Pk> {
+    /// Creates an empty pool holding at most `capacity` descriptors
+    pub fn new(capacity: usize) -> DescriptorInterner<Pk> {
+        DescriptorInterner {
+            cache: Mutex::new(HashMap::new()),
+            capacity: capacity,
+        }
+    }
+
+    /// Number of descriptors currently in the pool
+    pub fn len(&self) -> usize {
+        self.cache.lock().expect("poisoned lock").len()
+    }
+
+    /// Whether the pool is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all descriptors from the pool
+    pub fn clear(&self) {
+        self.cache.lock().expect("poisoned lock").clear()
+    }
+}
+
+impl<Pk> DescriptorInterner<Pk>
+where
+    Pk: MiniscriptKey,
+    <Pk as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+{
+    /// Returns the descriptor for `s`, parsing it only if it is not already
+    /// in the pool. Parse errors are not cached.
+    pub fn get(&self, s: &str) -> Result<Arc<Descriptor<Pk>>, Error> {
+        if let Some(desc) = self.cache.lock().expect("poisoned lock").get(s) {
+            return Ok(desc.clone());
+        }
+
+        // Parse without holding the lock, so that other threads aren't
+        // blocked on an expensive descriptor
+        let desc = Descriptor::<Pk>::from_str(s)?;
+        let canonical = desc.to_string();
+        let mut cache = self.cache.lock().expect("poisoned lock");
+        let desc = match cache.get(&canonical) {
+            Some(desc) => desc.clone(),
+            None => Arc::new(desc),
+        };
+        if cache.len() >= self.capacity {
+            cache.clear();
+        }
+        cache.insert(s.to_owned(), desc.clone());
+        if cache.len() < self.capacity {
+            cache.entry(canonical).or_insert_with(|| desc.clone());
+        }
+        Ok(desc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::PublicKey;
+    use std::sync::Arc;
+
+    use super::DescriptorInterner;
+
+    const KEY: &'static str = "020000000000000000000000000000000000000000000000000000000000000002";
+
+    #[test]
+    fn interning() {
+        let pool = DescriptorInterner::<PublicKey>::new(3);
+        let a = pool.get(&format!("wpkh({})", KEY)).unwrap();
+        let b = pool.get(&format!("wpkh({})", KEY)).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(pool.len(), 1);
+
+        // Other spellings are parsed as given, but share the canonical entry
+        let with_checksum = a.to_string_with_checksum();
+        let c = pool.get(&with_checksum).unwrap();
+        assert!(Arc::ptr_eq(&a, &c));
+        assert_eq!(pool.len(), 2);
+        assert!(pool.get(&format!(" wpkh(\n{} )", KEY)).is_err());
+        assert_eq!(pool.len(), 2);
+
+        pool.get(&format!("pkh({})", KEY)).unwrap();
+        assert_eq!(pool.len(), 3);
+        // Full, so the pool is emptied first
+        pool.get(&format!("pk({})", KEY)).unwrap();
+        assert_eq!(pool.len(), 1);
+        assert_eq!(*a, *pool.get(&with_checksum).unwrap());
+        assert_eq!(pool.len(), 3);
+    }
+}
@@ -0,0 +1,407 @@
+//! # Interpreter
+//!
+//! Checks that transaction inputs satisfy the outputs they spend. The
+//! descriptor of each spent output is reconstructed from its scriptPubKey
+//! and the scriptSig and witness spending it, and the witness is then
+//! interpreted against it, verifying signatures against the transaction.
+//!
+
+use std::collections::BTreeSet;
+
+use bitcoin::{self, secp256k1};
+
+use descriptor::create_descriptor::from_txin_with_witness_stack;
+use descriptor::satisfied_constraints::Error as IntError;
+use descriptor::satisfied_constraints::{SatisfiedConstraint, SatisfiedConstraints};
+use descriptor::satisfied_constraints::{Stack, TxSignatureChecker};
+use descriptor::Descriptor;
+use {AbsLockTime, RelLockTime};
+use {BitcoinSig, Error};
+
+/// Transactions with an `nSequence` with this bit set, or of version 1,
+/// are not subject to relative timelocks, see BIP68
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+
+/// The timelocks a witness relies on, see `Interpreter::timelocks`
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct RequiredTimelocks {
+    /// The relative timelocks, to be met by the `nSequence` of the input
+    pub older: BTreeSet<RelLockTime>,
+    /// The absolute timelocks, to be met by the `nLockTime` of the
+    /// transaction
+    pub after: BTreeSet<AbsLockTime>,
+}
+
+impl RequiredTimelocks {
+    /// Checks that input `index` of `tx` meets all timelocks, comparing
+    /// block based and time based ones separately. Relative timelocks need
+    /// a transaction of version 2 or higher, and absolute ones an input
+    /// whose `nSequence` is not final.
+    pub fn check_tx(&self, tx: &bitcoin::Transaction, index: usize) -> Result<(), IntError> {
+        let sequence = tx.input.get(index).map_or(0xffffffff, |txin| txin.sequence);
+        for &t in &self.older {
+            if tx.version < 2 || !t.is_satisfied_by(sequence) {
+                return Err(IntError::RelativeLocktimeNotMet(t.to_u32()));
+            }
+        }
+        for &t in &self.after {
+            if sequence == 0xffffffff || !t.is_satisfied_by(tx.lock_time) {
+                return Err(IntError::AbsoluteLocktimeNotMet(t.to_u32()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Helper function to record the timelock of a satisfied constraint
+    fn insert(&mut self, constraint: &SatisfiedConstraint) {
+        match *constraint {
+            SatisfiedConstraint::RelativeTimeLock { time } => {
+                self.older.insert(*time);
+            }
+            SatisfiedConstraint::AbsoluteTimeLock { time } => {
+                self.after.insert(*time);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The descriptor of a spent output together with the witness stack
+/// spending it, reconstructed from the data of a transaction input
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Interpreter<'txin> {
+    descriptor: Descriptor<bitcoin::PublicKey>,
+    stack: Stack<'txin>,
+}
+
+impl<'txin> Interpreter<'txin> {
+    /// Reconstructs the descriptor of an output from its scriptPubKey and
+    /// the scriptSig and witness spending it, see
+    /// `from_txin_with_witness_stack`. Nothing is interpreted yet.
+    pub fn from_txdata(
+        script_pubkey: &bitcoin::Script,
+        script_sig: &'txin bitcoin::Script,
+        witness: &'txin [Vec<u8>],
+    ) -> Result<Interpreter<'txin>, Error> {
+        let (descriptor, stack) = from_txin_with_witness_stack(script_pubkey, script_sig, witness)?;
+        Ok(Interpreter {
+            descriptor: descriptor,
+            stack: stack,
+        })
+    }
+
+    /// The reconstructed descriptor of the spent output
+    pub fn descriptor(&self) -> &Descriptor<bitcoin::PublicKey> {
+        &self.descriptor
+    }
+
+    /// Iterates over the constraints satisfied by the witness, checking
+    /// signatures with `verify_sig`, see `SatisfiedConstraints::from_descriptor`.
+    /// Use `iter_with_tx` to verify them against the spending transaction.
+    pub fn iter<'iter, F>(
+        &'iter self,
+        verify_sig: F,
+        age: u32,
+        height: u32,
+    ) -> SatisfiedConstraints<'iter, 'txin, F>
+    where
+        F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool,
+    {
+        SatisfiedConstraints::from_descriptor(
+            &self.descriptor,
+            self.stack.clone(),
+            verify_sig,
+            age,
+            height,
+        )
+    }
+
+    /// Iterates over the constraints satisfied by the witness, assuming
+    /// every signature to be valid. This is much cheaper than verifying
+    /// them, e.g. to find out which witness elements satisfy which
+    /// fragments, but must not be used to decide whether an input is valid.
+    pub fn iter_assume_sigs<'iter>(
+        &'iter self,
+        age: u32,
+        height: u32,
+    ) -> SatisfiedConstraints<'iter, 'txin, fn(&bitcoin::PublicKey, BitcoinSig) -> bool> {
+        let verify_sig: fn(&bitcoin::PublicKey, BitcoinSig) -> bool = assume_valid_sig;
+        self.iter(verify_sig, age, height)
+    }
+
+    /// The timelocks the witness relies on, so that they can be checked
+    /// against the spending transaction, see `RequiredTimelocks::check_tx`.
+    /// Signatures are not verified, and no timelock is considered unmet.
+    pub fn timelocks(&self) -> Result<RequiredTimelocks, Error> {
+        let mut ret = RequiredTimelocks::default();
+        for constraint in self.iter_assume_sigs(0, 0).assume_timelocks() {
+            ret.insert(&constraint.map_err(Error::InterpreterError)?);
+        }
+        Ok(ret)
+    }
+
+    /// Iterates over the constraints satisfied by the witness of input
+    /// `index` of `tx`, which spends an output worth `value` satoshis.
+    /// Signatures are verified against `tx`, and timelocks against the
+    /// `nSequence` of the input and the `nLockTime` of `tx`.
+    pub fn iter_with_tx<'iter, C: secp256k1::Verification>(
+        &'iter self,
+        secp: &'iter secp256k1::Secp256k1<C>,
+        tx: &'iter bitcoin::Transaction,
+        index: usize,
+        value: u64,
+    ) -> SatisfiedConstraints<'iter, 'txin, TxSignatureChecker<'iter, C>> {
+        let sequence = tx.input.get(index).map_or(0xffffffff, |txin| txin.sequence);
+        // The disable flag meets no relative timelock
+        let age = if tx.version >= 2 {
+            sequence
+        } else {
+            SEQUENCE_LOCKTIME_DISABLE_FLAG
+        };
+        // An input with a final `nSequence` disables `OP_CHECKLOCKTIMEVERIFY`
+        let height = if sequence == 0xffffffff {
+            0
+        } else {
+            tx.lock_time
+        };
+        SatisfiedConstraints::from_descriptor_with_tx(
+            &self.descriptor,
+            self.stack.clone(),
+            secp,
+            tx,
+            index,
+            value,
+            age,
+            height,
+        )
+    }
+}
+
+/// Signature check of `Interpreter::iter_assume_sigs`
+fn assume_valid_sig(_: &bitcoin::PublicKey, _: BitcoinSig) -> bool {
+    true
+}
+
+/// Checks that every input of `tx` satisfies the output it spends, given in
+/// `spent` in the same order as the inputs. Returns, for each input, the
+/// reconstructed descriptor of the spent output or the reason the input
+/// does not satisfy it.
+pub fn verify_tx<C: secp256k1::Verification>(
+    secp: &secp256k1::Secp256k1<C>,
+    tx: &bitcoin::Transaction,
+    spent: &[bitcoin::TxOut],
+) -> Vec<Result<Descriptor<bitcoin::PublicKey>, Error>> {
+    (0..tx.input.len())
+        .map(|index| match spent.get(index) {
+            Some(txout) => verify_input(secp, tx, index, txout),
+            None => Err(Error::InputIndexOutOfRange(index)),
+        })
+        .collect()
+}
+
+/// Helper function for `verify_tx` checking a single input
+fn verify_input<C: secp256k1::Verification>(
+    secp: &secp256k1::Secp256k1<C>,
+    tx: &bitcoin::Transaction,
+    index: usize,
+    txout: &bitcoin::TxOut,
+) -> Result<Descriptor<bitcoin::PublicKey>, Error> {
+    let txin = &tx.input[index];
+    let interpreter =
+        Interpreter::from_txdata(&txout.script_pubkey, &txin.script_sig, &txin.witness)?;
+    for constraint in interpreter.iter_with_tx(secp, tx, index, txout.value) {
+        constraint.map_err(Error::InterpreterError)?;
+    }
+    Ok(interpreter.descriptor)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{self, secp256k1};
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn verify_tx() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).expect("secret key");
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let des = Descriptor::Wpkh(pk);
+        let spent = vec![bitcoin::TxOut {
+            value: 10_000,
+            script_pubkey: des.script_pubkey(),
+        }];
+        let mut tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::default(),
+                script_sig: bitcoin::Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        let msg = des
+            .sighash_message(&tx, 0, 10_000, bitcoin::SigHashType::All)
+            .unwrap();
+        let mut sig = secp.sign(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x01); // sighash_all
+        tx.input[0].witness = vec![sig, pk.to_bytes()];
+
+        let interpreter = Interpreter::from_txdata(
+            &spent[0].script_pubkey,
+            &tx.input[0].script_sig,
+            &tx.input[0].witness,
+        )
+        .unwrap();
+        assert_eq!(*interpreter.descriptor(), des);
+        assert_eq!(interpreter.iter_with_tx(&secp, &tx, 0, 10_000).count(), 1);
+
+        let res = super::verify_tx(&secp, &tx, &spent);
+        assert_eq!(res.len(), 1);
+        assert_eq!(*res[0].as_ref().unwrap(), des);
+
+        // The signature commits to the spent amount
+        let other = vec![bitcoin::TxOut {
+            value: 9_999,
+            script_pubkey: des.script_pubkey(),
+        }];
+        match super::verify_tx(&secp, &tx, &other)[0] {
+            Err(Error::InterpreterError(_)) => {}
+            ref x => panic!("unexpected result {:?}", x),
+        }
+        match super::verify_tx(&secp, &tx, &[])[0] {
+            Err(Error::InputIndexOutOfRange(0)) => {}
+            ref x => panic!("unexpected result {:?}", x),
+        }
+    }
+    #[test]
+    fn iter_with_tx_timelocks() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).expect("secret key");
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let des = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(and_v(vc:pk_k({}),older(144)))",
+            pk
+        ))
+        .unwrap();
+        let spent = vec![bitcoin::TxOut {
+            value: 10_000,
+            script_pubkey: des.script_pubkey(),
+        }];
+        let mut tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::default(),
+                script_sig: bitcoin::Script::new(),
+                sequence: 0,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+
+        // A time based sequence doesn't meet a block based timelock
+        for &(version, sequence, valid) in &[
+            (2, 144, true),
+            (2, 143, false),
+            (2, (1 << 22) | 144, false),
+            (1, 144, false),
+        ] {
+            tx.version = version;
+            tx.input[0].sequence = sequence;
+            let msg = des
+                .sighash_message(&tx, 0, 10_000, bitcoin::SigHashType::All)
+                .unwrap();
+            let mut sig = secp.sign(&msg, &sk).serialize_der().to_vec();
+            sig.push(0x01); // sighash_all
+            tx.input[0].witness = vec![sig, des.witness_script().into_bytes()];
+
+            let interpreter = Interpreter::from_txdata(
+                &spent[0].script_pubkey,
+                &tx.input[0].script_sig,
+                &tx.input[0].witness,
+            )
+            .unwrap();
+            let res: Result<Vec<_>, _> = interpreter.iter_with_tx(&secp, &tx, 0, 10_000).collect();
+            assert_eq!(res.is_ok(), valid);
+            assert_eq!(super::verify_tx(&secp, &tx, &spent)[0].is_ok(), valid);
+        }
+    }
+
+    #[test]
+    fn iter_assume_sigs() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).expect("secret key");
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        // A signature of some unrelated message
+        let msg = secp256k1::Message::from_slice(&[2; 32]).expect("32 bytes");
+        let mut sig = secp.sign(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x01); // sighash_all
+
+        let des = Descriptor::Wpkh(pk);
+        let witness = vec![sig, pk.to_bytes()];
+        let script_sig = bitcoin::Script::new();
+        let interpreter =
+            Interpreter::from_txdata(&des.script_pubkey(), &script_sig, &witness).unwrap();
+
+        let constraints: Result<Vec<_>, _> = interpreter.iter_assume_sigs(0, 0).collect();
+        assert_eq!(constraints.unwrap().len(), 1);
+        let constraints: Result<Vec<_>, _> = interpreter
+            .iter(|_: &bitcoin::PublicKey, _| false, 0, 0)
+            .collect();
+        assert!(constraints.is_err());
+    }
+
+    #[test]
+    fn required_timelocks() {
+        let older = RelLockTime::from_consensus(144).unwrap();
+        let after = AbsLockTime::from_consensus(600_000).unwrap();
+        let mut required = RequiredTimelocks::default();
+        required.older.insert(older);
+        required.after.insert(after);
+
+        let mut tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 600_000,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::default(),
+                script_sig: bitcoin::Script::new(),
+                sequence: 144,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        assert_eq!(required.check_tx(&tx, 0), Ok(()));
+
+        // A timestamp does not meet a block height, however large
+        tx.lock_time = 1_500_000_000;
+        assert_eq!(
+            required.check_tx(&tx, 0),
+            Err(IntError::AbsoluteLocktimeNotMet(600_000))
+        );
+        tx.lock_time = 600_000;
+        tx.input[0].sequence = 143;
+        assert_eq!(
+            required.check_tx(&tx, 0),
+            Err(IntError::RelativeLocktimeNotMet(144))
+        );
+        tx.input[0].sequence = 144;
+        tx.version = 1;
+        assert_eq!(
+            required.check_tx(&tx, 0),
+            Err(IntError::RelativeLocktimeNotMet(144))
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! # Witness Minimization
+//!
+//! Signers which are over-eager (or simply naive) may produce witnesses which
+//! are valid but larger than they need to be, e.g. by providing signatures
+//! for every key in a multisig branch that is not actually taken. This module
+//! re-derives a minimal satisfaction from whatever data the interpreter was
+//! able to validate in the original witness.
+//!
+
+use std::collections::HashMap;
+
+use bitcoin;
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+
+use descriptor::satisfied_constraints::{HashLockType, SatisfiedConstraint, SatisfiedConstraints};
+use descriptor::{from_txin_with_witness_stack, Descriptor};
+use miniscript::locktime::{AbsLockTime, RelLockTime};
+use miniscript::satisfy::{BitcoinSig, Satisfier};
+use {Error, MiniscriptKey};
+
+/// Satisfier built from the constraints the interpreter found satisfied
+struct InterpretedSatisfier {
+    sigs: HashMap<bitcoin::PublicKey, BitcoinSig>,
+    pkh_sigs: HashMap<hash160::Hash, (bitcoin::PublicKey, BitcoinSig)>,
+    sha256: HashMap<sha256::Hash, [u8; 32]>,
+    hash256: HashMap<sha256d::Hash, [u8; 32]>,
+    hash160: HashMap<hash160::Hash, [u8; 32]>,
+    ripemd160: HashMap<ripemd160::Hash, [u8; 32]>,
+    age: u32,
+    height: u32,
+}
+
+impl Satisfier<bitcoin::PublicKey> for InterpretedSatisfier {
+    fn lookup_sig(&self, pk: &bitcoin::PublicKey) -> Option<BitcoinSig> {
+        self.sigs.get(pk).map(|s| *s)
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        self.pkh_sigs.get(pkh).map(|&(pk, _)| pk)
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &hash160::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        self.pkh_sigs.get(pkh).map(|s| *s)
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.sha256.get(&h).map(|p| *p)
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.hash256.get(&h).map(|p| *p)
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.ripemd160.get(&h).map(|p| *p)
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.hash160.get(&h).map(|p| *p)
+    }
+
+    // Timelocks are checked exactly as the interpreter checks them, so
+    // that the re-verification below accepts whatever we produce here
+    fn check_older(&self, n: u32) -> bool {
+        RelLockTime::from_consensus(n).map_or(false, |t| t.is_satisfied_by(self.age))
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        AbsLockTime::from_consensus(n).map_or(false, |t| t.is_satisfied_by(self.height))
+    }
+}
+
+/// Copies a 32-byte preimage out of the witness
+fn preimage(p: &[u8]) -> Option<[u8; 32]> {
+    if p.len() == 32 {
+        let mut ret = [0; 32];
+        ret.copy_from_slice(p);
+        Some(ret)
+    } else {
+        None
+    }
+}
+
+impl Descriptor<bitcoin::PublicKey> {
+    /// Replaces the witness and scriptSig of `txin`, which must already
+    /// satisfy this descriptor, with a minimal satisfaction using only the
+    /// signatures and preimages that the interpreter validated in the
+    /// original. The new satisfaction is itself checked with the interpreter
+    /// before being written to `txin`; on any error `txin` is left untouched.
+    ///
+    /// `verify_sig`, `age` and `height` are passed to the interpreter as in
+    /// `SatisfiedConstraints::from_descriptor`.
+    pub fn minimize_witness<F>(
+        &self,
+        txin: &mut bitcoin::TxIn,
+        mut verify_sig: F,
+        age: u32,
+        height: u32,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool,
+    {
+        let spk = self.script_pubkey();
+        let mut satisfier = InterpretedSatisfier {
+            sigs: HashMap::new(),
+            pkh_sigs: HashMap::new(),
+            sha256: HashMap::new(),
+            hash256: HashMap::new(),
+            hash160: HashMap::new(),
+            ripemd160: HashMap::new(),
+            age: age,
+            height: height,
+        };
+
+        {
+            let (des, stack) = from_txin_with_witness_stack(&spk, &txin.script_sig, &txin.witness)?;
+            let iter =
+                SatisfiedConstraints::from_descriptor(&des, stack, &mut verify_sig, age, height);
+            for constraint in iter {
+                match constraint.map_err(Error::InterpreterError)? {
+                    SatisfiedConstraint::PublicKey { key, sig } => {
+                        // The interpreter does not report the sighash flag,
+                        // so recover it from the original witness below
+                        satisfier
+                            .sigs
+                            .insert(*key, (sig, bitcoin::SigHashType::All));
+                    }
+                    SatisfiedConstraint::PublicKeyHash { keyhash, key, sig } => {
+                        satisfier
+                            .pkh_sigs
+                            .insert(*keyhash, (key, (sig, bitcoin::SigHashType::All)));
+                    }
+                    SatisfiedConstraint::HashLock { hash, preimage: p } => {
+                        let p = match preimage(p) {
+                            Some(p) => p,
+                            None => continue,
+                        };
+                        match hash {
+                            HashLockType::Sha256(h) => satisfier.sha256.insert(*h, p),
+                            HashLockType::Hash256(h) => satisfier.hash256.insert(*h, p),
+                            HashLockType::Hash160(h) => satisfier.hash160.insert(*h, p),
+                            HashLockType::Ripemd160(h) => satisfier.ripemd160.insert(*h, p),
+                        };
+                    }
+                    SatisfiedConstraint::RelativeTimeLock { .. }
+                    | SatisfiedConstraint::AbsoluteTimeLock { .. } => {}
+                }
+            }
+        }
+
+        // Restore the sighash flags of the original signatures
+        let pushes = txin
+            .script_sig
+            .iter(true)
+            .filter_map(|ins| match ins {
+                bitcoin::blockdata::script::Instruction::PushBytes(b) => Some(b.to_vec()),
+                _ => None,
+            })
+            .chain(txin.witness.iter().cloned());
+        for push in pushes {
+            if let Some((flag, der)) = push.split_last() {
+                let flag = bitcoin::SigHashType::from_u32(*flag as u32);
+                for sig in satisfier.sigs.values_mut() {
+                    if sig.0.serialize_der()[..] == der[..] {
+                        sig.1 = flag;
+                    }
+                }
+                for &mut (_, ref mut sig) in satisfier.pkh_sigs.values_mut() {
+                    if sig.0.serialize_der()[..] == der[..] {
+                        sig.1 = flag;
+                    }
+                }
+            }
+        }
+        // Keys revealed through a pkh are usable wherever the key appears
+        for &(pk, sig) in satisfier.pkh_sigs.values() {
+            satisfier.sigs.entry(pk).or_insert(sig);
+        }
+        for (pk, sig) in satisfier.sigs.clone() {
+            satisfier
+                .pkh_sigs
+                .entry(pk.to_pubkeyhash())
+                .or_insert((pk, sig));
+        }
+
+        let mut minimal = txin.clone();
+        self.satisfy(&mut minimal, &satisfier)?;
+
+        {
+            let (des, stack) =
+                from_txin_with_witness_stack(&spk, &minimal.script_sig, &minimal.witness)?;
+            let iter =
+                SatisfiedConstraints::from_descriptor(&des, stack, &mut verify_sig, age, height);
+            for constraint in iter {
+                constraint.map_err(Error::InterpreterError)?;
+            }
+        }
+
+        *txin = minimal;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{self, secp256k1};
+    use std::str::FromStr;
+
+    use descriptor::Descriptor;
+    use BitcoinSig;
+
+    #[test]
+    fn strips_extra_signatures() {
+        let secp = secp256k1::Secp256k1::new();
+        let msg = secp256k1::Message::from_slice(&b"michael was a message, amusingly"[..])
+            .expect("32 bytes");
+        let mut pks = vec![];
+        let mut sigs = vec![];
+        for i in 1..4 {
+            let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+            pks.push(bitcoin::PublicKey {
+                key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                compressed: true,
+            });
+            let mut sigser = secp.sign(&msg, &sk).serialize_der().to_vec();
+            sigser.push(0x01); // sighash_all
+            sigs.push(sigser);
+        }
+
+        // or_b(pk(A), s:pk(B)) satisfied with both signatures, although
+        // one would have been enough
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(or_b(pk({}),s:pk({})))",
+            pks[0], pks[1],
+        ))
+        .unwrap();
+        let wit_script = match desc {
+            Descriptor::Wsh(ref ms) => ms.encode(),
+            _ => unreachable!(),
+        };
+        let mut txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![sigs[1].clone(), sigs[0].clone(), wit_script.to_bytes()],
+        };
+        let vfyfn = |pk: &bitcoin::PublicKey, (sig, _)| secp.verify(&msg, &sig, &pk.key).is_ok();
+
+        desc.minimize_witness(&mut txin, vfyfn, 0, 0).unwrap();
+        assert_eq!(txin.witness.len(), 3);
+        assert!(txin.witness.contains(&vec![]));
+        let before = txin.clone();
+
+        // Minimizing is idempotent
+        desc.minimize_witness(&mut txin, vfyfn, 0, 0).unwrap();
+        assert_eq!(txin, before);
+
+        // A witness which does not satisfy is rejected and left alone
+        let mut bad = txin.clone();
+        bad.witness = vec![vec![], vec![], wit_script.to_bytes()];
+        assert!(desc.minimize_witness(&mut bad, vfyfn, 0, 0).is_err());
+        assert_eq!(bad.witness[0], Vec::<u8>::new());
+    }
+
+    #[test]
+    fn keeps_timelocked_branches() {
+        let secp = secp256k1::Secp256k1::new();
+        let msg = secp256k1::Message::from_slice(&b"michael was a message, amusingly"[..])
+            .expect("32 bytes");
+        let mut pks = vec![];
+        let mut sigs = vec![];
+        for i in 1..3 {
+            let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+            pks.push(bitcoin::PublicKey {
+                key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                compressed: true,
+            });
+            let mut sigser = secp.sign(&msg, &sk).serialize_der().to_vec();
+            sigser.push(0x01); // sighash_all
+            sigs.push(sigser);
+        }
+        let vfyfn = |pk: &bitcoin::PublicKey, (sig, _): BitcoinSig| {
+            secp.verify(&msg, &sig, &pk.key).is_ok()
+        };
+
+        // Only the timelocked branch can be taken, the relative timelock
+        // being met by the age and the absolute one by the height
+        for &(timelock, age, height) in &[("older(144)", 144, 0), ("after(100)", 0, 100)] {
+            let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+                "wsh(or_d(pk({}),and_v(v:pk({}),{})))",
+                pks[0], pks[1], timelock,
+            ))
+            .unwrap();
+            let wit_script = match desc {
+                Descriptor::Wsh(ref ms) => ms.encode(),
+                _ => unreachable!(),
+            };
+            let witness = vec![sigs[1].clone(), vec![], wit_script.to_bytes()];
+            let mut txin = bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::default(),
+                script_sig: bitcoin::Script::new(),
+                sequence: 0xffffffff,
+                witness: witness.clone(),
+            };
+
+            let res = desc.minimize_witness(&mut txin, &vfyfn, age, height);
+            assert!(res.is_ok());
+            assert_eq!(txin.witness, witness);
+            // Neither timelock is met the other way round
+            let res = desc.minimize_witness(&mut txin, &vfyfn, height, age);
+            assert!(res.is_err());
+        }
+    }
+}
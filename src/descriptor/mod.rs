@@ -27,33 +27,75 @@ use bitcoin::blockdata::{opcodes, script};
 use bitcoin::{self, PublicKey, Script};
 #[cfg(feature = "serde")]
 use serde::{de, ser};
-use std::fmt;
 use std::str::{self, FromStr};
+use std::vec;
+use std::{cmp, fmt};
 
 use expression;
-use miniscript::Miniscript;
+use miniscript::analyzable::AnalysisError;
+use miniscript::context::{Legacy, ScriptContext, Segwitv0};
+use miniscript::{ForEach, Miniscript, ParseLimits};
+#[cfg(feature = "compiler")]
+use policy::compiler::CompilerError;
+#[cfg(feature = "compiler")]
+use policy::{Concrete, Liftable};
+use BitcoinSig;
 use Error;
 use MiniscriptKey;
 use Satisfier;
 use ToPublicKey;
+use Translator;
 
+mod cache;
+mod callback;
+mod checksum;
 mod create_descriptor;
+mod intern;
+mod interpreter;
+mod minimize;
+mod partial;
 mod satisfied_constraints;
-
-pub use self::create_descriptor::from_txin_with_witness_stack;
+mod secret;
+mod sighash;
+mod signer;
+mod standardness;
+mod template;
+mod weights;
+mod xprv;
+
+pub use self::cache::DescriptorCache;
+pub use self::callback::CallbackSigner;
+pub use self::checksum::desc_checksum;
+pub use self::create_descriptor::{descriptor_string_from_txin, from_txin_with_witness_stack};
+pub use self::intern::DescriptorInterner;
+pub use self::interpreter::{verify_tx, Interpreter, RequiredTimelocks};
+pub use self::partial::PartialSatisfaction;
 pub use self::satisfied_constraints::Error as InterpreterError;
+pub use self::satisfied_constraints::ErrorLocation as InterpreterErrorLocation;
 pub use self::satisfied_constraints::SatisfiedConstraint;
 pub use self::satisfied_constraints::SatisfiedConstraints;
 pub use self::satisfied_constraints::Stack;
+pub use self::satisfied_constraints::{SignatureChecker, TxSignatureChecker};
+pub use self::secret::{DescriptorSecretKey, DescriptorXPrv, KeyMap};
+pub use self::signer::{SignedInput, Signer};
+pub use self::standardness::StandardnessError;
+use self::standardness::MAX_SCRIPT_ELEMENT_SIZE;
+pub use self::standardness::{MAX_STANDARD_P2WSH_SCRIPT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEMS};
+pub use self::template::{SatisfactionTemplate, TemplateElement};
+pub use self::weights::SpendPathWeight;
+pub use self::xprv::XprvSatisfier;
 use bitcoin::hashes::core::fmt::Formatter;
 use bitcoin::hashes::hash160;
-use bitcoin::hashes::hex::FromHex;
-use bitcoin::secp256k1::Secp256k1;
-use bitcoin::util::bip32::{ChildNumber, DerivationPath, Error as Bip32Error, ExtendedPubKey};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32::{
+    ChildNumber, DerivationPath, Error as Bip32Error, ExtendedPubKey, Fingerprint,
+};
 use std::fmt::{Display, Write};
+use std::ops::Range;
 
 /// Script descriptor
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Descriptor<Pk: MiniscriptKey> {
     /// A raw scriptpubkey (including pay-to-pubkey)
     Bare(Miniscript<Pk>),
@@ -71,151 +113,474 @@ pub enum Descriptor<Pk: MiniscriptKey> {
     Wsh(Miniscript<Pk>),
     /// P2SH-P2WSH
     ShWsh(Miniscript<Pk>),
+    /// An address whose spending conditions are unknown, `addr(ADDR)`
+    Addr(bitcoin::Address),
+    /// A scriptpubkey whose spending conditions are unknown, `raw(HEX)`
+    Raw(Script),
+}
+
+/// The kind of a descriptor, without its keys or script, see
+/// `Descriptor::desc_type`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DescriptorType {
+    /// Bare script, `Descriptor::Bare`
+    Bare,
+    /// Pay-to-Pubkey
+    Pk,
+    /// Pay-to-PubKey-Hash
+    Pkh,
+    /// Pay-to-Witness-PubKey-Hash
+    Wpkh,
+    /// Pay-to-Witness-PubKey-Hash inside P2SH
+    ShWpkh,
+    /// Pay-to-ScriptHash
+    Sh,
+    /// Pay-to-Witness-ScriptHash
+    Wsh,
+    /// P2SH-P2WSH
+    ShWsh,
+    /// Address with unknown spending conditions
+    Addr,
+    /// Raw scriptpubkey with unknown spending conditions
+    Raw,
+}
+
+impl DescriptorType {
+    /// Whether outputs of this type are spent using the witness. Always
+    /// `false` for `Addr` and `Raw`, whose spending conditions are unknown.
+    pub fn is_witness(&self) -> bool {
+        self.segwit_version().is_some()
+    }
+
+    /// The segwit version of outputs of this type, `None` for non-segwit
+    /// outputs as well as for `Addr` and `Raw`
+    pub fn segwit_version(&self) -> Option<u8> {
+        match *self {
+            DescriptorType::Wpkh
+            | DescriptorType::ShWpkh
+            | DescriptorType::Wsh
+            | DescriptorType::ShWsh => Some(0),
+            DescriptorType::Bare
+            | DescriptorType::Pk
+            | DescriptorType::Pkh
+            | DescriptorType::Sh
+            | DescriptorType::Addr
+            | DescriptorType::Raw => None,
+        }
+    }
+
+    /// Whether outputs of this type are P2SH, including wrapped segwit
+    pub fn is_sh(&self) -> bool {
+        match *self {
+            DescriptorType::ShWpkh | DescriptorType::Sh | DescriptorType::ShWsh => true,
+            _ => false,
+        }
+    }
+}
+
+/// Assumption on the size of the signatures in a satisfaction, used for
+/// weight estimation, see `Descriptor::max_satisfaction_weight_with`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SigSizeAssumption {
+    /// ECDSA signatures taking up to 73 bytes including push opcode and
+    /// sighash suffix, which covers any signature with a high R value
+    Ecdsa73,
+    /// ECDSA signatures taking up to 72 bytes including push opcode and
+    /// sighash suffix, as produced by signers grinding for a low R value
+    Ecdsa72,
+    /// ECDSA signatures taking up to 71 bytes including push opcode and
+    /// sighash suffix
+    Ecdsa71,
+    /// 64-byte Schnorr signatures, using the default sighash
+    Schnorr64,
+    /// 65-byte Schnorr signatures, with an explicit sighash suffix
+    Schnorr65,
+}
+
+impl SigSizeAssumption {
+    /// Size of a signature in a satisfaction, including its push opcode or
+    /// length prefix and sighash suffix
+    pub fn sig_len(&self) -> usize {
+        match *self {
+            SigSizeAssumption::Ecdsa73 => 73,
+            SigSizeAssumption::Ecdsa72 => 72,
+            SigSizeAssumption::Ecdsa71 => 71,
+            SigSizeAssumption::Schnorr64 => 65,
+            SigSizeAssumption::Schnorr65 => 66,
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey> Descriptor<Pk> {
+    /// Creates a `wpkh` descriptor, erroring if `pk` is uncompressed
+    pub fn new_wpkh(pk: Pk) -> Result<Descriptor<Pk>, Error> {
+        Segwitv0::check_pk(&pk)?;
+        Ok(Descriptor::Wpkh(pk))
+    }
+
+    /// Creates a `sh(wpkh)` descriptor, erroring if `pk` is uncompressed
+    pub fn new_sh_wpkh(pk: Pk) -> Result<Descriptor<Pk>, Error> {
+        Segwitv0::check_pk(&pk)?;
+        Ok(Descriptor::ShWpkh(pk))
+    }
+
+    /// Creates a `wsh` descriptor, erroring if any key of `ms` is
+    /// uncompressed
+    pub fn new_wsh(ms: Miniscript<Pk>) -> Result<Descriptor<Pk>, Error> {
+        ms.check_keys::<Segwitv0>()?;
+        Ok(Descriptor::Wsh(ms))
+    }
+
+    /// Creates a `sh(wsh)` descriptor, erroring if any key of `ms` is
+    /// uncompressed
+    pub fn new_sh_wsh(ms: Miniscript<Pk>) -> Result<Descriptor<Pk>, Error> {
+        ms.check_keys::<Segwitv0>()?;
+        Ok(Descriptor::ShWsh(ms))
+    }
+
+    /// The kind of this descriptor. Prefer this over matching on the
+    /// descriptor itself when the keys and script don't matter.
+    pub fn desc_type(&self) -> DescriptorType {
+        match *self {
+            Descriptor::Bare(..) => DescriptorType::Bare,
+            Descriptor::Pk(..) => DescriptorType::Pk,
+            Descriptor::Pkh(..) => DescriptorType::Pkh,
+            Descriptor::Wpkh(..) => DescriptorType::Wpkh,
+            Descriptor::ShWpkh(..) => DescriptorType::ShWpkh,
+            Descriptor::Sh(..) => DescriptorType::Sh,
+            Descriptor::Wsh(..) => DescriptorType::Wsh,
+            Descriptor::ShWsh(..) => DescriptorType::ShWsh,
+            Descriptor::Addr(..) => DescriptorType::Addr,
+            Descriptor::Raw(..) => DescriptorType::Raw,
+        }
+    }
+
+    /// Checks that the Miniscript of the descriptor, if any, does not exceed
+    /// the consensus or standardness limits of its context: `Legacy` for
+    /// bare and P2SH descriptors, `Segwitv0` for (wrapped) P2WSH ones.
+    /// Descriptors parsed from strings, except by `from_str_insane`, are
+    /// always within them.
+    pub fn check_resource_limits(&self) -> Result<(), Error> {
+        match *self {
+            Descriptor::Bare(ref ms) | Descriptor::Sh(ref ms) => {
+                ms.check_resource_limits::<Legacy>()
+            }
+            Descriptor::Wsh(ref ms) | Descriptor::ShWsh(ref ms) => {
+                ms.check_resource_limits::<Segwitv0>()
+            }
+            Descriptor::Pk(..)
+            | Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::ShWpkh(..)
+            | Descriptor::Addr(..)
+            | Descriptor::Raw(..) => Ok(()),
+        }
+    }
+
+    /// Whether the descriptor is within the resource limits of its context,
+    /// see `check_resource_limits`
+    pub fn within_resource_limits(&self) -> bool {
+        self.check_resource_limits().is_ok()
+    }
+
+    /// Checks that the descriptor does not exceed the consensus limits of
+    /// its context, i.e. that it can be spent at all. In addition to the
+    /// limits of the Miniscript, P2SH redeem scripts may be at most 520
+    /// bytes, as they are pushed by the scriptSig. As for the Miniscript
+    /// limits, script sizes are computed assuming compressed keys. Exceeding
+    /// only the standardness limits checked by `check_resource_limits` and
+    /// `check_standard` makes spends non-standard, but valid.
+    pub fn check_consensus_limits(&self) -> Result<(), Error> {
+        match *self {
+            Descriptor::Bare(ref ms) => ms.check_consensus_limits::<Legacy>(),
+            Descriptor::Sh(ref ms) => {
+                ms.check_consensus_limits::<Legacy>()?;
+                if ms.ext.pk_cost > MAX_SCRIPT_ELEMENT_SIZE {
+                    Err(Error::MaxScriptSizeExceeded(ms.ext.pk_cost))
+                } else {
+                    Ok(())
+                }
+            }
+            Descriptor::Wsh(ref ms) | Descriptor::ShWsh(ref ms) => {
+                ms.check_consensus_limits::<Segwitv0>()
+            }
+            Descriptor::Pk(..)
+            | Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::ShWpkh(..)
+            | Descriptor::Addr(..)
+            | Descriptor::Raw(..) => Ok(()),
+        }
+    }
+
+    /// Whether the descriptor is within the consensus limits of its
+    /// context, see `check_consensus_limits`
+    pub fn within_consensus_limits(&self) -> bool {
+        self.check_consensus_limits().is_ok()
+    }
+
+    /// Runs `Miniscript::sanity_check` on the Miniscript of the descriptor,
+    /// if any. Descriptors with a single key always pass, as do addresses
+    /// and raw scripts, which can't be analyzed.
+    pub fn sanity_check(&self) -> Result<(), AnalysisError> {
+        match *self {
+            Descriptor::Bare(ref ms)
+            | Descriptor::Sh(ref ms)
+            | Descriptor::Wsh(ref ms)
+            | Descriptor::ShWsh(ref ms) => ms.sanity_check(),
+            Descriptor::Pk(..)
+            | Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::ShWpkh(..)
+            | Descriptor::Addr(..)
+            | Descriptor::Raw(..) => Ok(()),
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey> PartialOrd for Descriptor<Pk> {
+    fn partial_cmp(&self, other: &Descriptor<Pk>) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Orders like a derived `Ord` would; `bitcoin::Address` is not `Ord`, so
+/// addresses are ordered by their string form
+impl<Pk: MiniscriptKey> Ord for Descriptor<Pk> {
+    fn cmp(&self, other: &Descriptor<Pk>) -> cmp::Ordering {
+        match (self, other) {
+            (&Descriptor::Bare(ref a), &Descriptor::Bare(ref b))
+            | (&Descriptor::Sh(ref a), &Descriptor::Sh(ref b))
+            | (&Descriptor::Wsh(ref a), &Descriptor::Wsh(ref b))
+            | (&Descriptor::ShWsh(ref a), &Descriptor::ShWsh(ref b)) => a.cmp(b),
+            (&Descriptor::Pk(ref a), &Descriptor::Pk(ref b))
+            | (&Descriptor::Pkh(ref a), &Descriptor::Pkh(ref b))
+            | (&Descriptor::Wpkh(ref a), &Descriptor::Wpkh(ref b))
+            | (&Descriptor::ShWpkh(ref a), &Descriptor::ShWpkh(ref b)) => a.cmp(b),
+            (&Descriptor::Addr(ref a), &Descriptor::Addr(ref b)) => {
+                a.to_string().cmp(&b.to_string())
+            }
+            (&Descriptor::Raw(ref a), &Descriptor::Raw(ref b)) => a.cmp(b),
+            _ => self.desc_type().cmp(&other.desc_type()),
+        }
+    }
 }
 
+/// A public key in a descriptor: either a single key, or an xpub from
+/// which the actual key is derived. Hardened derivation steps are displayed
+/// as `'`, or as `h` using the alternate flag (`{:#}`).
 #[derive(Debug, Eq, PartialEq, Clone, Ord, PartialOrd, Hash)]
-pub enum DescriptorKey {
-    PukKey(bitcoin::PublicKey),
+pub enum DescriptorPublicKey {
+    /// A single public key
+    PubKey(bitcoin::PublicKey),
+    /// An extended public key with derivation path
     XPub(DescriptorXPub),
 }
 
+/// The former name of `DescriptorPublicKey`
+#[deprecated(note = "use DescriptorPublicKey")]
+pub type DescriptorKey = DescriptorPublicKey;
+
+/// An xpub in a descriptor, e.g. `[d34db33f/44'/0'/0']xpub.../1/*`
 #[derive(Debug, Eq, PartialEq, Clone, Ord, PartialOrd, Hash)]
 pub struct DescriptorXPub {
-    source: Option<([u8; 4], DerivationPath)>,
-    xpub: bitcoin::util::bip32::ExtendedPubKey,
-    derivation_path: DerivationPath,
-    is_wildcard: bool,
+    /// Fingerprint of the master key and path from it to `xpub`, if known
+    pub origin: Option<([u8; 4], DerivationPath)>,
+    /// The extended public key
+    pub xpub: bitcoin::util::bip32::ExtendedPubKey,
+    /// Path from `xpub` to the actual key, not including the wildcard
+    pub derivation_path: DerivationPath,
+    /// Whether the path ends in a wildcard (`/*`), i.e. the key describes a
+    /// range of keys
+    pub is_wildcard: bool,
+    /// Whether the wildcard step is hardened, only derivable from an xprv
+    pub hardened_wildcard: bool,
 }
 
+/// Error parsing a `DescriptorPublicKey`
 #[derive(Debug)]
 pub struct DescriptorKeyParseError(&'static str);
 
-impl Display for DescriptorKey {
+impl Display for DescriptorPublicKey {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            DescriptorKey::PukKey(pk) => pk.fmt(f),
-            DescriptorKey::XPub(xpub) => {
-                if let Some((master_id, ref master_deriv)) = &xpub.source {
-                    f.write_char('[')?;
-                    for byte in master_id {
-                        write!(f, "{:02x}", byte)?;
-                    }
-                    fmt_derivation_path(f, master_deriv)?;
-                    f.write_char(']')?;
-                }
+            DescriptorPublicKey::PubKey(pk) => pk.fmt(f),
+            DescriptorPublicKey::XPub(xpub) => {
+                fmt_key_origin(f, &xpub.origin)?;
                 xpub.xpub.fmt(f)?;
                 fmt_derivation_path(f, &xpub.derivation_path)?;
-                if xpub.is_wildcard {
-                    write!(f, "/*")?;
-                }
-                Ok(())
+                fmt_wildcard(f, xpub.is_wildcard, xpub.hardened_wildcard)
             }
         }
     }
 }
 
+fn fmt_key_origin(
+    f: &mut Formatter<'_>,
+    origin: &Option<([u8; 4], DerivationPath)>,
+) -> std::fmt::Result {
+    if let Some((master_id, ref master_deriv)) = origin {
+        f.write_char('[')?;
+        for byte in master_id {
+            write!(f, "{:02x}", byte)?;
+        }
+        fmt_derivation_path(f, master_deriv)?;
+        f.write_char(']')?;
+    }
+    Ok(())
+}
+
+/// Hardened steps are marked by `'`, or by `h` with the alternate flag (`{:#}`)
 fn fmt_derivation_path(f: &mut Formatter<'_>, path: &DerivationPath) -> std::fmt::Result {
     for child in path {
-        write!(f, "/{}", child)?;
+        match *child {
+            ChildNumber::Hardened { index } if f.alternate() => write!(f, "/{}h", index)?,
+            _ => write!(f, "/{}", child)?,
+        }
     }
     Ok(())
 }
 
-impl FromStr for DescriptorKey {
+fn fmt_wildcard(f: &mut Formatter<'_>, is_wildcard: bool, hardened: bool) -> std::fmt::Result {
+    if is_wildcard {
+        write!(f, "/*")?;
+        if hardened {
+            f.write_char(if f.alternate() { 'h' } else { '\'' })?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses a single derivation step, which may be marked hardened by either
+/// `'` or `h`
+fn parse_child_number(s: &str) -> Result<ChildNumber, Bip32Error> {
+    let (index, hardened) = if s.ends_with('\'') || s.ends_with('h') {
+        (&s[..s.len() - 1], true)
+    } else {
+        (s, false)
+    };
+    let index = u32::from_str(index).map_err(|_| Bip32Error::InvalidChildNumberFormat)?;
+    if hardened {
+        ChildNumber::from_hardened_idx(index)
+    } else {
+        ChildNumber::from_normal_idx(index)
+    }
+}
+
+/// Splits the origin `[fingerprint/path]` off a key, if there is one
+fn parse_key_origin(
+    s: &str,
+) -> Result<(Option<([u8; 4], DerivationPath)>, &str), DescriptorKeyParseError> {
+    if !s.starts_with('[') {
+        return Ok((None, s));
+    }
+
+    let mut parts = s[1..].split(']');
+    let mut origin = parts
+        .next()
+        .ok_or(DescriptorKeyParseError("Unclosed '['"))?
+        .split('/');
+
+    let origin_id_hex = origin.next().ok_or(DescriptorKeyParseError(
+        "No master fingerprint found after '['",
+    ))?;
+
+    if origin_id_hex.len() != 8 {
+        return Err(DescriptorKeyParseError(
+            "Master fingerprint should be 8 characters long",
+        ));
+    }
+
+    let origin_id: [u8; 4] = FromHex::from_hex(origin_id_hex).map_err(|_| {
+        DescriptorKeyParseError("Malformed master fingerprint, expected 8 hex chars")
+    })?;
+
+    let origin_path = origin
+        .map(parse_child_number)
+        .collect::<Result<DerivationPath, Bip32Error>>()
+        .map_err(|_| DescriptorKeyParseError("Error while parsing master derivation path"))?;
+
+    let key_deriv = parts.next().ok_or(DescriptorKeyParseError(
+        "No key found after origin description",
+    ))?;
+
+    Ok((Some((origin_id, origin_path)), key_deriv))
+}
+
+/// Splits an extended key followed by a derivation path, which may end in
+/// a wildcard, into the key, the path, whether there is a wildcard and
+/// whether it is hardened
+fn parse_xkey_deriv(
+    key_deriv: &str,
+) -> Result<(&str, DerivationPath, bool, bool), DescriptorKeyParseError> {
+    let mut key_deriv = key_deriv.split('/');
+    let key_str = key_deriv.next().ok_or(DescriptorKeyParseError(
+        "No key found after origin description",
+    ))?;
+
+    let mut is_wildcard = false;
+    let mut hardened_wildcard = false;
+    let derivation_path = key_deriv
+        .filter_map(|p| {
+            if !is_wildcard && (p == "*" || p == "*'" || p == "*h") {
+                is_wildcard = true;
+                hardened_wildcard = p != "*";
+                None
+            } else if is_wildcard {
+                Some(Err(DescriptorKeyParseError(
+                    "'*' may only appear as last element in a derivation path.",
+                )))
+            } else {
+                Some(parse_child_number(p).map_err(|_| {
+                    DescriptorKeyParseError("Error while parsing key derivation path")
+                }))
+            }
+        })
+        .collect::<Result<DerivationPath, _>>()?;
+
+    Ok((key_str, derivation_path, is_wildcard, hardened_wildcard))
+}
+
+impl FromStr for DescriptorPublicKey {
     type Err = DescriptorKeyParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.len() < 66 {
-            Err(DescriptorKeyParseError(
+            return Err(DescriptorKeyParseError(
                 "Key too short (<66 char), doesn't match any format",
-            ))
-        } else if s.chars().next().unwrap() == '[' {
-            let mut parts = s[1..].split(']');
-            let mut origin = parts
-                .next()
-                .ok_or(DescriptorKeyParseError("Unclosed '['"))?
-                .split('/');
-
-            let origin_id_hex = origin.next().ok_or(DescriptorKeyParseError(
-                "No master fingerprint found after '['",
-            ))?;
-
-            if origin_id_hex.len() != 8 {
-                return Err(DescriptorKeyParseError(
-                    "Master fingerprint should be 8 characters long",
-                ));
-            }
-
-            let origin_id: [u8; 4] = FromHex::from_hex(origin_id_hex).map_err(|_| {
-                DescriptorKeyParseError("Malformed master fingerprint, expected 8 hex chars")
-            })?;
-
-            let origin_path = origin
-                .map(|p| ChildNumber::from_str(p))
-                .collect::<Result<DerivationPath, Bip32Error>>()
-                .map_err(|_| {
-                    DescriptorKeyParseError("Error while parsing master derivation path")
-                })?;
-
-            let key_deriv = parts.next().ok_or(DescriptorKeyParseError(
-                "No key found after origin description",
-            ))?;
-
-            let (xpub, derivation_path, is_wildcard) = Self::parse_xpub_deriv(key_deriv)?;
-
-            Ok(DescriptorKey::XPub(DescriptorXPub {
-                source: Some((origin_id, origin_path)),
-                xpub,
-                derivation_path,
-                is_wildcard,
-            }))
-        } else if s.starts_with("02") || s.starts_with("03") || s.starts_with("04") {
+            ));
+        }
+
+        let (origin, key_deriv) = parse_key_origin(s)?;
+        if origin.is_none() && (s.starts_with("02") || s.starts_with("03") || s.starts_with("04")) {
             let pk = PublicKey::from_str(s)
                 .map_err(|_| DescriptorKeyParseError("Error while parsing simple public key"))?;
-            Ok(DescriptorKey::PukKey(pk))
+            Ok(DescriptorPublicKey::PubKey(pk))
         } else {
-            let (xpub, derivation_path, is_wildcard) = Self::parse_xpub_deriv(s)?;
-            Ok(DescriptorKey::XPub(DescriptorXPub {
-                source: None,
+            let (xpub, derivation_path, is_wildcard, hardened_wildcard) =
+                Self::parse_xpub_deriv(key_deriv)?;
+            Ok(DescriptorPublicKey::XPub(DescriptorXPub {
+                origin,
                 xpub,
                 derivation_path,
                 is_wildcard,
+                hardened_wildcard,
             }))
         }
     }
 }
 
-impl DescriptorKey {
+impl DescriptorPublicKey {
     fn parse_xpub_deriv(
         key_deriv: &str,
-    ) -> Result<(ExtendedPubKey, DerivationPath, bool), DescriptorKeyParseError> {
-        let mut key_deriv = key_deriv.split('/');
-        let xpub_str = key_deriv.next().ok_or(DescriptorKeyParseError(
-            "No key found after origin description",
-        ))?;
+    ) -> Result<(ExtendedPubKey, DerivationPath, bool, bool), DescriptorKeyParseError> {
+        let (xpub_str, derivation_path, is_wildcard, hardened_wildcard) =
+            parse_xkey_deriv(key_deriv)?;
         let xpub = ExtendedPubKey::from_str(xpub_str)
             .map_err(|_| DescriptorKeyParseError("Error while parsing xpub."))?;
 
-        let mut is_wildcard = false;
-        let derivation_path = key_deriv
-            .filter_map(|p| {
-                if !is_wildcard && p == "*" {
-                    is_wildcard = true;
-                    None
-                } else if is_wildcard {
-                    Some(Err(DescriptorKeyParseError(
-                        "'*' may only appear as last element in a derivation path.",
-                    )))
-                } else {
-                    Some(ChildNumber::from_str(p).map_err(|_| {
-                        DescriptorKeyParseError("Error while parsing key derivation path")
-                    }))
-                }
-            })
-            .collect::<Result<DerivationPath, _>>()?;
-
         if (&derivation_path).into_iter().all(|c| c.is_normal()) {
-            Ok((xpub, derivation_path, is_wildcard))
+            Ok((xpub, derivation_path, is_wildcard, hardened_wildcard))
         } else {
             Err(DescriptorKeyParseError(
                 "Hardened derivation is currently not supported.",
@@ -223,19 +588,73 @@ impl DescriptorKey {
         }
     }
 
+    /// Fingerprint of the master key this key was derived from, as recorded
+    /// in its origin. Keys without origin are their own master: for an xpub
+    /// this is its fingerprint, for a single key the first 4 bytes of its
+    /// hash160.
+    pub fn master_fingerprint(&self) -> Fingerprint {
+        match self {
+            DescriptorPublicKey::PubKey(pk) => Fingerprint::from(&pk.to_pubkeyhash()[0..4]),
+            DescriptorPublicKey::XPub(xpub) => match xpub.origin {
+                Some((ref fingerprint, _)) => Fingerprint::from(&fingerprint[..]),
+                None => xpub.xpub.fingerprint(),
+            },
+        }
+    }
+
+    /// Full path from the master key to this key, i.e. the origin path
+    /// followed by the key's own derivation path. Wildcards are not part of
+    /// the path, so keys have to be derived first to get the path of the
+    /// actual key. Single keys have an empty path.
+    pub fn full_derivation_path(&self) -> DerivationPath {
+        match self {
+            DescriptorPublicKey::PubKey(_) => Vec::<ChildNumber>::new().into(),
+            DescriptorPublicKey::XPub(xpub) => {
+                let origin = xpub.origin.as_ref().map(|&(_, ref path)| path);
+                origin
+                    .into_iter()
+                    .flat_map(|path| path.into_iter())
+                    .chain(&xpub.derivation_path)
+                    .cloned()
+                    .collect()
+            }
+        }
+    }
+
+    /// Whether the key is an xpub ending in a wildcard, which has to be derived before use
+    pub fn is_wildcard(&self) -> bool {
+        match self {
+            DescriptorPublicKey::PubKey(_) => false,
+            DescriptorPublicKey::XPub(xpub) => xpub.is_wildcard,
+        }
+    }
+
     /// Derives a new key using the path if self is a wildcard xpub. Otehrwise returns a copy of
     /// self.
     ///
-    /// Panics if derivation path contains a hardened child number
-    pub fn derive(&self, path: &[ChildNumber]) -> DescriptorKey {
-        assert!(path.into_iter().all(|c| c.is_normal()));
+    /// Panics if derivation path contains a hardened child number or if self has a hardened
+    /// wildcard, see `try_derive`.
+    pub fn derive(&self, path: &[ChildNumber]) -> DescriptorPublicKey {
+        self.try_derive(path)
+            .expect("only normal derivation is possible from an xpub")
+    }
+
+    /// Derives a new key using the path if self is a wildcard xpub. Otherwise returns a copy of
+    /// self. Fails if either the path or the wildcard is hardened, as neither can be derived
+    /// from an xpub.
+    pub fn try_derive(&self, path: &[ChildNumber]) -> Result<DescriptorPublicKey, DerivationError> {
+        if let Some(child) = path.iter().find(|c| !c.is_normal()) {
+            return Err(DerivationError::HardenedChild(*child));
+        }
 
         match self {
-            DescriptorKey::PukKey(pk) => DescriptorKey::PukKey(*pk),
-            DescriptorKey::XPub(xpub) => {
-                if xpub.is_wildcard {
-                    DescriptorKey::XPub(DescriptorXPub {
-                        source: xpub.source.clone(),
+            DescriptorPublicKey::PubKey(pk) => Ok(DescriptorPublicKey::PubKey(*pk)),
+            DescriptorPublicKey::XPub(xpub) => {
+                if xpub.is_wildcard && xpub.hardened_wildcard {
+                    Err(DerivationError::HardenedWildcard(self.clone()))
+                } else if xpub.is_wildcard {
+                    Ok(DescriptorPublicKey::XPub(DescriptorXPub {
+                        origin: xpub.origin.clone(),
                         xpub: xpub.xpub.clone(),
                         derivation_path: (&xpub.derivation_path)
                             .into_iter()
@@ -243,22 +662,45 @@ impl DescriptorKey {
                             .cloned()
                             .collect(),
                         is_wildcard: false,
-                    })
+                        hardened_wildcard: false,
+                    }))
                 } else {
-                    self.clone()
+                    Ok(self.clone())
                 }
             }
         }
     }
 }
 
-impl MiniscriptKey for DescriptorKey {
+/// Error deriving a child of a descriptor key
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum DerivationError {
+    /// A hardened child number was requested, which an xpub can't derive
+    HardenedChild(ChildNumber),
+    /// The key has a hardened wildcard (`/*'`), which an xpub can't derive
+    HardenedWildcard(DescriptorPublicKey),
+}
+
+impl Display for DerivationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DerivationError::HardenedChild(child) => {
+                write!(f, "cannot derive hardened child {} from an xpub", child)
+            }
+            DerivationError::HardenedWildcard(key) => {
+                write!(f, "cannot derive hardened wildcard of {} from an xpub", key)
+            }
+        }
+    }
+}
+
+impl MiniscriptKey for DescriptorPublicKey {
     type Hash = hash160::Hash;
 
     fn to_pubkeyhash(&self) -> Self::Hash {
         match self {
-            DescriptorKey::PukKey(pk) => pk.to_pubkeyhash(),
-            DescriptorKey::XPub(xpub) => {
+            DescriptorPublicKey::PubKey(pk) => pk.to_pubkeyhash(),
+            DescriptorPublicKey::XPub(xpub) => {
                 let ctx = Secp256k1::verification_only();
                 xpub.xpub
                     .derive_pub(&ctx, &xpub.derivation_path)
@@ -268,13 +710,21 @@ impl MiniscriptKey for DescriptorKey {
             }
         }
     }
+
+    fn is_uncompressed(&self) -> bool {
+        match self {
+            DescriptorPublicKey::PubKey(pk) => pk.is_uncompressed(),
+            // BIP32 only derives compressed keys
+            DescriptorPublicKey::XPub(..) => false,
+        }
+    }
 }
 
-impl ToPublicKey for DescriptorKey {
+impl ToPublicKey for DescriptorPublicKey {
     fn to_public_key(&self) -> PublicKey {
         match self {
-            DescriptorKey::PukKey(pk) => *pk,
-            DescriptorKey::XPub(xpub) => {
+            DescriptorPublicKey::PubKey(pk) => *pk,
+            DescriptorPublicKey::XPub(xpub) => {
                 let ctx = Secp256k1::verification_only();
                 xpub.xpub
                     .derive_pub(&ctx, &xpub.derivation_path)
@@ -289,6 +739,190 @@ impl ToPublicKey for DescriptorKey {
     }
 }
 
+/// A descriptor key without wildcard, which hence stands for exactly one
+/// public key. Unlike a plain `PublicKey`, it keeps the key's origin.
+#[derive(Debug, Eq, PartialEq, Clone, Ord, PartialOrd, Hash)]
+pub struct DefiniteDescriptorKey(DescriptorPublicKey);
+
+impl DefiniteDescriptorKey {
+    /// Wraps `key`, or returns `None` if it has a wildcard
+    pub fn new(key: DescriptorPublicKey) -> Option<DefiniteDescriptorKey> {
+        if key.is_wildcard() {
+            None
+        } else {
+            Some(DefiniteDescriptorKey(key))
+        }
+    }
+
+    /// The underlying descriptor key
+    pub fn as_descriptor_public_key(&self) -> &DescriptorPublicKey {
+        &self.0
+    }
+
+    /// Unwraps the underlying descriptor key
+    pub fn into_descriptor_public_key(self) -> DescriptorPublicKey {
+        self.0
+    }
+}
+
+impl Display for DefiniteDescriptorKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for DefiniteDescriptorKey {
+    type Err = DescriptorKeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = DescriptorPublicKey::from_str(s)?;
+        DefiniteDescriptorKey::new(key).ok_or(DescriptorKeyParseError(
+            "Definite key can't have a wildcard",
+        ))
+    }
+}
+
+impl MiniscriptKey for DefiniteDescriptorKey {
+    type Hash = hash160::Hash;
+
+    fn to_pubkeyhash(&self) -> Self::Hash {
+        self.0.to_pubkeyhash()
+    }
+
+    fn is_uncompressed(&self) -> bool {
+        self.0.is_uncompressed()
+    }
+}
+
+impl ToPublicKey for DefiniteDescriptorKey {
+    fn to_public_key(&self) -> PublicKey {
+        self.0.to_public_key()
+    }
+
+    fn hash_to_hash160(hash: &Self::Hash) -> hash160::Hash {
+        *hash
+    }
+}
+
+/// A key origin in a descriptor which could not be verified against the
+/// provided trusted xpubs
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum KeyOriginError {
+    /// The key has no origin to verify, e.g. it is a plain public key
+    MissingOrigin(DescriptorPublicKey),
+    /// None of the trusted xpubs has the origin's fingerprint
+    UnknownFingerprint([u8; 4]),
+    /// The origin path has hardened steps past the path of every trusted
+    /// xpub with its fingerprint, which can't be derived from an xpub
+    HardenedOrigin(DescriptorPublicKey),
+    /// Deriving the origin path from the trusted xpub does not yield the
+    /// descriptor's xpub
+    Mismatch(DescriptorPublicKey),
+}
+
+/// An xpub known to be genuine, e.g. exported by a hardware wallet, along
+/// with its own origin. Usually an account-level xpub such as
+/// `[d34db33f/84'/0'/0']xpub...`, whose hardened path can't be derived from
+/// the master xpub.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct TrustedXpub {
+    /// Fingerprint of the master key
+    pub fingerprint: [u8; 4],
+    /// Path from the master key to `xpub`
+    pub path: DerivationPath,
+    /// The extended public key
+    pub xpub: ExtendedPubKey,
+}
+
+impl TrustedXpub {
+    /// Trusts a master xpub, from which origins may only have normal steps
+    pub fn master(xpub: ExtendedPubKey) -> TrustedXpub {
+        let mut fingerprint = [0; 4];
+        fingerprint.copy_from_slice(&xpub.fingerprint()[..]);
+        TrustedXpub {
+            fingerprint: fingerprint,
+            path: DerivationPath::from(Vec::new()),
+            xpub: xpub,
+        }
+    }
+}
+
+impl Display for KeyOriginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyOriginError::MissingOrigin(key) => write!(f, "key {} has no origin", key),
+            KeyOriginError::UnknownFingerprint(fingerprint) => {
+                f.write_str("no master key with fingerprint ")?;
+                for byte in fingerprint {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            KeyOriginError::HardenedOrigin(key) => {
+                write!(f, "no trusted xpub to derive the origin of {} from", key)
+            }
+            KeyOriginError::Mismatch(key) => {
+                write!(f, "key {} does not match its origin", key)
+            }
+        }
+    }
+}
+
+impl DescriptorPublicKey {
+    /// Checks that the key's origin derives from one of `trusted` to the
+    /// key's xpub. The origin must start with the trusted xpub's own path;
+    /// only the remaining steps, which must all be normal, are derived.
+    ///
+    /// Keys without an origin, including plain public keys, can't be
+    /// verified and are rejected with `KeyOriginError::MissingOrigin`.
+    pub fn verify_origin<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        trusted: &[TrustedXpub],
+    ) -> Result<(), KeyOriginError> {
+        let xpub = match self {
+            DescriptorPublicKey::XPub(xpub) => xpub,
+            DescriptorPublicKey::PubKey(_) => {
+                return Err(KeyOriginError::MissingOrigin(self.clone()))
+            }
+        };
+        let (fingerprint, origin_path) = match xpub.origin {
+            Some((ref fingerprint, ref path)) => (fingerprint, path.as_ref()),
+            None => return Err(KeyOriginError::MissingOrigin(self.clone())),
+        };
+
+        let mut candidates = trusted
+            .iter()
+            .filter(|t| t.fingerprint == *fingerprint)
+            .peekable();
+        if candidates.peek().is_none() {
+            return Err(KeyOriginError::UnknownFingerprint(*fingerprint));
+        }
+
+        let mut err = KeyOriginError::HardenedOrigin(self.clone());
+        for t in candidates {
+            let prefix = t.path.as_ref();
+            if !origin_path.starts_with(prefix) {
+                continue;
+            }
+            let rest = &origin_path[prefix.len()..];
+            if !rest.iter().all(|c| c.is_normal()) {
+                continue;
+            }
+            match t.xpub.derive_pub(secp, &rest.to_vec()) {
+                Ok(ref derived)
+                    if derived.public_key == xpub.xpub.public_key
+                        && derived.chain_code == xpub.xpub.chain_code =>
+                {
+                    return Ok(())
+                }
+                _ => err = KeyOriginError::Mismatch(self.clone()),
+            }
+        }
+        Err(err)
+    }
+}
+
 impl Display for DescriptorKeyParseError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(self.0)
@@ -296,40 +930,77 @@ impl Display for DescriptorKeyParseError {
 }
 
 impl<Pk: MiniscriptKey> Descriptor<Pk> {
-    /// Convert a descriptor using abstract keys to one using specific keys
-    pub fn translate_pk<Fpk, Fpkh, Q, E>(
-        &self,
-        mut translatefpk: Fpk,
-        mut translatefpkh: Fpkh,
-    ) -> Result<Descriptor<Q>, E>
+    /// Convert a descriptor using abstract keys to one using specific keys,
+    /// see `Translator`
+    pub fn translate_pk<T, Q, E>(&self, t: &mut T) -> Result<Descriptor<Q>, E>
     where
-        Fpk: FnMut(&Pk) -> Result<Q, E>,
-        Fpkh: FnMut(&Pk::Hash) -> Result<Q::Hash, E>,
+        T: Translator<Pk, Q, E>,
         Q: MiniscriptKey,
     {
         match *self {
-            Descriptor::Bare(ref ms) => Ok(Descriptor::Bare(
-                ms.translate_pk(&mut translatefpk, &mut translatefpkh)?,
-            )),
-            Descriptor::Pk(ref pk) => translatefpk(pk).map(Descriptor::Pk),
-            Descriptor::Pkh(ref pk) => translatefpk(pk).map(Descriptor::Pkh),
-            Descriptor::Wpkh(ref pk) => translatefpk(pk).map(Descriptor::Wpkh),
-            Descriptor::ShWpkh(ref pk) => translatefpk(pk).map(Descriptor::ShWpkh),
-            Descriptor::Sh(ref ms) => Ok(Descriptor::Sh(
-                ms.translate_pk(&mut translatefpk, &mut translatefpkh)?,
-            )),
-            Descriptor::Wsh(ref ms) => Ok(Descriptor::Wsh(
-                ms.translate_pk(&mut translatefpk, &mut translatefpkh)?,
-            )),
-            Descriptor::ShWsh(ref ms) => Ok(Descriptor::ShWsh(
-                ms.translate_pk(&mut translatefpk, &mut translatefpkh)?,
-            )),
+            Descriptor::Bare(ref ms) => Ok(Descriptor::Bare(ms.translate_pk(t)?)),
+            Descriptor::Pk(ref pk) => t.pk(pk).map(Descriptor::Pk),
+            Descriptor::Pkh(ref pk) => t.pk(pk).map(Descriptor::Pkh),
+            Descriptor::Wpkh(ref pk) => t.pk(pk).map(Descriptor::Wpkh),
+            Descriptor::ShWpkh(ref pk) => t.pk(pk).map(Descriptor::ShWpkh),
+            Descriptor::Sh(ref ms) => Ok(Descriptor::Sh(ms.translate_pk(t)?)),
+            Descriptor::Wsh(ref ms) => Ok(Descriptor::Wsh(ms.translate_pk(t)?)),
+            Descriptor::ShWsh(ref ms) => Ok(Descriptor::ShWsh(ms.translate_pk(t)?)),
+            Descriptor::Addr(ref addr) => Ok(Descriptor::Addr(addr.clone())),
+            Descriptor::Raw(ref script) => Ok(Descriptor::Raw(script.clone())),
+        }
+    }
+
+    /// Calls `pred` on every key and key hash in the descriptor, in the
+    /// order they appear in its string form, until it returns `false`.
+    /// Returns whether `pred` returned `true` for all of them. `addr()` and
+    /// `raw()` descriptors have no keys.
+    pub fn for_each_key<'a, F>(&'a self, mut pred: F) -> bool
+    where
+        F: FnMut(ForEach<'a, Pk>) -> bool,
+    {
+        match *self {
+            Descriptor::Bare(ref ms)
+            | Descriptor::Sh(ref ms)
+            | Descriptor::Wsh(ref ms)
+            | Descriptor::ShWsh(ref ms) => ms.for_each_key(pred),
+            Descriptor::Pk(ref pk)
+            | Descriptor::Pkh(ref pk)
+            | Descriptor::Wpkh(ref pk)
+            | Descriptor::ShWpkh(ref pk) => pred(ForEach::Key(pk)),
+            Descriptor::Addr(..) | Descriptor::Raw(..) => true,
+        }
+    }
+
+    /// Iterates over every key and key hash in the descriptor, see
+    /// `for_each_key`
+    pub fn iter_pk<'a>(&'a self) -> vec::IntoIter<ForEach<'a, Pk>> {
+        let mut keys = vec![];
+        self.for_each_key(|key| {
+            keys.push(key);
+            true
+        });
+        keys.into_iter()
+    }
+}
+
+/// Encodes a satisfaction stack as a push-only scriptSig
+fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
+    let mut b = script::Builder::new();
+    for wit in witness {
+        if let Ok(n) = script::read_scriptint(wit) {
+            b = b.push_int(n);
+        } else {
+            b = b.push_slice(wit);
         }
     }
+    b.into_script()
 }
 
 impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
-    /// Computes the Bitcoin address of the descriptor, if one exists
+    /// Computes the Bitcoin address of the descriptor, if one exists. The
+    /// address of an `addr()` descriptor is returned as is, regardless of
+    /// `network`.
     pub fn address(&self, network: bitcoin::Network) -> Option<bitcoin::Address> {
         match *self {
             Descriptor::Bare(..) => None,
@@ -350,6 +1021,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::ShWsh(ref miniscript) => {
                 Some(bitcoin::Address::p2shwsh(&miniscript.encode(), network))
             }
+            Descriptor::Addr(ref addr) => Some(addr.clone()),
+            Descriptor::Raw(ref script) => bitcoin::Address::from_script(script, network),
         }
     }
 
@@ -377,6 +1050,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Sh(ref miniscript) => miniscript.encode().to_p2sh(),
             Descriptor::Wsh(ref miniscript) => miniscript.encode().to_v0_p2wsh(),
             Descriptor::ShWsh(ref miniscript) => miniscript.encode().to_v0_p2wsh().to_p2sh(),
+            Descriptor::Addr(ref addr) => addr.script_pubkey(),
+            Descriptor::Raw(ref script) => script.clone(),
         }
     }
 
@@ -395,6 +1070,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             | Descriptor::Pk(..)
             | Descriptor::Pkh(..)
             | Descriptor::Sh(..) => Script::new(),
+            // unknown, nothing we could put there
+            Descriptor::Addr(..) | Descriptor::Raw(..) => Script::new(),
             // pure segwit, empty scriptSig
             Descriptor::Wsh(..) | Descriptor::Wpkh(..) => Script::new(),
             // segwit+p2sh
@@ -415,15 +1092,20 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
     }
 
     /// Computes the "witness script" of the descriptor, i.e. the underlying
-    /// script before any hashing is done. For `Bare`, `Pkh` and `Wpkh` this
-    /// is the scriptPubkey; for `ShWpkh` and `Sh` this is the redeemScript;
-    /// for the others it is the witness script.
+    /// script before any hashing is done. For `Bare`, `Pkh`, `Wpkh`, `Addr`
+    /// and `Raw` this is the scriptPubkey; for `ShWpkh` and `Sh` this is the
+    /// redeemScript; for the others it is the witness script.
+    ///
+    /// This is not necessarily the script to sign, use `script_code` for
+    /// computing sighashes.
     pub fn witness_script(&self) -> Script {
         match *self {
             Descriptor::Bare(..)
             | Descriptor::Pk(..)
             | Descriptor::Pkh(..)
-            | Descriptor::Wpkh(..) => self.script_pubkey(),
+            | Descriptor::Wpkh(..)
+            | Descriptor::Addr(..)
+            | Descriptor::Raw(..) => self.script_pubkey(),
             Descriptor::ShWpkh(ref pk) => {
                 let addr = bitcoin::Address::p2wpkh(&pk.to_public_key(), bitcoin::Network::Bitcoin);
                 addr.script_pubkey()
@@ -432,146 +1114,202 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
         }
     }
 
+    /// Computes the scriptCode committed to by signatures spending the
+    /// descriptor, to be passed to legacy or BIP143 sighash computation. For
+    /// `Wpkh` and `ShWpkh` this is the P2PKH script of the key, as required
+    /// by BIP143; for `Sh` the redeemScript; for `Wsh` and `ShWsh` the
+    /// witness script; and for the remaining descriptors the scriptPubKey.
+    ///
+    /// Errors for `Addr` and `Raw` descriptors, whose scripts are unknown.
+    pub fn script_code(&self) -> Result<Script, Error> {
+        match *self {
+            Descriptor::Bare(..) | Descriptor::Pk(..) | Descriptor::Pkh(..) => {
+                Ok(self.script_pubkey())
+            }
+            Descriptor::Wpkh(ref pk) | Descriptor::ShWpkh(ref pk) => {
+                let addr = bitcoin::Address::p2pkh(&pk.to_public_key(), bitcoin::Network::Bitcoin);
+                Ok(addr.script_pubkey())
+            }
+            Descriptor::Sh(ref d) | Descriptor::Wsh(ref d) | Descriptor::ShWsh(ref d) => {
+                Ok(d.encode())
+            }
+            Descriptor::Addr(..) | Descriptor::Raw(..) => Err(Error::OpaqueDescriptor),
+        }
+    }
+
     /// Attempts to produce a satisfying witness and scriptSig to spend an
-    /// output controlled by the given descriptor; add the data to a given
-    /// `TxIn` output.
-    pub fn satisfy<S: Satisfier<Pk>>(
+    /// output controlled by the given descriptor, returned in this order.
+    /// Use this rather than `satisfy` when not building a `bitcoin::TxIn`.
+    pub fn get_satisfaction<S: Satisfier<Pk>>(
         &self,
-        txin: &mut bitcoin::TxIn,
         satisfier: S,
-    ) -> Result<(), Error> {
-        fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
-            let mut b = script::Builder::new();
-            for wit in witness {
-                if let Ok(n) = script::read_scriptint(wit) {
-                    b = b.push_int(n);
-                } else {
-                    b = b.push_slice(wit);
-                }
-            }
-            b.into_script()
+    ) -> Result<(Vec<Vec<u8>>, Script), Error> {
+        self.get_satisfaction_helper(satisfier, false)
+    }
+
+    /// Like `get_satisfaction`, but produces the cheapest satisfaction even
+    /// if it is malleable, see `Miniscript::satisfy_malleable`
+    pub fn get_satisfaction_mall<S: Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error> {
+        self.get_satisfaction_helper(satisfier, true)
+    }
+
+    fn get_satisfaction_helper<S: Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+        malleable: bool,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error> {
+        fn sig_vec(sig: BitcoinSig) -> Vec<u8> {
+            let mut sig_vec = sig.0.serialize_der().to_vec();
+            sig_vec.push(sig.1.as_u32() as u8);
+            sig_vec
         }
+        let satisfy_ms = |ms: &Miniscript<Pk>| {
+            let witness = if malleable {
+                ms.satisfy_malleable(&satisfier)
+            } else {
+                ms.satisfy(&satisfier)
+            };
+            witness.ok_or(Error::CouldNotSatisfy)
+        };
 
         match *self {
             Descriptor::Bare(ref d) => {
-                let wit = match d.satisfy(satisfier) {
-                    Some(wit) => wit,
-                    None => return Err(Error::CouldNotSatisfy),
-                };
-                txin.script_sig = witness_to_scriptsig(&wit);
-                txin.witness = vec![];
-                Ok(())
+                let wit = satisfy_ms(d)?;
+                Ok((vec![], witness_to_scriptsig(&wit)))
             }
             Descriptor::Pk(ref pk) => {
                 if let Some(sig) = satisfier.lookup_sig(pk) {
-                    let mut sig_vec = sig.0.serialize_der().to_vec();
-                    sig_vec.push(sig.1.as_u32() as u8);
-                    txin.script_sig = script::Builder::new()
-                        .push_slice(&sig_vec[..])
+                    let script_sig = script::Builder::new()
+                        .push_slice(&sig_vec(sig)[..])
                         .into_script();
-                    txin.witness = vec![];
-                    Ok(())
+                    Ok((vec![], script_sig))
                 } else {
                     Err(Error::MissingSig(pk.to_public_key()))
                 }
             }
             Descriptor::Pkh(ref pk) => {
                 if let Some(sig) = satisfier.lookup_sig(pk) {
-                    let mut sig_vec = sig.0.serialize_der().to_vec();
-                    sig_vec.push(sig.1.as_u32() as u8);
-                    txin.script_sig = script::Builder::new()
-                        .push_slice(&sig_vec[..])
+                    let script_sig = script::Builder::new()
+                        .push_slice(&sig_vec(sig)[..])
                         .push_key(&pk.to_public_key())
                         .into_script();
-                    txin.witness = vec![];
-                    Ok(())
+                    Ok((vec![], script_sig))
                 } else {
                     Err(Error::MissingSig(pk.to_public_key()))
                 }
             }
             Descriptor::Wpkh(ref pk) => {
                 if let Some(sig) = satisfier.lookup_sig(pk) {
-                    let mut sig_vec = sig.0.serialize_der().to_vec();
-                    sig_vec.push(sig.1.as_u32() as u8);
-                    txin.script_sig = Script::new();
-                    txin.witness = vec![sig_vec, pk.to_public_key().to_bytes()];
-                    Ok(())
+                    let witness = vec![sig_vec(sig), pk.to_public_key().to_bytes()];
+                    Ok((witness, Script::new()))
                 } else {
                     Err(Error::MissingSig(pk.to_public_key()))
                 }
             }
             Descriptor::ShWpkh(ref pk) => {
                 if let Some(sig) = satisfier.lookup_sig(pk) {
-                    let mut sig_vec = sig.0.serialize_der().to_vec();
-                    sig_vec.push(sig.1.as_u32() as u8);
                     let addr =
                         bitcoin::Address::p2wpkh(&pk.to_public_key(), bitcoin::Network::Bitcoin);
                     let redeem_script = addr.script_pubkey();
 
-                    txin.script_sig = script::Builder::new()
+                    let script_sig = script::Builder::new()
                         .push_slice(&redeem_script[..])
                         .into_script();
-                    txin.witness = vec![sig_vec, pk.to_public_key().to_bytes()];
-                    Ok(())
+                    let witness = vec![sig_vec(sig), pk.to_public_key().to_bytes()];
+                    Ok((witness, script_sig))
                 } else {
                     Err(Error::MissingSig(pk.to_public_key()))
                 }
             }
             Descriptor::Sh(ref d) => {
-                let mut witness = match d.satisfy(satisfier) {
-                    Some(wit) => wit,
-                    None => return Err(Error::CouldNotSatisfy),
-                };
+                let mut witness = satisfy_ms(d)?;
                 witness.push(d.encode().into_bytes());
-                txin.script_sig = witness_to_scriptsig(&witness);
-                txin.witness = vec![];
-                Ok(())
+                Ok((vec![], witness_to_scriptsig(&witness)))
             }
             Descriptor::Wsh(ref d) => {
-                let mut witness = match d.satisfy(satisfier) {
-                    Some(wit) => wit,
-                    None => return Err(Error::CouldNotSatisfy),
-                };
+                let mut witness = satisfy_ms(d)?;
                 witness.push(d.encode().into_bytes());
-                txin.script_sig = Script::new();
-                txin.witness = witness;
-                Ok(())
+                Ok((witness, Script::new()))
             }
             Descriptor::ShWsh(ref d) => {
                 let witness_script = d.encode();
-                txin.script_sig = script::Builder::new()
+                let script_sig = script::Builder::new()
                     .push_slice(&witness_script.to_v0_p2wsh()[..])
                     .into_script();
 
-                let mut witness = match d.satisfy(satisfier) {
-                    Some(wit) => wit,
-                    None => return Err(Error::CouldNotSatisfy),
-                };
+                let mut witness = satisfy_ms(d)?;
                 witness.push(witness_script.into_bytes());
-                txin.witness = witness;
-                Ok(())
+                Ok((witness, script_sig))
             }
+            Descriptor::Addr(..) | Descriptor::Raw(..) => Err(Error::OpaqueDescriptor),
         }
     }
 
+    /// Attempts to produce a satisfying witness and scriptSig to spend an
+    /// output controlled by the given descriptor; add the data to a given
+    /// `TxIn` output.
+    pub fn satisfy<S: Satisfier<Pk>>(
+        &self,
+        txin: &mut bitcoin::TxIn,
+        satisfier: S,
+    ) -> Result<(), Error> {
+        let (witness, script_sig) = self.get_satisfaction(satisfier)?;
+        txin.witness = witness;
+        txin.script_sig = script_sig;
+        Ok(())
+    }
+
+    /// Like `satisfy`, but produces the cheapest satisfaction even if it is
+    /// malleable, see `Miniscript::satisfy_malleable`
+    pub fn satisfy_malleable<S: Satisfier<Pk>>(
+        &self,
+        txin: &mut bitcoin::TxIn,
+        satisfier: S,
+    ) -> Result<(), Error> {
+        let (witness, script_sig) = self.get_satisfaction_mall(satisfier)?;
+        txin.witness = witness;
+        txin.script_sig = script_sig;
+        Ok(())
+    }
+
     /// Computes an upper bound on the weight of a satisfying witness to the
     /// transaction. Assumes all signatures are 73 bytes, including push opcode
     /// and sighash suffix. Includes the weight of the VarInts encoding the
     /// scriptSig and witness stack length.
-    pub fn max_satisfaction_weight(&self) -> usize {
+    ///
+    /// Errors for `Addr` and `Raw` descriptors, whose satisfactions are
+    /// unknown.
+    pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        self.max_satisfaction_weight_with(SigSizeAssumption::Ecdsa73)
+    }
+
+    /// Like `max_satisfaction_weight`, but assumes all signatures are of the
+    /// size given by `assumption`, e.g. to match a wallet which only ever
+    /// produces low-R signatures.
+    ///
+    /// Errors for `Addr` and `Raw` descriptors, whose satisfactions are
+    /// unknown.
+    pub fn max_satisfaction_weight_with(
+        &self,
+        assumption: SigSizeAssumption,
+    ) -> Result<usize, Error> {
         fn varint_len(n: usize) -> usize {
             bitcoin::VarInt(n as u64).len()
         }
 
-        match *self {
+        let sig_len = assumption.sig_len();
+        let weight = match *self {
             Descriptor::Bare(ref ms) => {
-                let scriptsig_len = ms.max_satisfaction_size(1);
+                let scriptsig_len = ms.max_satisfaction_size_with_sig_len(1, sig_len);
                 4 * (varint_len(scriptsig_len) + scriptsig_len)
             }
-            Descriptor::Pk(..) => 4 * (1 + 73),
-            Descriptor::Pkh(ref pk) => 4 * (1 + 73 + pk.serialized_len()),
-            Descriptor::Wpkh(ref pk) => 4 + 1 + 73 + pk.serialized_len(),
-            Descriptor::ShWpkh(ref pk) => 4 * 24 + 1 + 73 + pk.serialized_len(),
+            Descriptor::Pk(..) => 4 * (1 + sig_len),
+            Descriptor::Pkh(ref pk) => 4 * (1 + sig_len + pk.serialized_len()),
+            Descriptor::Wpkh(ref pk) => 4 + 1 + sig_len + pk.serialized_len(),
+            Descriptor::ShWpkh(ref pk) => 4 * 24 + 1 + sig_len + pk.serialized_len(),
             Descriptor::Sh(ref ms) => {
                 let ss = ms.script_size();
                 let push_size = if ss < 76 {
@@ -584,34 +1322,200 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
                     5
                 };
 
-                let scriptsig_len = push_size + ss + ms.max_satisfaction_size(1);
-                4 * (varint_len(scriptsig_len) + scriptsig_len)
-            }
-            Descriptor::Wsh(ref ms) => {
-                let script_size = ms.script_size();
-                4 +  // scriptSig length byte
-                    varint_len(script_size) +
-                    script_size +
-                    varint_len(ms.max_satisfaction_witness_elements()) +
-                    ms.max_satisfaction_size(2)
+                let scriptsig_len =
+                    push_size + ss + ms.max_satisfaction_size_with_sig_len(1, sig_len);
+                4 * (varint_len(scriptsig_len) + scriptsig_len)
+            }
+            Descriptor::Wsh(ref ms) => {
+                let script_size = ms.script_size();
+                4 +  // scriptSig length byte
+                    varint_len(script_size) +
+                    script_size +
+                    varint_len(ms.max_satisfaction_witness_elements()) +
+                    ms.max_satisfaction_size_with_sig_len(2, sig_len)
+            }
+            Descriptor::ShWsh(ref ms) => {
+                let script_size = ms.script_size();
+                4 * 36
+                    + varint_len(script_size)
+                    + script_size
+                    + varint_len(ms.max_satisfaction_witness_elements())
+                    + ms.max_satisfaction_size_with_sig_len(2, sig_len)
+            }
+            Descriptor::Addr(..) | Descriptor::Raw(..) => return Err(Error::OpaqueDescriptor),
+        };
+        Ok(weight)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, assuming low-R grinded signers. This is tighter than
+    /// `max_satisfaction_weight`: all signatures are assumed to be 72 bytes,
+    /// including push opcode and sighash suffix, see
+    /// `max_satisfaction_weight_with`.
+    ///
+    /// Non-witness bytes (the scriptSig and its length) count four weight
+    /// units each, witness bytes count one. The empty witness of a
+    /// non-segwit input is not counted; add one weight unit for it when the
+    /// transaction has other segwit inputs.
+    ///
+    /// Errors for `Addr` and `Raw` descriptors, whose satisfactions are
+    /// unknown.
+    pub fn max_weight_to_satisfy(&self) -> Result<usize, Error> {
+        self.max_satisfaction_weight_with(SigSizeAssumption::Ecdsa72)
+    }
+}
+
+#[cfg(feature = "compiler")]
+impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
+    /// Adds a recovery path to the descriptor, which lets `recovery` spend
+    /// alone once the absolute timelock `after` has passed. Since a
+    /// Miniscript cannot in general be turned back into the policy it was
+    /// compiled from, `policy` must be given and is checked to have the
+    /// same semantics as `self`.
+    ///
+    /// The extended policy is recompiled and wrapped in the same kind of
+    /// output as `self`; single-key descriptors become the corresponding
+    /// script-hash descriptor (e.g. `wpkh` becomes `wsh`). An error is
+    /// returned if the result would not be standard.
+    pub fn with_recovery(
+        &self,
+        policy: &Concrete<Pk>,
+        recovery: Pk,
+        after: u32,
+    ) -> Result<Descriptor<Pk>, Error> {
+        if self.lift().normalized().sorted() != policy.lift().normalized().sorted() {
+            return Err(Error::PolicyMismatch);
+        }
+        let new_policy = policy
+            .clone()
+            .with_recovery(recovery, after)
+            .map_err(CompilerError::PolicyError)?;
+        let ret = match *self {
+            Descriptor::Bare(..) => Descriptor::Bare(new_policy.compile::<Legacy>()?),
+            Descriptor::Pk(..) | Descriptor::Pkh(..) | Descriptor::Sh(..) => {
+                Descriptor::Sh(new_policy.compile::<Legacy>()?)
+            }
+            Descriptor::Wpkh(..) | Descriptor::Wsh(..) => {
+                Descriptor::Wsh(new_policy.compile::<Segwitv0>()?)
+            }
+            Descriptor::ShWpkh(..) | Descriptor::ShWsh(..) => {
+                Descriptor::ShWsh(new_policy.compile::<Segwitv0>()?)
+            }
+            Descriptor::Addr(..) | Descriptor::Raw(..) => return Err(Error::OpaqueDescriptor),
+        };
+        ret.check_standard()?;
+        Ok(ret)
+    }
+}
+
+impl Descriptor<DescriptorPublicKey> {
+    /// Derives all wildcard keys in the descriptor using the supplied `path`
+    ///
+    /// Panics if the path or any wildcard is hardened, see `try_derive`.
+    pub fn derive(&self, path: &[ChildNumber]) -> Descriptor<DescriptorPublicKey> {
+        self.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| Result::<_, ()>::Ok(pk.derive(path)),
+            |pkh: &hash160::Hash| Ok(*pkh),
+        ))
+        .expect("Translation fn can't fail.")
+    }
+
+    /// Derives all wildcard keys in the descriptor using the supplied `path`,
+    /// failing on the first key which can't be derived
+    pub fn try_derive(
+        &self,
+        path: &[ChildNumber],
+    ) -> Result<Descriptor<DescriptorPublicKey>, DerivationError> {
+        self.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| pk.try_derive(path),
+            |pkh: &hash160::Hash| Ok(*pkh),
+        ))
+    }
+
+    /// Whether the descriptor contains any wildcard keys, i.e. describes a
+    /// range of scripts rather than a single one
+    pub fn is_ranged(&self) -> bool {
+        let mut ranged = false;
+        self.for_each_key(|key| {
+            if let ForEach::Key(pk) = key {
+                ranged |= pk.is_wildcard();
             }
-            Descriptor::ShWsh(ref ms) => {
-                let script_size = ms.script_size();
-                4 * 36
-                    + varint_len(script_size)
-                    + script_size
-                    + varint_len(ms.max_satisfaction_witness_elements())
-                    + ms.max_satisfaction_size(2)
+            true
+        });
+        ranged
+    }
+
+    /// Derives the wildcard keys of a ranged descriptor at the given
+    /// (non-hardened) `index` and returns the resulting descriptor using
+    /// plain public keys, e.g. to compute the `index`th receive address.
+    /// Descriptors without wildcards derive to the same script at every index.
+    pub fn derive_index(&self, index: u32) -> Result<Descriptor<PublicKey>, DerivationError> {
+        self.at_derivation_index(index)?.translate_pk(&mut (
+            |pk: &DefiniteDescriptorKey| Ok(pk.to_public_key()),
+            |pkh: &hash160::Hash| Ok(*pkh),
+        ))
+    }
+
+    /// Fixes the wildcards of a ranged descriptor at the given (non-hardened)
+    /// `index`, like `derive_index`, but keeps xpubs and key origins so that
+    /// they can e.g. be put into a PSBT's BIP32 derivation fields
+    pub fn at_derivation_index(
+        &self,
+        index: u32,
+    ) -> Result<Descriptor<DefiniteDescriptorKey>, DerivationError> {
+        let child = ChildNumber::from_normal_idx(index)
+            .map_err(|_| DerivationError::HardenedChild(ChildNumber::from(index)))?;
+        self.try_derive(&[child])?.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| Ok(DefiniteDescriptorKey(pk.clone())),
+            |pkh: &hash160::Hash| Ok(*pkh),
+        ))
+    }
+
+    /// Searches `range` for the derivation index at which the descriptor
+    /// produces `script_pubkey`, e.g. to match on-chain outputs when
+    /// recovering a wallet. Returns the first matching index together with
+    /// the descriptor fixed at it, or `None` if there is no match.
+    pub fn find_derivation_index_for_spk<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        script_pubkey: &Script,
+        range: Range<u32>,
+    ) -> Result<Option<(u32, Descriptor<DefiniteDescriptorKey>)>, DerivationError> {
+        // Everything up to the wildcards is derived once and then shared by
+        // all indices
+        let mut cache = DescriptorCache::new();
+        for index in range {
+            let derived = self.derive_index_cached(secp, index, &mut cache)?;
+            if derived.script_pubkey() == *script_pubkey {
+                return Ok(Some((index, self.at_derivation_index(index)?)));
             }
         }
+        Ok(None)
     }
-}
 
-impl Descriptor<DescriptorKey> {
-    /// Derives all wildcard keys in the descriptor using the supplied `path`
-    pub fn derive(&self, path: &[ChildNumber]) -> Descriptor<DescriptorKey> {
-        self.translate_pk(|pk| Result::<_, ()>::Ok(pk.derive(path)), |pkh| Ok(*pkh))
-            .expect("Translation fn can't fail.")
+    /// Checks the origin of every key in the descriptor against `trusted`,
+    /// see `DescriptorPublicKey::verify_origin`. Use this to catch descriptors
+    /// which were corrupted or tampered with before deriving receive
+    /// addresses. Keys without an origin make the check fail.
+    pub fn verify_key_origins(&self, trusted: &[TrustedXpub]) -> Result<(), KeyOriginError> {
+        let secp = Secp256k1::verification_only();
+        self.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| pk.verify_origin(&secp, trusted).map(|_| pk.clone()),
+            |pkh: &hash160::Hash| Ok(*pkh),
+        ))
+        .map(|_| ())
+    }
+
+    /// Serializes the descriptor marking hardened derivation steps with `h`
+    /// instead of `'`, as some tools including Bitcoin Core do. Both forms
+    /// parse to the same descriptor.
+    pub fn to_string_with_h_markers(&self) -> String {
+        self.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| Result::<_, ()>::Ok(format!("{:#}", pk)),
+            |pkh: &hash160::Hash| Ok(pkh.to_string()),
+        ))
+        .expect("Translation fn can't fail.")
+        .to_string()
     }
 }
 
@@ -631,25 +1535,36 @@ where
                 expression::terminal(&top.args[0], |pk| Pk::from_str(pk).map(Descriptor::Pkh))
             }
             ("wpkh", 1) => {
-                expression::terminal(&top.args[0], |pk| Pk::from_str(pk).map(Descriptor::Wpkh))
+                let pk = expression::terminal(&top.args[0], |pk| Pk::from_str(pk))?;
+                Descriptor::new_wpkh(pk)
             }
             ("sh", 1) => {
                 let newtop = &top.args[0];
                 match (newtop.name, newtop.args.len()) {
                     ("wsh", 1) => {
                         let sub = Miniscript::from_tree(&newtop.args[0])?;
-                        Ok(Descriptor::ShWsh(sub))
+                        Descriptor::new_sh_wsh(sub)
+                    }
+                    ("wpkh", 1) => {
+                        let pk = expression::terminal(&newtop.args[0], |pk| Pk::from_str(pk))?;
+                        Descriptor::new_sh_wpkh(pk)
                     }
-                    ("wpkh", 1) => expression::terminal(&newtop.args[0], |pk| {
-                        Pk::from_str(pk).map(Descriptor::ShWpkh)
-                    }),
                     _ => {
                         let sub = Miniscript::from_tree(&top.args[0])?;
                         Ok(Descriptor::Sh(sub))
                     }
                 }
             }
-            ("wsh", 1) => expression::unary(top, Descriptor::Wsh),
+            ("wsh", 1) => {
+                let sub = Miniscript::from_tree(&top.args[0])?;
+                Descriptor::new_wsh(sub)
+            }
+            ("addr", 1) => expression::terminal(&top.args[0], |addr| {
+                bitcoin::Address::from_str(addr).map(Descriptor::Addr)
+            }),
+            ("raw", 1) => expression::terminal(&top.args[0], |hex| {
+                Vec::<u8>::from_hex(hex).map(|bytes| Descriptor::Raw(Script::from(bytes)))
+            }),
             _ => {
                 let sub = expression::FromTree::from_tree(&top)?;
                 Ok(Descriptor::Bare(sub))
@@ -667,14 +1582,75 @@ where
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Descriptor<Pk>, Error> {
+        Descriptor::from_str_with_limits(s, &ParseLimits::default())
+    }
+}
+
+impl<Pk> Descriptor<Pk>
+where
+    Pk: MiniscriptKey,
+    <Pk as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+{
+    /// Parse a descriptor from its string form, rejecting descriptors whose
+    /// Miniscript exceeds the given `limits` or the resource limits of its
+    /// context, see `check_consensus_limits` and `check_resource_limits`.
+    /// A trailing checksum, as produced by `to_string_with_checksum`, is
+    /// verified if present.
+    pub fn from_str_with_limits(s: &str, limits: &ParseLimits) -> Result<Descriptor<Pk>, Error> {
+        let desc = Descriptor::parse_unchecked(s, limits)?;
+        desc.check_consensus_limits()?;
+        desc.check_resource_limits()?;
+        Ok(desc)
+    }
+
+    /// Parse a descriptor from its string form without enforcing resource
+    /// limits on its Miniscript, see `Miniscript::from_str_insane`
+    pub fn from_str_insane(s: &str) -> Result<Descriptor<Pk>, Error> {
+        Descriptor::parse_unchecked(s, &ParseLimits::insane())
+    }
+
+    /// Parses a descriptor, checking only its checksum and `limits`
+    fn parse_unchecked(s: &str, limits: &ParseLimits) -> Result<Descriptor<Pk>, Error> {
+        let s = checksum::verify_checksum(s)?;
         for ch in s.as_bytes() {
             if *ch < 20 || *ch > 127 {
                 return Err(Error::Unprintable(*ch));
             }
         }
 
-        let top = expression::Tree::from_str(s)?;
-        expression::FromTree::from_tree(&top)
+        // Allow for the `sh(wsh(...))` wrapping around the Miniscript
+        let max_depth = limits.max_depth.saturating_add(2);
+        let top = expression::Tree::from_str_with_max_depth(s, max_depth)?;
+        let desc = expression::FromTree::from_tree(&top)?;
+        match desc {
+            Descriptor::Bare(ref ms)
+            | Descriptor::Sh(ref ms)
+            | Descriptor::Wsh(ref ms)
+            | Descriptor::ShWsh(ref ms) => ms.check_limits(limits)?,
+            Descriptor::Pk(..)
+            | Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::ShWpkh(..)
+            | Descriptor::Addr(..)
+            | Descriptor::Raw(..) => {}
+        }
+        Ok(desc)
+    }
+
+    /// Parses a descriptor and serializes it back in canonical form, to
+    /// compare descriptors written by different tools. In canonical form
+    /// keys, hashes and fingerprints are lowercase hex, hardened steps are
+    /// marked by `'`, fragments use their shortest alias (e.g. `v:pk(K)`
+    /// for `vc:pk_k(K)`) and the descriptor is followed by its checksum.
+    pub fn canonicalize(s: &str) -> Result<String, Error> {
+        Descriptor::<Pk>::from_str(s).map(|desc| desc.to_string_with_checksum())
+    }
+
+    /// Whether two descriptor strings canonicalize to the same descriptor,
+    /// see `canonicalize`. Errors if either fails to parse.
+    pub fn canonical_eq(a: &str, b: &str) -> Result<bool, Error> {
+        Ok(Descriptor::<Pk>::canonicalize(a)? == Descriptor::<Pk>::canonicalize(b)?)
     }
 }
 
@@ -689,10 +1665,25 @@ impl<Pk: MiniscriptKey> fmt::Debug for Descriptor<Pk> {
             Descriptor::Sh(ref sub) => write!(f, "sh({:?})", sub),
             Descriptor::Wsh(ref sub) => write!(f, "wsh({:?})", sub),
             Descriptor::ShWsh(ref sub) => write!(f, "sh(wsh({:?}))", sub),
+            Descriptor::Addr(ref addr) => write!(f, "addr({})", addr),
+            Descriptor::Raw(ref script) => write!(f, "raw({})", script[..].to_hex()),
         }
     }
 }
 
+impl<Pk: MiniscriptKey> Descriptor<Pk> {
+    /// Serializes the descriptor followed by `#` and its checksum, the form
+    /// expected by Bitcoin Core's `importdescriptors`
+    ///
+    /// Panics if a key is displayed using characters other than printable
+    /// ASCII, which can't be checksummed.
+    pub fn to_string_with_checksum(&self) -> String {
+        let desc = self.to_string();
+        let checksum = desc_checksum(&desc).expect("descriptor with unprintable characters");
+        format!("{}#{}", desc, checksum)
+    }
+}
+
 impl<Pk: MiniscriptKey> fmt::Display for Descriptor<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -704,6 +1695,8 @@ impl<Pk: MiniscriptKey> fmt::Display for Descriptor<Pk> {
             Descriptor::Sh(ref sub) => write!(f, "sh({})", sub),
             Descriptor::Wsh(ref sub) => write!(f, "wsh({})", sub),
             Descriptor::ShWsh(ref sub) => write!(f, "sh(wsh({}))", sub),
+            Descriptor::Addr(ref addr) => write!(f, "addr({})", addr),
+            Descriptor::Raw(ref script) => write!(f, "raw({})", script[..].to_hex()),
         }
     }
 }
@@ -767,15 +1760,21 @@ mod tests {
     use bitcoin::blockdata::opcodes::all::{OP_CLTV, OP_CSV};
     use bitcoin::blockdata::script::Instruction;
     use bitcoin::blockdata::{opcodes, script};
-    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::hashes::hex::{FromHex, ToHex};
     use bitcoin::hashes::{hash160, sha256};
-    use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
+    use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
     use bitcoin::{self, secp256k1, PublicKey};
-    use descriptor::{DescriptorKey, DescriptorXPub};
+    use descriptor::{
+        DefiniteDescriptorKey, DerivationError, DescriptorPublicKey, DescriptorType,
+        DescriptorXPub, KeyOriginError, SigSizeAssumption, TrustedXpub,
+    };
     use miniscript::satisfy::BitcoinSig;
     use std::str::FromStr;
+    use AnalysisError;
     use Descriptor;
+    use ForEach;
     use Miniscript;
+    use MiniscriptKey;
     use Satisfier;
 
     type StdDescriptor = Descriptor<PublicKey>;
@@ -1111,6 +2110,256 @@ mod tests {
                 .push_slice(&ms.encode().to_v0_p2wsh()[..])
                 .into_script()
         );
+
+        // The same data without going through a `TxIn`
+        assert_eq!(
+            shwsh.get_satisfaction(&satisfier).expect("satisfaction"),
+            (txin.witness.clone(), txin.script_sig.clone())
+        );
+        assert_eq!(
+            pkh.get_satisfaction(&satisfier).expect("satisfaction"),
+            (
+                vec![],
+                script::Builder::new()
+                    .push_slice(&sigser[..])
+                    .push_key(&pk)
+                    .into_script()
+            )
+        );
+    }
+
+    #[test]
+    fn script_code() {
+        let pk = PublicKey::from_str(
+            "020000000000000000000000000000000000000000000000000000000000000002",
+        )
+        .unwrap();
+        let p2pkh = StdDescriptor::from_str(&format!("pkh({})", pk)).unwrap();
+        assert_eq!(p2pkh.script_code().unwrap(), p2pkh.script_pubkey());
+
+        // BIP143 signs the P2PKH script for both native and wrapped P2WPKH
+        let wpkh = StdDescriptor::from_str(&format!("wpkh({})", pk)).unwrap();
+        assert_eq!(wpkh.script_code().unwrap(), p2pkh.script_pubkey());
+        assert!(wpkh.script_code().unwrap() != wpkh.witness_script());
+        let shwpkh = StdDescriptor::from_str(&format!("sh(wpkh({}))", pk)).unwrap();
+        assert_eq!(shwpkh.script_code().unwrap(), p2pkh.script_pubkey());
+
+        let shwsh = StdDescriptor::from_str(&format!("sh(wsh(pk({})))", pk)).unwrap();
+        assert_eq!(shwsh.script_code().unwrap(), shwsh.witness_script());
+
+        match StdDescriptor::from_str("raw(deadbeef)")
+            .unwrap()
+            .script_code()
+        {
+            Err(::Error::OpaqueDescriptor) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn max_weight_to_satisfy() {
+        let pk1 = "020000000000000000000000000000000000000000000000000000000000000002";
+        let pk2 = "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8";
+
+        // Single-key spends: one byte less per signature
+        let wpkh = StdDescriptor::from_str(&format!("wpkh({})", pk1)).unwrap();
+        assert_eq!(wpkh.max_weight_to_satisfy().unwrap(), 4 + 1 + 72 + 33);
+        let pkh = StdDescriptor::from_str(&format!("pkh({})", pk1)).unwrap();
+        assert_eq!(pkh.max_weight_to_satisfy().unwrap(), 4 * (1 + 72 + 33));
+
+        // 4 + 1 (item count) + 1 + 2 * 72 (dummy, sigs) + 1 + 71 (script)
+        let wsh = StdDescriptor::from_str(&format!("wsh(multi(2,{},{}))", pk1, pk2)).unwrap();
+        assert_eq!(wsh.max_weight_to_satisfy().unwrap(), 222);
+        assert_eq!(wsh.max_satisfaction_weight().unwrap(), 224);
+
+        // 4 * (1 (scriptSig length) + 1 + 2 * 72 + 1 + 71)
+        let sh = StdDescriptor::from_str(&format!("sh(multi(2,{},{}))", pk1, pk2)).unwrap();
+        assert_eq!(sh.max_weight_to_satisfy().unwrap(), 872);
+        assert_eq!(sh.max_satisfaction_weight().unwrap(), 880);
+    }
+
+    #[test]
+    fn max_satisfaction_weight_with() {
+        let pk1 = "020000000000000000000000000000000000000000000000000000000000000002";
+        let pk2 = "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8";
+        let weight = |desc: &StdDescriptor, assumption: SigSizeAssumption| {
+            desc.max_satisfaction_weight_with(assumption).unwrap()
+        };
+
+        let wpkh = StdDescriptor::from_str(&format!("wpkh({})", pk1)).unwrap();
+        assert_eq!(
+            weight(&wpkh, SigSizeAssumption::Ecdsa73),
+            wpkh.max_satisfaction_weight().unwrap()
+        );
+        assert_eq!(weight(&wpkh, SigSizeAssumption::Schnorr64), 4 + 1 + 65 + 33);
+
+        // One byte less per signature each step down
+        let wsh = StdDescriptor::from_str(&format!("wsh(multi(2,{},{}))", pk1, pk2)).unwrap();
+        assert_eq!(weight(&wsh, SigSizeAssumption::Ecdsa73), 224);
+        assert_eq!(weight(&wsh, SigSizeAssumption::Ecdsa72), 222);
+        assert_eq!(weight(&wsh, SigSizeAssumption::Ecdsa71), 220);
+        assert_eq!(weight(&wsh, SigSizeAssumption::Schnorr65), 210);
+        assert_eq!(weight(&wsh, SigSizeAssumption::Schnorr64), 208);
+
+        let sh = StdDescriptor::from_str(&format!("sh(multi(2,{},{}))", pk1, pk2)).unwrap();
+        assert_eq!(
+            weight(&sh, SigSizeAssumption::Ecdsa72),
+            sh.max_weight_to_satisfy().unwrap()
+        );
+
+        let raw = StdDescriptor::from_str("raw(deadbeef)").unwrap();
+        match raw.max_satisfaction_weight_with(SigSizeAssumption::Ecdsa72) {
+            Err(::Error::OpaqueDescriptor) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn resource_limits() {
+        let pk = "020000000000000000000000000000000000000000000000000000000000000002";
+        // 101 signatures exceed the P2WSH standardness limit on witness elements
+        let mut ms = format!("pk({})", pk);
+        for _ in 0..100 {
+            ms = format!("and_v(v:pk({}),{})", pk, ms);
+        }
+
+        match StdDescriptor::from_str(&format!("wsh({})", ms)) {
+            Err(::Error::MaxWitnessItemsExceeded(101)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        match StdDescriptor::from_str(&format!("sh(wsh({}))", ms)) {
+            Err(::Error::MaxWitnessItemsExceeded(101)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        let wsh = StdDescriptor::from_str_insane(&format!("wsh({})", ms)).unwrap();
+        assert!(!wsh.within_resource_limits());
+        assert!(wsh.within_consensus_limits());
+
+        // There is no such limit on legacy scripts
+        let sh = StdDescriptor::from_str_insane(&format!("sh({})", ms)).unwrap();
+        assert!(sh.within_resource_limits());
+        // Though as a P2SH redeem script it is too large to be pushed
+        match sh.check_consensus_limits() {
+            Err(::Error::MaxScriptSizeExceeded(3535)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        match StdDescriptor::from_str(&format!("sh({})", ms)) {
+            Err(::Error::MaxScriptSizeExceeded(3535)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn send_sync() {
+        // Subexpressions are behind `Arc`s, so trees can be shared between
+        // threads and cloned cheaply
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Miniscript<PublicKey>>();
+        assert_send_sync::<Descriptor<PublicKey>>();
+        assert_send_sync::<Descriptor<DescriptorPublicKey>>();
+    }
+
+    #[test]
+    fn sanity_check() {
+        let pk = &TEST_PK[3..TEST_PK.len() - 1];
+        let desc = StdDescriptor::from_str(&format!("wsh(or_d(pk({}),older(144)))", pk)).unwrap();
+        assert_eq!(desc.sanity_check(), Err(AnalysisError::SiglessBranch));
+        let desc = StdDescriptor::from_str(&format!("wpkh({})", pk)).unwrap();
+        assert_eq!(desc.sanity_check(), Ok(()));
+    }
+
+    #[test]
+    fn hash_map_keys() {
+        use std::collections::HashMap;
+
+        let desc = StdDescriptor::from_str(&format!("wsh({})", TEST_PK)).unwrap();
+        let mut descs = HashMap::new();
+        descs.insert(desc.clone(), "wsh");
+        descs.insert(
+            StdDescriptor::from_str(&format!("sh({})", TEST_PK)).unwrap(),
+            "sh",
+        );
+        let reparsed = StdDescriptor::from_str(&desc.to_string()).unwrap();
+        assert_eq!(descs.get(&reparsed), Some(&"wsh"));
+
+        // Like equality, hashes only depend on the script and not on the
+        // type information stored alongside it
+        let ms = Miniscript::<PublicKey>::from_str(TEST_PK).unwrap();
+        let mut stale = ms.clone();
+        stale.ext.pk_cost += 1;
+        let mut scripts = HashMap::new();
+        scripts.insert(ms, 1);
+        assert_eq!(scripts.get(&stale), Some(&1));
+    }
+
+    #[test]
+    fn uncompressed_segwit_keys() {
+        let comp = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let uncomp = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let comp_pk = bitcoin::PublicKey::from_str(comp).unwrap();
+        let uncomp_pk = bitcoin::PublicKey::from_str(uncomp).unwrap();
+        assert!(!comp_pk.is_uncompressed());
+        assert!(uncomp_pk.is_uncompressed());
+        assert!(DescriptorPublicKey::from_str(uncomp)
+            .unwrap()
+            .is_uncompressed());
+
+        for desc in &[
+            format!("wpkh({})", uncomp),
+            format!("sh(wpkh({}))", uncomp),
+            format!("wsh(c:pk_k({}))", uncomp),
+            format!("sh(wsh(or_d(pk({}),pk({}))))", comp, uncomp),
+        ] {
+            match StdDescriptor::from_str(desc) {
+                Err(::Error::UncompressedSegwitKey(ref pk)) if pk == uncomp => {}
+                x => panic!("unexpected result {:?}", x),
+            }
+        }
+        assert!(StdDescriptor::new_wpkh(uncomp_pk).is_err());
+        assert!(StdDescriptor::new_sh_wpkh(uncomp_pk).is_err());
+        assert_eq!(
+            StdDescriptor::new_wpkh(comp_pk).unwrap(),
+            StdDescriptor::Wpkh(comp_pk)
+        );
+
+        // Legacy descriptors may use them
+        StdDescriptor::from_str(&format!("pkh({})", uncomp)).unwrap();
+        StdDescriptor::from_str(&format!("sh(c:pk_k({}))", uncomp)).unwrap();
+    }
+
+    #[test]
+    fn canonicalize() {
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let key = "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8";
+        let canonical = format!(
+            "wsh(and_v(v:pk([d34db33f/44'/0']{}/1/*),pk({})))",
+            xpub, key
+        );
+        let canonical_str = Descriptor::<DescriptorPublicKey>::canonicalize(&canonical).unwrap();
+        assert_eq!(
+            canonical_str,
+            Descriptor::<DescriptorPublicKey>::from_str(&canonical)
+                .unwrap()
+                .to_string_with_checksum()
+        );
+
+        let other = format!(
+            "wsh(and_v(vc:pk_k([D34DB33F/44h/0h]{}/1/*),c:pk_k({})))",
+            xpub,
+            key.to_uppercase()
+        );
+        assert_eq!(
+            Descriptor::<DescriptorPublicKey>::canonicalize(&other).unwrap(),
+            canonical_str
+        );
+        assert!(Descriptor::<DescriptorPublicKey>::canonical_eq(&other, &canonical_str).unwrap());
+
+        let different = format!(
+            "wsh(and_v(v:pk([d34db33f/44'/0']{}/1/*),pk({})))",
+            xpub, xpub
+        );
+        assert!(!Descriptor::<DescriptorPublicKey>::canonical_eq(&different, &canonical).unwrap());
+        assert!(Descriptor::<DescriptorPublicKey>::canonical_eq("wsh(", &canonical).is_err());
     }
 
     #[test]
@@ -1138,8 +2387,8 @@ mod tests {
     #[test]
     fn parse_descriptor_key() {
         let key = "[d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*";
-        let expected = DescriptorKey::XPub(DescriptorXPub {
-            source: Some((
+        let expected = DescriptorPublicKey::XPub(DescriptorXPub {
+            origin: Some((
                 [0xd3, 0x4d, 0xb3, 0x3f],
                 (&[
                     ChildNumber::from_hardened_idx(44).unwrap(),
@@ -1151,32 +2400,35 @@ mod tests {
             xpub: ExtendedPubKey::from_str("xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL").unwrap(),
             derivation_path: (&[ChildNumber::from_normal_idx(1).unwrap()][..]).into(),
             is_wildcard: true,
+            hardened_wildcard: false,
         });
         assert_eq!(expected, key.parse().unwrap());
         assert_eq!(format!("{}", expected), key);
 
         let key = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1";
-        let expected = DescriptorKey::XPub(DescriptorXPub {
-            source: None,
+        let expected = DescriptorPublicKey::XPub(DescriptorXPub {
+            origin: None,
             xpub: ExtendedPubKey::from_str("xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL").unwrap(),
             derivation_path: (&[ChildNumber::from_normal_idx(1).unwrap()][..]).into(),
             is_wildcard: false,
+            hardened_wildcard: false,
         });
         assert_eq!(expected, key.parse().unwrap());
         assert_eq!(format!("{}", expected), key);
 
         let key = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
-        let expected = DescriptorKey::XPub(DescriptorXPub {
-            source: None,
+        let expected = DescriptorPublicKey::XPub(DescriptorXPub {
+            origin: None,
             xpub: ExtendedPubKey::from_str("xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL").unwrap(),
             derivation_path: DerivationPath::from(&[][..]),
             is_wildcard: false,
+            hardened_wildcard: false,
         });
         assert_eq!(expected, key.parse().unwrap());
         assert_eq!(format!("{}", expected), key);
 
         let key = "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8";
-        let expected = DescriptorKey::PukKey(
+        let expected = DescriptorPublicKey::PubKey(
             bitcoin::PublicKey::from_str(
                 "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8",
             )
@@ -1189,23 +2441,429 @@ mod tests {
     #[test]
     #[cfg(feature = "compiler")]
     fn parse_and_derive() {
+        use Legacy;
+
         let descriptor_str = "thresh(2,\
 pk([d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*),\
 pk(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1),\
 pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
-        let policy: crate::policy::concrete::Policy<DescriptorKey> =
+        let policy: crate::policy::concrete::Policy<DescriptorPublicKey> =
             descriptor_str.parse().unwrap();
-        let descriptor = Descriptor::Sh(policy.compile().unwrap());
+        let descriptor = Descriptor::Sh(policy.compile::<Legacy>().unwrap());
         let derived_descriptor = descriptor.derive(&[ChildNumber::from_normal_idx(42).unwrap()]);
 
         let res_descriptor_str = "thresh(2,\
 pk([d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/42),\
 pk(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1),\
 pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
-        let res_policy: crate::policy::concrete::Policy<DescriptorKey> =
+        let res_policy: crate::policy::concrete::Policy<DescriptorPublicKey> =
             res_descriptor_str.parse().unwrap();
-        let res_descriptor = Descriptor::Sh(res_policy.compile().unwrap());
+        let res_descriptor = Descriptor::Sh(res_policy.compile::<Legacy>().unwrap());
 
         assert_eq!(res_descriptor, derived_descriptor);
     }
+
+    #[test]
+    #[cfg(feature = "compiler")]
+    fn with_recovery() {
+        use policy::{Concrete, Liftable, Semantic};
+        use MiniscriptKey;
+        use Segwitv0;
+
+        let secp = secp256k1::Secp256k1::new();
+        let keys: Vec<PublicKey> = (1..4)
+            .map(|i| {
+                let mut sk = [0; 32];
+                sk[31] = i;
+                PublicKey {
+                    key: secp256k1::PublicKey::from_secret_key(
+                        &secp,
+                        &secp256k1::SecretKey::from_slice(&sk[..]).unwrap(),
+                    ),
+                    compressed: true,
+                }
+            })
+            .collect();
+
+        let policy = Concrete::Threshold(2, vec![Concrete::Key(keys[0]), Concrete::Key(keys[1])]);
+        let desc = Descriptor::Wsh(policy.compile::<Segwitv0>().unwrap());
+        let recovered = desc.with_recovery(&policy, keys[2], 630000).unwrap();
+        match recovered {
+            Descriptor::Wsh(..) => {}
+            _ => panic!("expected wsh descriptor"),
+        }
+        assert_eq!(
+            recovered.lift().normalized().sorted(),
+            Semantic::Or(vec![
+                desc.lift(),
+                Semantic::And(vec![
+                    Semantic::KeyHash(keys[2].to_pubkeyhash()),
+                    Semantic::After(630000),
+                ]),
+            ])
+            .normalized()
+            .sorted()
+        );
+
+        let wpkh = Descriptor::Wpkh(keys[0]);
+        let recovered = wpkh
+            .with_recovery(&Concrete::Key(keys[0]), keys[2], 630000)
+            .unwrap();
+        match recovered {
+            Descriptor::Wsh(..) => {}
+            _ => panic!("expected wsh descriptor"),
+        }
+
+        assert!(wpkh
+            .with_recovery(&Concrete::Key(keys[1]), keys[2], 630000)
+            .is_err());
+    }
+
+    #[test]
+    fn verify_key_origins() {
+        let secp = secp256k1::Secp256k1::new();
+        let xprv = ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &[0xcd; 32]).unwrap();
+        let master = ExtendedPubKey::from_private(&secp, &xprv);
+        let other = ExtendedPubKey::from_private(
+            &secp,
+            &ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &[0xce; 32]).unwrap(),
+        );
+        let child = master
+            .derive_pub(&secp, &DerivationPath::from_str("m/0/1").unwrap())
+            .unwrap();
+        let fingerprint = master.fingerprint()[..].to_hex();
+        let trusted_master = TrustedXpub::master(master);
+        let trusted_other = TrustedXpub::master(other);
+
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(1,[{}/0/1]{}/*,[{}/0/1]{}/*))",
+            fingerprint, child, fingerprint, child
+        ))
+        .unwrap();
+        let trusted = [trusted_other.clone(), trusted_master.clone()];
+        assert_eq!(desc.verify_key_origins(&trusted), Ok(()));
+        assert_eq!(
+            desc.verify_key_origins(&[trusted_other.clone()]),
+            Err(KeyOriginError::UnknownFingerprint(
+                FromHex::from_hex(&fingerprint).unwrap()
+            ))
+        );
+
+        // Origin path edited
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wpkh([{}/0/2]{}/*)",
+            fingerprint, child
+        ))
+        .unwrap();
+        match desc.verify_key_origins(&[trusted_master.clone()]) {
+            Err(KeyOriginError::Mismatch(_)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // Origin removed, or key replaced by one without origin
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(1,[{}/0/1]{}/*,{}/*))",
+            fingerprint, child, child
+        ))
+        .unwrap();
+        match desc.verify_key_origins(&[trusted_master.clone()]) {
+            Err(KeyOriginError::MissingOrigin(_)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        let desc =
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({})", child.public_key))
+                .unwrap();
+        match desc.verify_key_origins(&[trusted_master.clone()]) {
+            Err(KeyOriginError::MissingOrigin(_)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // Hardened origins are verified against the account xpub, deriving
+        // only the normal steps after its path
+        let account_path = DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        let account =
+            ExtendedPubKey::from_private(&secp, &xprv.derive_priv(&secp, &account_path).unwrap());
+        let trusted_account = TrustedXpub {
+            fingerprint: trusted_master.fingerprint,
+            path: account_path,
+            xpub: account,
+        };
+        let receive = account
+            .derive_pub(&secp, &DerivationPath::from_str("m/0").unwrap())
+            .unwrap();
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(1,[{}/84'/0'/0']{}/0/*,[{}/84'/0'/0'/0]{}/*))",
+            fingerprint, account, fingerprint, receive
+        ))
+        .unwrap();
+        let trusted = [trusted_master.clone(), trusted_account.clone()];
+        assert_eq!(desc.verify_key_origins(&trusted), Ok(()));
+        // ... which the master xpub alone can't do
+        match desc.verify_key_origins(&[trusted_master]) {
+            Err(KeyOriginError::HardenedOrigin(_)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // Account xpub swapped for another one
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wpkh([{}/84'/0'/0']{}/0/*)",
+            fingerprint, other
+        ))
+        .unwrap();
+        match desc.verify_key_origins(&[trusted_account]) {
+            Err(KeyOriginError::Mismatch(_)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn hardened_wildcard() {
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let key = DescriptorPublicKey::from_str(&format!("{}/1/*h", xpub)).unwrap();
+        assert_eq!(key.to_string(), format!("{}/1/*'", xpub));
+        assert_eq!(
+            DescriptorPublicKey::from_str(&key.to_string()).unwrap(),
+            key
+        );
+
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({})", key)).unwrap();
+        assert_eq!(
+            desc.try_derive(&[ChildNumber::from_normal_idx(3).unwrap()]),
+            Err(DerivationError::HardenedWildcard(key))
+        );
+
+        let desc =
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({}/1/*)", xpub)).unwrap();
+        let hardened = ChildNumber::from_hardened_idx(3).unwrap();
+        assert_eq!(
+            desc.try_derive(&[hardened]),
+            Err(DerivationError::HardenedChild(hardened))
+        );
+        assert_eq!(
+            desc.try_derive(&[ChildNumber::from_normal_idx(3).unwrap()]),
+            Ok(desc.derive(&[ChildNumber::from_normal_idx(3).unwrap()]))
+        );
+    }
+
+    #[test]
+    fn iter_keys() {
+        let keys: Vec<PublicKey> = (1..4)
+            .map(|i| PublicKey {
+                compressed: true,
+                key: secp256k1::PublicKey::from_secret_key(
+                    &secp256k1::Secp256k1::signing_only(),
+                    &secp256k1::SecretKey::from_slice(&[i; 32]).unwrap(),
+                ),
+            })
+            .collect();
+        let pkh = keys[2].to_pubkeyhash();
+        let desc = StdDescriptor::from_str(&format!(
+            "wsh(or_d(multi(1,{},{}),and_v(vc:pk_h({}),older(10))))",
+            keys[0], keys[1], pkh
+        ))
+        .unwrap();
+
+        assert_eq!(
+            desc.iter_pk().collect::<Vec<_>>(),
+            vec![
+                ForEach::Key(&keys[0]),
+                ForEach::Key(&keys[1]),
+                ForEach::Hash(&pkh)
+            ]
+        );
+
+        let mut seen = 0;
+        assert!(!desc.for_each_key(|key| {
+            seen += 1;
+            key != ForEach::Key(&keys[1])
+        }));
+        assert_eq!(seen, 2);
+
+        let desc = StdDescriptor::from_str(&format!("sh(wpkh({}))", keys[2])).unwrap();
+        assert_eq!(
+            desc.iter_pk().collect::<Vec<_>>(),
+            vec![ForEach::Key(&keys[2])]
+        );
+        assert_eq!(
+            StdDescriptor::from_str("raw(deadbeef)")
+                .unwrap()
+                .iter_pk()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn desc_type() {
+        let pk = "020000000000000000000000000000000000000000000000000000000000000002";
+        let desc = |s: String| StdDescriptor::from_str(&s).unwrap().desc_type();
+        assert_eq!(desc(format!("pkh({})", pk)), DescriptorType::Pkh);
+        assert_eq!(desc(format!("sh(wpkh({}))", pk)), DescriptorType::ShWpkh);
+        assert_eq!(desc(format!("wsh(pk({}))", pk)), DescriptorType::Wsh);
+        assert_eq!(desc("raw(deadbeef)".to_owned()), DescriptorType::Raw);
+
+        assert!(DescriptorType::ShWpkh.is_witness());
+        assert!(DescriptorType::ShWpkh.is_sh());
+        assert_eq!(DescriptorType::Wsh.segwit_version(), Some(0));
+        assert!(!DescriptorType::Pkh.is_witness());
+        assert_eq!(DescriptorType::Raw.segwit_version(), None);
+    }
+
+    #[test]
+    fn addr_and_raw() {
+        let addr = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        let desc = StdDescriptor::from_str(&format!("addr({})", addr)).unwrap();
+        assert_eq!(desc.to_string(), format!("addr({})", addr));
+        let spk = bitcoin::Address::from_str(addr).unwrap().script_pubkey();
+        assert_eq!(desc.script_pubkey(), spk);
+        assert_eq!(
+            desc.address(bitcoin::Network::Bitcoin).unwrap().to_string(),
+            addr
+        );
+
+        let raw = StdDescriptor::from_str(&format!("raw({})", spk.to_hex())).unwrap();
+        assert_eq!(raw.to_string(), format!("raw({})", spk.to_hex()));
+        assert_eq!(raw.script_pubkey(), spk);
+        assert_eq!(
+            raw.address(bitcoin::Network::Bitcoin),
+            desc.address(bitcoin::Network::Bitcoin)
+        );
+        assert!(desc < raw);
+
+        StdDescriptor::from_str("addr(notanaddress)").unwrap_err();
+        StdDescriptor::from_str("raw(0g)").unwrap_err();
+
+        let mut txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        match raw.satisfy(&mut txin, ()) {
+            Err(::Error::OpaqueDescriptor) => {}
+            _ => panic!("raw() descriptor must not be satisfiable"),
+        }
+    }
+
+    #[test]
+    fn derive_index() {
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let desc =
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({}/0/*)", xpub)).unwrap();
+        assert!(desc.is_ranged());
+
+        let derived = desc.derive_index(7).unwrap();
+        let expected =
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({}/0/7)", xpub)).unwrap();
+        assert!(!expected.is_ranged());
+        assert_eq!(derived.script_pubkey(), expected.script_pubkey());
+        assert_eq!(expected.derive_index(8).unwrap(), derived);
+        assert!(desc.derive_index(8).unwrap() != derived);
+
+        assert_eq!(
+            desc.derive_index(1 << 31),
+            Err(DerivationError::HardenedChild(ChildNumber::from(1 << 31)))
+        );
+    }
+
+    #[test]
+    fn at_derivation_index() {
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wpkh([d34db33f/84'/0'/0']{}/0/*)",
+            xpub
+        ))
+        .unwrap();
+
+        let definite = desc.at_derivation_index(7).unwrap();
+        assert_eq!(
+            definite.to_string(),
+            format!("wpkh([d34db33f/84'/0'/0']{}/0/7)", xpub)
+        );
+        assert_eq!(
+            definite.script_pubkey(),
+            desc.derive_index(7).unwrap().script_pubkey()
+        );
+        match definite {
+            Descriptor::Wpkh(ref key) => assert_eq!(
+                key.as_descriptor_public_key().full_derivation_path(),
+                DerivationPath::from_str("m/84'/0'/0'/0/7").unwrap()
+            ),
+            _ => unreachable!(),
+        }
+
+        DefiniteDescriptorKey::from_str(&format!("{}/0/*", xpub)).unwrap_err();
+        DefiniteDescriptorKey::from_str(&format!("{}/0/7", xpub)).unwrap();
+    }
+
+    #[test]
+    fn find_derivation_index_for_spk() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "sh(wsh(multi(1,[d34db33f/48'/0'/0'/1']{}/1/*,{})))",
+            xpub, "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8"
+        ))
+        .unwrap();
+        let spk = desc.derive_index(42).unwrap().script_pubkey();
+
+        let (index, found) = desc
+            .find_derivation_index_for_spk(&secp, &spk, 0..100)
+            .unwrap()
+            .unwrap();
+        assert_eq!(index, 42);
+        assert_eq!(found, desc.at_derivation_index(42).unwrap());
+        assert_eq!(
+            desc.find_derivation_index_for_spk(&secp, &spk, 0..42),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn key_origin_accessors() {
+        let key = DescriptorPublicKey::from_str("[d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*").unwrap();
+        assert_eq!(key.master_fingerprint()[..], [0xd3, 0x4d, 0xb3, 0x3f]);
+        assert_eq!(
+            key.full_derivation_path(),
+            DerivationPath::from_str("m/44'/0'/0'/1").unwrap()
+        );
+        assert_eq!(
+            key.derive(&[ChildNumber::from_normal_idx(5).unwrap()])
+                .full_derivation_path(),
+            DerivationPath::from_str("m/44'/0'/0'/1/5").unwrap()
+        );
+
+        let xpub = ExtendedPubKey::from_str("xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL").unwrap();
+        let key = DescriptorPublicKey::from_str(&format!("{}/2", xpub)).unwrap();
+        assert_eq!(key.master_fingerprint(), xpub.fingerprint());
+        assert_eq!(
+            key.full_derivation_path(),
+            DerivationPath::from_str("m/2").unwrap()
+        );
+
+        let key = DescriptorPublicKey::from_str(
+            "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8",
+        )
+        .unwrap();
+        assert_eq!(key.full_derivation_path(), DerivationPath::from(Vec::new()));
+    }
+
+    #[test]
+    fn hardened_markers() {
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let apostrophe = format!("wpkh([d34db33f/84'/0'/0']{}/1/*')", xpub);
+        let h = format!("wpkh([d34db33f/84h/0h/0h]{}/1/*h)", xpub);
+
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&apostrophe).unwrap();
+        assert_eq!(
+            Descriptor::<DescriptorPublicKey>::from_str(&h).unwrap(),
+            desc
+        );
+        assert_eq!(desc.to_string(), apostrophe);
+        assert_eq!(desc.to_string_with_h_markers(), h);
+
+        let key = DescriptorPublicKey::from_str(&format!("[d34db33f/0h/1']{}/2", xpub)).unwrap();
+        assert_eq!(format!("{:#}", key), format!("[d34db33f/0h/1h]{}/2", xpub));
+        DescriptorPublicKey::from_str(&format!("{}/2x", xpub)).unwrap_err();
+    }
 }
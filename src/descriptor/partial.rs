@@ -0,0 +1,637 @@
+//! # Partial Satisfactions
+//!
+//! Tracking of signatures collected so far for a descriptor, so that signing
+//! coordinators can report progress (e.g. "2 of 3 signatures collected")
+//! and determine which signers are still needed, without re-deriving this
+//! from scratch every time a new signature arrives. Signatures, hash
+//! preimages and timelocks can be added over several signing rounds; once
+//! enough of them are known the final witness can be produced.
+//!
+
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use std::{fmt, marker, str};
+
+use bitcoin;
+#[cfg(feature = "serde")]
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+#[cfg(feature = "serde")]
+use bitcoin::secp256k1;
+#[cfg(feature = "serde")]
+use serde::{de, ser};
+
+use descriptor::Descriptor;
+use miniscript::locktime::{AbsLockTime, RelLockTime};
+use miniscript::satisfy::{BitcoinSig, Satisfier};
+use policy::{Liftable, Semantic};
+use {Error, MiniscriptKey, ToPublicKey};
+
+/// The signatures collected so far towards satisfying a descriptor
+#[derive(Clone, Debug)]
+pub struct PartialSatisfaction<Pk: MiniscriptKey> {
+    /// The descriptor being satisfied
+    descriptor: Descriptor<Pk>,
+    /// The lifted policy of `descriptor`, cached
+    policy: Semantic<Pk>,
+    /// The transaction being signed, if known
+    txid: Option<bitcoin::Txid>,
+    /// Signatures collected so far
+    signatures: HashMap<Pk, BitcoinSig>,
+    /// Hash preimages collected so far
+    preimages: Vec<[u8; 32]>,
+    /// Relative locktime (sequence number) of the spending input, if known
+    older: Option<u32>,
+    /// Absolute locktime of the spending transaction, if known
+    after: Option<u32>,
+}
+
+impl<Pk: MiniscriptKey> PartialSatisfaction<Pk> {
+    /// Start tracking the satisfaction of a descriptor, with no signatures
+    pub fn new(descriptor: Descriptor<Pk>) -> PartialSatisfaction<Pk> {
+        PartialSatisfaction {
+            policy: descriptor.lift(),
+            descriptor,
+            txid: None,
+            signatures: HashMap::new(),
+            preimages: vec![],
+            older: None,
+            after: None,
+        }
+    }
+
+    /// Start tracking the satisfaction of a descriptor for an input of a
+    /// specific transaction, with no signatures
+    pub fn for_tx(descriptor: Descriptor<Pk>, txid: bitcoin::Txid) -> PartialSatisfaction<Pk> {
+        let mut ret = PartialSatisfaction::new(descriptor);
+        ret.txid = Some(txid);
+        ret
+    }
+
+    /// The descriptor being satisfied
+    pub fn descriptor(&self) -> &Descriptor<Pk> {
+        &self.descriptor
+    }
+
+    /// The transaction being signed, if one was given
+    pub fn txid(&self) -> Option<bitcoin::Txid> {
+        self.txid
+    }
+
+    /// Checks that a (e.g. reloaded) partial satisfaction still refers to
+    /// the given descriptor and transaction, so that signatures collected
+    /// earlier are not applied to something else. A partial satisfaction
+    /// not tied to any transaction matches only on the descriptor.
+    pub fn check_matches(
+        &self,
+        descriptor: &Descriptor<Pk>,
+        txid: bitcoin::Txid,
+    ) -> Result<(), Error> {
+        if self.descriptor != *descriptor {
+            return Err(Error::PartialSatisfactionMismatch);
+        }
+        match self.txid {
+            Some(ref own) if *own != txid => Err(Error::PartialSatisfactionMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    /// The signatures collected so far
+    pub fn signatures(&self) -> &HashMap<Pk, BitcoinSig> {
+        &self.signatures
+    }
+
+    /// Record a signature. Returns `false`, and ignores the signature, if
+    /// the key does not appear in the descriptor.
+    pub fn add_signature(&mut self, pk: Pk, sig: BitcoinSig) -> bool {
+        if !self.policy.involves_any(&[pk.to_pubkeyhash()]) {
+            return false;
+        }
+        self.signatures.insert(pk, sig);
+        true
+    }
+
+    /// The hash preimages collected so far
+    pub fn preimages(&self) -> &[[u8; 32]] {
+        &self.preimages
+    }
+
+    /// Record a hash preimage. Returns `false`, and ignores the preimage, if
+    /// none of its hashes appears in the descriptor.
+    pub fn add_preimage(&mut self, preimage: [u8; 32]) -> bool {
+        if !uses_preimage(&self.policy, &preimage) {
+            return false;
+        }
+        if !self.preimages.contains(&preimage) {
+            self.preimages.push(preimage);
+        }
+        true
+    }
+
+    /// Record the relative locktime (sequence number) the input will be
+    /// spent with, allowing `older` fragments up to it and of the same
+    /// unit to be satisfied
+    pub fn set_older(&mut self, n: u32) {
+        self.older = Some(n);
+    }
+
+    /// Record the absolute locktime the transaction will be spent with,
+    /// allowing `after` fragments up to it and of the same unit to be
+    /// satisfied
+    pub fn set_after(&mut self, n: u32) {
+        self.after = Some(n);
+    }
+
+    /// Number of signatures collected so far
+    pub fn n_collected(&self) -> usize {
+        self.signatures.len()
+    }
+
+    /// The minimum number of additional signatures needed to satisfy the
+    /// descriptor, or `None` if it cannot be satisfied at all. Hash preimages
+    /// and timelocks are assumed to be available.
+    pub fn n_remaining(&self) -> Option<usize> {
+        self.cheapest_path(&self.policy)
+            .map(|missing| missing.len())
+    }
+
+    /// Hashes of the keys which still need to sign along the spending path
+    /// needing the fewest additional signatures. If several paths are
+    /// equally cheap the first (leftmost) one is chosen.
+    pub fn remaining_signers(&self) -> Vec<Pk::Hash> {
+        self.cheapest_path(&self.policy).unwrap_or_default()
+    }
+
+    /// Whether enough signatures have been collected to satisfy the
+    /// descriptor (assuming hash preimages and timelocks are available)
+    pub fn is_complete(&self) -> bool {
+        self.n_remaining() == Some(0)
+    }
+
+    /// Whether enough signatures, preimages and timelocks are known to
+    /// produce a non-malleable satisfaction of the descriptor
+    pub fn can_finalize(&self) -> bool
+    where
+        Pk: ToPublicKey,
+    {
+        self.finalize().is_ok()
+    }
+
+    /// Produces the witness and scriptSig satisfying the descriptor from the
+    /// material collected so far, see `Descriptor::get_satisfaction`
+    pub fn finalize(&self) -> Result<(Vec<Vec<u8>>, bitcoin::Script), Error>
+    where
+        Pk: ToPublicKey,
+    {
+        self.descriptor.get_satisfaction(self)
+    }
+
+    /// Computes the key hashes missing from the cheapest path through a
+    /// (sub)policy, or `None` if it is unsatisfiable
+    fn cheapest_path(&self, policy: &Semantic<Pk>) -> Option<Vec<Pk::Hash>> {
+        match *policy {
+            Semantic::Unsatisfiable => None,
+            Semantic::KeyHash(ref pkh) => {
+                if self.signatures.keys().any(|pk| pk.to_pubkeyhash() == *pkh) {
+                    Some(vec![])
+                } else {
+                    Some(vec![pkh.clone()])
+                }
+            }
+            Semantic::Trivial
+            | Semantic::After(..)
+            | Semantic::Older(..)
+            | Semantic::Sha256(..)
+            | Semantic::Hash256(..)
+            | Semantic::Ripemd160(..)
+            | Semantic::Hash160(..) => Some(vec![]),
+            Semantic::And(ref subs) => {
+                let mut ret = vec![];
+                for sub in subs {
+                    ret.extend(self.cheapest_path(sub)?);
+                }
+                Some(ret)
+            }
+            Semantic::Or(ref subs) => subs
+                .iter()
+                .filter_map(|sub| self.cheapest_path(sub))
+                .min_by_key(Vec::len),
+            Semantic::Threshold(k, ref subs) => {
+                let mut paths: Vec<_> = subs
+                    .iter()
+                    .filter_map(|sub| self.cheapest_path(sub))
+                    .collect();
+                if paths.len() < k {
+                    return None;
+                }
+                paths.sort_by_key(Vec::len);
+                Some(paths.into_iter().take(k).flat_map(Vec::into_iter).collect())
+            }
+        }
+    }
+}
+
+/// Whether any of the hashes of `preimage` appears in a policy
+fn uses_preimage<Pk: MiniscriptKey>(policy: &Semantic<Pk>, preimage: &[u8; 32]) -> bool {
+    match *policy {
+        Semantic::Sha256(h) => sha256::Hash::hash(preimage) == h,
+        Semantic::Hash256(h) => sha256d::Hash::hash(preimage) == h,
+        Semantic::Ripemd160(h) => ripemd160::Hash::hash(preimage) == h,
+        Semantic::Hash160(h) => hash160::Hash::hash(preimage) == h,
+        Semantic::And(ref subs) | Semantic::Or(ref subs) | Semantic::Threshold(_, ref subs) => {
+            subs.iter().any(|sub| uses_preimage(sub, preimage))
+        }
+        _ => false,
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PartialSatisfaction<Pk> {
+    fn lookup_sig(&self, pk: &Pk) -> Option<BitcoinSig> {
+        self.signatures.get(pk).map(|x| *x)
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.signatures
+            .keys()
+            .find(|pk| pk.to_pubkeyhash() == *pkh)
+            .cloned()
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        self.signatures
+            .iter()
+            .find(|&(pk, _)| pk.to_pubkeyhash() == *pkh)
+            .map(|(pk, sig)| (pk.to_public_key(), *sig))
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.preimages
+            .iter()
+            .find(|p| sha256::Hash::hash(&p[..]) == h)
+            .cloned()
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.preimages
+            .iter()
+            .find(|p| sha256d::Hash::hash(&p[..]) == h)
+            .cloned()
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.preimages
+            .iter()
+            .find(|p| ripemd160::Hash::hash(&p[..]) == h)
+            .cloned()
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.preimages
+            .iter()
+            .find(|p| hash160::Hash::hash(&p[..]) == h)
+            .cloned()
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        match (RelLockTime::from_consensus(n), self.older) {
+            (Ok(t), Some(older)) => t.is_satisfied_by(older),
+            _ => false,
+        }
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        match (AbsLockTime::from_consensus(n), self.after) {
+            (Ok(t), Some(after)) => t.is_satisfied_by(after),
+            _ => false,
+        }
+    }
+}
+
+/// Serializes a signature the way it appears in a witness, as hex
+#[cfg(feature = "serde")]
+fn sig_to_hex(sig: &BitcoinSig) -> String {
+    let mut ret = sig.0.serialize_der().to_vec();
+    ret.push(sig.1.as_u32() as u8);
+    ret.to_hex()
+}
+
+/// Parses a hex-encoded signature with sighash byte
+#[cfg(feature = "serde")]
+fn sig_from_hex(s: &str) -> Option<BitcoinSig> {
+    let bytes = Vec::<u8>::from_hex(s).ok()?;
+    let (flag, sig) = bytes.split_last()?;
+    let sig = secp256k1::Signature::from_der(sig).ok()?;
+    Some((sig, bitcoin::SigHashType::from_u32(*flag as u32)))
+}
+
+#[cfg(feature = "serde")]
+impl<Pk: MiniscriptKey> ser::Serialize for PartialSatisfaction<Pk> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        // Use an ordered map so the serialization is deterministic
+        let sigs: BTreeMap<String, String> = self
+            .signatures
+            .iter()
+            .map(|(pk, sig)| (pk.to_string(), sig_to_hex(sig)))
+            .collect();
+
+        let preimages: Vec<String> = self.preimages.iter().map(|p| p.to_hex()).collect();
+
+        let mut st = s.serialize_struct("PartialSatisfaction", 6)?;
+        st.serialize_field("descriptor", &self.descriptor)?;
+        st.serialize_field("txid", &self.txid.map(|txid| txid.to_string()))?;
+        st.serialize_field("signatures", &sigs)?;
+        st.serialize_field("preimages", &preimages)?;
+        st.serialize_field("older", &self.older)?;
+        st.serialize_field("after", &self.after)?;
+        st.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Pk> de::Deserialize<'de> for PartialSatisfaction<Pk>
+where
+    Pk: MiniscriptKey,
+    <Pk as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+{
+    fn deserialize<D: de::Deserializer<'de>>(d: D) -> Result<PartialSatisfaction<Pk>, D::Error> {
+        const FIELDS: &'static [&'static str] = &[
+            "descriptor",
+            "txid",
+            "signatures",
+            "preimages",
+            "older",
+            "after",
+        ];
+
+        struct MapVisitor<Qk>(marker::PhantomData<Qk>);
+
+        impl<'de, Qk> de::Visitor<'de> for MapVisitor<Qk>
+        where
+            Qk: MiniscriptKey,
+            <Qk as str::FromStr>::Err: ToString,
+            <<Qk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+        {
+            type Value = PartialSatisfaction<Qk>;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt.write_str("a partial satisfaction")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: de::MapAccess<'de>,
+            {
+                let mut descriptor: Option<Descriptor<Qk>> = None;
+                let mut txid: Option<String> = None;
+                let mut sigs: Option<BTreeMap<String, String>> = None;
+                let mut preimages: Vec<String> = vec![];
+                let mut older: Option<u32> = None;
+                let mut after: Option<u32> = None;
+
+                while let Some(key) = map.next_key::<String>()? {
+                    match &key[..] {
+                        "descriptor" => descriptor = Some(map.next_value()?),
+                        "txid" => txid = map.next_value()?,
+                        "signatures" => sigs = Some(map.next_value()?),
+                        "preimages" => preimages = map.next_value()?,
+                        "older" => older = map.next_value()?,
+                        "after" => after = map.next_value()?,
+                        _ => return Err(de::Error::unknown_field(&key, FIELDS)),
+                    }
+                }
+
+                let descriptor =
+                    descriptor.ok_or_else(|| de::Error::missing_field("descriptor"))?;
+                let sigs = sigs.ok_or_else(|| de::Error::missing_field("signatures"))?;
+
+                let mut ret = PartialSatisfaction::new(descriptor);
+                if let Some(txid) = txid {
+                    ret.txid = Some(
+                        str::FromStr::from_str(&txid)
+                            .map_err(|_| de::Error::custom(format!("bad txid {}", txid)))?,
+                    );
+                }
+                for (pk, sig) in sigs {
+                    let key: Qk = str::FromStr::from_str(&pk)
+                        .map_err(|e: <Qk as str::FromStr>::Err| de::Error::custom(e.to_string()))?;
+                    let sig = sig_from_hex(&sig)
+                        .ok_or_else(|| de::Error::custom(format!("bad signature {}", sig)))?;
+                    if !ret.add_signature(key, sig) {
+                        return Err(de::Error::custom(format!(
+                            "signature for key {} not in descriptor",
+                            pk
+                        )));
+                    }
+                }
+                for preimage in preimages {
+                    let bytes = match Vec::<u8>::from_hex(&preimage) {
+                        Ok(ref bytes) if bytes.len() == 32 => bytes.clone(),
+                        _ => return Err(de::Error::custom(format!("bad preimage {}", preimage))),
+                    };
+                    let mut array = [0; 32];
+                    array.copy_from_slice(&bytes);
+                    if !ret.add_preimage(array) {
+                        return Err(de::Error::custom(format!(
+                            "preimage {} not in descriptor",
+                            preimage
+                        )));
+                    }
+                }
+                ret.older = older;
+                ret.after = after;
+                Ok(ret)
+            }
+        }
+
+        d.deserialize_struct(
+            "PartialSatisfaction",
+            FIELDS,
+            MapVisitor(marker::PhantomData),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{self, secp256k1};
+    #[cfg(feature = "serde")]
+    use serde_json;
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn dummy_sig() -> BitcoinSig {
+        (
+            secp256k1::Signature::from_compact(&[0x01; 64]).unwrap(),
+            bitcoin::SigHashType::All,
+        )
+    }
+
+    #[test]
+    fn multisig_progress() {
+        let desc = Descriptor::<String>::from_str("wsh(multi(2,A,B,C))").unwrap();
+        let mut partial = PartialSatisfaction::new(desc);
+        assert_eq!(partial.n_collected(), 0);
+        assert_eq!(partial.n_remaining(), Some(2));
+        assert!(!partial.is_complete());
+
+        assert!(!partial.add_signature("D".to_owned(), dummy_sig()));
+        assert!(partial.add_signature("B".to_owned(), dummy_sig()));
+        assert_eq!(partial.n_collected(), 1);
+        assert_eq!(partial.n_remaining(), Some(1));
+        assert_eq!(partial.remaining_signers(), vec!["A".to_owned()]);
+
+        assert!(partial.add_signature("C".to_owned(), dummy_sig()));
+        assert_eq!(partial.n_remaining(), Some(0));
+        assert!(partial.is_complete());
+        assert!(partial.remaining_signers().is_empty());
+    }
+
+    #[test]
+    fn cheapest_branch() {
+        let desc =
+            Descriptor::<String>::from_str("wsh(or_d(multi(2,A,B),and_v(vc:pk_k(C),older(10))))")
+                .unwrap();
+        let mut partial = PartialSatisfaction::new(desc);
+        assert_eq!(partial.n_remaining(), Some(1));
+        assert_eq!(partial.remaining_signers(), vec!["C".to_owned()]);
+
+        partial.add_signature("A".to_owned(), dummy_sig());
+        assert_eq!(partial.n_remaining(), Some(1));
+        assert_eq!(partial.remaining_signers(), vec!["B".to_owned()]);
+    }
+
+    #[test]
+    fn multi_round_finalize() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let pks: Vec<bitcoin::PublicKey> = (1..4)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                bitcoin::PublicKey {
+                    compressed: true,
+                    key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                }
+            })
+            .collect();
+        let preimage = [0xab; 32];
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(and_v(v:multi(2,{},{},{}),and_v(v:sha256({}),older(144))))",
+            pks[0],
+            pks[1],
+            pks[2],
+            sha256::Hash::hash(&preimage),
+        ))
+        .unwrap();
+
+        let mut partial = PartialSatisfaction::new(desc);
+        assert!(partial.add_signature(pks[0], dummy_sig()));
+        assert!(!partial.can_finalize());
+        assert!(partial.add_signature(pks[2], dummy_sig()));
+        assert!(partial.is_complete());
+        // Signatures alone are not enough, the preimage and timelock are missing
+        assert!(!partial.can_finalize());
+
+        assert!(!partial.add_preimage([0xcd; 32]));
+        assert!(partial.add_preimage(preimage));
+        assert_eq!(partial.preimages(), &[preimage]);
+        assert!(!partial.can_finalize());
+        partial.set_older(143);
+        assert!(!partial.can_finalize());
+        // A time based sequence does not satisfy a block based timelock
+        partial.set_older((1 << 22) | 144);
+        assert!(!partial.can_finalize());
+        partial.set_older(144);
+        assert!(partial.can_finalize());
+
+        let (witness, script_sig) = partial.finalize().unwrap();
+        assert!(script_sig.is_empty());
+        assert!(witness.contains(&preimage.to_vec()));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_roundtrip() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let pks: Vec<bitcoin::PublicKey> = (1..4)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                bitcoin::PublicKey {
+                    compressed: true,
+                    key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+                }
+            })
+            .collect();
+        let preimage = [0xab; 32];
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(and_v(v:multi(2,{},{},{}),and_v(v:sha256({}),and_v(v:older(144),after(1000)))))",
+            pks[0],
+            pks[1],
+            pks[2],
+            sha256::Hash::hash(&preimage),
+        ))
+        .unwrap();
+        let other = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(multi(2,{},{},{}))",
+            pks[0], pks[1], pks[2],
+        ))
+        .unwrap();
+        let txid = bitcoin::Txid::from_str(
+            "0101010101010101010101010101010101010101010101010101010101010101",
+        )
+        .unwrap();
+
+        let mut partial = PartialSatisfaction::for_tx(desc.clone(), txid);
+        assert!(partial.add_signature(pks[0], dummy_sig()));
+        assert!(partial.add_signature(pks[2], dummy_sig()));
+        assert!(partial.add_preimage(preimage));
+        partial.set_older(144);
+        partial.set_after(1000);
+        assert!(partial.can_finalize());
+
+        let json = serde_json::to_string(&partial).unwrap();
+        let reloaded: PartialSatisfaction<bitcoin::PublicKey> =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded.descriptor(), &desc);
+        assert_eq!(reloaded.txid(), Some(txid));
+        assert_eq!(reloaded.signatures(), partial.signatures());
+        assert_eq!(reloaded.preimages(), &[preimage]);
+        assert!(reloaded.check_older(144));
+        assert!(!reloaded.check_older(145));
+        assert!(reloaded.check_after(1000));
+        assert!(!reloaded.check_after(1001));
+        assert_eq!(reloaded.finalize().unwrap(), partial.finalize().unwrap());
+        assert_eq!(serde_json::to_string(&reloaded).unwrap(), json);
+
+        // The reloaded session must not be applied to another spend
+        assert!(reloaded.check_matches(&desc, txid).is_ok());
+        assert!(reloaded.check_matches(&other, txid).is_err());
+        assert!(reloaded
+            .check_matches(&desc, bitcoin::Txid::default())
+            .is_err());
+    }
+
+    #[test]
+    fn check_matches() {
+        let desc = Descriptor::<String>::from_str("wsh(multi(2,A,B,C))").unwrap();
+        let other = Descriptor::<String>::from_str("wsh(multi(2,A,B,D))").unwrap();
+        let txid = bitcoin::Txid::default();
+        let other_txid = bitcoin::Txid::from_str(
+            "0101010101010101010101010101010101010101010101010101010101010101",
+        )
+        .unwrap();
+
+        let partial = PartialSatisfaction::new(desc.clone());
+        assert!(partial.check_matches(&desc, other_txid).is_ok());
+        assert!(partial.check_matches(&other, txid).is_err());
+
+        let partial = PartialSatisfaction::for_tx(desc.clone(), txid);
+        assert_eq!(partial.txid(), Some(txid));
+        assert!(partial.check_matches(&desc, txid).is_ok());
+        assert!(partial.check_matches(&desc, other_txid).is_err());
+    }
+}
@@ -12,12 +12,18 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+#[cfg(feature = "serde")]
+use bitcoin::hashes::hex::ToHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use bitcoin::{self, secp256k1};
 use fmt;
+#[cfg(feature = "serde")]
+use serde::ser;
+use std::ptr;
 use Descriptor;
 use Terminal;
 use {error, Miniscript};
+use {AbsLockTime, RelLockTime};
 use {BitcoinSig, ToPublicKey};
 
 /// Detailed Error type for Interpreter
@@ -126,6 +132,14 @@ impl fmt::Display for Error {
     }
 }
 
+/// Errors are serialized as their human-readable description
+#[cfg(feature = "serde")]
+impl ser::Serialize for Error {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
 /// Definition of Stack Element of the Stack used for interpretation of Miniscript.
 /// All stack elements with vec![] go to Dissatisfied and vec![1] are marked to Satisfied.
 /// Others are directly pushed as witness
@@ -189,9 +203,67 @@ pub enum SatisfiedConstraint<'desc, 'stack> {
         preimage: &'stack [u8],
     },
     ///Relative Timelock for CSV.
-    RelativeTimeLock { time: &'desc u32 },
+    RelativeTimeLock { time: &'desc RelLockTime },
     ///Absolute Timelock for CLTV.
-    AbsoluteTimeLock { time: &'desc u32 },
+    AbsoluteTimeLock { time: &'desc AbsLockTime },
+}
+
+/// Serialized as a struct tagged with a `type` field, with keys, signatures,
+/// hashes and preimages hex-encoded. Together with the serialization of
+/// `Error` this allows dumping the collected output of `SatisfiedConstraints`,
+/// a `Vec<Result<SatisfiedConstraint, Error>>`, directly.
+#[cfg(feature = "serde")]
+impl<'desc, 'stack> ser::Serialize for SatisfiedConstraint<'desc, 'stack> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        match *self {
+            SatisfiedConstraint::PublicKey { key, ref sig } => {
+                let mut st = s.serialize_struct("SatisfiedConstraint", 3)?;
+                st.serialize_field("type", "pk")?;
+                st.serialize_field("key", &key.to_string())?;
+                st.serialize_field("sig", &sig.serialize_der().to_hex())?;
+                st.end()
+            }
+            SatisfiedConstraint::PublicKeyHash {
+                keyhash,
+                ref key,
+                ref sig,
+            } => {
+                let mut st = s.serialize_struct("SatisfiedConstraint", 4)?;
+                st.serialize_field("type", "pkh")?;
+                st.serialize_field("keyhash", &keyhash.to_string())?;
+                st.serialize_field("key", &key.to_string())?;
+                st.serialize_field("sig", &sig.serialize_der().to_hex())?;
+                st.end()
+            }
+            SatisfiedConstraint::HashLock { hash, preimage } => {
+                let (ty, hash) = match hash {
+                    HashLockType::Sha256(h) => ("sha256", h.to_string()),
+                    HashLockType::Hash256(h) => ("hash256", h.to_string()),
+                    HashLockType::Hash160(h) => ("hash160", h.to_string()),
+                    HashLockType::Ripemd160(h) => ("ripemd160", h.to_string()),
+                };
+                let mut st = s.serialize_struct("SatisfiedConstraint", 3)?;
+                st.serialize_field("type", ty)?;
+                st.serialize_field("hash", &hash)?;
+                st.serialize_field("preimage", &preimage.to_hex())?;
+                st.end()
+            }
+            SatisfiedConstraint::RelativeTimeLock { time } => {
+                let mut st = s.serialize_struct("SatisfiedConstraint", 2)?;
+                st.serialize_field("type", "older")?;
+                st.serialize_field("time", &time.to_u32())?;
+                st.end()
+            }
+            SatisfiedConstraint::AbsoluteTimeLock { time } => {
+                let mut st = s.serialize_struct("SatisfiedConstraint", 2)?;
+                st.serialize_field("type", "after")?;
+                st.serialize_field("time", &time.to_u32())?;
+                st.end()
+            }
+        }
+    }
 }
 
 ///This is used by the interpreter to know which evaluation state a AstemElem is.
@@ -209,6 +281,49 @@ struct NodeEvaluationState<'desc> {
     n_satisfied: usize,
 }
 
+/// Checks the signatures found by `SatisfiedConstraints`. Implemented for
+/// closures taking the public key and the signature, which can decide on
+/// any criterion, and for `TxSignatureChecker`, which verifies signatures
+/// against the transaction being interpreted.
+pub trait SignatureChecker {
+    /// Whether `sig` is a valid signature by `pk`
+    fn check_sig(&mut self, pk: &bitcoin::PublicKey, sig: BitcoinSig) -> bool;
+}
+
+impl<F> SignatureChecker for F
+where
+    F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool,
+{
+    fn check_sig(&mut self, pk: &bitcoin::PublicKey, sig: BitcoinSig) -> bool {
+        self(pk, sig)
+    }
+}
+
+/// Verifies ECDSA signatures against the signature hash of an input of a
+/// transaction spending an output of a descriptor, using the sighash type
+/// attached to each signature, see `Descriptor::sighash`
+pub struct TxSignatureChecker<'tx, C: secp256k1::Verification + 'tx> {
+    secp: &'tx secp256k1::Secp256k1<C>,
+    descriptor: &'tx Descriptor<bitcoin::PublicKey>,
+    tx: &'tx bitcoin::Transaction,
+    index: usize,
+    value: u64,
+}
+
+impl<'tx, C: secp256k1::Verification> SignatureChecker for TxSignatureChecker<'tx, C> {
+    fn check_sig(&mut self, pk: &bitcoin::PublicKey, sig: BitcoinSig) -> bool {
+        let (sig, sighash_type) = sig;
+        match self
+            .descriptor
+            .sighash_message(self.tx, self.index, self.value, sighash_type)
+        {
+            Ok(msg) => self.secp.verify(&msg, &sig, &pk.key).is_ok(),
+            // Without a scriptCode no signature can be valid
+            Err(_) => false,
+        }
+    }
+}
+
 /// An iterator over all the satisfied constraints satisfied by a given
 /// descriptor/scriptSig/witness stack tuple. This returns all the redundant
 /// satisfied constraints even if they were not required for the entire
@@ -217,16 +332,37 @@ struct NodeEvaluationState<'desc> {
 /// In case the script would abort on the given witness stack OR if the entire
 /// script is dissatisfied, this would return keep on returning values
 ///_until_Error.
-pub struct SatisfiedConstraints<'desc, 'stack, F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool> {
+pub struct SatisfiedConstraints<'desc, 'stack, F: SignatureChecker> {
     verify_sig: F,
     public_key: Option<&'desc bitcoin::PublicKey>,
+    root: Option<&'desc Miniscript<bitcoin::PublicKey>>,
     state: Vec<NodeEvaluationState<'desc>>,
     stack: Stack<'stack>,
     age: u32,
     height: u32,
+    assume_timelocks: bool,
+    location: ErrorLocation<'desc, 'stack>,
     has_errored: bool,
 }
 
+/// Where the interpretation of a witness failed, see
+/// `SatisfiedConstraints::error_location`
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ErrorLocation<'desc, 'stack> {
+    /// The fragment being evaluated, if the failure was within one. `None`
+    /// if the descriptor has no Miniscript, or if the whole script was
+    /// evaluated without leaving exactly one satisfied element on the stack.
+    pub fragment: Option<&'desc Miniscript<bitcoin::PublicKey>>,
+    /// The position of `fragment` in the Miniscript: the index into
+    /// `Miniscript::branches` at each level, starting from the root
+    pub path: Vec<usize>,
+    /// The element on top of the stack when the evaluation of `fragment`
+    /// started, i.e. the one it failed on if it takes any input
+    pub stack_top: Option<StackElement<'stack>>,
+    /// The number of elements on the stack at that time
+    pub stack_len: usize,
+}
+
 /// Stack Data structure representing the stack input to Miniscript. This Stack
 /// is created from the combination of ScriptSig and Witness stack.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
@@ -235,7 +371,7 @@ pub struct Stack<'stack>(pub Vec<StackElement<'stack>>);
 ///Iterator for SatisfiedConstraints
 impl<'desc, 'stack, F> Iterator for SatisfiedConstraints<'desc, 'stack, F>
 where
-    F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool,
+    F: SignatureChecker,
 {
     type Item = Result<SatisfiedConstraint<'desc, 'stack>, Error>;
 
@@ -245,8 +381,16 @@ where
             None
         } else {
             let res = self.iter_next();
-            if let Some(Err(_)) = res {
-                self.has_errored = true;
+            match res {
+                Some(Ok(ref _constraint)) => {
+                    trace_event!(constraint = ?_constraint, "constraint satisfied");
+                }
+                Some(Err(ref _e)) => {
+                    trace_event!(error = %_e, "interpreter failed");
+                    self.location.path = self.fragment_path();
+                    self.has_errored = true;
+                }
+                None => {}
             }
             res
         }
@@ -256,6 +400,58 @@ where
 impl<'desc, 'stack, F> SatisfiedConstraints<'desc, 'stack, F>
 where
     F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool,
+{
+    /// Creates a new iterator over all constraints satisfied for a given
+    /// descriptor by a given witness stack. Because this iterator is lazy,
+    /// it may return satisfied constraints even if these turn out to be
+    /// irrelevant to the final (dis)satisfaction of the descriptor.
+    ///
+    /// Signatures are only checked by `verify_sig`, see
+    /// `from_descriptor_with_tx` to verify them against a transaction.
+    pub fn from_descriptor(
+        des: &'desc Descriptor<bitcoin::PublicKey>,
+        stack: Stack<'stack>,
+        verify_sig: F,
+        age: u32,
+        height: u32,
+    ) -> SatisfiedConstraints<'desc, 'stack, F> {
+        SatisfiedConstraints::new(des, stack, verify_sig, age, height)
+    }
+}
+
+impl<'desc, 'stack, 'tx, C> SatisfiedConstraints<'desc, 'stack, TxSignatureChecker<'tx, C>>
+where
+    'desc: 'tx,
+    C: secp256k1::Verification,
+{
+    /// Like `from_descriptor`, but verifies every signature against the
+    /// signature hash of input `index` of `tx`, which spends an output of
+    /// `des` worth `value` satoshis. Invalid signatures are reported as
+    /// `Error::InvalidSignature`.
+    pub fn from_descriptor_with_tx(
+        des: &'desc Descriptor<bitcoin::PublicKey>,
+        stack: Stack<'stack>,
+        secp: &'tx secp256k1::Secp256k1<C>,
+        tx: &'tx bitcoin::Transaction,
+        index: usize,
+        value: u64,
+        age: u32,
+        height: u32,
+    ) -> SatisfiedConstraints<'desc, 'stack, TxSignatureChecker<'tx, C>> {
+        let checker = TxSignatureChecker {
+            secp: secp,
+            descriptor: des,
+            tx: tx,
+            index: index,
+            value: value,
+        };
+        SatisfiedConstraints::new(des, stack, checker, age, height)
+    }
+}
+
+impl<'desc, 'stack, F> SatisfiedConstraints<'desc, 'stack, F>
+where
+    F: SignatureChecker,
 {
     /// Helper function to push a NodeEvaluationState on state stack
     fn push_evaluation_state(
@@ -271,11 +467,47 @@ where
         })
     }
 
-    /// Creates a new iterator over all constraints satisfied for a given
-    /// descriptor by a given witness stack. Because this iterator is lazy,
-    /// it may return satisfied constraints even if these turn out to be
-    /// irrelevant to the final (dis)satisfaction of the descriptor.
-    pub fn from_descriptor(
+    /// Treats every timelock as met, whatever `age` and `height` are, e.g.
+    /// to find out which timelocks a witness relies on
+    pub fn assume_timelocks(mut self) -> Self {
+        self.assume_timelocks = true;
+        self
+    }
+
+    /// Where the interpretation failed, once the iterator has returned an
+    /// error; `None` before, and if the witness satisfies the descriptor
+    pub fn error_location(&self) -> Option<&ErrorLocation<'desc, 'stack>> {
+        if self.has_errored {
+            Some(&self.location)
+        } else {
+            None
+        }
+    }
+
+    /// Helper function for `next` to find the path from the root to the
+    /// fragment in `self.location`
+    fn fragment_path(&self) -> Vec<usize> {
+        let (root, fragment) = match (self.root, self.location.fragment) {
+            (Some(root), Some(fragment)) => (root, fragment),
+            _ => return vec![],
+        };
+        let mut stack = vec![(root, vec![])];
+        while let Some((node, path)) = stack.pop() {
+            if ptr::eq(node, fragment) {
+                return path;
+            }
+            for (i, sub) in node.branches().into_iter().enumerate() {
+                let mut sub_path = path.clone();
+                sub_path.push(i);
+                stack.push((sub, sub_path));
+            }
+        }
+        vec![]
+    }
+
+    /// Helper function for the constructors, checking signatures with
+    /// `verify_sig`
+    fn new(
         des: &'desc Descriptor<bitcoin::PublicKey>,
         stack: Stack<'stack>,
         verify_sig: F,
@@ -289,10 +521,13 @@ where
             | &Descriptor::Wpkh(ref pk) => SatisfiedConstraints {
                 verify_sig: verify_sig,
                 public_key: Some(pk),
+                root: None,
                 state: vec![],
                 stack: stack,
                 age,
                 height,
+                assume_timelocks: false,
+                location: ErrorLocation::default(),
                 has_errored: false,
             },
             &Descriptor::Sh(ref miniscript)
@@ -301,6 +536,7 @@ where
             | &Descriptor::Wsh(ref miniscript) => SatisfiedConstraints {
                 verify_sig: verify_sig,
                 public_key: None,
+                root: Some(miniscript),
                 state: vec![NodeEvaluationState {
                     node: miniscript,
                     n_evaluated: 0,
@@ -309,6 +545,22 @@ where
                 stack: stack,
                 age,
                 height,
+                assume_timelocks: false,
+                location: ErrorLocation::default(),
+                has_errored: false,
+            },
+            // There is nothing to interpret, so the iterator immediately
+            // reports that the script is not satisfied
+            &Descriptor::Addr(..) | &Descriptor::Raw(..) => SatisfiedConstraints {
+                verify_sig: verify_sig,
+                public_key: None,
+                root: None,
+                state: vec![],
+                stack: Stack(vec![]),
+                age,
+                height,
+                assume_timelocks: false,
+                location: ErrorLocation::default(),
                 has_errored: false,
             },
         }
@@ -317,6 +569,9 @@ where
     /// Helper function to step the iterator
     fn iter_next(&mut self) -> Option<Result<SatisfiedConstraint<'desc, 'stack>, Error>> {
         while let Some(node_state) = self.state.pop() {
+            self.location.fragment = Some(node_state.node);
+            self.location.stack_top = self.stack.last().cloned();
+            self.location.stack_len = self.stack.len();
             //non-empty stack
             match node_state.node.node {
                 Terminal::True => {
@@ -348,7 +603,12 @@ where
                 Terminal::After(ref n) => {
                     debug_assert_eq!(node_state.n_evaluated, 0);
                     debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_after(n, self.age);
+                    let height = if self.assume_timelocks {
+                        n.to_u32()
+                    } else {
+                        self.height
+                    };
+                    let res = self.stack.evaluate_after(n, height);
                     if res.is_some() {
                         return res;
                     }
@@ -356,7 +616,12 @@ where
                 Terminal::Older(ref n) => {
                     debug_assert_eq!(node_state.n_evaluated, 0);
                     debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_older(n, self.height);
+                    let age = if self.assume_timelocks {
+                        n.to_u32()
+                    } else {
+                        self.age
+                    };
+                    let res = self.stack.evaluate_older(n, age);
                     if res.is_some() {
                         return res;
                     }
@@ -665,6 +930,9 @@ where
 
         //state empty implies that either the execution has terminated or we have a
         //Pk based descriptor
+        self.location.fragment = None;
+        self.location.stack_top = self.stack.last().cloned();
+        self.location.stack_len = self.stack.len();
         if let Some(pk) = self.public_key {
             if let Some(StackElement::Push(sig)) = self.stack.pop() {
                 if let Ok(sig) = verify_sersig(&mut self.verify_sig, &pk, &sig) {
@@ -693,17 +961,17 @@ where
 
 /// Helper function to verify serialized signature
 fn verify_sersig<'stack, F>(
-    verify_sig: F,
+    verify_sig: &mut F,
     pk: &bitcoin::PublicKey,
     sigser: &[u8],
 ) -> Result<secp256k1::Signature, Error>
 where
-    F: FnOnce(&bitcoin::PublicKey, BitcoinSig) -> bool,
+    F: SignatureChecker,
 {
     if let Some((sighash_byte, sig)) = sigser.split_last() {
         let sighashtype = bitcoin::SigHashType::from_u32(*sighash_byte as u32);
         let sig = secp256k1::Signature::from_der(sig)?;
-        if verify_sig(pk, (sig, sighashtype)) {
+        if verify_sig.check_sig(pk, (sig, sighashtype)) {
             Ok(sig)
         } else {
             Err(Error::InvalidSignature(*pk))
@@ -752,11 +1020,11 @@ impl<'stack> Stack<'stack> {
     /// `pk` CHECKSIG
     fn evaluate_pk<'desc, F>(
         &mut self,
-        verify_sig: F,
+        verify_sig: &mut F,
         pk: &'desc bitcoin::PublicKey,
     ) -> Option<Result<SatisfiedConstraint<'desc, 'stack>, Error>>
     where
-        F: FnMut(&bitcoin::PublicKey, BitcoinSig) -> bool,
+        F: SignatureChecker,
     {
         if let Some(sigser) = self.pop() {
             match sigser {
@@ -791,11 +1059,11 @@ impl<'stack> Stack<'stack> {
     /// `DUP HASH160 <keyhash> EQUALVERIY CHECKSIG`
     fn evaluate_pkh<'desc, F>(
         &mut self,
-        verify_sig: F,
+        verify_sig: &mut F,
         pkh: &'desc hash160::Hash,
     ) -> Option<Result<SatisfiedConstraint<'desc, 'stack>, Error>>
     where
-        F: FnOnce(&bitcoin::PublicKey, BitcoinSig) -> bool,
+        F: SignatureChecker,
     {
         if let Some(StackElement::Push(pk)) = self.pop() {
             let pk_hash = hash160::Hash::hash(pk);
@@ -842,40 +1110,40 @@ impl<'stack> Stack<'stack> {
     }
 
     /// Helper function to evaluate a After Node. Takes no argument from stack
-    /// `n CHECKSEQUENCEVERIFY 0NOTEQUAL` and `n CHECKSEQUENCEVERIFY`
+    /// `n CHECKLOCKTIMEVERIFY 0NOTEQUAL` and `n CHECKLOCKTIMEVERIFY`
     /// Ideally this should return int value as n: build_scriptint(t as i64)),
     /// The reason we don't need to copy the Script semantics is that
     /// Miniscript never evaluates integers and it is safe to treat them as
     /// booleans
     fn evaluate_after<'desc>(
         &mut self,
-        n: &'desc u32,
-        age: u32,
+        n: &'desc AbsLockTime,
+        height: u32,
     ) -> Option<Result<SatisfiedConstraint<'desc, 'stack>, Error>> {
-        if age >= *n {
+        if n.is_satisfied_by(height) {
             self.push(StackElement::Satisfied);
-            Some(Ok(SatisfiedConstraint::RelativeTimeLock { time: n }))
+            Some(Ok(SatisfiedConstraint::AbsoluteTimeLock { time: n }))
         } else {
-            Some(Err(Error::AbsoluteLocktimeNotMet(*n)))
+            Some(Err(Error::AbsoluteLocktimeNotMet(n.to_u32())))
         }
     }
 
     /// Helper function to evaluate a Older Node. Takes no argument from stack
-    /// `n CHECKLOCKTIMEVERIFY 0NOTEQUAL` and `n CHECKLOCKTIMEVERIFY`
+    /// `n CHECKSEQUENCEVERIFY 0NOTEQUAL` and `n CHECKSEQUENCEVERIFY`
     /// Ideally this should return int value as n: build_scriptint(t as i64)),
     /// The reason we don't need to copy the Script semantics is that
     /// Miniscript never evaluates integers and it is safe to treat them as
     /// booleans
     fn evaluate_older<'desc>(
         &mut self,
-        n: &'desc u32,
-        height: u32,
+        n: &'desc RelLockTime,
+        age: u32,
     ) -> Option<Result<SatisfiedConstraint<'desc, 'stack>, Error>> {
-        if height >= *n {
+        if n.is_satisfied_by(age) {
             self.push(StackElement::Satisfied);
-            Some(Ok(SatisfiedConstraint::AbsoluteTimeLock { time: n }))
+            Some(Ok(SatisfiedConstraint::RelativeTimeLock { time: n }))
         } else {
-            Some(Err(Error::RelativeLocktimeNotMet(*n)))
+            Some(Err(Error::RelativeLocktimeNotMet(n.to_u32())))
         }
     }
 
@@ -987,11 +1255,11 @@ impl<'stack> Stack<'stack> {
     /// `[0 sig2 sig1]`
     fn evaluate_multi<'desc, F>(
         &mut self,
-        verify_sig: F,
+        verify_sig: &mut F,
         pk: &'desc bitcoin::PublicKey,
     ) -> Option<Result<SatisfiedConstraint<'desc, 'stack>, Error>>
     where
-        F: FnOnce(&bitcoin::PublicKey, BitcoinSig) -> bool,
+        F: SignatureChecker,
     {
         if let Some(witness_sig) = self.pop() {
             if let StackElement::Push(sigser) = witness_sig {
@@ -1019,14 +1287,16 @@ mod tests {
     use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
     use bitcoin::secp256k1::{self, Secp256k1, VerifyOnly};
     use descriptor::satisfied_constraints::{
-        Error, HashLockType, NodeEvaluationState, SatisfiedConstraint, SatisfiedConstraints, Stack,
-        StackElement,
+        Error, ErrorLocation, HashLockType, NodeEvaluationState, SatisfiedConstraint,
+        SatisfiedConstraints, Stack, StackElement,
     };
     use std::str::FromStr;
     use BitcoinSig;
+    use Descriptor;
     use Miniscript;
     use MiniscriptKey;
     use ToPublicKey;
+    use {AbsLockTime, RelLockTime};
 
     fn setup_keys_sigs(
         n: usize,
@@ -1083,6 +1353,7 @@ mod tests {
                 verify_sig: verify_fn,
                 stack: stack,
                 public_key: None,
+                root: Some(ms),
                 state: vec![NodeEvaluationState {
                     node: ms,
                     n_evaluated: 0,
@@ -1090,6 +1361,8 @@ mod tests {
                 }],
                 age: 1002,
                 height: 1002,
+                assume_timelocks: false,
+                location: ErrorLocation::default(),
                 has_errored: false,
             }
         };
@@ -1153,7 +1426,9 @@ mod tests {
         let after_satisfied: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
         assert_eq!(
             after_satisfied.unwrap(),
-            vec![SatisfiedConstraint::RelativeTimeLock { time: &1000 }]
+            vec![SatisfiedConstraint::AbsoluteTimeLock {
+                time: &AbsLockTime::from_consensus(1000).unwrap(),
+            }]
         );
 
         //Check Older
@@ -1162,7 +1437,9 @@ mod tests {
         let older_satisfied: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
         assert_eq!(
             older_satisfied.unwrap(),
-            vec![SatisfiedConstraint::AbsoluteTimeLock { time: &1000 }]
+            vec![SatisfiedConstraint::RelativeTimeLock {
+                time: &RelLockTime::from_consensus(1000).unwrap(),
+            }]
         );
 
         //Check Sha256
@@ -1471,4 +1748,145 @@ mod tests {
         let multi_error: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
         assert!(multi_error.is_err());
     }
+
+    #[test]
+    fn verify_tx_signatures() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).expect("secret key");
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let des: Descriptor<bitcoin::PublicKey> = des_str!("wsh(c:pk_k({}))", pk);
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::default(),
+                script_sig: bitcoin::Script::new(),
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        let all = bitcoin::SigHashType::All;
+        let msg = des.sighash_message(&tx, 0, 10_000, all).unwrap();
+        let mut sig = secp.sign(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x01); // sighash_all
+
+        let constraints: Result<Vec<SatisfiedConstraint>, Error> =
+            SatisfiedConstraints::from_descriptor_with_tx(
+                &des,
+                Stack(vec![StackElement::Push(&sig)]),
+                &secp,
+                &tx,
+                0,
+                10_000,
+                0,
+                0,
+            )
+            .collect();
+        assert_eq!(constraints.unwrap().len(), 1);
+
+        // The signature commits to the amount spent, so it is invalid for
+        // any other amount
+        let constraints: Result<Vec<SatisfiedConstraint>, Error> =
+            SatisfiedConstraints::from_descriptor_with_tx(
+                &des,
+                Stack(vec![StackElement::Push(&sig)]),
+                &secp,
+                &tx,
+                0,
+                9_999,
+                0,
+                0,
+            )
+            .collect();
+        assert_eq!(constraints, Err(Error::InvalidSignature(pk)));
+    }
+    #[test]
+    fn error_location() {
+        let (pks, der_sigs, _, sighash, secp) = setup_keys_sigs(1);
+        let vfyfn =
+            |pk: &bitcoin::PublicKey, (sig, _)| secp.verify(&sighash, &sig, &pk.key).is_ok();
+        let hash = sha256::Hash::hash(&[0xab; 32]);
+        let des: Descriptor<bitcoin::PublicKey> =
+            des_str!("wsh(and_v(v:sha256({}),c:pk_k({})))", hash, pks[0]);
+
+        // The preimage is too short
+        let preimage = vec![0xab; 31];
+        let stack = Stack(vec![
+            StackElement::Push(&der_sigs[0]),
+            StackElement::Push(&preimage),
+        ]);
+        let mut constraints = SatisfiedConstraints::from_descriptor(&des, stack, &vfyfn, 0, 0);
+        assert_eq!(constraints.error_location(), None);
+        assert_eq!(
+            constraints.next(),
+            Some(Err(Error::HashPreimageLengthMismatch))
+        );
+        let location = constraints.error_location().unwrap();
+        assert_eq!(
+            location.fragment.unwrap().to_string(),
+            format!("sha256({})", hash)
+        );
+        assert_eq!(location.path, vec![0, 0]);
+        assert_eq!(location.stack_top, Some(StackElement::Push(&preimage)));
+        assert_eq!(location.stack_len, 2);
+
+        // The whole script is dissatisfied, which no fragment is to blame for
+        let stack = Stack(vec![StackElement::Dissatisfied]);
+        let des: Descriptor<bitcoin::PublicKey> = des_str!("wsh(c:pk_k({}))", pks[0]);
+        let mut constraints = SatisfiedConstraints::from_descriptor(&des, stack, &vfyfn, 0, 0);
+        assert_eq!(
+            constraints.next(),
+            Some(Err(Error::ScriptSatisfactionError))
+        );
+        assert_eq!(
+            constraints.error_location(),
+            Some(&ErrorLocation {
+                fragment: None,
+                path: vec![],
+                stack_top: Some(StackElement::Dissatisfied),
+                stack_len: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn timelock_units() {
+        let (pks, der_sigs, _, sighash, secp) = setup_keys_sigs(1);
+        let vfyfn =
+            |pk: &bitcoin::PublicKey, (sig, _)| secp.verify(&sighash, &sig, &pk.key).is_ok();
+        let time_flag = 1 << 22;
+
+        let des: Descriptor<bitcoin::PublicKey> =
+            des_str!("wsh(and_v(vc:pk_k({}),older(1000)))", pks[0]);
+        let stack = Stack(vec![StackElement::Push(&der_sigs[0])]);
+        let constraints = SatisfiedConstraints::from_descriptor(&des, stack, &vfyfn, 1000, 0);
+        assert!(constraints.collect::<Result<Vec<_>, _>>().is_ok());
+        // A time based sequence doesn't satisfy a block based timelock,
+        // however large it is
+        let stack = Stack(vec![StackElement::Push(&der_sigs[0])]);
+        let age = time_flag | 1000;
+        let constraints = SatisfiedConstraints::from_descriptor(&des, stack, &vfyfn, age, 0);
+        assert_eq!(
+            constraints.collect::<Result<Vec<_>, _>>(),
+            Err(Error::RelativeLocktimeNotMet(1000))
+        );
+
+        let des: Descriptor<bitcoin::PublicKey> =
+            des_str!("wsh(and_v(vc:pk_k({}),after(1000)))", pks[0]);
+        let stack = Stack(vec![StackElement::Push(&der_sigs[0])]);
+        let constraints = SatisfiedConstraints::from_descriptor(&des, stack, &vfyfn, 0, 1000);
+        assert!(constraints.collect::<Result<Vec<_>, _>>().is_ok());
+        // Nor does a timestamp satisfy a height
+        let stack = Stack(vec![StackElement::Push(&der_sigs[0])]);
+        let height = 500_001_000;
+        let constraints = SatisfiedConstraints::from_descriptor(&des, stack, &vfyfn, 0, height);
+        assert_eq!(
+            constraints.collect::<Result<Vec<_>, _>>(),
+            Err(Error::AbsoluteLocktimeNotMet(1000))
+        );
+    }
 }
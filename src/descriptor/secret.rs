@@ -0,0 +1,505 @@
+//! # Secret Descriptor Keys
+//!
+//! Descriptors as held by hot wallets contain xprvs in place of xpubs, or
+//! WIF private keys in place of single public keys. Such
+//! descriptors are parsed into a descriptor over the corresponding public
+//! keys plus a `KeyMap` from each public key to its secret, so that
+//! everything but signing works on the public descriptor alone.
+//!
+//! With the `zeroize` feature enabled, secret keys are wiped from memory
+//! when dropped.
+//!
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+#[cfg(feature = "zeroize")]
+use std::slice;
+use std::str::FromStr;
+
+use bitcoin::hashes::hash160;
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::{PrivateKey, PublicKey};
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+use descriptor::{fmt_derivation_path, fmt_key_origin, fmt_wildcard};
+use descriptor::{parse_key_origin, parse_xkey_deriv};
+use descriptor::{
+    DerivationError, Descriptor, DescriptorCache, DescriptorKeyParseError, DescriptorPublicKey,
+    DescriptorXPub,
+};
+use {Error, ToPublicKey, Translator};
+
+/// Map from the public keys of a descriptor to the secret keys they were
+/// created from
+pub type KeyMap = HashMap<DescriptorPublicKey, DescriptorSecretKey>;
+
+/// A secret key in a descriptor
+#[derive(Clone, PartialEq, Eq)]
+pub enum DescriptorSecretKey {
+    /// A single private key, serialized as WIF
+    PrivKey(PrivateKey),
+    /// An extended private key with derivation path
+    XPrv(DescriptorXPrv),
+}
+
+impl Drop for DescriptorSecretKey {
+    fn drop(&mut self) {
+        if let DescriptorSecretKey::PrivKey(ref mut sk) = *self {
+            zeroize_private_key(sk);
+        }
+    }
+}
+
+/// An xprv in a descriptor, e.g. `[d34db33f/44'/0'/0']xprv.../1/*`
+#[derive(Clone, PartialEq, Eq)]
+pub struct DescriptorXPrv {
+    /// Fingerprint of the master key and path from it to `xprv`, if known
+    pub origin: Option<([u8; 4], DerivationPath)>,
+    /// The extended private key
+    pub xprv: ExtendedPrivKey,
+    /// Path from `xprv` to the actual key, not including the wildcard. Unlike
+    /// for xpubs, this may contain hardened steps.
+    pub derivation_path: DerivationPath,
+    /// Whether the path ends in a wildcard (`/*`)
+    pub is_wildcard: bool,
+    /// Whether the wildcard step is hardened
+    pub hardened_wildcard: bool,
+}
+
+impl Drop for DescriptorXPrv {
+    fn drop(&mut self) {
+        zeroize_xprv(&mut self.xprv);
+    }
+}
+
+impl Display for DescriptorSecretKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            DescriptorSecretKey::PrivKey(ref sk) => sk.fmt(f),
+            DescriptorSecretKey::XPrv(ref xprv) => {
+                fmt_key_origin(f, &xprv.origin)?;
+                xprv.xprv.fmt(f)?;
+                fmt_derivation_path(f, &xprv.derivation_path)?;
+                fmt_wildcard(f, xprv.is_wildcard, xprv.hardened_wildcard)
+            }
+        }
+    }
+}
+
+/// Secrets are never printed, use `Display` to serialize the key
+impl fmt::Debug for DescriptorSecretKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            DescriptorSecretKey::PrivKey(_) => f.write_str("PrivKey(<redacted>)"),
+            DescriptorSecretKey::XPrv(ref xprv) => {
+                f.write_str("XPrv(")?;
+                fmt_key_origin(f, &xprv.origin)?;
+                f.write_str("<redacted>")?;
+                fmt_derivation_path(f, &xprv.derivation_path)?;
+                fmt_wildcard(f, xprv.is_wildcard, xprv.hardened_wildcard)?;
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl FromStr for DescriptorSecretKey {
+    type Err = DescriptorKeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (origin, key_deriv) = parse_key_origin(s)?;
+        if origin.is_none() && !s.contains('/') {
+            if let Ok(sk) = PrivateKey::from_wif(s) {
+                return Ok(DescriptorSecretKey::PrivKey(sk));
+            }
+        }
+
+        let (xprv_str, derivation_path, is_wildcard, hardened_wildcard) =
+            parse_xkey_deriv(key_deriv)?;
+        let xprv = ExtendedPrivKey::from_str(xprv_str)
+            .map_err(|_| DescriptorKeyParseError("Error while parsing xprv."))?;
+        Ok(DescriptorSecretKey::XPrv(DescriptorXPrv {
+            origin: origin,
+            xprv: xprv,
+            derivation_path: derivation_path,
+            is_wildcard: is_wildcard,
+            hardened_wildcard: hardened_wildcard,
+        }))
+    }
+}
+
+impl DescriptorSecretKey {
+    /// Computes the public key for this secret key. For xprvs, hardened
+    /// steps of the derivation path can't be expressed after an xpub, so the xprv is
+    /// derived up to the last hardened step and the path to there becomes
+    /// part of the public key's origin.
+    pub fn to_public<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<DescriptorPublicKey, DescriptorKeyParseError> {
+        match *self {
+            DescriptorSecretKey::PrivKey(ref sk) => {
+                Ok(DescriptorPublicKey::PubKey(sk.public_key(secp)))
+            }
+            DescriptorSecretKey::XPrv(ref xprv) => {
+                let path: Vec<ChildNumber> = (&xprv.derivation_path).into_iter().cloned().collect();
+                let split = path
+                    .iter()
+                    .rposition(|c| !c.is_normal())
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                let (hardened, normal) = path.split_at(split);
+
+                let (xpub, origin) = if hardened.is_empty() {
+                    (
+                        ExtendedPubKey::from_private(secp, &xprv.xprv),
+                        xprv.origin.clone(),
+                    )
+                } else {
+                    let mut derived = xprv.xprv.derive_priv(secp, &hardened).map_err(|_| {
+                        DescriptorKeyParseError("Error while deriving xpub from xprv")
+                    })?;
+                    let xpub = ExtendedPubKey::from_private(secp, &derived);
+                    zeroize_xprv(&mut derived);
+
+                    let origin = match xprv.origin {
+                        Some((fingerprint, ref origin_path)) => (
+                            fingerprint,
+                            origin_path.into_iter().chain(hardened).cloned().collect(),
+                        ),
+                        None => {
+                            let mut fingerprint = [0; 4];
+                            fingerprint.copy_from_slice(&xprv.xprv.fingerprint(secp)[..]);
+                            (fingerprint, hardened.to_vec().into())
+                        }
+                    };
+                    (xpub, Some(origin))
+                };
+
+                Ok(DescriptorPublicKey::XPub(DescriptorXPub {
+                    origin: origin,
+                    xpub: xpub,
+                    derivation_path: normal.to_vec().into(),
+                    is_wildcard: xprv.is_wildcard,
+                    hardened_wildcard: xprv.hardened_wildcard,
+                }))
+            }
+        }
+    }
+
+    /// Derives the public key at `index` of the wildcard, which may be
+    /// hardened; keys without wildcard ignore `index`
+    fn derive_public_key<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<PublicKey, DerivationError> {
+        let mut sk = self.derive_private_key(secp, index)?;
+        let pk = sk.public_key(secp);
+        zeroize_private_key(&mut sk);
+        Ok(pk)
+    }
+
+    /// Signs `msg` with the key at `index` of the wildcard, which may be
+    /// hardened; keys without wildcard ignore `index`. Returns the public
+    /// key belonging to the signature alongside it.
+    pub fn sign<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        msg: &secp256k1::Message,
+    ) -> Result<(PublicKey, secp256k1::Signature), DerivationError> {
+        let mut sk = self.derive_private_key(secp, index)?;
+        let pk = sk.public_key(secp);
+        let sig = secp.sign(msg, &sk.key);
+        zeroize_private_key(&mut sk);
+        Ok((pk, sig))
+    }
+
+    /// Derives the private key at `index` of the wildcard. The caller has
+    /// to wipe the returned key.
+    fn derive_private_key<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<PrivateKey, DerivationError> {
+        match *self {
+            DescriptorSecretKey::PrivKey(ref sk) => Ok(*sk),
+            DescriptorSecretKey::XPrv(ref xprv) => {
+                let mut path: Vec<ChildNumber> =
+                    (&xprv.derivation_path).into_iter().cloned().collect();
+                if xprv.is_wildcard {
+                    let child = if xprv.hardened_wildcard {
+                        ChildNumber::from_hardened_idx(index)
+                    } else {
+                        ChildNumber::from_normal_idx(index)
+                    };
+                    let child = child
+                        .map_err(|_| DerivationError::HardenedChild(ChildNumber::from(index)))?;
+                    path.push(child);
+                }
+                let mut derived = xprv
+                    .xprv
+                    .derive_priv(secp, &path)
+                    .expect("only fails for invalid child keys, which are unreachable");
+                let sk = derived.private_key;
+                zeroize_xprv(&mut derived);
+                Ok(sk)
+            }
+        }
+    }
+}
+
+/// Parses the keys of a `Descriptor<String>`, collecting the secret ones
+/// into a key map
+struct KeyParser<'a, C: 'a + secp256k1::Signing> {
+    secp: &'a Secp256k1<C>,
+    key_map: KeyMap,
+}
+
+impl<'a, C: secp256k1::Signing> Translator<String, DescriptorPublicKey, Error>
+    for KeyParser<'a, C>
+{
+    fn pk(&mut self, key: &String) -> Result<DescriptorPublicKey, Error> {
+        if let Ok(sk) = DescriptorSecretKey::from_str(key) {
+            let pk = sk
+                .to_public(self.secp)
+                .map_err(|e| Error::Unexpected(e.to_string()))?;
+            self.key_map.insert(pk.clone(), sk);
+            Ok(pk)
+        } else {
+            DescriptorPublicKey::from_str(key).map_err(|e| Error::Unexpected(e.to_string()))
+        }
+    }
+
+    fn pkh(&mut self, pkh: &String) -> Result<hash160::Hash, Error> {
+        hash160::Hash::from_str(pkh).map_err(|e| Error::Unexpected(e.to_string()))
+    }
+}
+
+impl Descriptor<DescriptorPublicKey> {
+    /// Parses a descriptor which may contain secret keys, returning the
+    /// descriptor with every secret key replaced by its public key, see
+    /// `DescriptorSecretKey::to_public`, and the map back to the secrets
+    pub fn parse_descriptor<C: secp256k1::Signing>(
+        secp: &Secp256k1<C>,
+        s: &str,
+    ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+        let desc = Descriptor::<String>::from_str(s)?;
+        let mut parser = KeyParser {
+            secp: secp,
+            key_map: KeyMap::new(),
+        };
+        let desc = desc.translate_pk(&mut parser)?;
+        Ok((desc, parser.key_map))
+    }
+
+    /// Serializes the descriptor with every key found in `key_map` replaced
+    /// by its secret key. The result parses back into the same descriptor
+    /// and key map using `parse_descriptor`.
+    pub fn to_string_with_secret(&self, key_map: &KeyMap) -> String {
+        self.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| {
+                Result::<_, ()>::Ok(match key_map.get(pk) {
+                    Some(sk) => sk.to_string(),
+                    None => pk.to_string(),
+                })
+            },
+            |pkh: &hash160::Hash| Ok(pkh.to_string()),
+        ))
+        .expect("Translation fn can't fail.")
+        .to_string()
+    }
+
+    /// Like `derive_index`, but keys with a hardened wildcard are derived
+    /// using their secret key from `key_map`
+    pub fn derive_index_with_secrets<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        key_map: &KeyMap,
+        index: u32,
+    ) -> Result<Descriptor<PublicKey>, DerivationError> {
+        let child = ChildNumber::from_normal_idx(index)
+            .map_err(|_| DerivationError::HardenedChild(ChildNumber::from(index)))?;
+        self.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| match (pk, key_map.get(pk)) {
+                (&DescriptorPublicKey::XPub(ref xpub), Some(sk)) if xpub.hardened_wildcard => {
+                    sk.derive_public_key(secp, index)
+                }
+                _ => pk.try_derive(&[child]).map(|pk| pk.to_public_key()),
+            },
+            |pkh: &hash160::Hash| Ok(*pkh),
+        ))
+    }
+
+    /// Like `derive_index_with_secrets`, but keys without a hardened
+    /// wildcard are taken from `cache` where possible, see
+    /// `derive_index_cached`
+    pub fn derive_index_with_secrets_cached<C>(
+        &self,
+        secp: &Secp256k1<C>,
+        key_map: &KeyMap,
+        index: u32,
+        cache: &mut DescriptorCache,
+    ) -> Result<Descriptor<PublicKey>, DerivationError>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        ChildNumber::from_normal_idx(index)
+            .map_err(|_| DerivationError::HardenedChild(ChildNumber::from(index)))?;
+        self.translate_pk(&mut (
+            |pk: &DescriptorPublicKey| match (pk, key_map.get(pk)) {
+                (&DescriptorPublicKey::XPub(ref xpub), Some(sk)) if xpub.hardened_wildcard => {
+                    sk.derive_public_key(secp, index)
+                }
+                _ => cache.derive_key(secp, pk, index),
+            },
+            |pkh: &hash160::Hash| Ok(*pkh),
+        ))
+    }
+}
+
+/// Wipes the secret key and chain code of an xprv
+#[cfg(feature = "zeroize")]
+pub fn zeroize_xprv(xprv: &mut ExtendedPrivKey) {
+    zeroize_private_key(&mut xprv.private_key);
+    // Newtype around `[u8; 32]` which offers no mutable access to its
+    // contents, see `zeroize_private_key`
+    unsafe {
+        slice::from_raw_parts_mut(xprv.chain_code.as_mut_ptr(), 32).zeroize();
+    }
+}
+
+/// Wipes the secret key and chain code of an xprv
+#[cfg(not(feature = "zeroize"))]
+pub fn zeroize_xprv(_: &mut ExtendedPrivKey) {}
+
+/// Wipes a private key
+#[cfg(feature = "zeroize")]
+pub fn zeroize_private_key(sk: &mut PrivateKey) {
+    // `SecretKey` is a newtype around `[u8; 32]` which offers no mutable
+    // access to its contents; the key is never used again so leaving it
+    // invalid is fine. Sound because `as_mut_ptr` points to the start of the
+    // array, which is 32 bytes long and borrowed mutably for the duration,
+    // whereas casting the newtype itself would rely on its unspecified
+    // layout.
+    unsafe {
+        slice::from_raw_parts_mut(sk.key.as_mut_ptr(), 32).zeroize();
+    }
+}
+
+/// Wipes a private key
+#[cfg(not(feature = "zeroize"))]
+pub fn zeroize_private_key(_: &mut PrivateKey) {}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1;
+    use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+    use bitcoin::{Network, PrivateKey, PublicKey};
+    use std::str::FromStr;
+
+    use descriptor::{DerivationError, Descriptor, DescriptorCache, DescriptorPublicKey};
+
+    #[test]
+    fn parse_secret_descriptor() {
+        let secp = secp256k1::Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::Bitcoin, &[0xcd; 32]).unwrap();
+        let other = ExtendedPubKey::from_private(
+            &secp,
+            &ExtendedPrivKey::new_master(Network::Bitcoin, &[0xce; 32]).unwrap(),
+        );
+
+        let desc_str = format!("wsh(multi(1,{}/84'/0'/0'/0/*,{}/0/*))", master, other);
+        let (desc, key_map) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &desc_str).unwrap();
+        assert_eq!(key_map.len(), 1);
+        assert_eq!(desc.to_string_with_secret(&key_map), desc_str);
+
+        // The hardened part of the path moved into the origin
+        let account = master
+            .derive_priv(&secp, &DerivationPath::from_str("m/84'/0'/0'").unwrap())
+            .unwrap();
+        let expected = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(1,[{}/84'/0'/0']{}/0/*,{}/0/*))",
+            master.fingerprint(&secp),
+            ExtendedPubKey::from_private(&secp, &account),
+            other,
+        ))
+        .unwrap();
+        assert_eq!(desc, expected);
+
+        // Secrets don't show up in debug output
+        let debug = format!("{:?}", key_map);
+        assert!(!debug.contains(&master.to_string()));
+        assert!(debug.contains("<redacted>/84'/0'/0'/0/*"));
+    }
+
+    #[test]
+    fn hardened_wildcard_from_secret() {
+        let secp = secp256k1::Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::Bitcoin, &[0xcd; 32]).unwrap();
+        let desc_str = format!("wpkh({}/1/*')", master);
+        let (desc, key_map) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &desc_str).unwrap();
+
+        match desc.derive_index(3) {
+            Err(DerivationError::HardenedWildcard(..)) => {}
+            _ => panic!("hardened wildcard can't be derived from xpub"),
+        }
+
+        let child = master
+            .derive_priv(
+                &secp,
+                &[
+                    ChildNumber::from_normal_idx(1).unwrap(),
+                    ChildNumber::from_hardened_idx(3).unwrap(),
+                ],
+            )
+            .unwrap();
+        let expected = Descriptor::Wpkh(PublicKey::from_private_key(&secp, &child.private_key));
+        assert_eq!(
+            desc.derive_index_with_secrets(&secp, &key_map, 3).unwrap(),
+            expected
+        );
+        let mut cache = DescriptorCache::new();
+        assert_eq!(
+            desc.derive_index_with_secrets_cached(&secp, &key_map, 3, &mut cache)
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn wif_descriptor() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = PrivateKey {
+            compressed: true,
+            network: Network::Bitcoin,
+            key: secp256k1::SecretKey::from_slice(&[0x01; 32]).unwrap(),
+        };
+        let pk = sk.public_key(&secp);
+
+        let desc_str = format!("sh(wpkh({}))", sk.to_wif());
+        let (desc, key_map) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &desc_str).unwrap();
+        assert_eq!(desc.to_string(), format!("sh(wpkh({}))", pk));
+        assert_eq!(desc.to_string_with_secret(&key_map), desc_str);
+        assert_eq!(desc.derive_index(0).unwrap(), Descriptor::ShWpkh(pk));
+        assert_eq!(
+            format!("{:?}", key_map.values().next().unwrap()),
+            "PrivKey(<redacted>)"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "zeroize")]
+    fn zeroize_wipes_secrets() {
+        let mut xprv = ExtendedPrivKey::new_master(Network::Bitcoin, &[0xcd; 32]).unwrap();
+        super::zeroize_xprv(&mut xprv);
+        assert_eq!(xprv.private_key.key[..], [0; 32]);
+        assert_eq!(xprv.chain_code[..], [0; 32]);
+    }
+}
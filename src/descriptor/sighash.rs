@@ -0,0 +1,181 @@
+//! # Signature Hashes
+//!
+//! Computation of the message a signature spending a descriptor commits to.
+//! Which algorithm applies depends on the kind of output being spent:
+//! legacy outputs use the original `OP_CHECKSIG` hashing, segwit outputs
+//! (including P2SH-wrapped ones) the BIP143 one, which additionally commits
+//! to the spent amount. Picking the wrong one yields signatures which look
+//! fine but are rejected by the network.
+//!
+
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::secp256k1;
+use bitcoin::{self, Script};
+
+use descriptor::Descriptor;
+use {Error, MiniscriptKey, ToPublicKey};
+
+impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
+    /// Computes the signature hash of input `index` of `tx`, which spends an
+    /// output of this descriptor worth `value` satoshis, under `sighash_type`.
+    /// Legacy descriptors use the legacy algorithm, ignoring `value`; segwit
+    /// descriptors use BIP143.
+    ///
+    /// Errors for `Addr` and `Raw` descriptors, whose scriptCode is unknown,
+    /// and if `tx` has no input `index`.
+    pub fn sighash(
+        &self,
+        tx: &bitcoin::Transaction,
+        index: usize,
+        value: u64,
+        sighash_type: bitcoin::SigHashType,
+    ) -> Result<sha256d::Hash, Error> {
+        if index >= tx.input.len() {
+            return Err(Error::InputIndexOutOfRange(index));
+        }
+        let script_code = self.script_code()?;
+        if self.desc_type().is_witness() {
+            Ok(segwit_sighash(tx, index, &script_code, value, sighash_type))
+        } else {
+            Ok(tx.signature_hash(index, &script_code, sighash_type.as_u32()))
+        }
+    }
+
+    /// Like `sighash`, but returns the message to be passed to the signing
+    /// functions of `secp256k1`
+    pub fn sighash_message(
+        &self,
+        tx: &bitcoin::Transaction,
+        index: usize,
+        value: u64,
+        sighash_type: bitcoin::SigHashType,
+    ) -> Result<secp256k1::Message, Error> {
+        let sighash = self.sighash(tx, index, value, sighash_type)?;
+        Ok(secp256k1::Message::from_slice(&sighash[..]).expect("32-byte hash"))
+    }
+}
+
+/// Computes the BIP143 signature hash of input `index` of `tx`
+fn segwit_sighash(
+    tx: &bitcoin::Transaction,
+    index: usize,
+    script_code: &Script,
+    value: u64,
+    sighash_type: bitcoin::SigHashType,
+) -> sha256d::Hash {
+    let flag = sighash_type.as_u32();
+    let anyone_can_pay = flag & 0x80 != 0;
+    let (none, single) = (flag & 0x1f == 2, flag & 0x1f == 3);
+
+    let mut hash_prevouts = sha256d::Hash::from_inner([0; 32]);
+    let mut hash_sequence = sha256d::Hash::from_inner([0; 32]);
+    let mut hash_outputs = sha256d::Hash::from_inner([0; 32]);
+    if !anyone_can_pay {
+        let mut engine = sha256d::Hash::engine();
+        for txin in &tx.input {
+            txin.previous_output.consensus_encode(&mut engine).unwrap();
+        }
+        hash_prevouts = sha256d::Hash::from_engine(engine);
+    }
+    if !anyone_can_pay && !none && !single {
+        let mut engine = sha256d::Hash::engine();
+        for txin in &tx.input {
+            txin.sequence.consensus_encode(&mut engine).unwrap();
+        }
+        hash_sequence = sha256d::Hash::from_engine(engine);
+    }
+    if !none && !single {
+        let mut engine = sha256d::Hash::engine();
+        for txout in &tx.output {
+            txout.consensus_encode(&mut engine).unwrap();
+        }
+        hash_outputs = sha256d::Hash::from_engine(engine);
+    } else if single && index < tx.output.len() {
+        let mut engine = sha256d::Hash::engine();
+        tx.output[index].consensus_encode(&mut engine).unwrap();
+        hash_outputs = sha256d::Hash::from_engine(engine);
+    }
+
+    let txin = &tx.input[index];
+    let mut engine = sha256d::Hash::engine();
+    tx.version.consensus_encode(&mut engine).unwrap();
+    engine.input(&hash_prevouts[..]);
+    engine.input(&hash_sequence[..]);
+    txin.previous_output.consensus_encode(&mut engine).unwrap();
+    script_code.consensus_encode(&mut engine).unwrap();
+    value.consensus_encode(&mut engine).unwrap();
+    txin.sequence.consensus_encode(&mut engine).unwrap();
+    engine.input(&hash_outputs[..]);
+    tx.lock_time.consensus_encode(&mut engine).unwrap();
+    flag.consensus_encode(&mut engine).unwrap();
+    sha256d::Hash::from_engine(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::util::bip143::SighashComponents;
+    use bitcoin::{OutPoint, Transaction, TxIn, TxOut};
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn legacy_and_segwit_sighash() {
+        let pk = bitcoin::PublicKey::from_str(
+            "020000000000000000000000000000000000000000000000000000000000000002",
+        )
+        .unwrap();
+        let txin = TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::new(),
+            sequence: 0xfffffffe,
+            witness: vec![],
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![TxOut {
+                value: 5_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let all = bitcoin::SigHashType::All;
+
+        let pkh = Descriptor::Pkh(pk);
+        assert_eq!(
+            pkh.sighash(&tx, 1, 10_000, all).unwrap(),
+            tx.signature_hash(1, &pkh.script_pubkey(), all.as_u32())
+        );
+
+        // The scriptCode of both is the P2PKH script
+        let bip143 =
+            SighashComponents::new(&tx).sighash_all(&tx.input[1], &pkh.script_pubkey(), 10_000);
+        assert_eq!(
+            Descriptor::Wpkh(pk).sighash(&tx, 1, 10_000, all).unwrap(),
+            bip143
+        );
+        assert_eq!(
+            Descriptor::ShWpkh(pk).sighash(&tx, 1, 10_000, all).unwrap(),
+            bip143
+        );
+        assert_eq!(
+            Descriptor::Wpkh(pk)
+                .sighash_message(&tx, 1, 10_000, all)
+                .unwrap(),
+            secp256k1::Message::from_slice(&bip143[..]).unwrap()
+        );
+        // Unlike legacy ones, segwit sighashes commit to the amount
+        assert!(Descriptor::Wpkh(pk).sighash(&tx, 1, 9_999, all).unwrap() != bip143);
+
+        match Descriptor::Wpkh(pk).sighash(&tx, 2, 10_000, all) {
+            Err(Error::InputIndexOutOfRange(2)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        match Descriptor::<bitcoin::PublicKey>::Raw(Script::new()).sighash(&tx, 0, 10_000, all) {
+            Err(Error::OpaqueDescriptor) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! # Signer
+//!
+//! Signing of the inputs of a transaction which spend outputs of a
+//! descriptor, for the common case of the secret keys being at hand. The
+//! signatures are returned per input as a `HashMap`, which implements
+//! `Satisfier` and can hence be passed straight to `Descriptor::satisfy`.
+//!
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::{self, PublicKey};
+
+use descriptor::{
+    DerivationError, Descriptor, DescriptorCache, DescriptorKeyParseError, DescriptorPublicKey,
+    DescriptorSecretKey, KeyMap,
+};
+use {BitcoinSig, Error, ForEach};
+
+/// Signs transactions with a set of secret keys and xprvs
+#[derive(Clone, Debug)]
+pub struct Signer {
+    key_map: KeyMap,
+    sighash_type: bitcoin::SigHashType,
+}
+
+/// The signatures for an input spending an output of the descriptor
+#[derive(Clone, Debug)]
+pub struct SignedInput {
+    /// The derivation index of the spent output
+    pub index: u32,
+    /// The descriptor at `index`, to be satisfied using `signatures`
+    pub descriptor: Descriptor<PublicKey>,
+    /// The signatures made by the signer's keys
+    pub signatures: HashMap<PublicKey, BitcoinSig>,
+}
+
+impl Signer {
+    /// Creates a signer without any keys, signing with `SIGHASH_ALL`
+    pub fn new() -> Signer {
+        Signer::from_key_map(KeyMap::new())
+    }
+
+    /// Creates a signer with the secret keys of a descriptor, as returned by
+    /// `Descriptor::parse_descriptor`
+    pub fn from_key_map(key_map: KeyMap) -> Signer {
+        Signer {
+            key_map: key_map,
+            sighash_type: bitcoin::SigHashType::All,
+        }
+    }
+
+    /// Adds a secret key or xprv to sign with
+    pub fn add_key<C: secp256k1::Signing>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        sk: DescriptorSecretKey,
+    ) -> Result<(), DescriptorKeyParseError> {
+        let pk = sk.to_public(secp)?;
+        self.key_map.insert(pk, sk);
+        Ok(())
+    }
+
+    /// Sets the sighash flag to sign with, `SIGHASH_ALL` by default
+    pub fn set_sighash_type(&mut self, sighash_type: bitcoin::SigHashType) {
+        self.sighash_type = sighash_type;
+    }
+
+    /// Signs every input of `tx` which spends an output of `descriptor` at
+    /// one of the derivation indices in `range`, with every key of the
+    /// signer appearing in the descriptor at that index. `prevouts` are the
+    /// outputs spent by the inputs of `tx`, in the same order.
+    ///
+    /// Returns, for each input, the signatures made for it, or `None` if
+    /// the input does not spend an output of the descriptor. Errors if
+    /// `prevouts` does not have one output per input.
+    pub fn sign<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+        tx: &bitcoin::Transaction,
+        prevouts: &[bitcoin::TxOut],
+        range: Range<u32>,
+    ) -> Result<Vec<Option<SignedInput>>, Error> {
+        self.sign_with(secp, tx, prevouts, range, |index| {
+            descriptor.derive_index_with_secrets(secp, &self.key_map, index)
+        })
+    }
+
+    /// Like `sign`, but takes derived keys from `cache` where possible and
+    /// adds the ones it has to derive, see `Descriptor::derive_index_cached`
+    pub fn sign_cached<C>(
+        &self,
+        secp: &Secp256k1<C>,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+        tx: &bitcoin::Transaction,
+        prevouts: &[bitcoin::TxOut],
+        range: Range<u32>,
+        cache: &mut DescriptorCache,
+    ) -> Result<Vec<Option<SignedInput>>, Error>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        self.sign_with(secp, tx, prevouts, range, |index| {
+            descriptor.derive_index_with_secrets_cached(secp, &self.key_map, index, cache)
+        })
+    }
+
+    /// Helper function for `sign` and `sign_cached`, deriving the descriptor
+    /// at each index with `derive`
+    fn sign_with<C, F>(
+        &self,
+        secp: &Secp256k1<C>,
+        tx: &bitcoin::Transaction,
+        prevouts: &[bitcoin::TxOut],
+        range: Range<u32>,
+        mut derive: F,
+    ) -> Result<Vec<Option<SignedInput>>, Error>
+    where
+        C: secp256k1::Signing,
+        F: FnMut(u32) -> Result<Descriptor<PublicKey>, DerivationError>,
+    {
+        if prevouts.len() != tx.input.len() {
+            return Err(Error::Unexpected(format!(
+                "{} prevouts given for {} inputs",
+                prevouts.len(),
+                tx.input.len()
+            )));
+        }
+
+        // Derive the descriptor at each index at most once, however many
+        // inputs spend from it
+        let mut derived: HashMap<u32, Descriptor<PublicKey>> = HashMap::new();
+        let mut ret = Vec::with_capacity(tx.input.len());
+        for (n, prevout) in prevouts.iter().enumerate() {
+            let mut found = None;
+            for index in range.clone() {
+                if !derived.contains_key(&index) {
+                    match derive(index) {
+                        Ok(desc) => derived.insert(index, desc),
+                        Err(_) => continue,
+                    };
+                }
+                if derived[&index].script_pubkey() == prevout.script_pubkey {
+                    found = Some(index);
+                    break;
+                }
+            }
+            let index = match found {
+                Some(index) => index,
+                None => {
+                    ret.push(None);
+                    continue;
+                }
+            };
+
+            let desc = derived[&index].clone();
+            let msg = desc.sighash_message(tx, n, prevout.value, self.sighash_type)?;
+            let mut signatures = HashMap::new();
+            for sk in self.key_map.values() {
+                let (pk, sig) = match sk.sign(secp, index, &msg) {
+                    Ok(signed) => signed,
+                    Err(_) => continue,
+                };
+                if desc.iter_pk().any(|key| key == ForEach::Key(&pk)) {
+                    signatures.insert(pk, (sig, self.sighash_type));
+                }
+            }
+            ret.push(Some(SignedInput {
+                index: index,
+                descriptor: desc,
+                signatures: signatures,
+            }));
+        }
+        Ok(ret)
+    }
+}
+
+impl Default for Signer {
+    fn default() -> Signer {
+        Signer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::util::bip32::ExtendedPrivKey;
+    use bitcoin::{OutPoint, PrivateKey, Script, Transaction, TxIn, TxOut};
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn sign_transaction() {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &[0xcd; 32]).unwrap();
+        let single = PrivateKey {
+            compressed: true,
+            network: bitcoin::Network::Bitcoin,
+            key: secp256k1::SecretKey::from_slice(&[0x01; 32]).unwrap(),
+        };
+        let xprv = DescriptorSecretKey::from_str(&format!("{}/0/*", master)).unwrap();
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(2,{},{}))",
+            xprv.to_public(&secp).unwrap(),
+            single.public_key(&secp),
+        ))
+        .unwrap();
+
+        let txin = TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![],
+        };
+        let prevouts = vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: Script::new(),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: desc.derive_index(3).unwrap().script_pubkey(),
+            },
+        ];
+
+        let mut signer = Signer::new();
+        signer.add_key(&secp, xprv).unwrap();
+        signer
+            .add_key(&secp, DescriptorSecretKey::PrivKey(single))
+            .unwrap();
+        assert!(signer
+            .sign(&secp, &desc, &tx, &prevouts[..1], 0..10)
+            .is_err());
+
+        let signed = signer.sign(&secp, &desc, &tx, &prevouts, 0..10).unwrap();
+        assert!(signed[0].is_none());
+        let signed = signed[1].as_ref().unwrap();
+        assert_eq!(signed.index, 3);
+        assert_eq!(signed.signatures.len(), 2);
+
+        let msg = signed
+            .descriptor
+            .sighash_message(&tx, 1, 20_000, bitcoin::SigHashType::All)
+            .unwrap();
+        for (pk, &(ref sig, _)) in &signed.signatures {
+            assert!(secp.verify(&msg, sig, &pk.key).is_ok());
+        }
+        signed
+            .descriptor
+            .satisfy(&mut tx.input[1], &signed.signatures)
+            .unwrap();
+        assert_eq!(tx.input[1].witness.len(), 4);
+
+        // The spent output is not in the range searched
+        assert!(signer.sign(&secp, &desc, &tx, &prevouts, 0..3).unwrap()[1].is_none());
+
+        let mut cache = DescriptorCache::new();
+        let signed = signer
+            .sign_cached(&secp, &desc, &tx, &prevouts, 0..10, &mut cache)
+            .unwrap();
+        assert!(signed[0].is_none());
+        assert_eq!(signed[1].as_ref().unwrap().index, 3);
+        assert_eq!(signed[1].as_ref().unwrap().signatures.len(), 2);
+    }
+}
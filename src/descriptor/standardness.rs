@@ -0,0 +1,435 @@
+//! # Relay Standardness
+//!
+//! Checks whether a descriptor, or a satisfaction of it, would be accepted
+//! into the mempool of a Bitcoin Core node with default settings. Being
+//! standard is strictly stronger than being consensus-valid: a script that
+//! fails these checks can still be mined, but will not propagate over the
+//! p2p network.
+//!
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::{self, Script};
+use std::{error, fmt};
+
+use descriptor::Descriptor;
+use {MiniscriptKey, Terminal, ToPublicKey};
+
+/// Maximum number of keys in a standard bare `CHECKMULTISIG` output
+pub const MAX_STANDARD_BARE_MULTISIG_KEYS: usize = 3;
+/// Maximum size of a P2SH redeem script; this is the consensus limit on
+/// any single stack push
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Maximum number of signature operations in a standard P2SH redeem script
+pub const MAX_P2SH_SIGOPS: usize = 15;
+/// Maximum size of a standard scriptSig
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+/// Maximum size of a standard P2WSH witness script
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+/// Maximum number of witness stack items, not counting the witness
+/// script, in a standard P2WSH spend
+pub const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+/// Maximum size of each witness stack item, not counting the witness
+/// script, in a standard P2WSH spend
+pub const MAX_STANDARD_P2WSH_STACK_ITEM_SIZE: usize = 80;
+/// Maximum sigop cost of a standard transaction; a single input whose
+/// script exceeds this on its own can never be relayed
+pub const MAX_STANDARD_TX_SIGOPS_COST: usize = 16000;
+/// Feerate, in satoshis per 1000 virtual bytes, below which the cost of
+/// spending an output makes it dust
+pub const DUST_RELAY_TX_FEE: u64 = 3000;
+/// Satisfaction size Bitcoin Core assumes for outputs whose spending
+/// conditions are unknown; a signature and a compressed key with pushes
+const DUST_SATISFACTION_SIZE: usize = 107;
+
+/// Reasons a descriptor or satisfaction may fail relay-standardness checks
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StandardnessError {
+    /// Bare output scripts must match one of the standard templates
+    /// (P2PK, P2PKH or a `CHECKMULTISIG` of at most 3 keys)
+    NonStandardBareScript,
+    /// P2SH redeem script is larger than 520 bytes
+    RedeemScriptTooLarge(usize),
+    /// P2SH redeem script contains more than 15 signature operations
+    TooManyP2shSigops(usize),
+    /// The script's sigop cost alone exceeds the per-transaction limit
+    TooManySigops(usize),
+    /// P2WSH witness script is larger than 3600 bytes
+    WitnessScriptTooLarge(usize),
+    /// scriptSig is larger than 1650 bytes
+    ScriptSigTooLarge(usize),
+    /// scriptSig contains something other than data pushes
+    NonPushScriptSig,
+    /// P2WSH witness has more than 100 stack items
+    TooManyWitnessItems(usize),
+    /// A P2WSH witness stack item is larger than 80 bytes
+    WitnessItemTooLarge(usize),
+}
+
+impl error::Error for StandardnessError {
+    fn description(&self) -> &str {
+        ""
+    }
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
+
+impl fmt::Display for StandardnessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StandardnessError::NonStandardBareScript => {
+                f.write_str("bare scriptpubkey does not match a standard template")
+            }
+            StandardnessError::RedeemScriptTooLarge(n) => write!(
+                f,
+                "redeem script of {} bytes exceeds {} bytes",
+                n, MAX_SCRIPT_ELEMENT_SIZE
+            ),
+            StandardnessError::TooManyP2shSigops(n) => write!(
+                f,
+                "redeem script has {} sigops, more than {}",
+                n, MAX_P2SH_SIGOPS
+            ),
+            StandardnessError::TooManySigops(n) => write!(
+                f,
+                "script has sigop cost {}, more than {}",
+                n, MAX_STANDARD_TX_SIGOPS_COST
+            ),
+            StandardnessError::WitnessScriptTooLarge(n) => write!(
+                f,
+                "witness script of {} bytes exceeds {} bytes",
+                n, MAX_STANDARD_P2WSH_SCRIPT_SIZE
+            ),
+            StandardnessError::ScriptSigTooLarge(n) => write!(
+                f,
+                "scriptsig of {} bytes exceeds {} bytes",
+                n, MAX_STANDARD_SCRIPTSIG_SIZE
+            ),
+            StandardnessError::NonPushScriptSig => {
+                f.write_str("scriptsig must only consist of pushes")
+            }
+            StandardnessError::TooManyWitnessItems(n) => write!(
+                f,
+                "witness has {} stack items, more than {}",
+                n, MAX_STANDARD_P2WSH_STACK_ITEMS
+            ),
+            StandardnessError::WitnessItemTooLarge(n) => write!(
+                f,
+                "witness stack item of {} bytes exceeds {} bytes",
+                n, MAX_STANDARD_P2WSH_STACK_ITEM_SIZE
+            ),
+        }
+    }
+}
+
+/// Counts the signature operations in a script the way Bitcoin Core does
+/// for P2SH and segwit scripts, i.e. `CHECKMULTISIG` counts as the number
+/// of keys when it is preceded by a small integer push, and as 20 otherwise.
+pub fn count_sigops(script: &Script) -> usize {
+    let pushnum_1 = opcodes::all::OP_PUSHNUM_1.into_u8();
+    let pushnum_16 = opcodes::all::OP_PUSHNUM_16.into_u8();
+
+    let mut n = 0;
+    let mut last_pushnum = None;
+    for ins in script.iter(false) {
+        match ins {
+            Instruction::Op(op) => {
+                if op == opcodes::all::OP_CHECKSIG || op == opcodes::all::OP_CHECKSIGVERIFY {
+                    n += 1;
+                } else if op == opcodes::all::OP_CHECKMULTISIG
+                    || op == opcodes::all::OP_CHECKMULTISIGVERIFY
+                {
+                    n += last_pushnum.unwrap_or(20);
+                }
+                let byte = op.into_u8();
+                last_pushnum = if byte >= pushnum_1 && byte <= pushnum_16 {
+                    Some((byte - pushnum_1 + 1) as usize)
+                } else {
+                    None
+                };
+            }
+            _ => last_pushnum = None,
+        }
+    }
+    n
+}
+
+/// Whether a scriptSig consists only of data pushes (including the small
+/// integer opcodes), as required for standard transactions
+fn is_push_only(script: &Script) -> bool {
+    let pushnum_16 = opcodes::all::OP_PUSHNUM_16.into_u8();
+    script.iter(false).all(|ins| match ins {
+        Instruction::PushBytes(..) => true,
+        Instruction::Op(op) => op.into_u8() <= pushnum_16,
+        Instruction::Error(..) => false,
+    })
+}
+
+/// Checks the size and sigop limits applying to a witness script
+fn check_witness_script(script: &Script) -> Result<(), StandardnessError> {
+    if script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+        return Err(StandardnessError::WitnessScriptTooLarge(script.len()));
+    }
+    let sigops = count_sigops(script);
+    if sigops > MAX_STANDARD_TX_SIGOPS_COST {
+        return Err(StandardnessError::TooManySigops(sigops));
+    }
+    Ok(())
+}
+
+/// Checks the size and sigop limits applying to a P2SH redeem script
+fn check_redeem_script(script: &Script) -> Result<(), StandardnessError> {
+    if script.len() > MAX_SCRIPT_ELEMENT_SIZE {
+        return Err(StandardnessError::RedeemScriptTooLarge(script.len()));
+    }
+    let sigops = count_sigops(script);
+    if sigops > MAX_P2SH_SIGOPS {
+        return Err(StandardnessError::TooManyP2shSigops(sigops));
+    }
+    Ok(())
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
+    /// Checks whether outputs using this descriptor, and spends of them, can
+    /// be relayed under Bitcoin Core's default policy. This only looks at
+    /// the scripts themselves; use `check_standard_satisfaction` to check
+    /// an actual spending input.
+    pub fn check_standard(&self) -> Result<(), StandardnessError> {
+        match *self {
+            Descriptor::Bare(ref ms) => {
+                let script = ms.encode();
+                if script.is_p2pk() || script.is_p2pkh() {
+                    return Ok(());
+                }
+                match ms.node {
+                    Terminal::Multi(_, ref keys)
+                        if keys.len() <= MAX_STANDARD_BARE_MULTISIG_KEYS =>
+                    {
+                        Ok(())
+                    }
+                    _ => Err(StandardnessError::NonStandardBareScript),
+                }
+            }
+            Descriptor::Pk(..)
+            | Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::ShWpkh(..) => Ok(()),
+            Descriptor::Sh(ref ms) => check_redeem_script(&ms.encode()),
+            Descriptor::Wsh(ref ms) | Descriptor::ShWsh(ref ms) => {
+                check_witness_script(&ms.encode())
+            }
+            // Nothing is known about how these are spent
+            Descriptor::Addr(..) | Descriptor::Raw(..) => Ok(()),
+        }
+    }
+
+    /// Whether outputs using this descriptor can be relayed under Bitcoin
+    /// Core's default policy. See `check_standard` for the reason when
+    /// they cannot.
+    pub fn is_standard(&self) -> bool {
+        self.check_standard().is_ok()
+    }
+
+    /// The smallest amount, in satoshis, an output using this descriptor may
+    /// hold without being dust under Bitcoin Core's default policy: what it
+    /// costs at `DUST_RELAY_TX_FEE` to create the output and later spend it.
+    ///
+    /// The spending input is sized from `max_satisfaction_weight` rather than
+    /// Core's fixed estimate, so descriptors that are expensive to satisfy
+    /// get a correspondingly higher limit. `addr()` and `raw()` descriptors
+    /// fall back to Core's estimate, and provably unspendable outputs (e.g.
+    /// `OP_RETURN`) are never dust.
+    pub fn dust_value(&self) -> u64 {
+        let script_pubkey = self.script_pubkey();
+        if script_pubkey.is_provably_unspendable() {
+            return 0;
+        }
+
+        let spk_len = script_pubkey.len();
+        // value, script length and script
+        let output_size = 8 + bitcoin::VarInt(spk_len as u64).len() + spk_len;
+
+        let satisfaction_weight = match *self {
+            Descriptor::Addr(..) | Descriptor::Raw(..) => {
+                if script_pubkey.is_v0_p2wpkh() || script_pubkey.is_v0_p2wsh() {
+                    4 + DUST_SATISFACTION_SIZE
+                } else {
+                    4 * (1 + DUST_SATISFACTION_SIZE)
+                }
+            }
+            _ => self
+                .max_satisfaction_weight()
+                .expect("addr() and raw() are handled above"),
+        };
+        // outpoint, sequence and the satisfaction rounded down to vbytes,
+        // as Core does
+        let input_size = 32 + 4 + 4 + satisfaction_weight / 4;
+
+        (output_size + input_size) as u64 * DUST_RELAY_TX_FEE / 1000
+    }
+
+    /// Checks whether a `TxIn` spending this descriptor, e.g. as filled in by
+    /// `satisfy`, would be relayed under Bitcoin Core's default policy. In
+    /// addition to the checks of `check_standard` this limits the size and
+    /// content of the scriptSig and the shape of the witness stack.
+    pub fn check_standard_satisfaction(
+        &self,
+        txin: &bitcoin::TxIn,
+    ) -> Result<(), StandardnessError> {
+        self.check_standard()?;
+
+        let script_sig_len = txin.script_sig.len();
+        if script_sig_len > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(StandardnessError::ScriptSigTooLarge(script_sig_len));
+        }
+        if !is_push_only(&txin.script_sig) {
+            return Err(StandardnessError::NonPushScriptSig);
+        }
+
+        match *self {
+            Descriptor::Wsh(..) | Descriptor::ShWsh(..) => {
+                // The last element is the witness script, which is checked
+                // separately by `check_standard`
+                let n_items = txin.witness.len().saturating_sub(1);
+                if n_items > MAX_STANDARD_P2WSH_STACK_ITEMS {
+                    return Err(StandardnessError::TooManyWitnessItems(n_items));
+                }
+                for item in txin.witness.iter().take(n_items) {
+                    if item.len() > MAX_STANDARD_P2WSH_STACK_ITEM_SIZE {
+                        return Err(StandardnessError::WitnessItemTooLarge(item.len()));
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{self, secp256k1};
+    use std::str::FromStr;
+
+    use super::*;
+    use Descriptor;
+
+    fn pubkeys(n: usize) -> Vec<bitcoin::PublicKey> {
+        let secp = secp256k1::Secp256k1::new();
+        let mut sk = [0; 32];
+        (1..n + 1)
+            .map(|i| {
+                sk[31] = i as u8;
+                bitcoin::PublicKey {
+                    key: secp256k1::PublicKey::from_secret_key(
+                        &secp,
+                        &secp256k1::SecretKey::from_slice(&sk[..]).expect("secret key"),
+                    ),
+                    compressed: true,
+                }
+            })
+            .collect()
+    }
+
+    fn multi(desc: &str, k: usize, keys: &[bitcoin::PublicKey]) -> Descriptor<bitcoin::PublicKey> {
+        let keys: Vec<String> = keys.iter().map(|pk| pk.to_string()).collect();
+        let s = format!("{}(multi({},{}))", desc, k, keys.join(","));
+        Descriptor::from_str(&s[..]).unwrap()
+    }
+
+    #[test]
+    fn bare_standardness() {
+        let keys = pubkeys(4);
+
+        let pk =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("c:pk_k({})", keys[0])).unwrap();
+        assert!(pk.is_standard());
+
+        let keys_3: Vec<String> = keys[..3].iter().map(|pk| pk.to_string()).collect();
+        let bare_3 =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("multi(2,{})", keys_3.join(",")))
+                .unwrap();
+        assert!(bare_3.is_standard());
+
+        let keys_4: Vec<String> = keys.iter().map(|pk| pk.to_string()).collect();
+        let bare_4 =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("multi(2,{})", keys_4.join(",")))
+                .unwrap();
+        assert_eq!(
+            bare_4.check_standard(),
+            Err(StandardnessError::NonStandardBareScript)
+        );
+
+        let timelock = Descriptor::<bitcoin::PublicKey>::from_str("older(1000)").unwrap();
+        assert!(!timelock.is_standard());
+        assert!(
+            Descriptor::<bitcoin::PublicKey>::from_str("wsh(older(1000))")
+                .unwrap()
+                .is_standard()
+        );
+    }
+
+    #[test]
+    fn sh_standardness() {
+        let keys = pubkeys(16);
+        assert!(multi("sh", 1, &keys[..15]).is_standard());
+        assert!(multi("wsh", 1, &keys[..16]).is_standard());
+        assert_eq!(
+            multi("sh", 1, &keys[..16]).check_standard(),
+            Err(StandardnessError::RedeemScriptTooLarge(547))
+        );
+        assert_eq!(
+            count_sigops(&multi("wsh", 1, &keys[..16]).witness_script()),
+            16
+        );
+    }
+
+    #[test]
+    fn dust_value() {
+        let keys = pubkeys(3);
+        let wpkh =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("wpkh({})", keys[0])).unwrap();
+        assert_eq!(wpkh.dust_value(), 294);
+
+        // Costlier to spend than a single key, and so a higher limit
+        assert!(multi("wsh", 2, &keys).dust_value() > wpkh.dust_value());
+
+        // Core's estimates for unknown spending conditions
+        let p2wpkh = Descriptor::<bitcoin::PublicKey>::Raw(wpkh.script_pubkey());
+        assert_eq!(p2wpkh.dust_value(), 294);
+        let pkh = Descriptor::<bitcoin::PublicKey>::from_str(&format!("pkh({})", keys[0])).unwrap();
+        let p2pkh = Descriptor::<bitcoin::PublicKey>::Raw(pkh.script_pubkey());
+        assert_eq!(p2pkh.dust_value(), 546);
+
+        let op_return = Descriptor::<bitcoin::PublicKey>::from_str("raw(6a00)").unwrap();
+        assert_eq!(op_return.dust_value(), 0);
+    }
+
+    #[test]
+    fn satisfaction_standardness() {
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str("wsh(older(1000))").unwrap();
+        let mut txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 1000,
+            witness: vec![desc.witness_script().into_bytes()],
+        };
+        assert_eq!(desc.check_standard_satisfaction(&txin), Ok(()));
+
+        txin.witness.insert(0, vec![0; 81]);
+        assert_eq!(
+            desc.check_standard_satisfaction(&txin),
+            Err(StandardnessError::WitnessItemTooLarge(81))
+        );
+
+        txin.witness.remove(0);
+        txin.script_sig = bitcoin::blockdata::script::Builder::new()
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        assert_eq!(
+            desc.check_standard_satisfaction(&txin),
+            Err(StandardnessError::NonPushScriptSig)
+        );
+    }
+}
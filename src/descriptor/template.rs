@@ -0,0 +1,420 @@
+//! # Satisfaction Templates
+//!
+//! The layout of the scriptSig and witness for one spend path of a
+//! descriptor, computed once and then filled in for every input spending
+//! it. Signers handling many inputs of the same descriptor can thereby skip
+//! re-walking the Miniscript AST for every input.
+//!
+
+use std::cell::RefCell;
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::{self, secp256k1};
+
+use descriptor::{witness_to_scriptsig, Descriptor};
+use miniscript::satisfy::{BitcoinSig, Satisfier};
+use policy::Semantic;
+use {Error, MiniscriptKey, ToPublicKey};
+
+/// One element of a scriptSig or witness stack in a `SatisfactionTemplate`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TemplateElement<Pk: MiniscriptKey> {
+    /// Data which is the same for every spend, e.g. scripts, public keys or
+    /// the empty pushes of dissatisfied branches
+    Fixed(Vec<u8>),
+    /// Signature for the given key, including the sighash byte
+    Signature(Pk),
+    /// Signature for the key with the given hash, including the sighash byte
+    KeyHashSignature(Pk::Hash),
+    /// The public key with the given hash
+    KeyHashPublicKey(Pk::Hash),
+    /// Preimage of a SHA256 hash
+    Sha256Preimage(sha256::Hash),
+    /// Preimage of a double-SHA256 hash
+    Hash256Preimage(sha256d::Hash),
+    /// Preimage of a RIPEMD160 hash
+    Ripemd160Preimage(ripemd160::Hash),
+    /// Preimage of a HASH160 hash
+    Hash160Preimage(hash160::Hash),
+}
+
+/// The scriptSig and witness layout of one spend path of a descriptor
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SatisfactionTemplate<Pk: MiniscriptKey> {
+    /// Elements pushed by the scriptSig, bottom of the stack first
+    pub script_sig: Vec<TemplateElement<Pk>>,
+    /// Elements of the witness stack, bottom of the stack first
+    pub witness: Vec<TemplateElement<Pk>>,
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> SatisfactionTemplate<Pk> {
+    /// Fills in the template with data from `satisfier` and writes the
+    /// result to `txin`. Timelocks are not checked, they are part of the
+    /// spend path the template was created for. On error `txin` is left
+    /// untouched.
+    pub fn satisfy<S: Satisfier<Pk>>(
+        &self,
+        txin: &mut bitcoin::TxIn,
+        satisfier: S,
+    ) -> Result<(), Error> {
+        let script_sig = fill(&self.script_sig, &satisfier)?;
+        let witness = fill(&self.witness, &satisfier)?;
+        txin.script_sig = witness_to_scriptsig(&script_sig);
+        txin.witness = witness;
+        Ok(())
+    }
+}
+
+/// Serializes a signature the way it appears on the stack
+fn sig_vec(sig: BitcoinSig) -> Vec<u8> {
+    let mut ret = sig.0.serialize_der().to_vec();
+    ret.push(sig.1.as_u32() as u8);
+    ret
+}
+
+/// Replaces every slot in `elems` with data from `satisfier`
+fn fill<Pk, S>(elems: &[TemplateElement<Pk>], satisfier: &S) -> Result<Vec<Vec<u8>>, Error>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    S: Satisfier<Pk>,
+{
+    elems
+        .iter()
+        .map(|elem| match *elem {
+            TemplateElement::Fixed(ref data) => Ok(data.clone()),
+            TemplateElement::Signature(ref pk) => satisfier
+                .lookup_sig(pk)
+                .map(sig_vec)
+                .ok_or_else(|| Error::MissingSig(pk.to_public_key())),
+            TemplateElement::KeyHashSignature(ref pkh) => satisfier
+                .lookup_pkh_sig(pkh)
+                .map(|(_, sig)| sig_vec(sig))
+                .ok_or(Error::CouldNotSatisfy),
+            TemplateElement::KeyHashPublicKey(ref pkh) => satisfier
+                .lookup_pkh_pk(pkh)
+                .map(|pk| pk.to_public_key())
+                .or_else(|| satisfier.lookup_pkh_sig(pkh).map(|(pk, _)| pk))
+                .map(|pk| pk.to_bytes())
+                .ok_or(Error::CouldNotSatisfy),
+            TemplateElement::Sha256Preimage(h) => satisfier
+                .lookup_sha256(h)
+                .map(|p| p.to_vec())
+                .ok_or(Error::CouldNotSatisfy),
+            TemplateElement::Hash256Preimage(h) => satisfier
+                .lookup_hash256(h)
+                .map(|p| p.to_vec())
+                .ok_or(Error::CouldNotSatisfy),
+            TemplateElement::Ripemd160Preimage(h) => satisfier
+                .lookup_ripemd160(h)
+                .map(|p| p.to_vec())
+                .ok_or(Error::CouldNotSatisfy),
+            TemplateElement::Hash160Preimage(h) => satisfier
+                .lookup_hash160(h)
+                .map(|p| p.to_vec())
+                .ok_or(Error::CouldNotSatisfy),
+        })
+        .collect()
+}
+
+/// Satisfier which answers lookups for exactly the given conditions with
+/// dummy data unique to each lookup, so that the elements of the resulting
+/// satisfaction can be traced back to the lookup which produced them
+struct RecordingSatisfier<'a, Pk: MiniscriptKey + 'a> {
+    conditions: &'a [Semantic<Pk>],
+    secp: secp256k1::Secp256k1<secp256k1::SignOnly>,
+    /// Keys for which a signature was looked up, by condition index
+    keys: RefCell<Vec<Option<Pk>>>,
+}
+
+/// Writes `n` big-endian into the last 8 bytes of `buf`
+fn write_index(buf: &mut [u8], n: usize) {
+    let len = buf.len();
+    for (i, byte) in buf[len - 8..].iter_mut().enumerate() {
+        *byte = ((n as u64) >> (8 * (7 - i))) as u8;
+    }
+}
+
+impl<'a, Pk: MiniscriptKey + ToPublicKey> RecordingSatisfier<'a, Pk> {
+    /// A valid signature whose `r` value encodes `n`. Both `r` and `s`
+    /// have their top bit set, so that the signature has the largest
+    /// possible DER encoding.
+    fn dummy_sig(n: usize) -> BitcoinSig {
+        let mut compact = [0; 64];
+        compact[0] = 0x80;
+        write_index(&mut compact[..32], n);
+        compact[32] = 0x80;
+        let sig = secp256k1::Signature::from_compact(&compact).expect("valid signature");
+        (sig, bitcoin::SigHashType::All)
+    }
+
+    /// A valid public key derived from a secret key encoding `n`
+    fn dummy_key(&self, n: usize) -> bitcoin::PublicKey {
+        let mut sk = [0; 32];
+        write_index(&mut sk, n + 1);
+        bitcoin::PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(
+                &self.secp,
+                &secp256k1::SecretKey::from_slice(&sk).expect("valid secret key"),
+            ),
+        }
+    }
+
+    fn dummy_preimage(n: usize) -> [u8; 32] {
+        let mut ret = [0x5a; 32];
+        write_index(&mut ret, n);
+        ret
+    }
+
+    fn position(&self, cond: &Semantic<Pk>) -> Option<usize> {
+        self.conditions.iter().position(|c| c == cond)
+    }
+
+    /// Maps every piece of dummy data this satisfier may have handed out to
+    /// the template element it stands for
+    fn element(&self, data: &[u8]) -> TemplateElement<Pk> {
+        let keys = self.keys.borrow();
+        for (i, cond) in self.conditions.iter().enumerate() {
+            let elem = match *cond {
+                Semantic::KeyHash(ref pkh) => {
+                    if data[..] == sig_vec(Self::dummy_sig(2 * i))[..] {
+                        match keys[i] {
+                            Some(ref pk) => TemplateElement::Signature(pk.clone()),
+                            None => continue,
+                        }
+                    } else if data[..] == sig_vec(Self::dummy_sig(2 * i + 1))[..] {
+                        TemplateElement::KeyHashSignature(pkh.clone())
+                    } else if data[..] == self.dummy_key(i).to_bytes()[..] {
+                        TemplateElement::KeyHashPublicKey(pkh.clone())
+                    } else {
+                        continue;
+                    }
+                }
+                Semantic::Sha256(h) if data[..] == Self::dummy_preimage(i)[..] => {
+                    TemplateElement::Sha256Preimage(h)
+                }
+                Semantic::Hash256(h) if data[..] == Self::dummy_preimage(i)[..] => {
+                    TemplateElement::Hash256Preimage(h)
+                }
+                Semantic::Ripemd160(h) if data[..] == Self::dummy_preimage(i)[..] => {
+                    TemplateElement::Ripemd160Preimage(h)
+                }
+                Semantic::Hash160(h) if data[..] == Self::dummy_preimage(i)[..] => {
+                    TemplateElement::Hash160Preimage(h)
+                }
+                _ => continue,
+            };
+            return elem;
+        }
+        TemplateElement::Fixed(data.to_vec())
+    }
+
+    fn elements(&self, data: &[Vec<u8>]) -> Vec<TemplateElement<Pk>> {
+        data.iter().map(|d| self.element(d)).collect()
+    }
+
+    fn preimage(&self, hash: Semantic<Pk>) -> Option<[u8; 32]> {
+        self.position(&hash).map(Self::dummy_preimage)
+    }
+}
+
+impl<'a, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for RecordingSatisfier<'a, Pk> {
+    fn lookup_sig(&self, pk: &Pk) -> Option<BitcoinSig> {
+        let i = self.position(&Semantic::KeyHash(pk.to_pubkeyhash()))?;
+        self.keys.borrow_mut()[i] = Some(pk.clone());
+        Some(Self::dummy_sig(2 * i))
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        let i = self.position(&Semantic::KeyHash(pkh.clone()))?;
+        Some((self.dummy_key(i), Self::dummy_sig(2 * i + 1)))
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.preimage(Semantic::Sha256(h))
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.preimage(Semantic::Hash256(h))
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.preimage(Semantic::Ripemd160(h))
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.preimage(Semantic::Hash160(h))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.conditions.contains(&Semantic::Older(n))
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.conditions.contains(&Semantic::After(n))
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
+    /// Computes the satisfaction template for the spend path using exactly
+    /// `conditions`, usually one of the assignments returned by
+    /// `Semantic::minimal_satisfactions` on the lifted descriptor. Among the
+    /// satisfactions using only these conditions, the one `satisfy` would
+    /// pick for maximum-size signatures is used.
+    ///
+    /// Since there is no way to make up a key of type `Pk`, dissatisfying a
+    /// `pk_h` fragment is never considered, even if the satisfier used to
+    /// fill the template would be able to provide the key.
+    pub fn satisfaction_template(
+        &self,
+        conditions: &[Semantic<Pk>],
+    ) -> Result<SatisfactionTemplate<Pk>, Error> {
+        let rec = RecordingSatisfier {
+            conditions: conditions,
+            secp: secp256k1::Secp256k1::signing_only(),
+            keys: RefCell::new(vec![None; conditions.len()]),
+        };
+
+        let sig_for = |pk: &Pk| match rec.lookup_sig(pk) {
+            Some(_) => Ok(TemplateElement::Signature(pk.clone())),
+            None => Err(Error::MissingSig(pk.to_public_key())),
+        };
+        let key = |pk: &Pk| TemplateElement::Fixed(pk.to_public_key().to_bytes());
+
+        let (script_sig, witness) = match *self {
+            Descriptor::Bare(ref d) => {
+                let stack = d.satisfy(&rec).ok_or(Error::CouldNotSatisfy)?;
+                (rec.elements(&stack), vec![])
+            }
+            Descriptor::Pk(ref pk) => (vec![sig_for(pk)?], vec![]),
+            Descriptor::Pkh(ref pk) => (vec![sig_for(pk)?, key(pk)], vec![]),
+            Descriptor::Wpkh(ref pk) => (vec![], vec![sig_for(pk)?, key(pk)]),
+            Descriptor::ShWpkh(ref pk) => {
+                let addr = bitcoin::Address::p2wpkh(&pk.to_public_key(), bitcoin::Network::Bitcoin);
+                (
+                    vec![TemplateElement::Fixed(addr.script_pubkey().into_bytes())],
+                    vec![sig_for(pk)?, key(pk)],
+                )
+            }
+            Descriptor::Sh(ref d) => {
+                let stack = d.satisfy(&rec).ok_or(Error::CouldNotSatisfy)?;
+                let mut script_sig = rec.elements(&stack);
+                script_sig.push(TemplateElement::Fixed(d.encode().into_bytes()));
+                (script_sig, vec![])
+            }
+            Descriptor::Wsh(ref d) => {
+                let stack = d.satisfy(&rec).ok_or(Error::CouldNotSatisfy)?;
+                let mut witness = rec.elements(&stack);
+                witness.push(TemplateElement::Fixed(d.encode().into_bytes()));
+                (vec![], witness)
+            }
+            Descriptor::ShWsh(ref d) => {
+                let witness_script = d.encode();
+                let stack = d.satisfy(&rec).ok_or(Error::CouldNotSatisfy)?;
+                let mut witness = rec.elements(&stack);
+                let script_sig = vec![TemplateElement::Fixed(
+                    witness_script.to_v0_p2wsh().into_bytes(),
+                )];
+                witness.push(TemplateElement::Fixed(witness_script.into_bytes()));
+                (script_sig, witness)
+            }
+            Descriptor::Addr(..) | Descriptor::Raw(..) => return Err(Error::OpaqueDescriptor),
+        };
+
+        Ok(SatisfactionTemplate {
+            script_sig: script_sig,
+            witness: witness,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{self, secp256k1, PublicKey};
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use super::TemplateElement;
+    use descriptor::Descriptor;
+    use miniscript::satisfy::{BitcoinSig, Older};
+    use policy::{Liftable, Semantic};
+    use MiniscriptKey;
+
+    #[test]
+    fn satisfaction_template() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let msg = secp256k1::Message::from_slice(&[0x42; 32]).unwrap();
+        let mut keys = vec![];
+        let mut sigs = HashMap::<PublicKey, BitcoinSig>::new();
+        for i in 1..4 {
+            let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+            let pk = PublicKey {
+                compressed: true,
+                key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            };
+            keys.push(pk);
+            sigs.insert(pk, (secp.sign(&msg, &sk), bitcoin::SigHashType::All));
+        }
+
+        for desc in &[
+            format!(
+                "wsh(or_d(multi(2,{},{}),and_v(vc:pk_h({}),older(144))))",
+                keys[0], keys[1], keys[2],
+            ),
+            format!("sh(or_b(pk({}),s:pk({})))", keys[0], keys[1]),
+            format!("sh(wpkh({}))", keys[0]),
+        ] {
+            let desc = Descriptor::<PublicKey>::from_str(desc).unwrap();
+            for path in desc.lift().minimal_satisfactions(10).unwrap() {
+                let template = desc.satisfaction_template(&path).unwrap();
+
+                // Only provide what the path needs, so that `satisfy` takes
+                // the same path as the template
+                let path_sigs: HashMap<PublicKey, BitcoinSig> = sigs
+                    .iter()
+                    .filter(|&(pk, _)| path.contains(&Semantic::KeyHash(pk.to_pubkeyhash())))
+                    .map(|(pk, sig)| (*pk, *sig))
+                    .collect();
+                let pkh_sigs: HashMap<_, _> = path_sigs
+                    .iter()
+                    .map(|(pk, sig)| (pk.to_pubkeyhash(), (*pk, *sig)))
+                    .collect();
+
+                let mut expected = txin();
+                let mut actual = txin();
+                desc.satisfy(&mut expected, (&path_sigs, &pkh_sigs, Older(144)))
+                    .unwrap();
+                template
+                    .satisfy(&mut actual, (&path_sigs, &pkh_sigs))
+                    .unwrap();
+                assert_eq!(actual, expected);
+            }
+        }
+
+        // Keys committed to by hash are revealed by the satisfier
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(and_v(vc:pk_h({}),older(144)))",
+            keys[2]
+        ))
+        .unwrap();
+        let path = desc.lift().minimal_satisfactions(10).unwrap().remove(0);
+        let template = desc.satisfaction_template(&path).unwrap();
+        assert_eq!(
+            template.witness[..2],
+            [
+                TemplateElement::KeyHashSignature(keys[2].to_pubkeyhash()),
+                TemplateElement::KeyHashPublicKey(keys[2].to_pubkeyhash()),
+            ]
+        );
+        assert!(template.satisfy(&mut txin(), &sigs).is_err());
+    }
+
+    fn txin() -> bitcoin::TxIn {
+        bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! # Spend Path Weights
+//!
+//! Weight of the satisfaction for each way a descriptor can be spent, e.g.
+//! so that fee estimation UIs can show the cost of a cooperative spend next
+//! to that of a recovery spend.
+//!
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::{self, secp256k1};
+
+use descriptor::Descriptor;
+use miniscript::satisfy::{BitcoinSig, Satisfier};
+use policy::{Liftable, Semantic};
+use {MiniscriptKey, ToPublicKey};
+
+/// The weight of one way of spending a descriptor
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SpendPathWeight<Pk: MiniscriptKey> {
+    /// The keys, hashes and timelocks used by this spend path, as returned
+    /// by `Semantic::minimal_satisfactions`
+    pub conditions: Vec<Semantic<Pk>>,
+    /// Weight of the scriptSig and witness of the satisfaction, including
+    /// the VarInts encoding their lengths
+    pub weight: usize,
+}
+
+/// Satisfier which provides dummy data for exactly the given conditions
+struct PathSatisfier<'a, Pk: MiniscriptKey + 'a> {
+    conditions: &'a [Semantic<Pk>],
+    sig: BitcoinSig,
+    key: bitcoin::PublicKey,
+}
+
+impl<'a, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PathSatisfier<'a, Pk> {
+    fn lookup_sig(&self, pk: &Pk) -> Option<BitcoinSig> {
+        let pkh = Semantic::KeyHash(pk.to_pubkeyhash());
+        if self.conditions.contains(&pkh) {
+            Some(self.sig)
+        } else {
+            None
+        }
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &Pk::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        if self.conditions.contains(&Semantic::KeyHash(pkh.clone())) {
+            Some((self.key, self.sig))
+        } else {
+            None
+        }
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.preimage(Semantic::Sha256(h))
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.preimage(Semantic::Hash256(h))
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.preimage(Semantic::Ripemd160(h))
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.preimage(Semantic::Hash160(h))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.conditions.contains(&Semantic::Older(n))
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.conditions.contains(&Semantic::After(n))
+    }
+}
+
+impl<'a, Pk: MiniscriptKey> PathSatisfier<'a, Pk> {
+    fn preimage(&self, hash: Semantic<Pk>) -> Option<[u8; 32]> {
+        if self.conditions.contains(&hash) {
+            Some([0; 32])
+        } else {
+            None
+        }
+    }
+}
+
+/// Weight of the scriptSig and witness of a satisfied `TxIn`
+fn satisfaction_weight(txin: &bitcoin::TxIn) -> usize {
+    fn varint_len(n: usize) -> usize {
+        bitcoin::VarInt(n as u64).len()
+    }
+
+    let script_sig_len = txin.script_sig.len();
+    let mut weight = 4 * (varint_len(script_sig_len) + script_sig_len);
+    if !txin.witness.is_empty() {
+        weight += varint_len(txin.witness.len());
+        for elem in &txin.witness {
+            weight += varint_len(elem.len()) + elem.len();
+        }
+    }
+    weight
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
+    /// Computes the satisfaction weight of every minimal way of spending the
+    /// descriptor, cheapest first. Like `max_satisfaction_weight`, this
+    /// assumes all signatures are 73 bytes and that public keys revealed
+    /// for `pk_h` fragments are compressed.
+    ///
+    /// Returns `None` if there are more than `max_paths` spend paths, see
+    /// `Semantic::minimal_satisfactions`. Paths which cannot actually be
+    /// satisfied, e.g. because they need both a height based and a time
+    /// based timelock, are left out of the result, so it may list fewer
+    /// paths than the lifted policy has.
+    pub fn spend_path_weights(&self, max_paths: usize) -> Option<Vec<SpendPathWeight<Pk>>> {
+        // Largest DER encoding: both r and s need a padding byte
+        let mut compact = [0; 64];
+        compact[0] = 0x80;
+        compact[32] = 0x80;
+        let sig = secp256k1::Signature::from_compact(&compact).expect("valid signature");
+        // The generator, any compressed key would do
+        let key = bitcoin::PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_slice(&[
+                0x02, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce,
+                0x87, 0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81,
+                0x5b, 0x16, 0xf8, 0x17, 0x98,
+            ])
+            .expect("valid key"),
+        };
+
+        let paths = self.lift().minimal_satisfactions(max_paths)?;
+        let mut ret: Vec<_> = paths
+            .into_iter()
+            .filter_map(|conditions| {
+                let mut txin = bitcoin::TxIn {
+                    previous_output: bitcoin::OutPoint::default(),
+                    script_sig: bitcoin::Script::new(),
+                    sequence: 0xffffffff,
+                    witness: vec![],
+                };
+                let satisfier = PathSatisfier {
+                    conditions: &conditions,
+                    sig: (sig, bitcoin::SigHashType::All),
+                    key: key,
+                };
+                // Unsatisfiable paths are skipped, as documented above
+                self.satisfy(&mut txin, satisfier).ok()?;
+                Some(SpendPathWeight {
+                    weight: satisfaction_weight(&txin),
+                    conditions: conditions,
+                })
+            })
+            .collect();
+        ret.sort_by_key(|path| path.weight);
+        Some(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{secp256k1, PublicKey};
+    use std::str::FromStr;
+
+    use descriptor::Descriptor;
+    use policy::Semantic;
+    use MiniscriptKey;
+
+    #[test]
+    fn spend_path_weights() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let keys: Vec<PublicKey> = (1..4)
+            .map(|i| PublicKey {
+                compressed: true,
+                key: secp256k1::PublicKey::from_secret_key(
+                    &secp,
+                    &secp256k1::SecretKey::from_slice(&[i; 32]).unwrap(),
+                ),
+            })
+            .collect();
+
+        // Cooperative 2-of-2 or a single recovery key after a timelock
+        let desc = Descriptor::<PublicKey>::from_str(&format!(
+            "wsh(or_d(multi(2,{},{}),and_v(vc:pk_k({}),older(144))))",
+            keys[0], keys[1], keys[2],
+        ))
+        .unwrap();
+        let paths = desc.spend_path_weights(10).unwrap();
+        assert_eq!(paths.len(), 2);
+
+        let recovery = &paths[0];
+        assert_eq!(
+            recovery.conditions,
+            vec![
+                Semantic::KeyHash(keys[2].to_pubkeyhash()),
+                Semantic::Older(144),
+            ]
+        );
+        let cooperative = &paths[1];
+        assert!(cooperative
+            .conditions
+            .contains(&Semantic::KeyHash(keys[0].to_pubkeyhash())));
+
+        assert!(recovery.weight < cooperative.weight);
+
+        assert_eq!(desc.spend_path_weights(1), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! # Extended Private Key Satisfier
+//!
+//! A `Satisfier` which holds one or more extended private keys and derives
+//! the signing key for each descriptor key from its recorded origin, so that
+//! a single seed can sign for descriptors using heterogeneous paths.
+//!
+//! With the `zeroize` feature enabled, the held keys and every child key
+//! derived for signing are wiped from memory once they are no longer used.
+//! Copies made internally by `rust-bitcoin` during derivation are out of our
+//! reach.
+//!
+
+use std::fmt;
+
+use bitcoin;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32::{ChildNumber, ExtendedPrivKey, ExtendedPubKey};
+
+use descriptor::secret::zeroize_xprv;
+use descriptor::{DescriptorPublicKey, DescriptorXPub};
+use miniscript::satisfy::{BitcoinSig, Satisfier};
+use ToPublicKey;
+
+/// Signs a given sighash for every descriptor key derivable from one of its
+/// extended private keys
+pub struct XprvSatisfier {
+    /// Extended private keys, usually master keys
+    xprvs: Vec<ExtendedPrivKey>,
+    /// The message to sign
+    sighash: secp256k1::Message,
+    /// The sighash flag to attach to signatures
+    sighash_type: bitcoin::SigHashType,
+    secp: Secp256k1<secp256k1::All>,
+}
+
+impl XprvSatisfier {
+    /// Creates a new satisfier signing `sighash` with the given flag
+    pub fn new(
+        xprvs: Vec<ExtendedPrivKey>,
+        sighash: secp256k1::Message,
+        sighash_type: bitcoin::SigHashType,
+    ) -> XprvSatisfier {
+        XprvSatisfier {
+            xprvs: xprvs,
+            sighash: sighash,
+            sighash_type: sighash_type,
+            secp: Secp256k1::new(),
+        }
+    }
+
+    /// Derives the private key for `key`. Keys with an origin are derived
+    /// from the xprv with matching fingerprint along the origin path followed
+    /// by the key's own path; keys without one only match an xprv which is
+    /// the key's xpub itself. Wildcard keys must be derived first.
+    fn derive(&self, key: &DescriptorXPub) -> Option<ExtendedPrivKey> {
+        if key.is_wildcard {
+            return None;
+        }
+
+        for xprv in &self.xprvs {
+            let path: Vec<ChildNumber> = match key.origin {
+                Some((ref fingerprint, ref origin)) => {
+                    if xprv.fingerprint(&self.secp)[..] != fingerprint[..] {
+                        continue;
+                    }
+                    origin
+                        .into_iter()
+                        .chain(&key.derivation_path)
+                        .cloned()
+                        .collect()
+                }
+                None => {
+                    if ExtendedPubKey::from_private(&self.secp, xprv) != key.xpub {
+                        continue;
+                    }
+                    (&key.derivation_path).into_iter().cloned().collect()
+                }
+            };
+
+            if let Ok(child) = xprv.derive_priv(&self.secp, &path) {
+                return Some(child);
+            }
+        }
+        None
+    }
+}
+
+/// Secrets are never printed, only the fingerprints identifying them
+impl fmt::Debug for XprvSatisfier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("XprvSatisfier { xprvs: [")?;
+        for (i, xprv) in self.xprvs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "[{}]<redacted>",
+                xprv.fingerprint(&self.secp)[..].to_hex()
+            )?;
+        }
+        write!(
+            f,
+            "], sighash: {:?}, sighash_type: {:?} }}",
+            self.sighash, self.sighash_type
+        )
+    }
+}
+
+impl Satisfier<DescriptorPublicKey> for XprvSatisfier {
+    fn lookup_sig(&self, pk: &DescriptorPublicKey) -> Option<BitcoinSig> {
+        let xpub = match *pk {
+            DescriptorPublicKey::PubKey(_) => return None,
+            DescriptorPublicKey::XPub(ref xpub) => xpub,
+        };
+        let mut child = self.derive(xpub)?;
+
+        // Guard against an origin which doesn't actually lead to the key
+        let child_pk = bitcoin::PublicKey::from_private_key(&self.secp, &child.private_key);
+        let ret = if child_pk == pk.to_public_key() {
+            let sig = self.secp.sign(&self.sighash, &child.private_key.key);
+            Some((sig, self.sighash_type))
+        } else {
+            None
+        };
+
+        zeroize_xprv(&mut child);
+        ret
+    }
+}
+
+impl Drop for XprvSatisfier {
+    fn drop(&mut self) {
+        for xprv in &mut self.xprvs {
+            zeroize_xprv(xprv);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::secp256k1;
+    use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+    use bitcoin::{self, Network};
+    use std::str::FromStr;
+
+    use super::XprvSatisfier;
+    use descriptor::{Descriptor, DescriptorPublicKey};
+    use {Satisfier, ToPublicKey};
+
+    #[test]
+    fn sign_with_origins() {
+        let secp = secp256k1::Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::Bitcoin, &[0xcd; 32]).unwrap();
+        let fingerprint = master.fingerprint(&secp)[..].to_hex();
+        let account = master
+            .derive_priv(&secp, &DerivationPath::from_str("m/84'/0'/0'").unwrap())
+            .unwrap();
+        let account_xpub = ExtendedPubKey::from_private(&secp, &account);
+        let unrelated = ExtendedPubKey::from_private(
+            &secp,
+            &ExtendedPrivKey::new_master(Network::Bitcoin, &[0xce; 32]).unwrap(),
+        );
+
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(1,[{}/84'/0'/0']{}/0/*,{}/0/*))",
+            fingerprint, account_xpub, unrelated,
+        ))
+        .unwrap()
+        .derive(&[ChildNumber::from_normal_idx(7).unwrap()]);
+        let keys = match desc {
+            Descriptor::Wsh(ref ms) => match ms.node {
+                ::Terminal::Multi(_, ref keys) => keys.clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        let msg = secp256k1::Message::from_slice(&[0x42; 32]).unwrap();
+        let satisfier = XprvSatisfier::new(vec![master], msg, bitcoin::SigHashType::All);
+
+        let (sig, _) = satisfier.lookup_sig(&keys[0]).expect("derivable key");
+        secp.verify(&msg, &sig, &keys[0].to_public_key().key)
+            .unwrap();
+        assert!(satisfier.lookup_sig(&keys[1]).is_none());
+
+        let mut txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        desc.satisfy(&mut txin, &satisfier).unwrap();
+        assert_eq!(txin.witness.len(), 3);
+
+        let debug = format!("{:?}", satisfier);
+        assert!(debug.contains(&format!("[{}]<redacted>", fingerprint)));
+        assert!(!debug.contains(&master.to_string()));
+        assert!(!debug.contains(&master.private_key.to_string()));
+    }
+}
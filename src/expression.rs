@@ -18,6 +18,7 @@
 use std::str::FromStr;
 
 use errstr;
+use miniscript::MAX_RECURSION_DEPTH;
 use Error;
 
 #[derive(Debug)]
@@ -34,7 +35,14 @@ pub trait FromTree: Sized {
 }
 
 impl<'a> Tree<'a> {
-    fn from_slice(mut sl: &'a str) -> Result<(Tree<'a>, &'a str), Error> {
+    /// Parses a tree from the start of `sl`, returning the rest of it.
+    /// `sl` is always a suffix of the whole string, whose length is `len`,
+    /// so that errors can point at `len - sl.len()`.
+    fn from_slice(
+        mut sl: &'a str,
+        len: usize,
+        depth_left: usize,
+    ) -> Result<(Tree<'a>, &'a str), Error> {
         enum Found {
             Nothing,
             Lparen(usize),
@@ -80,25 +88,29 @@ impl<'a> Tree<'a> {
             )),
             // Function call
             Found::Lparen(n) => {
+                if depth_left == 0 {
+                    return Err(Error::MaxDepthExceeded);
+                }
                 let mut ret = Tree {
                     name: &sl[..n],
                     args: vec![],
                 };
 
+                let lparen = len - sl.len() + n;
                 sl = &sl[n + 1..];
                 loop {
-                    let (arg, new_sl) = Tree::from_slice(sl)?;
+                    let (arg, new_sl) = Tree::from_slice(sl, len, depth_left - 1)?;
                     ret.args.push(arg);
 
                     if new_sl.is_empty() {
-                        return Err(Error::ExpectedChar(')'));
+                        return Err(Error::UnbalancedParens(lparen));
                     }
 
                     sl = &new_sl[1..];
                     match new_sl.as_bytes()[0] {
                         b',' => {}
                         b')' => break,
-                        _ => return Err(Error::ExpectedChar(',')),
+                        ch => return Err(Error::UnexpectedChar(ch as char, len - new_sl.len())),
                     }
                 }
                 Ok((ret, sl))
@@ -108,6 +120,12 @@ impl<'a> Tree<'a> {
 
     /// Parses a tree from a string
     pub fn from_str(s: &'a str) -> Result<Tree<'a>, Error> {
+        Tree::from_str_with_max_depth(s, MAX_RECURSION_DEPTH)
+    }
+
+    /// Parses a tree from a string, failing if it is nested more than
+    /// `max_depth` levels deep
+    pub fn from_str_with_max_depth(s: &'a str, max_depth: usize) -> Result<Tree<'a>, Error> {
         // Filter out non-ASCII because we byte-index strings all over the
         // place and Rust gets very upset when you splinch a string.
         for ch in s.bytes() {
@@ -116,11 +134,11 @@ impl<'a> Tree<'a> {
             }
         }
 
-        let (top, rem) = Tree::from_slice(s)?;
-        if rem.is_empty() {
-            Ok(top)
-        } else {
-            Err(errstr(rem))
+        let (top, rem) = Tree::from_slice(s, s.len(), max_depth)?;
+        match rem.as_bytes().first() {
+            None => Ok(top),
+            Some(&b')') => Err(Error::UnbalancedParens(s.len() - rem.len())),
+            Some(&ch) => Err(Error::UnexpectedChar(ch as char, s.len() - rem.len())),
         }
     }
 }
@@ -172,3 +190,30 @@ where
         Err(errstr(term.name))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_offsets() {
+        let parse_err = |s| Tree::from_str(s).unwrap_err().to_string();
+        assert!(Tree::from_str("wsh(and_v(v:pk(A),older(144)))").is_ok());
+        assert_eq!(
+            parse_err("wsh(and_v(v:pk(A),older(144))"),
+            "unbalanced parenthesis at byte 3"
+        );
+        assert_eq!(
+            parse_err("wsh(pk(A)))"),
+            "unbalanced parenthesis at byte 10"
+        );
+        assert_eq!(
+            parse_err("wsh(pk(A)x)"),
+            "unexpected character 'x' at byte 9"
+        );
+        assert_eq!(
+            parse_err("pk(A),pk(B)"),
+            "unexpected character ',' at byte 5"
+        );
+    }
+}
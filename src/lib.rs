@@ -75,7 +75,7 @@
 //!     );
 //!
 //!     // Estimate the satisfaction cost
-//!     assert_eq!(desc.max_satisfaction_weight(), 293);
+//!     assert_eq!(desc.max_satisfaction_weight().unwrap(), 293);
 //! }
 //! ```
 //!
@@ -84,11 +84,16 @@
 pub extern crate bitcoin;
 #[cfg(feature = "serde")]
 pub extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 #[cfg(all(test, feature = "unstable"))]
 extern crate test;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "zeroize")]
+extern crate zeroize;
 
 #[macro_use]
-#[cfg(test)]
 mod macros;
 
 pub mod descriptor;
@@ -103,10 +108,16 @@ use std::{error, fmt, hash, str};
 use bitcoin::blockdata::{opcodes, script};
 use bitcoin::hashes::{hash160, sha256, Hash};
 
-pub use descriptor::{Descriptor, SatisfiedConstraints};
+pub use descriptor::{
+    DefiniteDescriptorKey, Descriptor, DescriptorPublicKey, Interpreter, SatisfiedConstraints,
+};
+pub use miniscript::analyzable::AnalysisError;
+pub use miniscript::context::{Legacy, ScriptContext, Segwitv0};
 pub use miniscript::decode::Terminal;
+pub use miniscript::locktime::{AbsLockTime, RelLockTime};
+pub use miniscript::matching::TemplateMatch;
 pub use miniscript::satisfy::{BitcoinSig, Satisfier};
-pub use miniscript::Miniscript;
+pub use miniscript::{ForEach, Miniscript, MAX_RECURSION_DEPTH};
 
 ///Public key trait which can be converted to Hash type
 pub trait MiniscriptKey:
@@ -116,6 +127,12 @@ pub trait MiniscriptKey:
 
     ///Converts an object to PublicHash
     fn to_pubkeyhash(&self) -> Self::Hash;
+
+    /// Whether the key is an uncompressed public key, which may not be used
+    /// in segwit scripts. Abstract keys are never considered uncompressed.
+    fn is_uncompressed(&self) -> bool {
+        false
+    }
 }
 
 impl MiniscriptKey for bitcoin::PublicKey {
@@ -126,6 +143,10 @@ impl MiniscriptKey for bitcoin::PublicKey {
         self.write_into(&mut engine);
         hash160::Hash::from_engine(engine)
     }
+
+    fn is_uncompressed(&self) -> bool {
+        !self.compressed
+    }
 }
 
 impl MiniscriptKey for String {
@@ -170,6 +191,38 @@ impl ToPublicKey for bitcoin::PublicKey {
     }
 }
 
+/// Converts the keys and key hashes of a Miniscript or descriptor from one
+/// key type to another, see `Descriptor::translate_pk`. Translations which
+/// carry state, e.g. a secp context or a derivation cache, are best written
+/// as a type implementing this trait.
+///
+/// A pair of closures `(FnMut(&P) -> Result<Q, E>, FnMut(&P::Hash) ->
+/// Result<Q::Hash, E>)` is a translator as well; their argument types may
+/// need to be annotated.
+pub trait Translator<P: MiniscriptKey, Q: MiniscriptKey, E> {
+    /// Translates a public key
+    fn pk(&mut self, pk: &P) -> Result<Q, E>;
+
+    /// Translates the hash of a public key
+    fn pkh(&mut self, pkh: &P::Hash) -> Result<Q::Hash, E>;
+}
+
+impl<P, Q, E, Fpk, Fpkh> Translator<P, Q, E> for (Fpk, Fpkh)
+where
+    P: MiniscriptKey,
+    Q: MiniscriptKey,
+    Fpk: FnMut(&P) -> Result<Q, E>,
+    Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
+{
+    fn pk(&mut self, pk: &P) -> Result<Q, E> {
+        (self.0)(pk)
+    }
+
+    fn pkh(&mut self, pkh: &P::Hash) -> Result<Q::Hash, E> {
+        (self.1)(pkh)
+    }
+}
+
 /// Dummy key which de/serializes to the empty string; useful sometimes for testing
 #[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Debug)]
 pub struct DummyKey;
@@ -266,6 +319,12 @@ pub enum Error {
     Unprintable(u8),
     /// expected character while parsing descriptor; didn't find one
     ExpectedChar(char),
+    /// A parenthesis at the given byte offset of an expression is never
+    /// closed, or closes one which was never opened
+    UnbalancedParens(usize),
+    /// Some other character at the given byte offset of an expression,
+    /// where only `,` or `)` may follow an argument
+    UnexpectedChar(char, usize),
     /// While parsing backward, hit beginning of script
     UnexpectedStart,
     /// Got something we were not expecting
@@ -328,6 +387,41 @@ pub enum Error {
     ///Incorrect Script pubkey Hash for the descriptor. This is used for both
     /// `Sh` and `Wsh` descriptors
     IncorrectScriptHash,
+    /// A stored partial satisfaction refers to a different descriptor or
+    /// transaction than the one it is being resumed for
+    PartialSatisfactionMismatch,
+    /// A policy given alongside a descriptor does not describe it
+    PolicyMismatch,
+    /// The descriptor would not be relayed by the network
+    NonStandard(descriptor::StandardnessError),
+    /// Fragments were nested deeper than allowed by the `ParseLimits` or
+    /// `MAX_RECURSION_DEPTH`
+    MaxDepthExceeded,
+    /// There were more fragments than allowed by the `ParseLimits`
+    TooManyFragments(usize),
+    /// An uncompressed key was used in a segwit descriptor, whose outputs
+    /// would not be spendable under standardness rules
+    UncompressedSegwitKey(String),
+    /// A timelock was zero or had its disable flag (bit 31) set
+    InvalidLockTime(u32),
+    /// A satisfaction may execute more than 201 non-push opcodes
+    MaxOpsExceeded(usize),
+    /// The script is larger than its context allows
+    MaxScriptSizeExceeded(usize),
+    /// A satisfaction may need more witness elements than its context
+    /// allows
+    MaxWitnessItemsExceeded(usize),
+    /// The script is provably unspendable, e.g. an `OP_RETURN` output.
+    /// Contains the data pushed after the `OP_RETURN`, if any.
+    Unspendable(Vec<u8>),
+    /// The spending conditions of an `addr()` or `raw()` descriptor are not
+    /// known, so it can't be satisfied
+    OpaqueDescriptor,
+    /// The checksum following the `#` of a descriptor is missing a
+    /// character, has too many or does not match the descriptor
+    BadChecksum(String),
+    /// The input to compute a signature hash for is not in the transaction
+    InputIndexOutOfRange(usize),
 }
 
 #[doc(hidden)]
@@ -369,6 +463,8 @@ impl fmt::Display for Error {
             Error::CmsTooManyKeys(n) => write!(f, "checkmultisig with {} keys", n),
             Error::Unprintable(x) => write!(f, "unprintable character 0x{:02x}", x),
             Error::ExpectedChar(c) => write!(f, "expected {}", c),
+            Error::UnbalancedParens(n) => write!(f, "unbalanced parenthesis at byte {}", n),
+            Error::UnexpectedChar(c, n) => write!(f, "unexpected character '{}' at byte {}", c, n),
             Error::UnexpectedStart => f.write_str("unexpected start of script"),
             Error::Unexpected(ref s) => write!(f, "unexpected «{}»", s),
             Error::MultiColon(ref s) => write!(f, "«{}» has multiple instances of «:»", s),
@@ -409,10 +505,47 @@ impl fmt::Display for Error {
             Error::IncorrectPubkeyHash => {
                 f.write_str("Incorrect pubkey hash for given descriptor pkh/wpkh")
             }
+            Error::PartialSatisfactionMismatch => {
+                f.write_str("partial satisfaction does not match descriptor or transaction")
+            }
+            Error::PolicyMismatch => f.write_str("policy does not match descriptor"),
+            Error::NonStandard(ref e) => write!(f, "non-standard: {}", e),
+            Error::MaxDepthExceeded => f.write_str("fragments nested too deeply"),
+            Error::TooManyFragments(n) => write!(f, "too many fragments: {}", n),
+            Error::InvalidLockTime(n) => write!(f, "invalid timelock: {}", n),
+            Error::UncompressedSegwitKey(ref pk) => {
+                write!(f, "uncompressed key {} in segwit descriptor", pk)
+            }
+            Error::MaxOpsExceeded(n) => write!(f, "may execute too many opcodes: {}", n),
+            Error::MaxScriptSizeExceeded(n) => write!(f, "script too large: {} bytes", n),
+            Error::MaxWitnessItemsExceeded(n) => {
+                write!(f, "may need too many witness elements: {}", n)
+            }
+            Error::Unspendable(ref data) => {
+                write!(
+                    f,
+                    "provably unspendable script with {} bytes of data",
+                    data.len()
+                )
+            }
+            Error::OpaqueDescriptor => {
+                f.write_str("cannot satisfy addr() or raw() descriptor with unknown script")
+            }
+            Error::BadChecksum(ref e) => write!(f, "invalid descriptor checksum: {}", e),
+            Error::InputIndexOutOfRange(n) => {
+                write!(f, "transaction has no input with index {}", n)
+            }
         }
     }
 }
 
+#[doc(hidden)]
+impl From<descriptor::StandardnessError> for Error {
+    fn from(e: descriptor::StandardnessError) -> Error {
+        Error::NonStandard(e)
+    }
+}
+
 #[doc(hidden)]
 impl From<psbt::Error> for Error {
     fn from(e: psbt::Error) -> Error {
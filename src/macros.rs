@@ -4,12 +4,14 @@
 
 /// Allows tests to create a miniscript directly from string as
 /// `ms_str!("c:or_i(pk({}),pk({}))", pk1, pk2)`
+#[cfg(test)]
 macro_rules! ms_str {
     ($($arg:tt)*) => (Miniscript::from_str(&format!($($arg)*)).unwrap())
 }
 
 /// Allows tests to create a descriptor directly from string as
 /// `des_str!("wsh(c:or_i(pk({}),pk({})))", pk1, pk2)`
+#[cfg(test)]
 macro_rules! des_str {
     ($($arg:tt)*) => (Descriptor::from_str(&format!($($arg)*)).unwrap())
 }
@@ -20,3 +22,24 @@ macro_rules! des_str {
 macro_rules! policy_str {
     ($($arg:tt)*) => (::policy::Concrete::from_str(&format!($($arg)*)).unwrap())
 }
+
+/// Emits a `tracing` event at debug level if the `tracing` feature is
+/// enabled, and expands to nothing otherwise
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        {
+            ::tracing::debug!($($arg)*);
+        }
+    };
+}
+
+/// Enters a `tracing` span at debug level until the end of the enclosing
+/// block if the `tracing` feature is enabled, and expands to nothing
+/// otherwise
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!($($arg)*).entered();
+    };
+}
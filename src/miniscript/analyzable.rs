@@ -0,0 +1,102 @@
+//! # Analysis
+//!
+//! Checks for Miniscripts which are valid, but dangerous to use: ones which
+//! can be spent without a signature, have malleable satisfactions or reuse
+//! keys. Wallets importing descriptors from elsewhere should usually refuse
+//! those failing `sanity_check`.
+//!
+
+use std::collections::HashSet;
+use std::{error, fmt};
+
+use {ForEach, Miniscript, MiniscriptKey};
+
+/// A reason for a Miniscript to fail `Miniscript::sanity_check`
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum AnalysisError {
+    /// Some satisfaction needs no signature, see `Miniscript::requires_sig`
+    SiglessBranch,
+    /// Some satisfactions can be turned into others by third parties, see
+    /// `Miniscript::is_non_malleable`
+    Malleable,
+    /// A key is used more than once, see `Miniscript::has_repeated_keys`
+    RepeatedPubkeys,
+}
+
+impl error::Error for AnalysisError {
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+
+    fn description(&self) -> &str {
+        ""
+    }
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AnalysisError::SiglessBranch => {
+                f.write_str("some spending path does not require a signature")
+            }
+            AnalysisError::Malleable => f.write_str("some satisfactions are malleable"),
+            AnalysisError::RepeatedPubkeys => f.write_str("a key is used more than once"),
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey> Miniscript<Pk> {
+    /// Whether some key is used more than once. Keys are compared by their
+    /// hashes, so a key used in both `pk` and `pk_h` counts as repeated.
+    /// Reusing a key lets a signature for one branch satisfy another one.
+    pub fn has_repeated_keys(&self) -> bool {
+        let mut seen = HashSet::new();
+        !self.for_each_key(|key| match key {
+            ForEach::Key(pk) => seen.insert(pk.to_pubkeyhash()),
+            ForEach::Hash(pkh) => seen.insert(pkh.clone()),
+        })
+    }
+
+    /// Checks that every satisfaction requires a signature and is
+    /// non-malleable, and that no key is used more than once
+    pub fn sanity_check(&self) -> Result<(), AnalysisError> {
+        if !self.requires_sig() {
+            Err(AnalysisError::SiglessBranch)
+        } else if !self.is_non_malleable() {
+            Err(AnalysisError::Malleable)
+        } else if self.has_repeated_keys() {
+            Err(AnalysisError::RepeatedPubkeys)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn sanity_check() {
+        let ms = Miniscript::<String>::from_str("or_d(pk(A),and_v(v:pk(B),older(144)))").unwrap();
+        assert!(!ms.has_repeated_keys());
+        assert_eq!(ms.sanity_check(), Ok(()));
+
+        let ms = Miniscript::<String>::from_str("or_d(pk(A),and_v(v:pk(A),older(144)))").unwrap();
+        assert!(ms.has_repeated_keys());
+        assert_eq!(ms.sanity_check(), Err(AnalysisError::RepeatedPubkeys));
+
+        // `String` keys hash to themselves
+        let ms = Miniscript::<String>::from_str("or_b(pk(A),a:pk_h(A))").unwrap();
+        assert!(ms.has_repeated_keys());
+
+        let ms = Miniscript::<String>::from_str("or_d(pk(A),older(144))").unwrap();
+        assert_eq!(ms.sanity_check(), Err(AnalysisError::SiglessBranch));
+
+        let ms = Miniscript::<String>::from_str("and_v(v:pk(A),or_i(older(1),older(2)))").unwrap();
+        assert!(ms.requires_sig());
+        assert_eq!(ms.sanity_check(), Err(AnalysisError::Malleable));
+    }
+}
@@ -27,6 +27,7 @@ use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 
 use errstr;
 use expression;
+use miniscript::locktime::{AbsLockTime, RelLockTime};
 use miniscript::types::{self, Property};
 use script_num_size;
 use std::sync::Arc;
@@ -36,6 +37,7 @@ use Miniscript;
 use MiniscriptKey;
 use Terminal;
 use ToPublicKey;
+use Translator;
 
 impl<Pk: MiniscriptKey> Terminal<Pk> {
     /// Internal helper function for displaying wrapper types; returns
@@ -61,19 +63,14 @@ impl<Pk: MiniscriptKey> Terminal<Pk> {
 impl<Pk: MiniscriptKey> Terminal<Pk> {
     /// Convert an AST element with one public key type to one of another
     /// public key type
-    pub fn translate_pk<FPk, FPkh, Q, Error>(
-        &self,
-        translatefpk: &mut FPk,
-        translatefpkh: &mut FPkh,
-    ) -> Result<Terminal<Q>, Error>
+    pub fn translate_pk<T, Q, E>(&self, t: &mut T) -> Result<Terminal<Q>, E>
     where
-        FPk: FnMut(&Pk) -> Result<Q, Error>,
-        FPkh: FnMut(&Pk::Hash) -> Result<Q::Hash, Error>,
+        T: Translator<Pk, Q, E>,
         Q: MiniscriptKey,
     {
         Ok(match *self {
-            Terminal::PkK(ref p) => Terminal::PkK(translatefpk(p)?),
-            Terminal::PkH(ref p) => Terminal::PkH(translatefpkh(p)?),
+            Terminal::PkK(ref p) => Terminal::PkK(t.pk(p)?),
+            Terminal::PkH(ref p) => Terminal::PkH(t.pkh(p)?),
             Terminal::After(n) => Terminal::After(n),
             Terminal::Older(n) => Terminal::Older(n),
             Terminal::Sha256(x) => Terminal::Sha256(x),
@@ -82,68 +79,53 @@ impl<Pk: MiniscriptKey> Terminal<Pk> {
             Terminal::Hash160(x) => Terminal::Hash160(x),
             Terminal::True => Terminal::True,
             Terminal::False => Terminal::False,
-            Terminal::Alt(ref sub) => {
-                Terminal::Alt(Arc::new(sub.translate_pk(translatefpk, translatefpkh)?))
-            }
-            Terminal::Swap(ref sub) => {
-                Terminal::Swap(Arc::new(sub.translate_pk(translatefpk, translatefpkh)?))
-            }
-            Terminal::Check(ref sub) => {
-                Terminal::Check(Arc::new(sub.translate_pk(translatefpk, translatefpkh)?))
-            }
-            Terminal::DupIf(ref sub) => {
-                Terminal::DupIf(Arc::new(sub.translate_pk(translatefpk, translatefpkh)?))
-            }
-            Terminal::Verify(ref sub) => {
-                Terminal::Verify(Arc::new(sub.translate_pk(translatefpk, translatefpkh)?))
-            }
-            Terminal::NonZero(ref sub) => {
-                Terminal::NonZero(Arc::new(sub.translate_pk(translatefpk, translatefpkh)?))
-            }
+            Terminal::Alt(ref sub) => Terminal::Alt(Arc::new(sub.translate_pk(t)?)),
+            Terminal::Swap(ref sub) => Terminal::Swap(Arc::new(sub.translate_pk(t)?)),
+            Terminal::Check(ref sub) => Terminal::Check(Arc::new(sub.translate_pk(t)?)),
+            Terminal::DupIf(ref sub) => Terminal::DupIf(Arc::new(sub.translate_pk(t)?)),
+            Terminal::Verify(ref sub) => Terminal::Verify(Arc::new(sub.translate_pk(t)?)),
+            Terminal::NonZero(ref sub) => Terminal::NonZero(Arc::new(sub.translate_pk(t)?)),
             Terminal::ZeroNotEqual(ref sub) => {
-                Terminal::ZeroNotEqual(Arc::new(sub.translate_pk(translatefpk, translatefpkh)?))
+                Terminal::ZeroNotEqual(Arc::new(sub.translate_pk(t)?))
             }
             Terminal::AndV(ref left, ref right) => Terminal::AndV(
-                Arc::new(left.translate_pk(&mut *translatefpk, &mut *translatefpkh)?),
-                Arc::new(right.translate_pk(translatefpk, translatefpkh)?),
+                Arc::new(left.translate_pk(t)?),
+                Arc::new(right.translate_pk(t)?),
             ),
             Terminal::AndB(ref left, ref right) => Terminal::AndB(
-                Arc::new(left.translate_pk(&mut *translatefpk, &mut *translatefpkh)?),
-                Arc::new(right.translate_pk(translatefpk, translatefpkh)?),
+                Arc::new(left.translate_pk(t)?),
+                Arc::new(right.translate_pk(t)?),
             ),
             Terminal::AndOr(ref a, ref b, ref c) => Terminal::AndOr(
-                Arc::new(a.translate_pk(&mut *translatefpk, &mut *translatefpkh)?),
-                Arc::new(b.translate_pk(&mut *translatefpk, &mut *translatefpkh)?),
-                Arc::new(c.translate_pk(translatefpk, translatefpkh)?),
+                Arc::new(a.translate_pk(t)?),
+                Arc::new(b.translate_pk(t)?),
+                Arc::new(c.translate_pk(t)?),
             ),
             Terminal::OrB(ref left, ref right) => Terminal::OrB(
-                Arc::new(left.translate_pk(&mut *translatefpk, &mut *translatefpkh)?),
-                Arc::new(right.translate_pk(translatefpk, translatefpkh)?),
+                Arc::new(left.translate_pk(t)?),
+                Arc::new(right.translate_pk(t)?),
             ),
             Terminal::OrD(ref left, ref right) => Terminal::OrD(
-                Arc::new(left.translate_pk(&mut *translatefpk, &mut *translatefpkh)?),
-                Arc::new(right.translate_pk(translatefpk, translatefpkh)?),
+                Arc::new(left.translate_pk(t)?),
+                Arc::new(right.translate_pk(t)?),
             ),
             Terminal::OrC(ref left, ref right) => Terminal::OrC(
-                Arc::new(left.translate_pk(&mut *translatefpk, &mut *translatefpkh)?),
-                Arc::new(right.translate_pk(translatefpk, translatefpkh)?),
+                Arc::new(left.translate_pk(t)?),
+                Arc::new(right.translate_pk(t)?),
             ),
             Terminal::OrI(ref left, ref right) => Terminal::OrI(
-                Arc::new(left.translate_pk(&mut *&mut *translatefpk, &mut *&mut *translatefpkh)?),
-                Arc::new(right.translate_pk(translatefpk, translatefpkh)?),
+                Arc::new(left.translate_pk(t)?),
+                Arc::new(right.translate_pk(t)?),
             ),
             Terminal::Thresh(k, ref subs) => {
                 let subs: Result<Vec<Arc<Miniscript<Q>>>, _> = subs
                     .iter()
-                    .map(|s| {
-                        s.translate_pk(&mut *translatefpk, &mut *translatefpkh)
-                            .and_then(|x| Ok(Arc::new(x)))
-                    })
+                    .map(|s| s.translate_pk(t).map(Arc::new))
                     .collect();
                 Terminal::Thresh(k, subs?)
             }
             Terminal::Multi(k, ref keys) => {
-                let keys: Result<Vec<Q>, _> = keys.iter().map(&mut *translatefpk).collect();
+                let keys: Result<Vec<Q>, _> = keys.iter().map(|k| t.pk(k)).collect();
                 Terminal::Multi(k, keys?)
             }
         })
@@ -244,76 +226,129 @@ impl<Pk: MiniscriptKey> fmt::Debug for Terminal<Pk> {
     }
 }
 
+/// A piece of the string form of a Miniscript still to be written, see
+/// the `Display` implementation of `Terminal`
+enum DisplayItem<'a, Pk: MiniscriptKey + 'a> {
+    Node(&'a Terminal<Pk>),
+    Str(&'static str),
+}
+
 impl<Pk: MiniscriptKey> fmt::Display for Terminal<Pk> {
+    // Subexpressions are written from an explicit stack rather than by
+    // recursing, so that arbitrarily deep scripts can't overflow the stack
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Terminal::PkK(ref pk) => write!(f, "pk_k({})", pk),
-            Terminal::PkH(ref pkh) => write!(f, "pk_h({})", pkh),
-            Terminal::After(t) => write!(f, "after({})", t),
-            Terminal::Older(t) => write!(f, "older({})", t),
-            Terminal::Sha256(h) => write!(f, "sha256({})", h),
-            Terminal::Hash256(h) => {
-                let mut x = h.into_inner();
-                x.reverse();
-                write!(f, "hash256({})", sha256d::Hash::from_inner(x))
-            }
-            Terminal::Ripemd160(h) => write!(f, "ripemd160({})", h),
-            Terminal::Hash160(h) => write!(f, "hash160({})", h),
-            Terminal::True => f.write_str("1"),
-            Terminal::False => f.write_str("0"),
-            Terminal::AndV(ref l, ref r) if r.node != Terminal::True => {
-                write!(f, "and_v({},{})", l, r)
-            }
-            Terminal::AndB(ref l, ref r) => write!(f, "and_b({},{})", l, r),
-            Terminal::AndOr(ref a, ref b, ref c) => {
-                if c.node == Terminal::False {
-                    write!(f, "and_n({},{})", a, b)
-                } else {
-                    write!(f, "andor({},{},{})", a, b, c)
+        let mut stack = vec![DisplayItem::Node(self)];
+        while let Some(item) = stack.pop() {
+            let node = match item {
+                DisplayItem::Node(node) => node,
+                DisplayItem::Str(s) => {
+                    f.write_str(s)?;
+                    continue;
                 }
-            }
-            Terminal::OrB(ref l, ref r) => write!(f, "or_b({},{})", l, r),
-            Terminal::OrD(ref l, ref r) => write!(f, "or_d({},{})", l, r),
-            Terminal::OrC(ref l, ref r) => write!(f, "or_c({},{})", l, r),
-            Terminal::OrI(ref l, ref r)
-                if l.node != Terminal::False && r.node != Terminal::False =>
-            {
-                write!(f, "or_i({},{})", l, r)
-            }
-            Terminal::Thresh(k, ref subs) => {
-                write!(f, "thresh({}", k)?;
-                for s in subs {
-                    write!(f, ",{}", s)?;
+            };
+            match *node {
+                Terminal::PkK(ref pk) => write!(f, "pk_k({})", pk)?,
+                Terminal::PkH(ref pkh) => write!(f, "pk_h({})", pkh)?,
+                Terminal::After(t) => write!(f, "after({})", t)?,
+                Terminal::Older(t) => write!(f, "older({})", t)?,
+                Terminal::Sha256(h) => write!(f, "sha256({})", h)?,
+                Terminal::Hash256(h) => {
+                    let mut x = h.into_inner();
+                    x.reverse();
+                    write!(f, "hash256({})", sha256d::Hash::from_inner(x))?
                 }
-                f.write_str(")")
-            }
-            Terminal::Multi(k, ref keys) => {
-                write!(f, "multi({}", k)?;
-                for k in keys {
-                    write!(f, ",{}", k)?;
+                Terminal::Ripemd160(h) => write!(f, "ripemd160({})", h)?,
+                Terminal::Hash160(h) => write!(f, "hash160({})", h)?,
+                Terminal::True => f.write_str("1")?,
+                Terminal::False => f.write_str("0")?,
+                Terminal::AndV(ref l, ref r) if r.node != Terminal::True => {
+                    f.write_str("and_v(")?;
+                    push_args(&mut stack, &[l, r]);
                 }
-                f.write_str(")")
-            }
-            // wrappers
-            _ => {
-                if let Some((ch, sub)) = self.wrap_char() {
+                Terminal::AndB(ref l, ref r) => {
+                    f.write_str("and_b(")?;
+                    push_args(&mut stack, &[l, r]);
+                }
+                Terminal::AndOr(ref a, ref b, ref c) => {
+                    if c.node == Terminal::False {
+                        f.write_str("and_n(")?;
+                        push_args(&mut stack, &[a, b]);
+                    } else {
+                        f.write_str("andor(")?;
+                        push_args(&mut stack, &[a, b, c]);
+                    }
+                }
+                Terminal::OrB(ref l, ref r) => {
+                    f.write_str("or_b(")?;
+                    push_args(&mut stack, &[l, r]);
+                }
+                Terminal::OrD(ref l, ref r) => {
+                    f.write_str("or_d(")?;
+                    push_args(&mut stack, &[l, r]);
+                }
+                Terminal::OrC(ref l, ref r) => {
+                    f.write_str("or_c(")?;
+                    push_args(&mut stack, &[l, r]);
+                }
+                Terminal::OrI(ref l, ref r)
+                    if l.node != Terminal::False && r.node != Terminal::False =>
+                {
+                    f.write_str("or_i(")?;
+                    push_args(&mut stack, &[l, r]);
+                }
+                Terminal::Thresh(k, ref subs) => {
+                    write!(f, "thresh({},", k)?;
+                    push_args(&mut stack, &subs.iter().collect::<Vec<_>>());
+                }
+                Terminal::Multi(k, ref keys) => {
+                    write!(f, "multi({}", k)?;
+                    for k in keys {
+                        write!(f, ",{}", k)?;
+                    }
+                    f.write_str(")")?
+                }
+                // wrappers
+                _ => {
+                    let (ch, sub) = node.wrap_char().expect("wrapper");
                     if ch == 'c' {
                         if let Terminal::PkK(ref pk) = sub.node {
                             // alias: pk(K) = c:pk_k(K)
-                            return write!(f, "pk({})", pk);
+                            write!(f, "pk({})", pk)?;
+                            continue;
                         }
                     }
 
                     fmt::Write::write_char(f, ch)?;
-                    if sub.node.wrap_char().is_none() {
-                        fmt::Write::write_char(f, ':')?;
+                    match sub.node.wrap_char() {
+                        None => fmt::Write::write_char(f, ':')?,
+                        // `pk` alias is printed without its `c` wrapper
+                        Some(('c', inner)) => {
+                            if let Terminal::PkK(..) = inner.node {
+                                fmt::Write::write_char(f, ':')?;
+                            }
+                        }
+                        Some(_) => {}
                     }
-                    write!(f, "{}", sub)
-                } else {
-                    unreachable!();
+                    stack.push(DisplayItem::Node(&sub.node));
                 }
             }
         }
+        Ok(())
+    }
+}
+
+/// Schedules writing `args`, separated by commas, followed by the closing
+/// parenthesis of the fragment they belong to
+fn push_args<'a, Pk: MiniscriptKey>(
+    stack: &mut Vec<DisplayItem<'a, Pk>>,
+    args: &[&'a Arc<Miniscript<Pk>>],
+) {
+    stack.push(DisplayItem::Str(")"));
+    for (i, arg) in args.iter().enumerate().rev() {
+        stack.push(DisplayItem::Node(&arg.node));
+        if i > 0 {
+            stack.push(DisplayItem::Str(","));
+        }
     }
 }
 
@@ -370,12 +405,14 @@ where
             ("pk_h", 1) => {
                 expression::terminal(&top.args[0], |x| Pk::Hash::from_str(x).map(Terminal::PkH))
             }
-            ("after", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Terminal::After)
-            }),
-            ("older", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Terminal::Older)
-            }),
+            ("after", 1) => {
+                let n = expression::terminal(&top.args[0], expression::parse_num)?;
+                AbsLockTime::from_consensus(n).map(Terminal::After)
+            }
+            ("older", 1) => {
+                let n = expression::terminal(&top.args[0], expression::parse_num)?;
+                RelLockTime::from_consensus(n).map(Terminal::Older)
+            }
             ("sha256", 1) => expression::terminal(&top.args[0], |x| {
                 sha256::Hash::from_hex(x).map(Terminal::Sha256)
             }),
@@ -513,6 +550,131 @@ impl<Pk: MiniscriptKey + ToPublicKey> PushAstElem<Pk> for script::Builder {
     }
 }
 
+impl<Pk: MiniscriptKey> Terminal<Pk> {
+    /// Maximum number of witness elements used to dissatisfy the Miniscript
+    /// fragment. Used to estimate the weight of the `VarInt` that specifies
+    /// this number in a serialized transaction.
+    ///
+    /// Will panic if the fragment is not an E, W or Ke.
+    pub fn max_dissatisfaction_witness_elements(&self) -> Option<usize> {
+        match *self {
+            Terminal::PkK(..) => Some(1),
+            Terminal::PkH(..) => Some(2),
+            Terminal::False => Some(0),
+            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
+                sub.node.max_dissatisfaction_witness_elements()
+            }
+            Terminal::DupIf(..) | Terminal::NonZero(..) => Some(1),
+            Terminal::AndB(ref l, ref r) => Some(
+                l.node.max_dissatisfaction_witness_elements()?
+                    + r.node.max_dissatisfaction_witness_elements()?,
+            ),
+            Terminal::AndOr(ref a, _, ref c) => Some(
+                a.node.max_dissatisfaction_witness_elements()?
+                    + c.node.max_dissatisfaction_witness_elements()?,
+            ),
+            Terminal::OrB(ref l, ref r) | Terminal::OrD(ref l, ref r) => Some(
+                l.node.max_dissatisfaction_witness_elements()?
+                    + r.node.max_dissatisfaction_witness_elements()?,
+            ),
+            Terminal::OrI(ref l, ref r) => match (
+                l.node.max_dissatisfaction_witness_elements(),
+                r.node.max_dissatisfaction_witness_elements(),
+            ) {
+                (None, Some(r)) => Some(1 + r),
+                (Some(l), None) => Some(1 + l),
+                (None, None) => None,
+                (..) => panic!("tried to dissatisfy or_i with both branches being dissatisfiable"),
+            },
+            Terminal::Thresh(_, ref subs) => {
+                let mut sum = 0;
+                for sub in subs {
+                    match sub.node.max_dissatisfaction_witness_elements() {
+                        Some(s) => sum += s,
+                        None => return None,
+                    }
+                }
+                Some(sum)
+            }
+            Terminal::Multi(k, _) => Some(1 + k),
+            _ => None,
+        }
+    }
+
+    /// Maximum number of witness elements used to satisfy the Miniscript
+    /// fragment. Used to estimate the weight of the `VarInt` that specifies
+    /// this number in a serialized transaction.
+    ///
+    /// This number does not include the witness script itself, so 1 needs
+    /// to be added to the final result.
+    pub fn max_satisfaction_witness_elements(&self) -> usize {
+        match *self {
+            Terminal::PkK(..) => 1,
+            Terminal::PkH(..) => 2,
+            Terminal::After(..) | Terminal::Older(..) => 0,
+            Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..) => 1,
+            Terminal::True => 0,
+            Terminal::False => 0,
+            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
+                sub.node.max_satisfaction_witness_elements()
+            }
+            Terminal::DupIf(ref sub) => 1 + sub.node.max_satisfaction_witness_elements(),
+            Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => sub.node.max_satisfaction_witness_elements(),
+            Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
+                l.node.max_satisfaction_witness_elements()
+                    + r.node.max_satisfaction_witness_elements()
+            }
+            Terminal::AndOr(ref a, ref b, ref c) => cmp::max(
+                a.node.max_satisfaction_witness_elements()
+                    + c.node.max_satisfaction_witness_elements(),
+                a.node.max_dissatisfaction_witness_elements().unwrap()
+                    + b.node.max_satisfaction_witness_elements(),
+            ),
+            Terminal::OrB(ref l, ref r) => cmp::max(
+                l.node.max_satisfaction_witness_elements()
+                    + r.node.max_dissatisfaction_witness_elements().unwrap(),
+                l.node.max_dissatisfaction_witness_elements().unwrap()
+                    + r.node.max_satisfaction_witness_elements(),
+            ),
+            Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => cmp::max(
+                l.node.max_satisfaction_witness_elements(),
+                l.node.max_dissatisfaction_witness_elements().unwrap()
+                    + r.node.max_satisfaction_witness_elements(),
+            ),
+            Terminal::OrI(ref l, ref r) => {
+                1 + cmp::max(
+                    l.node.max_satisfaction_witness_elements(),
+                    r.node.max_satisfaction_witness_elements(),
+                )
+            }
+            Terminal::Thresh(k, ref subs) => {
+                let mut sub_n = subs
+                    .iter()
+                    .map(|sub| {
+                        (
+                            sub.node.max_satisfaction_witness_elements(),
+                            sub.node.max_dissatisfaction_witness_elements().unwrap(),
+                        )
+                    })
+                    .collect::<Vec<(usize, usize)>>();
+                sub_n.sort_by_key(|&(x, y)| x - y);
+                sub_n
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(n, &(x, y))| if n < k { x } else { y })
+                    .sum::<usize>()
+            }
+            Terminal::Multi(k, _) => 1 + k,
+        }
+    }
+}
+
 impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
     /// Encode the element as a fragment of Bitcoin Script. The inverse
     /// function, from Script to an AST element, is implemented in the
@@ -526,9 +688,11 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
                 .push_slice(&Pk::hash_to_hash160(&hash)[..])
                 .push_opcode(opcodes::all::OP_EQUALVERIFY),
             Terminal::After(t) => builder
-                .push_int(t as i64)
+                .push_int(t.to_u32() as i64)
                 .push_opcode(opcodes::all::OP_CLTV),
-            Terminal::Older(t) => builder.push_int(t as i64).push_opcode(opcodes::all::OP_CSV),
+            Terminal::Older(t) => builder
+                .push_int(t.to_u32() as i64)
+                .push_opcode(opcodes::all::OP_CSV),
             Terminal::Sha256(h) => builder
                 .push_opcode(opcodes::all::OP_SIZE)
                 .push_int(32)
@@ -647,8 +811,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
         match *self {
             Terminal::PkK(ref pk) => pk.serialized_len(),
             Terminal::PkH(..) => 24,
-            Terminal::After(n) => script_num_size(n as usize) + 1,
-            Terminal::Older(n) => script_num_size(n as usize) + 1,
+            Terminal::After(n) => script_num_size(n.to_u32() as usize) + 1,
+            Terminal::Older(n) => script_num_size(n.to_u32() as usize) + 1,
             Terminal::Sha256(..) => 33 + 6,
             Terminal::Hash256(..) => 33 + 6,
             Terminal::Ripemd160(..) => 21 + 6,
@@ -690,56 +854,6 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
         }
     }
 
-    /// Maximum number of witness elements used to dissatisfy the Miniscript
-    /// fragment. Used to estimate the weight of the `VarInt` that specifies
-    /// this number in a serialized transaction.
-    ///
-    /// Will panic if the fragment is not an E, W or Ke.
-    pub fn max_dissatisfaction_witness_elements(&self) -> Option<usize> {
-        match *self {
-            Terminal::PkK(..) => Some(1),
-            Terminal::PkH(..) => Some(2),
-            Terminal::False => Some(0),
-            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
-                sub.node.max_dissatisfaction_witness_elements()
-            }
-            Terminal::DupIf(..) | Terminal::NonZero(..) => Some(1),
-            Terminal::AndB(ref l, ref r) => Some(
-                l.node.max_dissatisfaction_witness_elements()?
-                    + r.node.max_dissatisfaction_witness_elements()?,
-            ),
-            Terminal::AndOr(ref a, _, ref c) => Some(
-                a.node.max_dissatisfaction_witness_elements()?
-                    + c.node.max_dissatisfaction_witness_elements()?,
-            ),
-            Terminal::OrB(ref l, ref r) | Terminal::OrD(ref l, ref r) => Some(
-                l.node.max_dissatisfaction_witness_elements()?
-                    + r.node.max_dissatisfaction_witness_elements()?,
-            ),
-            Terminal::OrI(ref l, ref r) => match (
-                l.node.max_dissatisfaction_witness_elements(),
-                r.node.max_dissatisfaction_witness_elements(),
-            ) {
-                (None, Some(r)) => Some(1 + r),
-                (Some(l), None) => Some(1 + l),
-                (None, None) => None,
-                (..) => panic!("tried to dissatisfy or_i with both branches being dissatisfiable"),
-            },
-            Terminal::Thresh(_, ref subs) => {
-                let mut sum = 0;
-                for sub in subs {
-                    match sub.node.max_dissatisfaction_witness_elements() {
-                        Some(s) => sum += s,
-                        None => return None,
-                    }
-                }
-                Some(sum)
-            }
-            Terminal::Multi(k, _) => Some(1 + k),
-            _ => None,
-        }
-    }
-
     /// Maximum dissatisfaction cost, in bytes, of a Miniscript fragment,
     /// if it is possible to compute this. This function should probably
     /// not ever be used directly. It is called from `max_satisfaction_size`.
@@ -790,79 +904,6 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
         }
     }
 
-    /// Maximum number of witness elements used to satisfy the Miniscript
-    /// fragment. Used to estimate the weight of the `VarInt` that specifies
-    /// this number in a serialized transaction.
-    ///
-    /// This number does not include the witness script itself, so 1 needs
-    /// to be added to the final result.
-    pub fn max_satisfaction_witness_elements(&self) -> usize {
-        match *self {
-            Terminal::PkK(..) => 1,
-            Terminal::PkH(..) => 2,
-            Terminal::After(..) | Terminal::Older(..) => 0,
-            Terminal::Sha256(..)
-            | Terminal::Hash256(..)
-            | Terminal::Ripemd160(..)
-            | Terminal::Hash160(..) => 1,
-            Terminal::True => 0,
-            Terminal::False => 0,
-            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
-                sub.node.max_satisfaction_witness_elements()
-            }
-            Terminal::DupIf(ref sub) => 1 + sub.node.max_satisfaction_witness_elements(),
-            Terminal::Verify(ref sub)
-            | Terminal::NonZero(ref sub)
-            | Terminal::ZeroNotEqual(ref sub) => sub.node.max_satisfaction_witness_elements(),
-            Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
-                l.node.max_satisfaction_witness_elements()
-                    + r.node.max_satisfaction_witness_elements()
-            }
-            Terminal::AndOr(ref a, ref b, ref c) => cmp::max(
-                a.node.max_satisfaction_witness_elements()
-                    + c.node.max_satisfaction_witness_elements(),
-                a.node.max_dissatisfaction_witness_elements().unwrap()
-                    + b.node.max_satisfaction_witness_elements(),
-            ),
-            Terminal::OrB(ref l, ref r) => cmp::max(
-                l.node.max_satisfaction_witness_elements()
-                    + r.node.max_dissatisfaction_witness_elements().unwrap(),
-                l.node.max_dissatisfaction_witness_elements().unwrap()
-                    + r.node.max_satisfaction_witness_elements(),
-            ),
-            Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => cmp::max(
-                l.node.max_satisfaction_witness_elements(),
-                l.node.max_dissatisfaction_witness_elements().unwrap()
-                    + r.node.max_satisfaction_witness_elements(),
-            ),
-            Terminal::OrI(ref l, ref r) => {
-                1 + cmp::max(
-                    l.node.max_satisfaction_witness_elements(),
-                    r.node.max_satisfaction_witness_elements(),
-                )
-            }
-            Terminal::Thresh(k, ref subs) => {
-                let mut sub_n = subs
-                    .iter()
-                    .map(|sub| {
-                        (
-                            sub.node.max_satisfaction_witness_elements(),
-                            sub.node.max_dissatisfaction_witness_elements().unwrap(),
-                        )
-                    })
-                    .collect::<Vec<(usize, usize)>>();
-                sub_n.sort_by_key(|&(x, y)| x - y);
-                sub_n
-                    .iter()
-                    .rev()
-                    .enumerate()
-                    .map(|(n, &(x, y))| if n < k { x } else { y })
-                    .sum::<usize>()
-            }
-            Terminal::Multi(k, _) => 1 + k,
-        }
-    }
-
     /// Maximum size, in bytes, of a satisfying witness. For Segwit outputs
     /// `one_cost` should be set to 2, since the number `1` requires two
     /// bytes to encode. For non-segwit outputs `one_cost` should be set to
@@ -880,9 +921,16 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
     /// correspond to semantically sane Scripts. (Such scripts should be rejected
     /// at parse time. Any exceptions are bugs.)
     pub fn max_satisfaction_size(&self, one_cost: usize) -> usize {
+        self.max_satisfaction_size_with_sig_len(one_cost, 73)
+    }
+
+    /// Like `max_satisfaction_size`, but assumes all signatures are `sig_len`
+    /// bytes in size, including the length prefix or push opcode and sighash
+    /// postfix. This function should probably not ever be used directly.
+    pub fn max_satisfaction_size_with_sig_len(&self, one_cost: usize, sig_len: usize) -> usize {
         match *self {
-            Terminal::PkK(..) => 73,
-            Terminal::PkH(..) => 34 + 73,
+            Terminal::PkK(..) => sig_len,
+            Terminal::PkH(..) => 34 + sig_len,
             Terminal::After(..) | Terminal::Older(..) => 0,
             Terminal::Sha256(..)
             | Terminal::Hash256(..)
@@ -890,42 +938,52 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
             | Terminal::Hash160(..) => 33,
             Terminal::True => 0,
             Terminal::False => 0,
-            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
-                sub.node.max_satisfaction_size(one_cost)
+            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => sub
+                .node
+                .max_satisfaction_size_with_sig_len(one_cost, sig_len),
+            Terminal::DupIf(ref sub) => {
+                one_cost
+                    + sub
+                        .node
+                        .max_satisfaction_size_with_sig_len(one_cost, sig_len)
             }
-            Terminal::DupIf(ref sub) => one_cost + sub.node.max_satisfaction_size(one_cost),
             Terminal::Verify(ref sub)
             | Terminal::NonZero(ref sub)
-            | Terminal::ZeroNotEqual(ref sub) => sub.node.max_satisfaction_size(one_cost),
+            | Terminal::ZeroNotEqual(ref sub) => sub
+                .node
+                .max_satisfaction_size_with_sig_len(one_cost, sig_len),
             Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
-                l.node.max_satisfaction_size(one_cost) + r.node.max_satisfaction_size(one_cost)
+                l.node.max_satisfaction_size_with_sig_len(one_cost, sig_len)
+                    + r.node.max_satisfaction_size_with_sig_len(one_cost, sig_len)
             }
             Terminal::AndOr(ref a, ref b, ref c) => cmp::max(
-                a.node.max_satisfaction_size(one_cost) + c.node.max_satisfaction_size(one_cost),
+                a.node.max_satisfaction_size_with_sig_len(one_cost, sig_len)
+                    + c.node.max_satisfaction_size_with_sig_len(one_cost, sig_len),
                 a.node.max_dissatisfaction_size(one_cost).unwrap()
-                    + b.node.max_satisfaction_size(one_cost),
+                    + b.node.max_satisfaction_size_with_sig_len(one_cost, sig_len),
             ),
             Terminal::OrB(ref l, ref r) => cmp::max(
-                l.node.max_satisfaction_size(one_cost)
+                l.node.max_satisfaction_size_with_sig_len(one_cost, sig_len)
                     + r.node.max_dissatisfaction_size(one_cost).unwrap(),
                 l.node.max_dissatisfaction_size(one_cost).unwrap()
-                    + r.node.max_satisfaction_size(one_cost),
+                    + r.node.max_satisfaction_size_with_sig_len(one_cost, sig_len),
             ),
             Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => cmp::max(
-                l.node.max_satisfaction_size(one_cost),
+                l.node.max_satisfaction_size_with_sig_len(one_cost, sig_len),
                 l.node.max_dissatisfaction_size(one_cost).unwrap()
-                    + r.node.max_satisfaction_size(one_cost),
+                    + r.node.max_satisfaction_size_with_sig_len(one_cost, sig_len),
             ),
             Terminal::OrI(ref l, ref r) => cmp::max(
-                one_cost + l.node.max_satisfaction_size(one_cost),
-                1 + r.node.max_satisfaction_size(one_cost),
+                one_cost + l.node.max_satisfaction_size_with_sig_len(one_cost, sig_len),
+                1 + r.node.max_satisfaction_size_with_sig_len(one_cost, sig_len),
             ),
             Terminal::Thresh(k, ref subs) => {
                 let mut sub_n = subs
                     .iter()
                     .map(|sub| {
                         (
-                            sub.node.max_satisfaction_size(one_cost),
+                            sub.node
+                                .max_satisfaction_size_with_sig_len(one_cost, sig_len),
                             sub.node.max_dissatisfaction_size(one_cost).unwrap(),
                         )
                     })
@@ -938,7 +996,7 @@ impl<Pk: MiniscriptKey + ToPublicKey> Terminal<Pk> {
                     .map(|(n, &(x, y))| if n < k { x } else { y })
                     .sum::<usize>()
             }
-            Terminal::Multi(k, _) => 1 + 73 * k,
+            Terminal::Multi(k, _) => 1 + sig_len * k,
         }
     }
 }
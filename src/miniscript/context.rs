@@ -0,0 +1,132 @@
+//! # Script Contexts
+//!
+//! The same Miniscript may be used as a bare script, a P2SH redeem script
+//! or a P2WSH witness script. Which resource limits it has to respect
+//! depends on this context, which is given as a type parameter, e.g.
+//! `ms.within_resource_limits::<Segwitv0>()`.
+//!
+//! Some of these limits are enforced by consensus, so that a script
+//! exceeding them can never be spent. Others are only standardness rules of
+//! Bitcoin Core, so spends of the script will not be relayed, but can still
+//! be mined.
+//!
+
+use descriptor::{MAX_STANDARD_P2WSH_SCRIPT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEMS};
+use miniscript::types::extra_props::MAX_OPS_PER_SCRIPT;
+use miniscript::Miniscript;
+use {Error, MiniscriptKey};
+
+/// Maximum size of any script, enforced by consensus
+pub const MAX_SCRIPT_SIZE: usize = 10000;
+/// Maximum number of elements on the stack during script execution,
+/// enforced by consensus
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// The context a Miniscript is used in, determining the resource limits
+/// applying to it
+pub trait ScriptContext {
+    /// Maximum size in bytes of the script allowed by consensus
+    fn max_consensus_script_size() -> usize {
+        MAX_SCRIPT_SIZE
+    }
+
+    /// Maximum number of elements of a satisfaction allowed by consensus
+    fn max_consensus_witness_elements() -> usize {
+        MAX_STACK_SIZE
+    }
+
+    /// Maximum size in bytes of a standard script, if limited beyond the
+    /// consensus limit
+    fn max_standard_script_size() -> Option<usize>;
+
+    /// Maximum number of witness elements of a standard satisfaction, not
+    /// counting the witness script, if limited beyond the consensus limit
+    fn max_standard_witness_elements() -> Option<usize>;
+
+    /// Checks that `pk` may be used in this context
+    fn check_pk<Pk: MiniscriptKey>(_pk: &Pk) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Checks that `ms` does not exceed the consensus limits of this
+    /// context on its executed opcodes, script size and witness elements.
+    /// Scripts exceeding them can never be spent. Script sizes are computed
+    /// assuming compressed keys.
+    fn check_consensus_limits<Pk: MiniscriptKey>(ms: &Miniscript<Pk>) -> Result<(), Error> {
+        let ops = ms.ext.ops_count_sat.unwrap_or(ms.ext.ops_count_static);
+        if ops > MAX_OPS_PER_SCRIPT {
+            return Err(Error::MaxOpsExceeded(ops));
+        }
+        if ms.ext.pk_cost > Self::max_consensus_script_size() {
+            return Err(Error::MaxScriptSizeExceeded(ms.ext.pk_cost));
+        }
+        let n = ms.node.max_satisfaction_witness_elements();
+        if n > Self::max_consensus_witness_elements() {
+            return Err(Error::MaxWitnessItemsExceeded(n));
+        }
+        Ok(())
+    }
+
+    /// Checks that `ms` does not exceed the standardness limits of this
+    /// context on its script size and witness elements, beyond those
+    /// checked by `check_consensus_limits`. Spends of scripts exceeding them
+    /// are not relayed by Bitcoin Core, but are valid.
+    fn check_standard_limits<Pk: MiniscriptKey>(ms: &Miniscript<Pk>) -> Result<(), Error> {
+        if let Some(max) = Self::max_standard_script_size() {
+            if ms.ext.pk_cost > max {
+                return Err(Error::MaxScriptSizeExceeded(ms.ext.pk_cost));
+            }
+        }
+        if let Some(max) = Self::max_standard_witness_elements() {
+            let n = ms.node.max_satisfaction_witness_elements();
+            if n > max {
+                return Err(Error::MaxWitnessItemsExceeded(n));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `ms` does not exceed either the consensus or the
+    /// standardness limits of this context
+    fn check_resource_limits<Pk: MiniscriptKey>(ms: &Miniscript<Pk>) -> Result<(), Error> {
+        Self::check_consensus_limits(ms)?;
+        Self::check_standard_limits(ms)
+    }
+}
+
+/// Bare scripts and P2SH redeem scripts
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum Legacy {}
+
+/// P2WSH witness scripts, including P2SH-wrapped ones
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum Segwitv0 {}
+
+impl ScriptContext for Legacy {
+    fn max_standard_script_size() -> Option<usize> {
+        None
+    }
+
+    fn max_standard_witness_elements() -> Option<usize> {
+        None
+    }
+}
+
+impl ScriptContext for Segwitv0 {
+    fn max_standard_script_size() -> Option<usize> {
+        Some(MAX_STANDARD_P2WSH_SCRIPT_SIZE)
+    }
+
+    fn max_standard_witness_elements() -> Option<usize> {
+        Some(MAX_STANDARD_P2WSH_STACK_ITEMS)
+    }
+
+    /// Rejects uncompressed keys, which are non-standard in segwit
+    fn check_pk<Pk: MiniscriptKey>(pk: &Pk) -> Result<(), Error> {
+        if pk.is_uncompressed() {
+            Err(Error::UncompressedSegwitKey(pk.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -21,17 +21,11 @@ use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use {bitcoin, Miniscript};
 
 use miniscript::lex::{Token as Tk, TokenIter};
-use miniscript::types::extra_props::ExtData;
-use miniscript::types::Property;
-use miniscript::types::Type;
+use miniscript::locktime::{AbsLockTime, RelLockTime};
 use std::sync::Arc;
 use Error;
 use MiniscriptKey;
 
-fn return_none<T>(_: usize) -> Option<T> {
-    None
-}
-
 #[derive(Copy, Clone, Debug)]
 enum NonTerm {
     Expression,
@@ -71,10 +65,10 @@ pub enum Terminal<Pk: MiniscriptKey> {
     /// `DUP HASH160 <keyhash> EQUALVERIFY`
     PkH(Pk::Hash),
     // timelocks
-    /// `n CHECKSEQUENCEVERIFY`
-    After(u32),
     /// `n CHECKLOCKTIMEVERIFY`
-    Older(u32),
+    After(AbsLockTime),
+    /// `n CHECKSEQUENCEVERIFY`
+    Older(RelLockTime),
     // hashlocks
     /// `SIZE 32 EQUALVERIFY SHA256 <hash> EQUAL`
     Sha256(sha256::Hash),
@@ -152,13 +146,7 @@ impl<Pk: MiniscriptKey> TerminalStack<Pk> {
 
     ///reduce, type check and push a 0-arg node
     fn reduce0(&mut self, ms: Terminal<Pk>) -> Result<(), Error> {
-        let ty = Type::type_check(&ms, return_none)?;
-        let ext = ExtData::type_check(&ms, return_none)?;
-        self.0.push(Miniscript {
-            node: ms,
-            ty: ty,
-            ext: ext,
-        });
+        self.0.push(Miniscript::from_ast(ms)?);
         Ok(())
     }
 
@@ -170,13 +158,7 @@ impl<Pk: MiniscriptKey> TerminalStack<Pk> {
         let top = self.pop().unwrap();
         let wrapped_ms = wrap(Arc::new(top));
 
-        let ty = Type::type_check(&wrapped_ms, return_none)?;
-        let ext = ExtData::type_check(&wrapped_ms, return_none)?;
-        self.0.push(Miniscript {
-            node: wrapped_ms,
-            ty: ty,
-            ext: ext,
-        });
+        self.0.push(Miniscript::from_ast(wrapped_ms)?);
         Ok(())
     }
 
@@ -189,13 +171,7 @@ impl<Pk: MiniscriptKey> TerminalStack<Pk> {
         let right = self.pop().unwrap();
 
         let wrapped_ms = wrap(Arc::new(left), Arc::new(right));
-        let ty = Type::type_check(&wrapped_ms, return_none)?;
-        let ext = ExtData::type_check(&wrapped_ms, return_none)?;
-        self.0.push(Miniscript {
-            node: wrapped_ms,
-            ty: ty,
-            ext: ext,
-        });
+        self.0.push(Miniscript::from_ast(wrapped_ms)?);
         Ok(())
     }
 }
@@ -240,9 +216,9 @@ pub fn parse(tokens: &mut TokenIter) -> Result<Miniscript<bitcoin::PublicKey>, E
                     },
                     // timelocks
                     Tk::CheckSequenceVerify, Tk::Num(n)
-                        => term.reduce0(Terminal::Older(n))?,
+                        => term.reduce0(Terminal::Older(RelLockTime::from_consensus(n)?))?,
                     Tk::CheckLockTimeVerify, Tk::Num(n)
-                        => term.reduce0(Terminal::After(n))?,
+                        => term.reduce0(Terminal::After(AbsLockTime::from_consensus(n)?))?,
                     // hashlocks
                     Tk::Equal => match_token!(
                         tokens,
@@ -388,14 +364,7 @@ pub fn parse(tokens: &mut TokenIter) -> Result<Miniscript<bitcoin::PublicKey>, E
                 let c = term.pop().unwrap();
                 let wrapped_ms = Terminal::AndOr(Arc::new(a), Arc::new(c), Arc::new(b));
 
-                let ty = Type::type_check(&wrapped_ms, return_none)?;
-                let ext = ExtData::type_check(&wrapped_ms, return_none)?;
-
-                term.0.push(Miniscript {
-                    node: wrapped_ms,
-                    ty: ty,
-                    ext: ext,
-                });
+                term.0.push(Miniscript::from_ast(wrapped_ms)?);
             }
             Some(NonTerm::ThreshW { n, k }) => {
                 match_token!(
@@ -0,0 +1,151 @@
+//! # Iterators
+//!
+//! Depth-first iterators over the subexpressions of a Miniscript and the
+//! keys and key hashes in it, in the order they appear in its string form.
+//!
+
+use std::vec;
+
+use miniscript::decode::Terminal;
+use {Miniscript, MiniscriptKey};
+
+impl<Pk: MiniscriptKey> Miniscript<Pk> {
+    /// Iterates over the Miniscript and all its subexpressions, including
+    /// wrappers, visiting each node before its children
+    pub fn iter(&self) -> Iter<Pk> {
+        Iter { stack: vec![self] }
+    }
+
+    /// Iterates over the public keys of `pk_k` and `multi` fragments.
+    /// Keys of `pk_h` fragments, which only hold the hash, are not included.
+    pub fn iter_pk(&self) -> PkIter<Pk> {
+        PkIter {
+            nodes: self.iter(),
+            keys: vec![].into_iter(),
+        }
+    }
+
+    /// Iterates over the public key hashes of `pk_h` fragments
+    pub fn iter_pkh(&self) -> PkhIter<Pk> {
+        PkhIter { nodes: self.iter() }
+    }
+
+    /// The direct subexpressions of the Miniscript, in order
+    pub fn branches(&self) -> Vec<&Miniscript<Pk>> {
+        match self.node {
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::DupIf(ref sub)
+            | Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => vec![&**sub],
+            Terminal::AndV(ref l, ref r)
+            | Terminal::AndB(ref l, ref r)
+            | Terminal::OrB(ref l, ref r)
+            | Terminal::OrD(ref l, ref r)
+            | Terminal::OrC(ref l, ref r)
+            | Terminal::OrI(ref l, ref r) => vec![&**l, &**r],
+            Terminal::AndOr(ref a, ref b, ref c) => vec![&**a, &**b, &**c],
+            Terminal::Thresh(_, ref subs) => subs.iter().map(|sub| &**sub).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Iterator over a Miniscript and its subexpressions, see `Miniscript::iter`
+pub struct Iter<'a, Pk: MiniscriptKey + 'a> {
+    stack: Vec<&'a Miniscript<Pk>>,
+}
+
+impl<'a, Pk: MiniscriptKey> Iterator for Iter<'a, Pk> {
+    type Item = &'a Miniscript<Pk>;
+
+    fn next(&mut self) -> Option<&'a Miniscript<Pk>> {
+        let ms = self.stack.pop()?;
+        self.stack.extend(ms.branches().into_iter().rev());
+        Some(ms)
+    }
+}
+
+/// Iterator over the public keys of a Miniscript, see `Miniscript::iter_pk`
+pub struct PkIter<'a, Pk: MiniscriptKey + 'a> {
+    nodes: Iter<'a, Pk>,
+    keys: vec::IntoIter<&'a Pk>,
+}
+
+impl<'a, Pk: MiniscriptKey> Iterator for PkIter<'a, Pk> {
+    type Item = &'a Pk;
+
+    fn next(&mut self) -> Option<&'a Pk> {
+        loop {
+            if let Some(pk) = self.keys.next() {
+                return Some(pk);
+            }
+            match self.nodes.next()?.node {
+                Terminal::PkK(ref pk) => return Some(pk),
+                Terminal::Multi(_, ref keys) => {
+                    self.keys = keys.iter().collect::<Vec<_>>().into_iter();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Iterator over the public key hashes of a Miniscript, see
+/// `Miniscript::iter_pkh`
+pub struct PkhIter<'a, Pk: MiniscriptKey + 'a> {
+    nodes: Iter<'a, Pk>,
+}
+
+impl<'a, Pk: MiniscriptKey> Iterator for PkhIter<'a, Pk> {
+    type Item = &'a Pk::Hash;
+
+    fn next(&mut self) -> Option<&'a Pk::Hash> {
+        loop {
+            if let Terminal::PkH(ref pkh) = self.nodes.next()?.node {
+                return Some(pkh);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin;
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn iterators() {
+        let ms = Miniscript::<String>::from_str(
+            "or_d(multi(1,A,B),and_v(v:pk_h(C),or_b(pk(D),a:pk_h(E))))",
+        )
+        .unwrap();
+
+        // Wrappers, including the implicit `c:`s of `pk` and `pk_h`, are
+        // nodes of their own
+        let nodes: Vec<_> = ms.iter().collect();
+        assert_eq!(nodes.len(), 12);
+        assert_eq!(nodes[0], &ms);
+        assert_eq!(nodes[1].to_string(), "multi(1,A,B)");
+        match nodes[11].node {
+            Terminal::PkH(ref pkh) => assert_eq!(pkh, "E"),
+            ref x => panic!("unexpected node {:?}", x),
+        }
+
+        assert_eq!(ms.iter_pk().collect::<Vec<_>>(), vec!["A", "B", "D"]);
+        assert_eq!(ms.iter_pkh().collect::<Vec<_>>(), vec!["C", "E"]);
+        assert_eq!(ms.branches().len(), 2);
+
+        let pk = bitcoin::PublicKey::from_str(
+            "020000000000000000000000000000000000000000000000000000000000000002",
+        )
+        .unwrap();
+        let ms = Miniscript::<bitcoin::PublicKey>::from_str(&format!("pk({})", pk)).unwrap();
+        assert_eq!(ms.iter_pk().collect::<Vec<_>>(), vec![&pk]);
+        assert_eq!(ms.iter_pkh().count(), 0);
+    }
+}
@@ -0,0 +1,150 @@
+//! # Timelocks
+//!
+//! The values of the `after` and `older` fragments, i.e. the arguments of
+//! `OP_CHECKLOCKTIMEVERIFY` and `OP_CHECKSEQUENCEVERIFY`. Both are either
+//! block based or time based, which is determined by their value; they are
+//! kept as distinct types so that they can't be mixed up.
+//!
+
+use std::fmt;
+
+use Error;
+
+/// Absolute timelocks below this value are block heights, others are UNIX
+/// timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Relative timelocks with this bit set are in units of 512 seconds,
+/// others are in blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+
+/// Bits of a relative timelock holding its value
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0xffff;
+
+/// Timelocks with this bit set would disable the check, so they are not
+/// allowed in Miniscript
+const LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+
+/// An absolute timelock, as required by `after`, compared against the
+/// `nLockTime` of the spending transaction
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct AbsLockTime(u32);
+
+impl AbsLockTime {
+    /// Wraps the consensus encoding of the timelock, which must be at least
+    /// 1 and below 2^31
+    pub fn from_consensus(n: u32) -> Result<AbsLockTime, Error> {
+        if n == 0 || n & LOCKTIME_DISABLE_FLAG != 0 {
+            Err(Error::InvalidLockTime(n))
+        } else {
+            Ok(AbsLockTime(n))
+        }
+    }
+
+    /// The consensus encoding of the timelock
+    pub fn to_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Whether the timelock is a block height
+    pub fn is_block_height(self) -> bool {
+        self.0 < LOCKTIME_THRESHOLD
+    }
+
+    /// Whether the timelock is a UNIX timestamp
+    pub fn is_block_time(self) -> bool {
+        !self.is_block_height()
+    }
+
+    /// Whether a transaction with `nLockTime` set to `n` satisfies the
+    /// timelock. Heights and timestamps never satisfy each other.
+    pub fn is_satisfied_by(self, n: u32) -> bool {
+        (n < LOCKTIME_THRESHOLD) == self.is_block_height() && n >= self.0
+    }
+}
+
+impl fmt::Display for AbsLockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// A relative timelock, as required by `older`, compared against the
+/// `nSequence` of the spending input
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct RelLockTime(u32);
+
+impl RelLockTime {
+    /// Wraps the consensus encoding of the timelock, which must be at least
+    /// 1 and not have the disable flag (bit 31) set
+    pub fn from_consensus(n: u32) -> Result<RelLockTime, Error> {
+        if n == 0 || n & LOCKTIME_DISABLE_FLAG != 0 {
+            Err(Error::InvalidLockTime(n))
+        } else {
+            Ok(RelLockTime(n))
+        }
+    }
+
+    /// The consensus encoding of the timelock
+    pub fn to_u32(self) -> u32 {
+        self.0
+    }
+
+    /// Whether the timelock is a number of blocks
+    pub fn is_height_locked(self) -> bool {
+        self.0 & SEQUENCE_LOCKTIME_TYPE_FLAG == 0
+    }
+
+    /// Whether the timelock is in units of 512 seconds
+    pub fn is_time_locked(self) -> bool {
+        !self.is_height_locked()
+    }
+
+    /// Whether an input with `nSequence` set to `n` satisfies the timelock.
+    /// Only the type flag and the low 16 bits are compared, as by
+    /// `OP_CHECKSEQUENCEVERIFY`.
+    pub fn is_satisfied_by(self, n: u32) -> bool {
+        n & LOCKTIME_DISABLE_FLAG == 0
+            && (n & SEQUENCE_LOCKTIME_TYPE_FLAG) == (self.0 & SEQUENCE_LOCKTIME_TYPE_FLAG)
+            && n & SEQUENCE_LOCKTIME_MASK >= self.0 & SEQUENCE_LOCKTIME_MASK
+    }
+}
+
+impl fmt::Display for RelLockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_time_ranges() {
+        assert!(AbsLockTime::from_consensus(0).is_err());
+        assert!(AbsLockTime::from_consensus(0x8000_0000).is_err());
+        assert!(RelLockTime::from_consensus(0).is_err());
+        assert!(RelLockTime::from_consensus(0x8000_0000).is_err());
+
+        let height = AbsLockTime::from_consensus(600_000).unwrap();
+        let time = AbsLockTime::from_consensus(1_600_000_000).unwrap();
+        assert!(height.is_block_height() && !height.is_block_time());
+        assert!(time.is_block_time() && !time.is_block_height());
+        assert!(height.is_satisfied_by(600_000));
+        assert!(!height.is_satisfied_by(599_999));
+        assert!(!height.is_satisfied_by(1_600_000_000));
+        assert!(time.is_satisfied_by(1_600_000_001));
+
+        let blocks = RelLockTime::from_consensus(144).unwrap();
+        let time = RelLockTime::from_consensus(SEQUENCE_LOCKTIME_TYPE_FLAG | 10).unwrap();
+        assert!(blocks.is_height_locked() && !blocks.is_time_locked());
+        assert!(time.is_time_locked() && !time.is_height_locked());
+        assert!(blocks.is_satisfied_by(144));
+        assert!(!blocks.is_satisfied_by(143));
+        assert!(!blocks.is_satisfied_by(SEQUENCE_LOCKTIME_TYPE_FLAG | 200));
+        assert!(!blocks.is_satisfied_by(0xffff_ffff));
+        assert!(time.is_satisfied_by(SEQUENCE_LOCKTIME_TYPE_FLAG | 10));
+        assert_eq!(blocks.to_string(), "144");
+    }
+}
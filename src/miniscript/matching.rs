@@ -0,0 +1,206 @@
+//! # Template Matching
+//!
+//! Recognizes instances of a known contract, e.g. for a watchtower: a
+//! Miniscript whose keys are placeholders is used as a template, and
+//! matched against scripts found on chain. On a match each placeholder is
+//! bound to the key found in its place.
+//!
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash;
+
+use bitcoin;
+use bitcoin::blockdata::script;
+use bitcoin::hashes::hash160;
+
+use miniscript::decode::Terminal;
+use {Miniscript, MiniscriptKey};
+
+/// The keys and key hashes found in place of the placeholders of a
+/// template, see `Miniscript::match_script`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TemplateMatch<Pk: MiniscriptKey> {
+    /// The key found in place of each key of the template
+    pub keys: HashMap<Pk, bitcoin::PublicKey>,
+    /// The key hash found in place of each key hash of the template, as
+    /// used by `pk_h`
+    pub key_hashes: HashMap<Pk::Hash, hash160::Hash>,
+}
+
+impl<Pk: MiniscriptKey> Miniscript<Pk> {
+    /// Matches `script` against the Miniscript as a template. The script
+    /// has to have the same structure, with any key in place of each key of
+    /// the template, as long as the same placeholder always stands for the
+    /// same key. Timelocks and hashes have to match exactly.
+    pub fn match_script(&self, script: &script::Script) -> Option<TemplateMatch<Pk>> {
+        let ms = Miniscript::parse(script).ok()?;
+        self.match_miniscript(&ms)
+    }
+
+    /// Matches the witness of a P2WSH input against the Miniscript as a
+    /// template, see `match_script`. The last element of the witness is the
+    /// witness script; the others are ignored.
+    pub fn match_witness(&self, witness: &[Vec<u8>]) -> Option<TemplateMatch<Pk>> {
+        let script = script::Script::from(witness.last()?.clone());
+        self.match_script(&script)
+    }
+
+    /// Matches an already parsed Miniscript against the Miniscript as a
+    /// template, see `match_script`
+    pub fn match_miniscript(
+        &self,
+        ms: &Miniscript<bitcoin::PublicKey>,
+    ) -> Option<TemplateMatch<Pk>> {
+        let mut res = TemplateMatch {
+            keys: HashMap::new(),
+            key_hashes: HashMap::new(),
+        };
+
+        let mut stack = vec![(self, ms)];
+        while let Some((tmpl, ms)) = stack.pop() {
+            match (&tmpl.node, &ms.node) {
+                (&Terminal::PkK(ref t), &Terminal::PkK(ref pk)) => bind(&mut res.keys, t, pk)?,
+                (&Terminal::PkH(ref t), &Terminal::PkH(ref pkh)) => {
+                    bind(&mut res.key_hashes, t, pkh)?
+                }
+                (&Terminal::Multi(tk, ref tkeys), &Terminal::Multi(k, ref keys)) => {
+                    if tk != k || tkeys.len() != keys.len() {
+                        return None;
+                    }
+                    for (t, pk) in tkeys.iter().zip(keys) {
+                        bind(&mut res.keys, t, pk)?;
+                    }
+                }
+                (&Terminal::After(t), &Terminal::After(n)) if t == n => {}
+                (&Terminal::Older(t), &Terminal::Older(n)) if t == n => {}
+                (&Terminal::Sha256(t), &Terminal::Sha256(h)) if t == h => {}
+                (&Terminal::Hash256(t), &Terminal::Hash256(h)) if t == h => {}
+                (&Terminal::Ripemd160(t), &Terminal::Ripemd160(h)) if t == h => {}
+                (&Terminal::Hash160(t), &Terminal::Hash160(h)) if t == h => {}
+                (&Terminal::True, &Terminal::True) | (&Terminal::False, &Terminal::False) => {}
+                (&Terminal::Alt(ref t), &Terminal::Alt(ref sub))
+                | (&Terminal::Swap(ref t), &Terminal::Swap(ref sub))
+                | (&Terminal::Check(ref t), &Terminal::Check(ref sub))
+                | (&Terminal::DupIf(ref t), &Terminal::DupIf(ref sub))
+                | (&Terminal::Verify(ref t), &Terminal::Verify(ref sub))
+                | (&Terminal::NonZero(ref t), &Terminal::NonZero(ref sub))
+                | (&Terminal::ZeroNotEqual(ref t), &Terminal::ZeroNotEqual(ref sub)) => {
+                    stack.push((&**t, &**sub))
+                }
+                (&Terminal::AndV(ref tl, ref tr), &Terminal::AndV(ref l, ref r))
+                | (&Terminal::AndB(ref tl, ref tr), &Terminal::AndB(ref l, ref r))
+                | (&Terminal::OrB(ref tl, ref tr), &Terminal::OrB(ref l, ref r))
+                | (&Terminal::OrD(ref tl, ref tr), &Terminal::OrD(ref l, ref r))
+                | (&Terminal::OrC(ref tl, ref tr), &Terminal::OrC(ref l, ref r))
+                | (&Terminal::OrI(ref tl, ref tr), &Terminal::OrI(ref l, ref r)) => {
+                    stack.push((&**tl, &**l));
+                    stack.push((&**tr, &**r));
+                }
+                (
+                    &Terminal::AndOr(ref ta, ref tb, ref tc),
+                    &Terminal::AndOr(ref a, ref b, ref c),
+                ) => {
+                    stack.push((&**ta, &**a));
+                    stack.push((&**tb, &**b));
+                    stack.push((&**tc, &**c));
+                }
+                (&Terminal::Thresh(tk, ref tsubs), &Terminal::Thresh(k, ref subs)) => {
+                    if tk != k || tsubs.len() != subs.len() {
+                        return None;
+                    }
+                    for (t, sub) in tsubs.iter().zip(subs) {
+                        stack.push((&**t, &**sub));
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        // A placeholder used both as a key and as a key hash has to stand
+        // for the same key in both places
+        for (t, pk) in &res.keys {
+            if let Some(pkh) = res.key_hashes.get(&t.to_pubkeyhash()) {
+                if pk.to_pubkeyhash() != *pkh {
+                    return None;
+                }
+            }
+        }
+        Some(res)
+    }
+}
+
+/// Binds `placeholder` to `value`, failing if it is already bound to
+/// another value
+fn bind<K, V>(map: &mut HashMap<K, V>, placeholder: &K, value: &V) -> Option<()>
+where
+    K: Clone + Eq + hash::Hash,
+    V: Copy + PartialEq,
+{
+    match map.entry(placeholder.clone()) {
+        Entry::Vacant(e) => {
+            e.insert(*value);
+            Some(())
+        }
+        Entry::Occupied(e) => {
+            if e.get() == value {
+                Some(())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn match_script() {
+        let alice = bitcoin::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let bob = bitcoin::PublicKey::from_str(
+            "020000000000000000000000000000000000000000000000000000000000000002",
+        )
+        .unwrap();
+        let instance = |tmpl: &Miniscript<String>| {
+            let key = |name: &String| if name == "A" { alice } else { bob };
+            let ms: Result<Miniscript<bitcoin::PublicKey>, ()> = tmpl.translate_pk(&mut (
+                |k: &String| Ok(key(k)),
+                |h: &String| Ok(key(h).to_pubkeyhash()),
+            ));
+            ms.unwrap().encode()
+        };
+
+        let tmpl =
+            Miniscript::<String>::from_str("or_d(pk(A),and_v(v:pk_h(B),older(144)))").unwrap();
+        let res = tmpl.match_script(&instance(&tmpl)).unwrap();
+        assert_eq!(res.keys.len(), 1);
+        assert_eq!(res.keys["A"], alice);
+        assert_eq!(res.key_hashes["B"], bob.to_pubkeyhash());
+        assert_eq!(
+            tmpl.match_witness(&[vec![], instance(&tmpl).into_bytes()]),
+            Some(res)
+        );
+
+        // Timelocks have to match exactly
+        let other =
+            Miniscript::<String>::from_str("or_d(pk(A),and_v(v:pk_h(B),older(145)))").unwrap();
+        assert_eq!(tmpl.match_script(&instance(&other)), None);
+
+        // As do placeholders used more than once
+        let tmpl = Miniscript::<String>::from_str("and_v(v:pk(A),pk(A))").unwrap();
+        let other = Miniscript::<String>::from_str("and_v(v:pk(A),pk(B))").unwrap();
+        assert!(tmpl.match_script(&instance(&tmpl)).is_some());
+        assert_eq!(tmpl.match_script(&instance(&other)), None);
+        let tmpl = Miniscript::<String>::from_str("and_v(v:pk(A),pk_h(A))").unwrap();
+        let other = Miniscript::<String>::from_str("and_v(v:pk(A),pk_h(B))").unwrap();
+        assert!(tmpl.match_script(&instance(&tmpl)).is_some());
+        assert_eq!(tmpl.match_script(&instance(&other)), None);
+    }
+}
@@ -26,28 +26,101 @@
 
 #[cfg(feature = "serde")]
 use serde::{de, ser};
-use std::{fmt, str};
+use std::{fmt, hash, str};
 
 use bitcoin;
 use bitcoin::blockdata::script;
 
+pub mod analyzable;
 pub mod astelem;
+pub mod context;
 pub mod decode;
+pub mod iter;
 pub mod lex;
+pub mod locktime;
+pub mod matching;
 pub mod satisfy;
 pub mod types;
 
+use self::context::ScriptContext;
 use self::lex::{lex, TokenIter};
 use self::types::Property;
 use miniscript::types::extra_props::ExtData;
 use miniscript::types::Type;
 use std::cmp;
+use std::mem;
 use std::sync::Arc;
 use MiniscriptKey;
-use {expression, Error, ToPublicKey};
+use {expression, Error, ToPublicKey, Translator};
+
+/// The data pushed by a provably unspendable script, e.g. the payload of an
+/// `OP_RETURN` output
+fn unspendable_payload(script: &script::Script) -> Vec<u8> {
+    let mut ret = vec![];
+    for ins in script.iter(false) {
+        if let script::Instruction::PushBytes(data) = ins {
+            ret.extend_from_slice(data);
+        }
+    }
+    ret
+}
+
+/// Maximum number of levels fragments may be nested, including wrappers.
+/// Constructing or parsing a deeper Miniscript fails with
+/// `Error::MaxDepthExceeded`, so that code walking a Miniscript recursively
+/// cannot run out of stack.
+pub const MAX_RECURSION_DEPTH: usize = 402;
+
+/// Limits on the size of Miniscripts accepted by the parsers. The defaults
+/// are those of Bitcoin Core and admit every script within the consensus
+/// script size limit; embedded signers may tighten them, and analysis tools
+/// may relax them at the cost of more stack space while parsing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ParseLimits {
+    /// Maximum number of levels fragments may be nested. Values above
+    /// `MAX_RECURSION_DEPTH` have no effect.
+    pub max_depth: usize,
+    /// Maximum number of fragments, including wrappers
+    pub max_fragments: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> ParseLimits {
+        ParseLimits {
+            max_depth: MAX_RECURSION_DEPTH,
+            max_fragments: 10000,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// Limits for analysis of scripts beyond the consensus resource limits,
+    /// as used by the `from_str_insane` parsers. There is no limit on the
+    /// number of fragments; nesting is still limited to the default depth,
+    /// since the parsers recurse once per level.
+    pub fn insane() -> ParseLimits {
+        ParseLimits {
+            max_fragments: usize::max_value(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A rule broken by a script accepted by `Miniscript::parse_insane`
+#[derive(Debug)]
+pub enum Violation {
+    /// Some satisfaction needs no signature, so anyone may spend the output
+    /// once its other conditions are met
+    NotSafe,
+    /// Some satisfactions can be turned into others by third parties
+    Malleable,
+    /// The script breaks a rule of the context it was parsed for, see
+    /// `check_resource_limits` and `check_keys`
+    Context(Error),
+}
 
 /// Top-level script AST type
-#[derive(Clone, Hash)]
+#[derive(Clone)]
 pub struct Miniscript<Pk: MiniscriptKey> {
     ///A node in the Abstract Syntax Tree(
     pub node: decode::Terminal<Pk>,
@@ -89,6 +162,61 @@ impl<Pk: MiniscriptKey> PartialEq for Miniscript<Pk> {
 /// by the ast.
 impl<Pk: MiniscriptKey> Eq for Miniscript<Pk> {}
 
+/// `Hash` of `Miniscript` must depend only on node and not the type information,
+/// to agree with `PartialEq`.
+impl<Pk: MiniscriptKey> hash::Hash for Miniscript<Pk> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.node.hash(state);
+    }
+}
+
+impl<Pk: MiniscriptKey> Drop for Miniscript<Pk> {
+    // Dropping the subexpressions one by one by recursing could overflow
+    // the stack on deep trees, so those we hold the last reference to are
+    // taken apart on an explicit stack instead
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        take_branches(&mut self.node, &mut stack);
+        while let Some(sub) = stack.pop() {
+            if let Ok(mut ms) = Arc::try_unwrap(sub) {
+                take_branches(&mut ms.node, &mut stack);
+            }
+        }
+    }
+}
+
+/// Moves the subexpressions of `node` to `stack`, leaving `1` in its place
+fn take_branches<Pk: MiniscriptKey>(
+    node: &mut decode::Terminal<Pk>,
+    stack: &mut Vec<Arc<Miniscript<Pk>>>,
+) {
+    match mem::replace(node, decode::Terminal::True) {
+        decode::Terminal::Alt(sub)
+        | decode::Terminal::Swap(sub)
+        | decode::Terminal::Check(sub)
+        | decode::Terminal::DupIf(sub)
+        | decode::Terminal::Verify(sub)
+        | decode::Terminal::NonZero(sub)
+        | decode::Terminal::ZeroNotEqual(sub) => stack.push(sub),
+        decode::Terminal::AndV(l, r)
+        | decode::Terminal::AndB(l, r)
+        | decode::Terminal::OrB(l, r)
+        | decode::Terminal::OrD(l, r)
+        | decode::Terminal::OrC(l, r)
+        | decode::Terminal::OrI(l, r) => {
+            stack.push(l);
+            stack.push(r);
+        }
+        decode::Terminal::AndOr(a, b, c) => {
+            stack.push(a);
+            stack.push(b);
+            stack.push(c);
+        }
+        decode::Terminal::Thresh(_, subs) => stack.extend(subs),
+        _ => {}
+    }
+}
+
 impl<Pk: MiniscriptKey> fmt::Debug for Miniscript<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self.node)
@@ -98,11 +226,16 @@ impl<Pk: MiniscriptKey> fmt::Debug for Miniscript<Pk> {
 impl<Pk: MiniscriptKey> Miniscript<Pk> {
     /// Add type information(Type and Extdata) to Miniscript based on
     /// `AstElem` fragment. Dependent on display and clone because of Error
-    /// Display code of type_check.
+    /// Display code of type_check. Fails if the result would be nested
+    /// deeper than `MAX_RECURSION_DEPTH`.
     pub fn from_ast(t: decode::Terminal<Pk>) -> Result<Miniscript<Pk>, Error> {
+        let ext = ExtData::type_check(&t, |_| None)?;
+        if ext.tree_height >= MAX_RECURSION_DEPTH {
+            return Err(Error::MaxDepthExceeded);
+        }
         Ok(Miniscript {
             ty: Type::type_check(&t, |_| None)?,
-            ext: ExtData::type_check(&t, |_| None)?,
+            ext: ext,
             node: t,
         })
     }
@@ -116,18 +249,131 @@ impl<Pk: MiniscriptKey> fmt::Display for Miniscript<Pk> {
 
 impl<Pk: MiniscriptKey> Miniscript<Pk> {
     /// Extracts the `AstElem` representing the root of the miniscript
-    pub fn into_inner(self) -> decode::Terminal<Pk> {
-        self.node
+    pub fn into_inner(mut self) -> decode::Terminal<Pk> {
+        mem::replace(&mut self.node, decode::Terminal::True)
     }
 
     pub fn as_inner(&self) -> &decode::Terminal<Pk> {
         &self.node
     }
+
+    /// Checks that the Miniscript does not exceed the given limits. This
+    /// walks the script without recursing, so is safe to call on scripts of
+    /// any depth.
+    pub fn check_limits(&self, limits: &ParseLimits) -> Result<(), Error> {
+        let mut n_fragments = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((ms, depth)) = stack.pop() {
+            n_fragments += 1;
+            if depth > limits.max_depth {
+                return Err(Error::MaxDepthExceeded);
+            }
+            if n_fragments > limits.max_fragments {
+                return Err(Error::TooManyFragments(n_fragments));
+            }
+
+            match ms.node {
+                decode::Terminal::Alt(ref sub)
+                | decode::Terminal::Swap(ref sub)
+                | decode::Terminal::Check(ref sub)
+                | decode::Terminal::DupIf(ref sub)
+                | decode::Terminal::Verify(ref sub)
+                | decode::Terminal::NonZero(ref sub)
+                | decode::Terminal::ZeroNotEqual(ref sub) => stack.push((&**sub, depth + 1)),
+                decode::Terminal::AndV(ref l, ref r)
+                | decode::Terminal::AndB(ref l, ref r)
+                | decode::Terminal::OrB(ref l, ref r)
+                | decode::Terminal::OrD(ref l, ref r)
+                | decode::Terminal::OrC(ref l, ref r)
+                | decode::Terminal::OrI(ref l, ref r) => {
+                    stack.push((&**l, depth + 1));
+                    stack.push((&**r, depth + 1));
+                }
+                decode::Terminal::AndOr(ref a, ref b, ref c) => {
+                    stack.push((&**a, depth + 1));
+                    stack.push((&**b, depth + 1));
+                    stack.push((&**c, depth + 1));
+                }
+                decode::Terminal::Thresh(_, ref subs) => {
+                    stack.extend(subs.iter().map(|sub| (&**sub, depth + 1)));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the Miniscript does not exceed the consensus or the
+    /// standardness limits of the context `Ctx` it is used in, see
+    /// `ScriptContext`
+    pub fn check_resource_limits<Ctx: ScriptContext>(&self) -> Result<(), Error> {
+        Ctx::check_resource_limits(self)
+    }
+
+    /// Whether the Miniscript is within the resource limits of the context
+    /// `Ctx` it is used in, see `check_resource_limits`
+    pub fn within_resource_limits<Ctx: ScriptContext>(&self) -> bool {
+        self.check_resource_limits::<Ctx>().is_ok()
+    }
+
+    /// Checks that the Miniscript does not exceed the consensus limits of
+    /// the context `Ctx` it is used in. A Miniscript exceeding them can
+    /// never be spent.
+    pub fn check_consensus_limits<Ctx: ScriptContext>(&self) -> Result<(), Error> {
+        Ctx::check_consensus_limits(self)
+    }
+
+    /// Whether the Miniscript is within the consensus limits of the context
+    /// `Ctx` it is used in, see `check_consensus_limits`
+    pub fn within_consensus_limits<Ctx: ScriptContext>(&self) -> bool {
+        self.check_consensus_limits::<Ctx>().is_ok()
+    }
+
+    /// Checks that the Miniscript does not exceed the standardness limits
+    /// of the context `Ctx` it is used in. Spends of a Miniscript within
+    /// the consensus limits but exceeding these are valid, but not relayed.
+    pub fn check_standard_limits<Ctx: ScriptContext>(&self) -> Result<(), Error> {
+        Ctx::check_standard_limits(self)
+    }
+
+    /// Whether the Miniscript is within the standardness limits of the
+    /// context `Ctx` it is used in, see `check_standard_limits`
+    pub fn within_standard_limits<Ctx: ScriptContext>(&self) -> bool {
+        self.check_standard_limits::<Ctx>().is_ok()
+    }
+
+    /// Checks that every key of the Miniscript may be used in the context
+    /// `Ctx`, i.e. that there are no uncompressed keys in segwit scripts.
+    /// Keys only present as hashes can't be checked.
+    pub fn check_keys<Ctx: ScriptContext>(&self) -> Result<(), Error> {
+        let mut res = Ok(());
+        self.for_each_key(|key| match key {
+            ForEach::Key(pk) => {
+                res = Ctx::check_pk(pk);
+                res.is_ok()
+            }
+            ForEach::Hash(..) => true,
+        });
+        res
+    }
 }
 
 impl Miniscript<bitcoin::PublicKey> {
     /// Attempt to parse a script into a Miniscript representation
     pub fn parse(script: &script::Script) -> Result<Miniscript<bitcoin::PublicKey>, Error> {
+        Miniscript::parse_with_limits(script, &ParseLimits::default())
+    }
+
+    /// Attempt to parse a script into a Miniscript representation, rejecting
+    /// scripts which exceed the given `limits`
+    pub fn parse_with_limits(
+        script: &script::Script,
+        limits: &ParseLimits,
+    ) -> Result<Miniscript<bitcoin::PublicKey>, Error> {
+        if script.is_provably_unspendable() {
+            return Err(Error::Unspendable(unspendable_payload(script)));
+        }
+
         let tokens = lex(script)?;
         let mut iter = TokenIter::new(tokens);
 
@@ -139,9 +385,35 @@ impl Miniscript<bitcoin::PublicKey> {
         if let Some(leading) = iter.next() {
             Err(Error::Trailing(leading.to_string()))
         } else {
+            top.check_limits(limits)?;
             Ok(top)
         }
     }
+
+    /// Parse a script into a Miniscript as long as it is structurally
+    /// valid, without limiting its number of fragments. Instead of failing,
+    /// returns the rules the script breaks: whether it is safe and
+    /// non-malleable, and whether it fits the context `Ctx`. Meant for
+    /// tools inspecting scripts found on chain.
+    pub fn parse_insane<Ctx: ScriptContext>(
+        script: &script::Script,
+    ) -> Result<(Miniscript<bitcoin::PublicKey>, Vec<Violation>), Error> {
+        let ms = Miniscript::parse_with_limits(script, &ParseLimits::insane())?;
+        let mut violations = vec![];
+        if !ms.requires_sig() {
+            violations.push(Violation::NotSafe);
+        }
+        if !ms.is_non_malleable() {
+            violations.push(Violation::Malleable);
+        }
+        if let Err(e) = ms.check_resource_limits::<Ctx>() {
+            violations.push(Violation::Context(e));
+        }
+        if let Err(e) = ms.check_keys::<Ctx>() {
+            violations.push(Violation::Context(e));
+        }
+        Ok((ms, violations))
+    }
 }
 
 impl<Pk: MiniscriptKey + ToPublicKey> Miniscript<Pk> {
@@ -192,20 +464,25 @@ impl<Pk: MiniscriptKey + ToPublicKey> Miniscript<Pk> {
     pub fn max_satisfaction_size(&self, one_cost: usize) -> usize {
         self.node.max_satisfaction_size(one_cost)
     }
+
+    /// Like `max_satisfaction_size`, but assumes all signatures are `sig_len`
+    /// bytes in size, including the length prefix (segwit) or push opcode
+    /// (pre-segwit) and sighash postfix.
+    pub fn max_satisfaction_size_with_sig_len(&self, one_cost: usize, sig_len: usize) -> usize {
+        self.node
+            .max_satisfaction_size_with_sig_len(one_cost, sig_len)
+    }
 }
 
 impl<Pk: MiniscriptKey> Miniscript<Pk> {
-    pub fn translate_pk<FPk, FPkh, Q, Error>(
-        &self,
-        translatefpk: &mut FPk,
-        translatefpkh: &mut FPkh,
-    ) -> Result<Miniscript<Q>, Error>
+    /// Converts a Miniscript using one key type to one using another, see
+    /// `Translator`
+    pub fn translate_pk<T, Q, E>(&self, t: &mut T) -> Result<Miniscript<Q>, E>
     where
-        FPk: FnMut(&Pk) -> Result<Q, Error>,
-        FPkh: FnMut(&Pk::Hash) -> Result<Q::Hash, Error>,
+        T: Translator<Pk, Q, E>,
         Q: MiniscriptKey,
     {
-        let inner = self.node.translate_pk(translatefpk, translatefpkh)?;
+        let inner = self.node.translate_pk(t)?;
         Ok(Miniscript {
             //directly copying the type and ext is safe because translating public
             //key should not change any properties
@@ -216,11 +493,131 @@ impl<Pk: MiniscriptKey> Miniscript<Pk> {
     }
 }
 
+/// A key or key hash in a Miniscript or descriptor, see `for_each_key`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ForEach<'a, Pk: MiniscriptKey + 'a> {
+    /// A public key
+    Key(&'a Pk),
+    /// The hash of a public key, as in `pk_h`
+    Hash(&'a Pk::Hash),
+}
+
+impl<Pk: MiniscriptKey> Miniscript<Pk> {
+    /// Calls `pred` on every key and key hash in the script, in the order
+    /// they appear in its string form, until it returns `false`. Returns
+    /// whether `pred` returned `true` for all of them.
+    pub fn for_each_key<'a, F>(&'a self, mut pred: F) -> bool
+    where
+        F: FnMut(ForEach<'a, Pk>) -> bool,
+    {
+        let mut stack = vec![self];
+        while let Some(ms) = stack.pop() {
+            match ms.node {
+                decode::Terminal::PkK(ref pk) => {
+                    if !pred(ForEach::Key(pk)) {
+                        return false;
+                    }
+                }
+                decode::Terminal::PkH(ref pkh) => {
+                    if !pred(ForEach::Hash(pkh)) {
+                        return false;
+                    }
+                }
+                decode::Terminal::Multi(_, ref keys) => {
+                    if !keys.iter().all(|pk| pred(ForEach::Key(pk))) {
+                        return false;
+                    }
+                }
+                decode::Terminal::Alt(ref sub)
+                | decode::Terminal::Swap(ref sub)
+                | decode::Terminal::Check(ref sub)
+                | decode::Terminal::DupIf(ref sub)
+                | decode::Terminal::Verify(ref sub)
+                | decode::Terminal::NonZero(ref sub)
+                | decode::Terminal::ZeroNotEqual(ref sub) => stack.push(&**sub),
+                decode::Terminal::AndV(ref l, ref r)
+                | decode::Terminal::AndB(ref l, ref r)
+                | decode::Terminal::OrB(ref l, ref r)
+                | decode::Terminal::OrD(ref l, ref r)
+                | decode::Terminal::OrC(ref l, ref r)
+                | decode::Terminal::OrI(ref l, ref r) => {
+                    stack.push(&**r);
+                    stack.push(&**l);
+                }
+                decode::Terminal::AndOr(ref a, ref b, ref c) => {
+                    stack.push(&**c);
+                    stack.push(&**b);
+                    stack.push(&**a);
+                }
+                decode::Terminal::Thresh(_, ref subs) => {
+                    stack.extend(subs.iter().rev().map(|sub| &**sub));
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+impl<Pk: MiniscriptKey> Miniscript<Pk> {
+    /// Whether two scripts enforce the same spending conditions, as judged
+    /// by comparing their lifted policies after normalizing and sorting
+    /// them. Scripts which differ only in fragment choice, branch order or
+    /// `pk`/`pk_h` usage compare equal. Since normalization is syntactic,
+    /// equivalent policies written in very different shapes (e.g. a
+    /// `thresh` versus the equivalent nest of `and`s and `or`s) may not.
+    pub fn semantically_equals(&self, other: &Miniscript<Pk>) -> bool {
+        use policy::Liftable;
+
+        self.lift().normalized().sorted() == other.lift().normalized().sorted()
+    }
+
+    /// Whether a non-malleable satisfaction is guaranteed to exist, i.e. one
+    /// a third party can't turn into another valid satisfaction. `satisfy`
+    /// only ever produces such satisfactions, so it may fail for malleable
+    /// scripts even when `satisfy_malleable` succeeds.
+    pub fn is_non_malleable(&self) -> bool {
+        self.ty.mall.non_malleable
+    }
+
+    /// Whether every satisfaction requires a signature, so that no third
+    /// party can produce one without the signer's involvement
+    pub fn requires_sig(&self) -> bool {
+        self.ty.mall.safe
+    }
+}
+
 impl<Pk: MiniscriptKey + ToPublicKey> Miniscript<Pk> {
     /// Attempt to produce a satisfying witness for the
     /// witness script represented by the parse tree
     pub fn satisfy<S: satisfy::Satisfier<Pk>>(&self, satisfier: S) -> Option<Vec<Vec<u8>>> {
+        trace_span!("satisfy", miniscript = %self);
         match satisfy::Satisfaction::satisfy(&self.node, &satisfier).stack {
+            satisfy::Witness::Stack(stack) => {
+                trace_event!(
+                    witness_elements = stack.len(),
+                    witness_size = stack.iter().map(|elem| elem.len()).sum::<usize>(),
+                    "satisfied miniscript"
+                );
+                Some(stack)
+            }
+            satisfy::Witness::Unavailable => {
+                trace_event!("no satisfaction available");
+                None
+            }
+        }
+    }
+
+    /// Attempt to produce the cheapest satisfying witness for the witness
+    /// script represented by the parse tree, even if a third party could
+    /// malleate it, e.g. by swapping a satisfied branch without signature
+    /// for another one. Only use this if the transaction is broadcast right
+    /// away, or its txid may change otherwise.
+    pub fn satisfy_malleable<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+    ) -> Option<Vec<Vec<u8>>> {
+        match satisfy::Satisfaction::satisfy_mall(&self.node, &satisfier).stack {
             satisfy::Witness::Stack(stack) => Some(stack),
             satisfy::Witness::Unavailable => None,
         }
@@ -248,11 +645,7 @@ where
     /// should not be called directly; rather go through the descriptor API.
     fn from_tree(top: &expression::Tree) -> Result<Miniscript<Pk>, Error> {
         let inner: decode::Terminal<Pk> = expression::FromTree::from_tree(top)?;
-        Ok(Miniscript {
-            ty: Type::type_check(&inner, |_| None)?,
-            ext: ExtData::type_check(&inner, |_| None)?,
-            node: inner,
-        })
+        Miniscript::from_ast(inner)
     }
 }
 
@@ -265,21 +658,42 @@ where
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Miniscript<Pk>, Error> {
+        Miniscript::from_str_with_limits(s, &ParseLimits::default())
+    }
+}
+
+impl<Pk> Miniscript<Pk>
+where
+    Pk: MiniscriptKey,
+    <Pk as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+{
+    /// Parse a Miniscript from its string form, rejecting Miniscripts which
+    /// exceed the given `limits`
+    pub fn from_str_with_limits(s: &str, limits: &ParseLimits) -> Result<Miniscript<Pk>, Error> {
         for ch in s.as_bytes() {
             if *ch < 20 || *ch > 127 {
                 return Err(Error::Unprintable(*ch));
             }
         }
 
-        let top = expression::Tree::from_str(s)?;
+        let top = expression::Tree::from_str_with_max_depth(s, limits.max_depth)?;
         let ms: Miniscript<Pk> = expression::FromTree::from_tree(&top)?;
 
         if ms.ty.corr.base != types::Base::B {
             Err(Error::NonTopLevel(format!("{:?}", ms)))
         } else {
+            ms.check_limits(limits)?;
             Ok(ms)
         }
     }
+
+    /// Parse a Miniscript from its string form without enforcing resource
+    /// limits, see `ParseLimits::insane`. Meant for tools inspecting scripts
+    /// which the regular parser rejects; the result may not be spendable.
+    pub fn from_str_insane(s: &str) -> Result<Miniscript<Pk>, Error> {
+        Miniscript::from_str_with_limits(s, &ParseLimits::insane())
+    }
 }
 
 #[cfg(feature = "serde")]
@@ -339,7 +753,7 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::Miniscript;
+    use super::{Miniscript, ParseLimits};
     use hex_script;
     use miniscript::decode::Terminal;
     use miniscript::types::{self, ExtData, Property, Type};
@@ -352,6 +766,7 @@ mod tests {
     use std::str;
     use std::str::FromStr;
     use std::sync::Arc;
+    use Error;
     use MiniscriptKey;
 
     type BScript = Miniscript<bitcoin::PublicKey>;
@@ -400,8 +815,10 @@ mod tests {
         let roundtrip = Miniscript::from_str(&display).expect("parse string serialization");
         assert_eq!(roundtrip, script);
 
-        let translated: Result<_, ()> =
-            script.translate_pk(&mut |k| Ok(k.clone()), &mut |h| Ok(h.clone()));
+        let translated: Result<_, ()> = script.translate_pk(&mut (
+            |k: &bitcoin::PublicKey| Ok(k.clone()),
+            |h: &hash160::Hash| Ok(h.clone()),
+        ));
         assert_eq!(translated, Ok(script));
     }
 
@@ -578,6 +995,65 @@ mod tests {
         assert!(Miniscript::<bitcoin::PublicKey>::from_str("tv:1()").is_err());
     }
 
+    #[test]
+    fn parse_limits() {
+        let key = pubkeys(1)[0];
+        let s = format!("and_v(vc:pk_k({}),c:pk_k({}))", key, key);
+        let ms = BScript::from_str(&s).unwrap();
+
+        let shallow = ParseLimits {
+            max_depth: 3,
+            max_fragments: 100,
+        };
+        match BScript::from_str_with_limits(&s, &shallow) {
+            Err(Error::MaxDepthExceeded) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        match BScript::parse_with_limits(&ms.encode(), &shallow) {
+            Err(Error::MaxDepthExceeded) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        let small = ParseLimits {
+            max_depth: 10,
+            max_fragments: 4,
+        };
+        match BScript::from_str_with_limits(&s, &small) {
+            Err(Error::TooManyFragments(5)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // Deeply nested input is rejected instead of overflowing the stack
+        let deep = format!("{}0{}", "or_i(0,".repeat(10000), ")".repeat(10000));
+        match BScript::from_str(&deep) {
+            Err(Error::MaxDepthExceeded) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        match BScript::from_str_insane(&deep) {
+            Err(Error::MaxDepthExceeded) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        let wide = format!("thresh(1,0{})", ",a:0".repeat(5001));
+        match BScript::from_str(&wide) {
+            Err(Error::TooManyFragments(10001)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        assert_eq!(BScript::from_str_insane(&wide).unwrap().to_string(), wide);
+    }
+
+    #[test]
+    fn unspendable() {
+        let script = bitcoin::blockdata::script::Builder::new()
+            .push_opcode(bitcoin::blockdata::opcodes::all::OP_RETURN)
+            .push_slice(b"burn")
+            .into_script();
+        match BScript::parse(&script) {
+            Err(Error::Unspendable(ref data)) if data == b"burn" => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
     #[test]
     fn pk_alias() {
         let pubkey = pubkeys(1)[0];
@@ -597,6 +1073,15 @@ mod tests {
             "[B/onduesm]c:[K/onduesm]pk_k(PublicKey { compressed: true, key: PublicKey(aa4c32e50fb34a95a372940ae3654b692ea35294748c3dd2c08b29f87ba9288c8294efcb73dc719e45b91c45f084e77aebc07c1ff3ed8f37935130a36304a340) })", 
             "pk(028c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caa)"
         );
+
+        let script: Miniscript<bitcoin::PublicKey> =
+            ms_str!("and_v(vc:pk_k({}),older(144))", pubkey.to_string());
+
+        string_rtt(
+            script,
+            None,
+            "and_v(v:pk(028c28a97bf8298bc0d23d8c749452a32e694b65e30a9472a3954ab30fe5324caa),older(144))"
+        );
     }
 
     #[test]
@@ -688,6 +1173,287 @@ mod tests {
         );
     }
 
+    #[test]
+    fn semantically_equals() {
+        let a = Miniscript::<String>::from_str("and_v(v:pk(A),pk(B))").unwrap();
+        let b = Miniscript::<String>::from_str("and_b(pk(B),s:pk(A))").unwrap();
+        let c = Miniscript::<String>::from_str("or_b(pk(A),s:pk(B))").unwrap();
+        let d = Miniscript::<String>::from_str("and_v(v:pk(A),c:pk_h(B))").unwrap();
+        assert!(a.semantically_equals(&b));
+        assert!(a.semantically_equals(&d));
+        assert!(!a.semantically_equals(&c));
+    }
+
+    #[test]
+    fn malleable_satisfaction() {
+        use miniscript::satisfy::{BitcoinSig, Older};
+        use std::collections::HashMap;
+
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = secp.sign(&msg, &sk);
+        let mut sigs = HashMap::<bitcoin::PublicKey, BitcoinSig>::new();
+        sigs.insert(pk, (sig, bitcoin::SigHashType::All));
+        let mut sigser = sig.serialize_der().to_vec();
+        sigser.push(0x01);
+
+        // Anyone could replace the signature with a satisfaction of the
+        // timelock once it has expired
+        let ms = BScript::from_str(&format!("or_d(pk({}),older(10))", pk)).unwrap();
+        assert_eq!(ms.satisfy(&sigs), None);
+        assert_eq!(ms.satisfy_malleable(&sigs), Some(vec![sigser]));
+
+        let both = (&sigs, Older(10));
+        assert_eq!(ms.satisfy(&both), Some(vec![vec![]]));
+        assert_eq!(ms.satisfy_malleable(&both), Some(vec![vec![]]));
+    }
+
+    #[test]
+    fn malleability_queries() {
+        let pk = "020000000000000000000000000000000000000000000000000000000000000002";
+        let ms = BScript::from_str(&format!("or_d(pk({}),older(10))", pk)).unwrap();
+        assert!(ms.is_non_malleable());
+        assert!(!ms.requires_sig());
+
+        let ms = BScript::from_str(&format!("and_v(v:pk({}),older(10))", pk)).unwrap();
+        assert!(ms.is_non_malleable());
+        assert!(ms.requires_sig());
+
+        // Neither branch needs a signature, so third parties can swap them
+        let ms = BScript::from_str("or_d(sha256(38df1c1f64a24a77b23393bca50dff872e31edc4f3b5aa3b90ad0b82f4f089b6),and_n(un:after(499999999),older(4194305)))").unwrap();
+        assert!(!ms.is_non_malleable());
+        assert!(!ms.requires_sig());
+    }
+
+    #[test]
+    fn parse_uncompressed() {
+        let uncomp = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let comp = "020000000000000000000000000000000000000000000000000000000000000002";
+        let ms = BScript::from_str(&format!("or_b(pk({}),s:pk({}))", uncomp, comp)).unwrap();
+        let script = ms.encode();
+        assert_eq!(script.len(), ms.script_size());
+
+        let parsed = BScript::parse(&script).unwrap();
+        assert_eq!(parsed, ms);
+        assert!(parsed.iter_pk().next().unwrap().is_uncompressed());
+
+        // Parsed keys can be translated to any other key type afterwards
+        let translated: Result<Miniscript<String>, ()> = parsed.translate_pk(&mut (
+            |k: &bitcoin::PublicKey| Ok(if k.compressed { "C" } else { "U" }.to_owned()),
+            |h: &hash160::Hash| Ok(h.to_string()),
+        ));
+        assert_eq!(translated.unwrap().to_string(), "or_b(pk(U),s:pk(C))");
+    }
+
+    #[test]
+    fn deep_tree() {
+        // Built by hand, as no parser would accept this many levels
+        let mut ms = BScript::from_str("1").unwrap();
+        for _ in 0..100_000 {
+            let (ty, ext) = (ms.ty, ms.ext);
+            ms = Miniscript {
+                node: Terminal::ZeroNotEqual(Arc::new(ms)),
+                ty: ty,
+                ext: ext,
+            };
+        }
+        let s = ms.to_string();
+        assert_eq!(s.len(), 100_002);
+        assert!(s.starts_with("nnnn") && s.ends_with("n:1"));
+
+        let copy = ms.clone();
+        drop(ms);
+        assert_eq!(copy.into_inner().to_string().len(), 100_001);
+    }
+
+    #[test]
+    fn recursion_depth() {
+        use super::MAX_RECURSION_DEPTH;
+
+        let mut ms = BScript::from_str("1").unwrap();
+        for _ in 1..MAX_RECURSION_DEPTH {
+            ms = Miniscript::from_ast(Terminal::ZeroNotEqual(Arc::new(ms))).unwrap();
+        }
+        assert_eq!(ms.ext.tree_height, MAX_RECURSION_DEPTH - 1);
+        assert_eq!(BScript::parse(&ms.encode()).unwrap(), ms);
+        assert_eq!(BScript::from_str(&ms.to_string()).unwrap(), ms);
+
+        match Miniscript::from_ast(Terminal::ZeroNotEqual(Arc::new(ms.clone()))) {
+            Err(Error::MaxDepthExceeded) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        // One level too deep, built by hand
+        let deeper = Miniscript {
+            node: Terminal::ZeroNotEqual(Arc::new(ms.clone())),
+            ty: ms.ty,
+            ext: ms.ext,
+        };
+        match BScript::parse(&deeper.encode()) {
+            Err(Error::MaxDepthExceeded) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        match BScript::from_str(&deeper.to_string()) {
+            Err(Error::MaxDepthExceeded) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn parse_insane() {
+        use super::Violation;
+        use miniscript::context::{Legacy, Segwitv0};
+
+        let uncomp = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let script = BScript::from_str(&format!("pk({})", uncomp))
+            .unwrap()
+            .encode();
+        let (ms, violations) = BScript::parse_insane::<Legacy>(&script).unwrap();
+        assert_eq!(ms, BScript::parse(&script).unwrap());
+        assert!(violations.is_empty());
+        match BScript::parse_insane::<Segwitv0>(&script).unwrap().1[..] {
+            [Violation::Context(Error::UncompressedSegwitKey(_))] => {}
+            ref x => panic!("unexpected violations {:?}", x),
+        }
+
+        // Anyone can spend this once the timelock expires, and swap the
+        // branches of the `or_d`
+        let ms = BScript::from_str("or_d(sha256(38df1c1f64a24a77b23393bca50dff872e31edc4f3b5aa3b90ad0b82f4f089b6),and_n(un:after(499999999),older(4194305)))").unwrap();
+        match BScript::parse_insane::<Segwitv0>(&ms.encode()).unwrap().1[..] {
+            [Violation::NotSafe, Violation::Malleable] => {}
+            ref x => panic!("unexpected violations {:?}", x),
+        }
+
+        // Still not accepted: a script which isn't Miniscript at all
+        let script = hex_script("a914000000000000000000000000000000000000000087");
+        assert!(BScript::parse_insane::<Legacy>(&script).is_err());
+    }
+
+    #[test]
+    fn lock_times() {
+        use miniscript::locktime::{AbsLockTime, RelLockTime};
+
+        let ms = BScript::from_str("and_v(v:after(500000001),older(4194305))").unwrap();
+        let after = AbsLockTime::from_consensus(500000001).unwrap();
+        let older = RelLockTime::from_consensus(4194305).unwrap();
+        assert!(after.is_block_time());
+        assert!(older.is_time_locked());
+        match ms.node {
+            Terminal::AndV(ref l, ref r) => {
+                assert_eq!(l.to_string(), "v:after(500000001)");
+                assert_eq!(r.node, Terminal::Older(older));
+            }
+            ref x => panic!("unexpected node {:?}", x),
+        }
+        assert_eq!(BScript::parse(&ms.encode()).unwrap(), ms);
+
+        for s in &[
+            "after(0)",
+            "older(0)",
+            "after(2147483648)",
+            "older(4294967295)",
+        ] {
+            match BScript::from_str(s) {
+                Err(Error::InvalidLockTime(_)) => {}
+                x => panic!("unexpected result {:?}", x),
+            }
+        }
+        let script = bitcoin::blockdata::script::Builder::new()
+            .push_int(0)
+            .push_opcode(bitcoin::blockdata::opcodes::all::OP_CSV)
+            .into_script();
+        match BScript::parse(&script) {
+            Err(Error::InvalidLockTime(0)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn resource_limits() {
+        use miniscript::context::{Legacy, Segwitv0};
+
+        let pk = "020000000000000000000000000000000000000000000000000000000000000002";
+        // `n` keys all of which have to sign, one op and 35 bytes each
+        let and_chain = |n: usize| {
+            let mut s = format!("pk({})", pk);
+            for _ in 1..n {
+                s = format!("and_v(v:pk({}),{})", pk, s);
+            }
+            BScript::from_str(&s).unwrap()
+        };
+
+        let ms = and_chain(100);
+        assert!(ms.within_resource_limits::<Legacy>());
+        assert!(ms.within_resource_limits::<Segwitv0>());
+
+        let ms = and_chain(101);
+        assert!(ms.within_resource_limits::<Legacy>());
+        match ms.check_resource_limits::<Segwitv0>() {
+            Err(Error::MaxWitnessItemsExceeded(101)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        // Too many witness elements is only a standardness issue
+        assert!(ms.within_consensus_limits::<Segwitv0>());
+        assert!(!ms.within_standard_limits::<Segwitv0>());
+
+        let ms = and_chain(104);
+        assert!(ms.within_resource_limits::<Legacy>());
+        match ms.check_resource_limits::<Segwitv0>() {
+            Err(Error::MaxScriptSizeExceeded(3640)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // Three ops for each `s:pk`, whatever the context
+        let mut s = format!("thresh(1,pk({})", pk);
+        for _ in 0..70 {
+            s.push_str(&format!(",s:pk({})", pk));
+        }
+        s.push(')');
+        let ms = BScript::from_str(&s).unwrap();
+        match ms.check_resource_limits::<Legacy>() {
+            Err(Error::MaxOpsExceeded(_)) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        assert!(!ms.within_resource_limits::<Segwitv0>());
+        assert!(!ms.within_consensus_limits::<Legacy>());
+    }
+
+    #[test]
+    fn composed_satisfier() {
+        use miniscript::satisfy::BitcoinSig;
+        use std::collections::HashMap;
+
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+        let msg = secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = secp.sign(&msg, &sk);
+        let mut sigs = HashMap::<bitcoin::PublicKey, BitcoinSig>::new();
+        sigs.insert(pk, (sig, bitcoin::SigHashType::All));
+        let mut sigser = sig.serialize_der().to_vec();
+        sigser.push(0x01);
+
+        let preimage = [0xab; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let mut preimages = HashMap::new();
+        preimages.insert(hash, preimage);
+
+        let ms = BScript::from_str(&format!("and_v(v:pk({}),sha256({}))", pk, hash)).unwrap();
+        assert_eq!(ms.satisfy(&sigs), None);
+        assert_eq!(ms.satisfy(&preimages), None);
+        let witness = ms.satisfy((&sigs, &preimages)).unwrap();
+        assert_eq!(witness.len(), 2);
+        assert!(witness.contains(&sigser));
+        assert!(witness.contains(&preimage.to_vec()));
+    }
+
     #[test]
     fn deserialize() {
         // Most of these came from fuzzing, hence the increasing lengths
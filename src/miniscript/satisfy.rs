@@ -23,6 +23,7 @@ use std::{cmp, i64, mem};
 
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
 use bitcoin::{self, secp256k1};
+use miniscript::locktime::{AbsLockTime, RelLockTime};
 use {MiniscriptKey, ToPublicKey};
 
 use Terminal;
@@ -88,22 +89,22 @@ pub trait Satisfier<Pk: MiniscriptKey> {
 impl<Pk: MiniscriptKey> Satisfier<Pk> for () {}
 
 /// Newtype around `u32` which implements `Satisfier` using `n` as an
-/// relative locktime
+/// relative locktime, i.e. the input's sequence number
 pub struct Older(pub u32);
 
 impl<Pk: MiniscriptKey> Satisfier<Pk> for Older {
     fn check_older(&self, n: u32) -> bool {
-        n <= self.0
+        RelLockTime::from_consensus(n).map_or(false, |t| t.is_satisfied_by(self.0))
     }
 }
 
 /// Newtype around `u32` which implements `Satisfier` using `n` as an
-/// absolute locktime
+/// absolute locktime, i.e. the transaction's lock time
 pub struct After(pub u32);
 
 impl<Pk: MiniscriptKey> Satisfier<Pk> for After {
     fn check_after(&self, n: u32) -> bool {
-        n <= self.0
+        AbsLockTime::from_consensus(n).map_or(false, |t| t.is_satisfied_by(self.0))
     }
 }
 
@@ -131,6 +132,30 @@ where
     }
 }
 
+impl<Pk: MiniscriptKey> Satisfier<Pk> for HashMap<sha256::Hash, [u8; 32]> {
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.get(&h).cloned()
+    }
+}
+
+impl<Pk: MiniscriptKey> Satisfier<Pk> for HashMap<sha256d::Hash, [u8; 32]> {
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.get(&h).cloned()
+    }
+}
+
+impl<Pk: MiniscriptKey> Satisfier<Pk> for HashMap<ripemd160::Hash, [u8; 32]> {
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.get(&h).cloned()
+    }
+}
+
+impl<Pk: MiniscriptKey> Satisfier<Pk> for HashMap<hash160::Hash, [u8; 32]> {
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.get(&h).cloned()
+    }
+}
+
 impl<'a, Pk: MiniscriptKey, S: Satisfier<Pk>> Satisfier<Pk> for &'a S {
     fn lookup_sig(&self, p: &Pk) -> Option<BitcoinSig> {
         (**self).lookup_sig(p)
@@ -480,7 +505,11 @@ pub struct Satisfaction {
 }
 
 impl Satisfaction {
-    fn minimum(sat1: Self, sat2: Self) -> Self {
+    fn minimum(malleable: bool, sat1: Self, sat2: Self) -> Self {
+        if malleable {
+            return Self::minimum_mall(sat1, sat2);
+        }
+
         match (sat1.has_sig, sat2.has_sig) {
             // If neither option has a signature, this is a malleability
             // vector, so choose neither one.
@@ -509,10 +538,40 @@ impl Satisfaction {
         }
     }
 
+    /// Pick the cheaper of two (dis)satisfactions, ignoring malleability
+    fn minimum_mall(sat1: Self, sat2: Self) -> Self {
+        let size = |stack: &Witness| match *stack {
+            Witness::Stack(ref s) => Some(s.iter().map(|elem| 1 + elem.len()).sum::<usize>()),
+            Witness::Unavailable => None,
+        };
+        match (size(&sat1.stack), size(&sat2.stack)) {
+            (Some(size1), Some(size2)) if size2 < size1 => sat2,
+            (Some(_), _) => sat1,
+            (None, _) => sat2,
+        }
+    }
+
     /// Produce a satisfaction
     pub fn satisfy<Pk: MiniscriptKey + ToPublicKey, Sat: Satisfier<Pk>>(
         term: &Terminal<Pk>,
         stfr: &Sat,
+    ) -> Self {
+        Self::satisfy_helper(term, stfr, false)
+    }
+
+    /// Produce the cheapest satisfaction, even if a third party could
+    /// malleate it into a different valid one
+    pub fn satisfy_mall<Pk: MiniscriptKey + ToPublicKey, Sat: Satisfier<Pk>>(
+        term: &Terminal<Pk>,
+        stfr: &Sat,
+    ) -> Self {
+        Self::satisfy_helper(term, stfr, true)
+    }
+
+    fn satisfy_helper<Pk: MiniscriptKey + ToPublicKey, Sat: Satisfier<Pk>>(
+        term: &Terminal<Pk>,
+        stfr: &Sat,
+        malleable: bool,
     ) -> Self {
         match *term {
             Terminal::PkK(ref pk) => Satisfaction {
@@ -524,7 +583,7 @@ impl Satisfaction {
                 has_sig: true,
             },
             Terminal::After(t) => Satisfaction {
-                stack: if stfr.check_after(t) {
+                stack: if stfr.check_after(t.to_u32()) {
                     Witness::empty()
                 } else {
                     Witness::Unavailable
@@ -532,7 +591,7 @@ impl Satisfaction {
                 has_sig: false,
             },
             Terminal::Older(t) => Satisfaction {
-                stack: if stfr.check_older(t) {
+                stack: if stfr.check_older(t.to_u32()) {
                     Witness::empty()
                 } else {
                     Witness::Unavailable
@@ -568,29 +627,30 @@ impl Satisfaction {
             | Terminal::Check(ref sub)
             | Terminal::Verify(ref sub)
             | Terminal::NonZero(ref sub)
-            | Terminal::ZeroNotEqual(ref sub) => Self::satisfy(&sub.node, stfr),
+            | Terminal::ZeroNotEqual(ref sub) => Self::satisfy_helper(&sub.node, stfr, malleable),
             Terminal::DupIf(ref sub) => {
-                let sat = Self::satisfy(&sub.node, stfr);
+                let sat = Self::satisfy_helper(&sub.node, stfr, malleable);
                 Satisfaction {
                     stack: Witness::combine(sat.stack, Witness::push_1()),
                     has_sig: sat.has_sig,
                 }
             }
             Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
-                let l_sat = Self::satisfy(&l.node, stfr);
-                let r_sat = Self::satisfy(&r.node, stfr);
+                let l_sat = Self::satisfy_helper(&l.node, stfr, malleable);
+                let r_sat = Self::satisfy_helper(&r.node, stfr, malleable);
                 Satisfaction {
                     stack: Witness::combine(l_sat.stack, r_sat.stack),
                     has_sig: l_sat.has_sig || r_sat.has_sig,
                 }
             }
             Terminal::AndOr(ref a, ref b, ref c) => {
-                let a_sat = Self::satisfy(&a.node, stfr);
-                let a_nsat = Self::dissatisfy(&a.node, stfr);
-                let b_sat = Self::satisfy(&b.node, stfr);
-                let c_sat = Self::satisfy(&c.node, stfr);
+                let a_sat = Self::satisfy_helper(&a.node, stfr, malleable);
+                let a_nsat = Self::dissatisfy(&a.node, stfr, malleable);
+                let b_sat = Self::satisfy_helper(&b.node, stfr, malleable);
+                let c_sat = Self::satisfy_helper(&c.node, stfr, malleable);
 
                 Self::minimum(
+                    malleable,
                     Satisfaction {
                         stack: Witness::combine(a_sat.stack, b_sat.stack),
                         has_sig: a_sat.has_sig || b_sat.has_sig,
@@ -602,15 +662,16 @@ impl Satisfaction {
                 )
             }
             Terminal::OrB(ref l, ref r) => {
-                let l_sat = Self::satisfy(&l.node, stfr);
-                let r_sat = Self::satisfy(&r.node, stfr);
-                let l_nsat = Self::dissatisfy(&l.node, stfr);
-                let r_nsat = Self::dissatisfy(&r.node, stfr);
+                let l_sat = Self::satisfy_helper(&l.node, stfr, malleable);
+                let r_sat = Self::satisfy_helper(&r.node, stfr, malleable);
+                let l_nsat = Self::dissatisfy(&l.node, stfr, malleable);
+                let r_nsat = Self::dissatisfy(&r.node, stfr, malleable);
 
                 assert!(!l_nsat.has_sig);
                 assert!(!r_nsat.has_sig);
 
                 Self::minimum(
+                    malleable,
                     Satisfaction {
                         stack: Witness::combine(r_sat.stack, l_nsat.stack),
                         has_sig: r_sat.has_sig,
@@ -622,13 +683,14 @@ impl Satisfaction {
                 )
             }
             Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => {
-                let l_sat = Self::satisfy(&l.node, stfr);
-                let r_sat = Self::satisfy(&r.node, stfr);
-                let l_nsat = Self::dissatisfy(&l.node, stfr);
+                let l_sat = Self::satisfy_helper(&l.node, stfr, malleable);
+                let r_sat = Self::satisfy_helper(&r.node, stfr, malleable);
+                let l_nsat = Self::dissatisfy(&l.node, stfr, malleable);
 
                 assert!(!l_nsat.has_sig);
 
                 Self::minimum(
+                    malleable,
                     l_sat,
                     Satisfaction {
                         stack: Witness::combine(r_sat.stack, l_nsat.stack),
@@ -637,9 +699,10 @@ impl Satisfaction {
                 )
             }
             Terminal::OrI(ref l, ref r) => {
-                let l_sat = Self::satisfy(&l.node, stfr);
-                let r_sat = Self::satisfy(&r.node, stfr);
+                let l_sat = Self::satisfy_helper(&l.node, stfr, malleable);
+                let r_sat = Self::satisfy_helper(&r.node, stfr, malleable);
                 Self::minimum(
+                    malleable,
                     Satisfaction {
                         stack: Witness::combine(l_sat.stack, Witness::push_1()),
                         has_sig: l_sat.has_sig,
@@ -653,17 +716,17 @@ impl Satisfaction {
             Terminal::Thresh(k, ref subs) => {
                 let mut sats = subs
                     .iter()
-                    .map(|s| Self::satisfy(&s.node, stfr))
+                    .map(|s| Self::satisfy_helper(&s.node, stfr, malleable))
                     .collect::<Vec<_>>();
                 // Start with the to-return stack set to all dissatisfactions
                 let mut ret_stack = subs
                     .iter()
-                    .map(|s| Self::dissatisfy(&s.node, stfr))
+                    .map(|s| Self::dissatisfy(&s.node, stfr, malleable))
                     .collect::<Vec<_>>();
 
                 // Sort everything by (sat cost - dissat cost), except that
                 // satisfactions without signatures beat satisfactions with
-                // signatures
+                // signatures, unless malleability is of no concern
                 let mut sat_indices = (0..subs.len()).collect::<Vec<_>>();
                 sat_indices.sort_by_key(|&i| {
                     let stack_weight = match (&sats[i].stack, &ret_stack[i].stack) {
@@ -674,7 +737,7 @@ impl Satisfaction {
                                 - d.iter().map(Vec::len).sum::<usize>() as i64
                         }
                     };
-                    (sats[i].has_sig, stack_weight)
+                    (sats[i].has_sig && !malleable, stack_weight)
                 });
 
                 for i in 0..k {
@@ -685,7 +748,7 @@ impl Satisfaction {
                 // (since those were sorted higher than non-sigs). If there
                 // are remaining non-sig satisfactions this indicates a
                 // malleability vector
-                if k < sats.len() && !sats[sat_indices[k]].has_sig {
+                if !malleable && k < sats.len() && !sats[sat_indices[k]].has_sig {
                     // All arguments should be `d`, so dissatisfactions have no
                     // signatures; and in this branch we assume too many weak
                     // arguments, so none of the satisfactions should have
@@ -749,10 +812,11 @@ impl Satisfaction {
         }
     }
 
-    /// Produce a satisfaction
+    /// Produce a dissatisfaction
     fn dissatisfy<Pk: MiniscriptKey + ToPublicKey, Sat: Satisfier<Pk>>(
         term: &Terminal<Pk>,
         stfr: &Sat,
+        malleable: bool,
     ) -> Self {
         match *term {
             Terminal::PkK(..) => Satisfaction {
@@ -789,7 +853,7 @@ impl Satisfaction {
             Terminal::Alt(ref sub)
             | Terminal::Swap(ref sub)
             | Terminal::Check(ref sub)
-            | Terminal::ZeroNotEqual(ref sub) => Self::dissatisfy(&sub.node, stfr),
+            | Terminal::ZeroNotEqual(ref sub) => Self::dissatisfy(&sub.node, stfr, malleable),
             Terminal::DupIf(_) | Terminal::NonZero(_) => Satisfaction {
                 stack: Witness::push_0(),
                 has_sig: false,
@@ -799,8 +863,8 @@ impl Satisfaction {
                 has_sig: false,
             },
             Terminal::AndV(ref v, ref other) => {
-                let vsat = Self::satisfy(&v.node, stfr);
-                let odissat = Self::dissatisfy(&other.node, stfr);
+                let vsat = Self::satisfy_helper(&v.node, stfr, malleable);
+                let odissat = Self::dissatisfy(&other.node, stfr, malleable);
                 Satisfaction {
                     stack: Witness::combine(odissat.stack, vsat.stack),
                     has_sig: vsat.has_sig || odissat.has_sig,
@@ -810,8 +874,8 @@ impl Satisfaction {
             | Terminal::OrB(ref l, ref r)
             | Terminal::OrD(ref l, ref r)
             | Terminal::AndOr(ref l, _, ref r) => {
-                let lnsat = Self::dissatisfy(&l.node, stfr);
-                let rnsat = Self::dissatisfy(&r.node, stfr);
+                let lnsat = Self::dissatisfy(&l.node, stfr, malleable);
+                let rnsat = Self::dissatisfy(&r.node, stfr, malleable);
                 Satisfaction {
                     stack: Witness::combine(rnsat.stack, lnsat.stack),
                     has_sig: rnsat.has_sig || lnsat.has_sig,
@@ -822,23 +886,23 @@ impl Satisfaction {
                 has_sig: false,
             },
             Terminal::OrI(ref l, ref r) => {
-                let lnsat = Self::dissatisfy(&l.node, stfr);
+                let lnsat = Self::dissatisfy(&l.node, stfr, malleable);
                 let dissat_1 = Satisfaction {
                     stack: Witness::combine(lnsat.stack, Witness::push_1()),
                     has_sig: lnsat.has_sig,
                 };
 
-                let rnsat = Self::dissatisfy(&r.node, stfr);
+                let rnsat = Self::dissatisfy(&r.node, stfr, malleable);
                 let dissat_2 = Satisfaction {
                     stack: Witness::combine(rnsat.stack, Witness::push_0()),
                     has_sig: rnsat.has_sig,
                 };
 
-                Self::minimum(dissat_1, dissat_2)
+                Self::minimum(malleable, dissat_1, dissat_2)
             }
             Terminal::Thresh(_, ref subs) => Satisfaction {
                 stack: subs.iter().fold(Witness::empty(), |acc, sub| {
-                    let nsat = Self::dissatisfy(&sub.node, stfr);
+                    let nsat = Self::dissatisfy(&sub.node, stfr, malleable);
                     assert!(!nsat.has_sig);
                     Witness::combine(nsat.stack, acc)
                 }),
@@ -40,6 +40,9 @@ pub struct ExtData {
     pub ops_count_sat: Option<usize>,
     /// The worst case ops-count for dissatisfying this Miniscript fragment.
     pub ops_count_nsat: Option<usize>,
+    /// The number of levels of fragments below this one, see
+    /// `MAX_RECURSION_DEPTH`
+    pub tree_height: usize,
 }
 
 impl Property for ExtData {
@@ -55,6 +58,7 @@ impl Property for ExtData {
             ops_count_static: 0,
             ops_count_sat: Some(0),
             ops_count_nsat: None,
+            tree_height: 0,
         }
     }
 
@@ -66,6 +70,7 @@ impl Property for ExtData {
             ops_count_static: 0,
             ops_count_sat: None,
             ops_count_nsat: Some(0),
+            tree_height: 0,
         }
     }
 
@@ -77,6 +82,7 @@ impl Property for ExtData {
             ops_count_static: 0,
             ops_count_sat: Some(0),
             ops_count_nsat: Some(0),
+            tree_height: 0,
         }
     }
 
@@ -88,6 +94,7 @@ impl Property for ExtData {
             ops_count_static: 3,
             ops_count_sat: Some(3),
             ops_count_nsat: Some(3),
+            tree_height: 0,
         }
     }
 
@@ -105,6 +112,7 @@ impl Property for ExtData {
             ops_count_static: 1,
             ops_count_sat: Some(n + 1),
             ops_count_nsat: Some(n + 1),
+            tree_height: 0,
         }
     }
 
@@ -121,6 +129,7 @@ impl Property for ExtData {
             ops_count_static: 4,
             ops_count_sat: Some(4),
             ops_count_nsat: None,
+            tree_height: 0,
         }
     }
 
@@ -132,6 +141,7 @@ impl Property for ExtData {
             ops_count_static: 4,
             ops_count_sat: Some(4),
             ops_count_nsat: None,
+            tree_height: 0,
         }
     }
 
@@ -143,6 +153,7 @@ impl Property for ExtData {
             ops_count_static: 4,
             ops_count_sat: Some(4),
             ops_count_nsat: None,
+            tree_height: 0,
         }
     }
 
@@ -154,6 +165,7 @@ impl Property for ExtData {
             ops_count_static: 4,
             ops_count_sat: Some(4),
             ops_count_nsat: None,
+            tree_height: 0,
         }
     }
 
@@ -165,6 +177,7 @@ impl Property for ExtData {
             ops_count_static: 1,
             ops_count_sat: Some(1),
             ops_count_nsat: None,
+            tree_height: 0,
         }
     }
     fn cast_alt(self) -> Result<Self, ErrorKind> {
@@ -175,6 +188,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + 2,
             ops_count_sat: self.ops_count_sat.map(|x| x + 2),
             ops_count_nsat: self.ops_count_nsat.map(|x| x + 2),
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -186,6 +200,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + 1,
             ops_count_sat: self.ops_count_sat.map(|x| x + 1),
             ops_count_nsat: self.ops_count_nsat.map(|x| x + 1),
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -197,6 +212,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + 1,
             ops_count_sat: self.ops_count_sat.map(|x| x + 1),
             ops_count_nsat: self.ops_count_nsat.map(|x| x + 1),
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -208,6 +224,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + 3,
             ops_count_sat: self.ops_count_sat.map(|x| x + 3),
             ops_count_nsat: Some(self.ops_count_static + 3),
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -220,6 +237,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + verify_cost,
             ops_count_sat: self.ops_count_sat.map(|x| x + verify_cost),
             ops_count_nsat: None,
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -231,6 +249,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + 4,
             ops_count_sat: self.ops_count_sat.map(|x| x + 4),
             ops_count_nsat: Some(self.ops_count_static + 4),
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -242,6 +261,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + 1,
             ops_count_sat: self.ops_count_sat.map(|x| x + 1),
             ops_count_nsat: self.ops_count_nsat.map(|x| x + 1),
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -253,6 +273,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static,
             ops_count_sat: self.ops_count_sat,
             ops_count_nsat: None,
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -269,6 +290,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + 3,
             ops_count_sat: self.ops_count_sat.map(|x| x + 3),
             ops_count_nsat: Some(self.ops_count_static + 3),
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -280,6 +302,7 @@ impl Property for ExtData {
             ops_count_static: self.ops_count_static + 3,
             ops_count_sat: self.ops_count_sat.map(|x| x + 3),
             ops_count_nsat: Some(self.ops_count_static + 3),
+            tree_height: self.tree_height + 1,
         })
     }
 
@@ -295,6 +318,7 @@ impl Property for ExtData {
             ops_count_nsat: l
                 .ops_count_nsat
                 .and_then(|x| r.ops_count_nsat.map(|y| x + y + 1)),
+            tree_height: 1 + cmp::max(l.tree_height, r.tree_height),
         })
     }
 
@@ -306,6 +330,7 @@ impl Property for ExtData {
             ops_count_static: l.ops_count_static + r.ops_count_static,
             ops_count_sat: l.ops_count_sat.and_then(|x| r.ops_count_sat.map(|y| x + y)),
             ops_count_nsat: None,
+            tree_height: 1 + cmp::max(l.tree_height, r.tree_height),
         })
     }
 
@@ -324,6 +349,7 @@ impl Property for ExtData {
             ops_count_nsat: l
                 .ops_count_nsat
                 .and_then(|x| r.ops_count_nsat.map(|y| x + y + 1)),
+            tree_height: 1 + cmp::max(l.tree_height, r.tree_height),
         })
     }
 
@@ -341,6 +367,7 @@ impl Property for ExtData {
             ops_count_nsat: l
                 .ops_count_nsat
                 .and_then(|x| r.ops_count_nsat.map(|y| x + y + 3)),
+            tree_height: 1 + cmp::max(l.tree_height, r.tree_height),
         })
     }
 
@@ -356,6 +383,7 @@ impl Property for ExtData {
                     .and_then(|x| l.ops_count_nsat.map(|y| y + x + 2)),
             ),
             ops_count_nsat: None,
+            tree_height: 1 + cmp::max(l.tree_height, r.tree_height),
         })
     }
 
@@ -374,6 +402,7 @@ impl Property for ExtData {
                 (_, Some(x)) | (Some(x), _) => Some(x + 3),
                 (None, None) => None,
             },
+            tree_height: 1 + cmp::max(l.tree_height, r.tree_height),
         })
     }
 
@@ -392,6 +421,7 @@ impl Property for ExtData {
             ops_count_nsat: c
                 .ops_count_nsat
                 .and_then(|z| a.ops_count_nsat.map(|x| x + b.ops_count_static + z + 3)),
+            tree_height: 1 + cmp::max(a.tree_height, cmp::max(b.tree_height, c.tree_height)),
         })
     }
 
@@ -407,9 +437,11 @@ impl Property for ExtData {
         let mut ops_count_nsat = Some(0);
         let mut ops_count_sat = Some(0);
         let mut sat_count = 0;
+        let mut tree_height = 0;
         for i in 0..n {
             let sub = sub_ck(i)?;
             pk_cost += sub.pk_cost;
+            tree_height = cmp::max(tree_height, sub.tree_height);
             ops_count_static += sub.ops_count_static;
             match (sub.ops_count_sat, sub.ops_count_nsat) {
                 (Some(x), Some(y)) => {
@@ -447,6 +479,7 @@ impl Property for ExtData {
             ops_count_sat: ops_count_sat
                 .map(|x: usize| (x + (n - 1) + 1 + (sum + ops_count_nsat_sum as i32) as usize)), //adds and equal
             ops_count_nsat: ops_count_nsat.map(|x| x + (n - 1) + 1), //adds and equal
+            tree_height: tree_height + 1,
         })
     }
 
@@ -484,25 +517,8 @@ impl Property for ExtData {
                 }
                 Ok(Self::from_multi(k, pks.len()))
             }
-            Terminal::After(t) => {
-                // FIXME check if t > 2^31 - 1
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_after(t))
-            }
-            Terminal::Older(t) => {
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_older(t))
-            }
+            Terminal::After(t) => Ok(Self::from_after(t.to_u32())),
+            Terminal::Older(t) => Ok(Self::from_older(t.to_u32())),
             Terminal::Sha256(..) => Ok(Self::from_sha256()),
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
@@ -407,25 +407,8 @@ pub trait Property: Sized {
                 }
                 Ok(Self::from_multi(k, pks.len()))
             }
-            Terminal::After(t) => {
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_after(t))
-            }
-            Terminal::Older(t) => {
-                // FIXME check if t > 2^31 - 1
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_older(t))
-            }
+            Terminal::After(t) => Ok(Self::from_after(t.to_u32())),
+            Terminal::Older(t) => Ok(Self::from_older(t.to_u32())),
             Terminal::Sha256(..) => Ok(Self::from_sha256()),
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
@@ -780,25 +763,8 @@ impl Property for Type {
                 }
                 Ok(Self::from_multi(k, pks.len()))
             }
-            Terminal::After(t) => {
-                // FIXME check if t > 2^31 - 1
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_after(t))
-            }
-            Terminal::Older(t) => {
-                if t == 0 {
-                    return Err(Error {
-                        fragment: fragment.clone(),
-                        error: ErrorKind::ZeroTime,
-                    });
-                }
-                Ok(Self::from_older(t))
-            }
+            Terminal::After(t) => Ok(Self::from_after(t.to_u32())),
+            Terminal::Older(t) => Ok(Self::from_older(t.to_u32())),
             Terminal::Sha256(..) => Ok(Self::from_sha256()),
             Terminal::Hash256(..) => Ok(Self::from_hash256()),
             Terminal::Ripemd160(..) => Ok(Self::from_ripemd160()),
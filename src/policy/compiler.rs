@@ -18,8 +18,10 @@
 //!
 
 use std::collections::HashMap;
-use std::{cmp, error, f64, fmt};
+use std::{cmp, error, f64, fmt, usize};
 
+use miniscript::context::{ScriptContext, MAX_SCRIPT_SIZE, MAX_STACK_SIZE};
+use miniscript::locktime::{AbsLockTime, RelLockTime};
 use miniscript::types::extra_props::MAX_OPS_PER_SCRIPT;
 use miniscript::types::{self, ErrorKind, ExtData, Property, Type};
 use policy::Concrete;
@@ -29,8 +31,114 @@ use std::sync::Arc;
 use {policy, Terminal};
 use {Miniscript, MiniscriptKey};
 
-type PolicyCache<Pk> =
-    HashMap<(Concrete<Pk>, OrdF64, Option<OrdF64>), HashMap<CompilationKey, AstElemExt<Pk>>>;
+/// State shared by all recursive calls of a single compilation: the best
+/// compilations found so far for each (sub)policy and probability pair, the
+/// fragment hints supplied by the user and the limits of the script context
+struct PolicyCache<Pk: MiniscriptKey> {
+    map: HashMap<(Concrete<Pk>, OrdF64, Option<OrdF64>), HashMap<CompilationKey, AstElemExt<Pk>>>,
+    hints: HashMap<Concrete<Pk>, CompilerHint>,
+    constraints: CompilerConstraints,
+}
+
+impl<Pk: MiniscriptKey> PolicyCache<Pk> {
+    fn new() -> PolicyCache<Pk> {
+        PolicyCache {
+            map: HashMap::new(),
+            hints: HashMap::new(),
+            constraints: CompilerConstraints {
+                max_script_size: MAX_SCRIPT_SIZE,
+                max_ops: MAX_OPS_PER_SCRIPT,
+                max_witness_elements: MAX_STACK_SIZE,
+                max_satisfaction_size: None,
+            },
+        }
+    }
+}
+
+/// Resource limits every compilation, and so every sub-compilation, has to
+/// respect. Start from `for_context` and tighten the limits as needed, e.g.
+/// to bound the fee of spending an output.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CompilerConstraints {
+    /// Maximum size of the script in bytes
+    pub max_script_size: usize,
+    /// Maximum number of opcodes executed by any satisfaction, counted as
+    /// for `MAX_OPS_PER_SCRIPT`
+    pub max_ops: usize,
+    /// Maximum number of witness elements of any satisfaction, not counting
+    /// the witness script
+    pub max_witness_elements: usize,
+    /// Maximum size in bytes of any satisfaction, as computed by
+    /// `Miniscript::max_satisfaction_size(2)`, if any
+    pub max_satisfaction_size: Option<usize>,
+}
+
+impl CompilerConstraints {
+    /// The consensus and standardness limits of the context `Ctx`, with no
+    /// limit on the size of satisfactions
+    pub fn for_context<Ctx: ScriptContext>() -> CompilerConstraints {
+        let max_script_size = Ctx::max_consensus_script_size();
+        let max_witness_elements = Ctx::max_consensus_witness_elements();
+        CompilerConstraints {
+            max_script_size: Ctx::max_standard_script_size()
+                .map_or(max_script_size, |n| cmp::min(n, max_script_size)),
+            max_ops: MAX_OPS_PER_SCRIPT,
+            max_witness_elements: Ctx::max_standard_witness_elements()
+                .map_or(max_witness_elements, |n| cmp::min(n, max_witness_elements)),
+            max_satisfaction_size: None,
+        }
+    }
+
+    /// No limits at all, the starting point of `diagnose`
+    fn unconstrained() -> CompilerConstraints {
+        CompilerConstraints {
+            max_script_size: usize::MAX,
+            max_ops: usize::MAX,
+            max_witness_elements: usize::MAX,
+            max_satisfaction_size: None,
+        }
+    }
+
+    /// Copy the limit `constraint` from `other`
+    fn restrict(&mut self, constraint: Constraint, other: &CompilerConstraints) {
+        match constraint {
+            Constraint::ScriptSize => self.max_script_size = other.max_script_size,
+            Constraint::Ops => self.max_ops = other.max_ops,
+            Constraint::WitnessElements => self.max_witness_elements = other.max_witness_elements,
+            Constraint::SatisfactionSize => {
+                self.max_satisfaction_size = other.max_satisfaction_size
+            }
+        }
+    }
+}
+
+/// One of the limits of `CompilerConstraints`, reported by
+/// `CompilerError::ConstraintNotMet`
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Constraint {
+    /// `CompilerConstraints::max_script_size`
+    ScriptSize,
+    /// `CompilerConstraints::max_ops`
+    Ops,
+    /// `CompilerConstraints::max_witness_elements`
+    WitnessElements,
+    /// `CompilerConstraints::max_satisfaction_size`
+    SatisfactionSize,
+}
+
+/// Hint that forces the compiler to express a sub-policy using a specific
+/// Miniscript fragment, e.g. for compatibility with signers that only
+/// understand some fragments. Hints are attached to sub-policies by value,
+/// so they apply to every occurrence of an identical sub-policy.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum CompilerHint {
+    /// Express a `pk` as `pk_k`, i.e. with the key in the script
+    PkK,
+    /// Express a `pk` as `pk_h`, i.e. with only the key hash in the script
+    PkH,
+    /// Express a `thresh` of keys as `multi`
+    Multi,
+}
 
 ///Ordered f64 for comparison
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
@@ -51,11 +159,25 @@ pub enum CompilerError {
     TopLevelNonSafe,
     /// Non-Malleable compilation  does exists for the given sub-policy.
     ImpossibleNonMalleableCompilation,
-    /// Atleast one satisfaction path in the optimal Miniscript has opcodes
-    /// more than `MAX_OPS_PER_SCRIPT`(201). However, there may exist other
-    /// miniscripts which are under `MAX_OPS_PER_SCRIPT` but the compiler
-    /// currently does not find them.
-    MaxOpCountExceeded,
+    /// Every compilation found exceeds the constraints, i.e. the consensus
+    /// and standardness limits of the script context unless others were
+    /// given. The compilation functions report the responsible constraint
+    /// with `ConstraintNotMet` instead.
+    LimitsExceeded,
+    /// Every compilation found exceeds the given constraint, even when
+    /// only the constraints listed before it in `Constraint` are applied as
+    /// well. However, there may exist other miniscripts which meet all
+    /// constraints but the compiler currently does not find them.
+    ConstraintNotMet(Constraint),
+    /// A key of the policy can't be used in the script context, e.g. an
+    /// uncompressed key in a segwit script
+    IncompatibleKey,
+    /// Exhaustive compilation was asked for a policy with more than
+    /// `MAX_EXHAUSTIVE_LEAVES` keys, timelocks and hashes
+    PolicyTooLarge,
+    /// A compiler hint was attached to a sub-policy which cannot be
+    /// expressed using the hinted fragment
+    IncompatibleHint(CompilerHint),
     ///Policy related errors
     PolicyError(policy::concrete::PolicyError),
 }
@@ -79,10 +201,27 @@ impl fmt::Display for CompilerError {
             CompilerError::ImpossibleNonMalleableCompilation => {
                 f.write_str("The compiler could not find any non-malleable compilation")
             }
-            CompilerError::MaxOpCountExceeded => f.write_str(
-                "Atleast one spending path has more op codes executed than \
-                 MAX_OPS_PER_SCRIPT",
-            ),
+            CompilerError::LimitsExceeded => {
+                f.write_str("Every compilation found exceeds the resource limits")
+            }
+            CompilerError::ConstraintNotMet(constraint) => {
+                let limit = match constraint {
+                    Constraint::ScriptSize => "script size",
+                    Constraint::Ops => "number of executed opcodes",
+                    Constraint::WitnessElements => "number of witness elements",
+                    Constraint::SatisfactionSize => "satisfaction size",
+                };
+                write!(f, "Every compilation found exceeds the maximum {}", limit)
+            }
+            CompilerError::IncompatibleKey => {
+                f.write_str("Policy contains a key which cannot be used in the script context")
+            }
+            CompilerError::PolicyTooLarge => {
+                f.write_str("Policy is too large to be compiled exhaustively")
+            }
+            CompilerError::IncompatibleHint(hint) => {
+                write!(f, "Compiler hint {:?} cannot be applied to policy", hint)
+            }
             CompilerError::PolicyError(ref e) => fmt::Display::fmt(e, f),
         }
     }
@@ -624,6 +763,30 @@ fn all_casts<Pk: MiniscriptKey>() -> [Cast<Pk>; 10] {
     ]
 }
 
+/// Whether an element may be part of a compilation at all: it has to be
+/// non-malleable, as all casts of a malleable element are malleable, and be
+/// within the limits, as wrapping or combining it only makes it larger
+fn is_admissible<Pk: MiniscriptKey>(
+    elem: &AstElemExt<Pk>,
+    constraints: CompilerConstraints,
+) -> bool {
+    if !elem.ms.ty.mall.non_malleable {
+        return false;
+    }
+    if let Some(op_count) = elem.ms.ext.ops_count_sat {
+        if op_count > constraints.max_ops {
+            return false;
+        }
+    }
+    if let Some(max) = constraints.max_satisfaction_size {
+        if elem.ms.node.max_satisfaction_size(2) > max {
+            return false;
+        }
+    }
+    elem.ms.ext.pk_cost <= constraints.max_script_size
+        && elem.ms.node.max_satisfaction_witness_elements() <= constraints.max_witness_elements
+}
+
 /// Insert an element into the global map and return whether it got inserted
 /// If there is any element which is already better than current element
 /// (by subtyping rules), then don't process the element and return `False`.
@@ -637,17 +800,11 @@ fn insert_elem<Pk: MiniscriptKey>(
     elem: AstElemExt<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
+    constraints: CompilerConstraints,
 ) -> bool {
-    // return malleable types directly. If a elem is malleable, all the casts
-    // to it are also going to be malleable
-    if !elem.ms.ty.mall.non_malleable {
+    if !is_admissible(&elem, constraints) {
         return false;
     }
-    if let Some(op_count) = elem.ms.ext.ops_count_sat {
-        if op_count > MAX_OPS_PER_SCRIPT {
-            return false;
-        }
-    }
 
     let elem_cost = elem.cost_1d(sat_prob, dissat_prob);
 
@@ -688,9 +845,10 @@ fn insert_elem_closure<Pk: MiniscriptKey>(
     astelem_ext: AstElemExt<Pk>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
+    constraints: CompilerConstraints,
 ) {
     let mut cast_stack: VecDeque<AstElemExt<Pk>> = VecDeque::new();
-    if insert_elem(map, astelem_ext.clone(), sat_prob, dissat_prob) {
+    if insert_elem(map, astelem_ext.clone(), sat_prob, dissat_prob, constraints) {
         cast_stack.push_back(astelem_ext);
     }
 
@@ -700,7 +858,7 @@ fn insert_elem_closure<Pk: MiniscriptKey>(
 
         for i in 0..casts.len() {
             if let Ok(new_ext) = casts[i].cast(&current) {
-                if insert_elem(map, new_ext.clone(), sat_prob, dissat_prob) {
+                if insert_elem(map, new_ext.clone(), sat_prob, dissat_prob, constraints) {
                     cast_stack.push_back(new_ext);
                 }
             }
@@ -725,7 +883,8 @@ fn insert_best_wrapped<Pk: MiniscriptKey>(
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> Result<(), CompilerError> {
-    insert_elem_closure(map, data, sat_prob, dissat_prob);
+    let constraints = policy_cache.constraints;
+    insert_elem_closure(map, data, sat_prob, dissat_prob, constraints);
 
     if dissat_prob.is_some() {
         let casts: [Cast<Pk>; 10] = all_casts::<Pk>();
@@ -733,7 +892,7 @@ fn insert_best_wrapped<Pk: MiniscriptKey>(
         for i in 0..casts.len() {
             for x in best_compilations(policy_cache, policy, sat_prob, None)?.values() {
                 if let Ok(new_ext) = casts[i].cast(x) {
-                    insert_elem_closure(map, new_ext, sat_prob, dissat_prob);
+                    insert_elem_closure(map, new_ext, sat_prob, dissat_prob, constraints);
                 }
             }
         }
@@ -741,6 +900,15 @@ fn insert_best_wrapped<Pk: MiniscriptKey>(
     Ok(())
 }
 
+/// The error for a timelock which can't be expressed in Miniscript
+fn lock_time_error(n: u32) -> CompilerError {
+    if n == 0 {
+        CompilerError::PolicyError(policy::concrete::PolicyError::ZeroTime)
+    } else {
+        CompilerError::PolicyError(policy::concrete::PolicyError::TimeTooFar)
+    }
+}
+
 /// Get the best compilations of a policy with a given sat and dissat
 /// probabilities. This functions caches the results into a global policy cache.
 fn best_compilations<Pk>(
@@ -755,9 +923,13 @@ where
     //Check the cache for hits
     let ord_sat_prob = OrdF64(sat_prob);
     let ord_dissat_prob = dissat_prob.and_then(|x| Some(OrdF64(x)));
-    if let Some(ret) = policy_cache.get(&(policy.clone(), ord_sat_prob, ord_dissat_prob)) {
+    if let Some(ret) = policy_cache
+        .map
+        .get(&(policy.clone(), ord_sat_prob, ord_dissat_prob))
+    {
         return Ok(ret.clone());
     }
+    let hint = policy_cache.hints.get(policy).cloned();
 
     let mut ret = HashMap::new();
 
@@ -800,13 +972,23 @@ where
 
     match *policy {
         Concrete::Key(ref pk) => {
-            insert_wrap!(AstElemExt::terminal(Terminal::PkH(
-                pk.to_pubkeyhash().clone()
-            )));
-            insert_wrap!(AstElemExt::terminal(Terminal::PkK(pk.clone())));
+            if hint != Some(CompilerHint::PkK) {
+                insert_wrap!(AstElemExt::terminal(Terminal::PkH(
+                    pk.to_pubkeyhash().clone()
+                )));
+            }
+            if hint != Some(CompilerHint::PkH) {
+                insert_wrap!(AstElemExt::terminal(Terminal::PkK(pk.clone())));
+            }
+        }
+        Concrete::After(n) => {
+            let t = AbsLockTime::from_consensus(n).map_err(|_| lock_time_error(n))?;
+            insert_wrap!(AstElemExt::terminal(Terminal::After(t)))
+        }
+        Concrete::Older(n) => {
+            let t = RelLockTime::from_consensus(n).map_err(|_| lock_time_error(n))?;
+            insert_wrap!(AstElemExt::terminal(Terminal::Older(t)))
         }
-        Concrete::After(n) => insert_wrap!(AstElemExt::terminal(Terminal::After(n))),
-        Concrete::Older(n) => insert_wrap!(AstElemExt::terminal(Terminal::Older(n))),
         Concrete::Sha256(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Sha256(hash))),
         Concrete::Hash256(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Hash256(hash))),
         Concrete::Ripemd160(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Ripemd160(hash))),
@@ -923,6 +1105,19 @@ where
             compile_binary!(&mut l_comp[3], &mut r_comp[2], [lw, rw], Terminal::OrI);
             compile_binary!(&mut r_comp[3], &mut l_comp[2], [rw, lw], Terminal::OrI);
         }
+        Concrete::Threshold(k, ref subs) if hint == Some(CompilerHint::Multi) => {
+            let key_vec: Vec<Pk> = subs
+                .iter()
+                .filter_map(|s| {
+                    if let Concrete::Key(ref pk) = *s {
+                        Some(pk.clone())
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            insert_wrap!(AstElemExt::terminal(Terminal::Multi(k, key_vec)));
+        }
         Concrete::Threshold(k, ref subs) => {
             let n = subs.len();
             let k_over_n = k as f64 / n as f64;
@@ -959,12 +1154,17 @@ where
                 }
             }
 
+            // Not `Miniscript::from_ast`, which would refuse compilations
+            // nested deeper than `MAX_RECURSION_DEPTH`
             let ast = Terminal::Thresh(k, sub_ast);
             let ast_ext = AstElemExt {
-                ms: Arc::new(
-                    Miniscript::from_ast(ast)
+                ms: Arc::new(Miniscript {
+                    ty: types::Type::type_check(&ast, |_| None)
                         .expect("threshold subs, which we just compiled, typeck"),
-                ),
+                    ext: types::ExtData::type_check(&ast, |_| None)
+                        .expect("threshold subs, which we just compiled, typeck"),
+                    node: ast,
+                }),
                 comp_ext_data: CompilerExtData::threshold(k, n, |i| Ok(sub_ext_data[i]))
                     .expect("threshold subs, which we just compiled, typeck"),
             };
@@ -990,14 +1190,16 @@ where
     }
     if ret.len() == 0 {
         // The only reason we are discarding elements out of compiler is because
-        // compilations exceed opcount or are non-malleable . If there no possible
-        // compilations for any policies regardless of dissat probability then it
-        // must have all compilations exceeded the Max Opcount because we already
+        // compilations exceed the resource limits or are non-malleable . If there
+        // no possible compilations for any policies regardless of dissat probability
+        // then it must have all compilations exceeded the limits because we already
         // checked that policy must have non-malleable compilations before calling
         // this compile function
-        Err(CompilerError::MaxOpCountExceeded)
+        Err(CompilerError::LimitsExceeded)
     } else {
-        policy_cache.insert((policy.clone(), ord_sat_prob, ord_dissat_prob), ret.clone());
+        policy_cache
+            .map
+            .insert((policy.clone(), ord_sat_prob, ord_dissat_prob), ret.clone());
         Ok(ret)
     }
 }
@@ -1068,12 +1270,111 @@ fn compile_tern<Pk: MiniscriptKey>(
     Ok(())
 }
 
-/// Obtain the best compilation of for p=1.0 and q=0
-pub fn best_compilation<Pk: MiniscriptKey>(
+/// Obtain the best compilation of for p=1.0 and q=0 within the limits of
+/// the script context `Ctx`
+pub fn best_compilation<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    policy: &Concrete<Pk>,
+) -> Result<Miniscript<Pk>, CompilerError> {
+    best_compilation_with_hints::<Pk, Ctx>(policy, &[])
+}
+
+/// Checks that a hint can be honored for a given sub-policy
+fn check_hint<Pk: MiniscriptKey>(
+    policy: &Concrete<Pk>,
+    hint: CompilerHint,
+) -> Result<(), CompilerError> {
+    let ok = match (policy, hint) {
+        (&Concrete::Key(..), CompilerHint::PkK) | (&Concrete::Key(..), CompilerHint::PkH) => true,
+        (&Concrete::Threshold(_, ref subs), CompilerHint::Multi) => {
+            subs.len() <= 20
+                && subs.iter().all(|sub| match *sub {
+                    Concrete::Key(..) => true,
+                    _ => false,
+                })
+        }
+        _ => false,
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(CompilerError::IncompatibleHint(hint))
+    }
+}
+
+/// Obtain the best compilation of for p=1.0 and q=0 within the limits of
+/// the script context `Ctx`, expressing each sub-policy listed in `hints`
+/// with the fragment given for it
+pub fn best_compilation_with_hints<Pk: MiniscriptKey, Ctx: ScriptContext>(
     policy: &Concrete<Pk>,
+    hints: &[(Concrete<Pk>, CompilerHint)],
+) -> Result<Miniscript<Pk>, CompilerError> {
+    let constraints = CompilerConstraints::for_context::<Ctx>();
+    best_compilation_with_constraints(policy, hints, constraints)
+}
+
+/// Obtain the best compilation of for p=1.0 and q=0 within `constraints`,
+/// expressing each sub-policy listed in `hints` with the fragment given for
+/// it. If there is none, the constraint responsible is reported, see
+/// `CompilerError::ConstraintNotMet`.
+pub fn best_compilation_with_constraints<Pk: MiniscriptKey>(
+    policy: &Concrete<Pk>,
+    hints: &[(Concrete<Pk>, CompilerHint)],
+    constraints: CompilerConstraints,
+) -> Result<Miniscript<Pk>, CompilerError> {
+    trace_span!("compile", policy = %policy, n_hints = hints.len());
+    for &(ref sub, hint) in hints {
+        check_hint(sub, hint)?;
+    }
+    diagnose(constraints, |constraints| {
+        compile_constrained(policy, hints, constraints)
+    })
+}
+
+/// Run `compile` with `constraints` and, if no compilation meets them, find
+/// the first constraint which cannot be met together with the ones listed
+/// before it in `Constraint`, by adding them one at a time
+fn diagnose<T, F>(constraints: CompilerConstraints, mut compile: F) -> Result<T, CompilerError>
+where
+    F: FnMut(CompilerConstraints) -> Result<T, CompilerError>,
+{
+    match compile(constraints) {
+        Err(CompilerError::LimitsExceeded) => {}
+        res => return res,
+    }
+    let mut relaxed = CompilerConstraints::unconstrained();
+    for &constraint in &[
+        Constraint::ScriptSize,
+        Constraint::Ops,
+        Constraint::WitnessElements,
+        Constraint::SatisfactionSize,
+    ] {
+        relaxed.restrict(constraint, &constraints);
+        if let Err(CompilerError::LimitsExceeded) = compile(relaxed) {
+            return Err(CompilerError::ConstraintNotMet(constraint));
+        }
+    }
+    Err(CompilerError::LimitsExceeded)
+}
+
+/// Helper function for `best_compilation_with_constraints` doing a single
+/// compilation within `constraints`
+fn compile_constrained<Pk: MiniscriptKey>(
+    policy: &Concrete<Pk>,
+    hints: &[(Concrete<Pk>, CompilerHint)],
+    constraints: CompilerConstraints,
 ) -> Result<Miniscript<Pk>, CompilerError> {
     let mut policy_cache = PolicyCache::<Pk>::new();
+    policy_cache.constraints = constraints;
+    for &(ref sub, hint) in hints {
+        policy_cache.hints.insert(sub.clone(), hint);
+    }
     let x = &*best_t(&mut policy_cache, policy, 1.0, None)?.ms;
+    trace_event!(
+        miniscript = %x,
+        script_size = x.script_size(),
+        cache_entries = policy_cache.map.len(),
+        "chose compilation"
+    );
     if !x.ty.mall.safe {
         Err(CompilerError::TopLevelNonSafe)
     } else if !x.ty.mall.non_malleable {
@@ -1101,7 +1402,7 @@ where
         })
         .map(|(_, val)| val)
         .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
-        .ok_or(CompilerError::MaxOpCountExceeded)
+        .ok_or(CompilerError::LimitsExceeded)
 }
 
 /// Obtain the <basic-type>.deu (e.g. W.deu, B.deu) expression with the given sat and dissat
@@ -1125,7 +1426,303 @@ where
         })
         .map(|(_, val)| val)
         .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
-        .ok_or(CompilerError::MaxOpCountExceeded)
+        .ok_or(CompilerError::LimitsExceeded)
+}
+
+/// Maximum number of keys, timelocks and hashes of a policy compiled by
+/// `exhaustive_compilation`, whose running time is exponential in it
+pub const MAX_EXHAUSTIVE_LEAVES: usize = 6;
+
+/// Obtain the compilation for p=1.0 and q=0 with the lowest cost of all
+/// compilations within the limits of the script context `Ctx`, by trying
+/// every combination of fragments and wrappers. Unlike `best_compilation`
+/// this is guaranteed to find the optimum, but it refuses policies with
+/// more than `MAX_EXHAUSTIVE_LEAVES` leaves.
+pub fn exhaustive_compilation<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    policy: &Concrete<Pk>,
+) -> Result<Miniscript<Pk>, CompilerError> {
+    if n_leaves(policy) > MAX_EXHAUSTIVE_LEAVES {
+        return Err(CompilerError::PolicyTooLarge);
+    }
+    let constraints = CompilerConstraints::for_context::<Ctx>();
+    let x = diagnose(constraints, |constraints| {
+        best_exhaustive(policy, constraints)
+    })?;
+    Ok((*x.ms).clone())
+}
+
+/// The number of keys, timelocks and hashes of a policy
+fn n_leaves<Pk: MiniscriptKey>(policy: &Concrete<Pk>) -> usize {
+    match *policy {
+        Concrete::And(ref subs) | Concrete::Threshold(_, ref subs) => {
+            subs.iter().map(n_leaves).sum()
+        }
+        Concrete::Or(ref subs) => subs.iter().map(|&(_, ref sub)| n_leaves(sub)).sum(),
+        _ => 1,
+    }
+}
+
+/// Obtain the safe B expression with the lowest cost for p=1.0 and q=0
+/// among all compilations of the policy
+fn best_exhaustive<Pk: MiniscriptKey>(
+    policy: &Concrete<Pk>,
+    constraints: CompilerConstraints,
+) -> Result<AstElemExt<Pk>, CompilerError> {
+    all_compilations(policy, constraints)?
+        .into_iter()
+        .filter(|ext| ext.ms.ty.corr.base == types::Base::B && ext.ms.ty.mall.safe)
+        .min_by_key(|ext| OrdF64(ext.cost_1d(1.0, None)))
+        .ok_or(CompilerError::LimitsExceeded)
+}
+
+/// Whether `a` can replace `b` in any compilation, making it no more
+/// expensive at any satisfaction and dissatisfaction probabilities, and
+/// executing no more opcodes
+fn dominates<Pk: MiniscriptKey>(a: &AstElemExt<Pk>, b: &AstElemExt<Pk>) -> bool {
+    fn le<T: PartialOrd>(a: Option<T>, b: Option<T>) -> bool {
+        match (a, b) {
+            (Some(x), Some(y)) => x <= y,
+            (None, None) => true,
+            _ => false,
+        }
+    }
+    a.ms.ty.is_subtype(b.ms.ty)
+        && (a.ms.ext.has_verify_form || !b.ms.ext.has_verify_form)
+        && a.ms.ext.pk_cost <= b.ms.ext.pk_cost
+        && a.ms.ext.ops_count_static <= b.ms.ext.ops_count_static
+        && le(a.ms.ext.ops_count_sat, b.ms.ext.ops_count_sat)
+        && le(a.ms.ext.ops_count_nsat, b.ms.ext.ops_count_nsat)
+        && a.comp_ext_data.sat_cost <= b.comp_ext_data.sat_cost
+        && le(a.comp_ext_data.dissat_cost, b.comp_ext_data.dissat_cost)
+}
+
+/// Insert an element and its cast closure into `set`, skipping elements
+/// dominated by one already in it and removing the ones they dominate.
+/// As costs only add up when combining elements, this never loses the
+/// optimal compilation.
+fn insert_undominated<Pk: MiniscriptKey>(
+    set: &mut Vec<AstElemExt<Pk>>,
+    elem: AstElemExt<Pk>,
+    constraints: CompilerConstraints,
+) {
+    let casts: [Cast<Pk>; 10] = all_casts::<Pk>();
+    let mut stack = vec![elem];
+    while let Some(current) = stack.pop() {
+        if !is_admissible(&current, constraints) || set.iter().any(|x| dominates(x, &current)) {
+            continue;
+        }
+        set.retain(|x| !dominates(&current, x));
+        for cast in casts.iter() {
+            if let Ok(new_ext) = cast.cast(&current) {
+                stack.push(new_ext);
+            }
+        }
+        set.push(current);
+    }
+}
+
+/// Helper function for `all_compilations` to attach the probability of a
+/// disjunction's branch to the compilation of that branch
+fn with_branch_prob<Pk: MiniscriptKey>(elem: &AstElemExt<Pk>, prob: f64) -> AstElemExt<Pk> {
+    let mut ret = elem.clone();
+    ret.comp_ext_data.branch_prob = Some(prob);
+    ret
+}
+
+/// Every compilation of a policy which is not dominated by another one
+fn all_compilations<Pk: MiniscriptKey>(
+    policy: &Concrete<Pk>,
+    constraints: CompilerConstraints,
+) -> Result<Vec<AstElemExt<Pk>>, CompilerError> {
+    let mut ret = vec![];
+
+    macro_rules! insert_binary {
+        ($f:expr, $l:expr, $r:expr, $w:expr) => {
+            let ast = $f(Arc::clone(&$l.ms), Arc::clone(&$r.ms));
+            let l = with_branch_prob($l, $w[0]);
+            let r = with_branch_prob($r, $w[1]);
+            if let Ok(new_ext) = AstElemExt::binary(ast, &l, &r) {
+                insert_undominated(&mut ret, new_ext, constraints);
+            }
+        };
+    }
+    macro_rules! insert_tern {
+        ($a:expr, $b:expr, $c:expr, $w:expr) => {
+            let ast = Terminal::AndOr(Arc::clone(&$a.ms), Arc::clone(&$b.ms), Arc::clone(&$c.ms));
+            let a = with_branch_prob($a, $w[0]);
+            let b = with_branch_prob($b, $w[0]);
+            let c = with_branch_prob($c, $w[1]);
+            if let Ok(new_ext) = AstElemExt::ternary(ast, &a, &b, &c) {
+                insert_undominated(&mut ret, new_ext, constraints);
+            }
+        };
+    }
+
+    match *policy {
+        Concrete::Key(ref pk) => {
+            let pkh = AstElemExt::terminal(Terminal::PkH(pk.to_pubkeyhash()));
+            insert_undominated(&mut ret, pkh, constraints);
+            let pk = AstElemExt::terminal(Terminal::PkK(pk.clone()));
+            insert_undominated(&mut ret, pk, constraints);
+        }
+        Concrete::After(n) => {
+            let t = AbsLockTime::from_consensus(n).map_err(|_| lock_time_error(n))?;
+            let ext = AstElemExt::terminal(Terminal::After(t));
+            insert_undominated(&mut ret, ext, constraints);
+        }
+        Concrete::Older(n) => {
+            let t = RelLockTime::from_consensus(n).map_err(|_| lock_time_error(n))?;
+            let ext = AstElemExt::terminal(Terminal::Older(t));
+            insert_undominated(&mut ret, ext, constraints);
+        }
+        Concrete::Sha256(hash) => {
+            let ext = AstElemExt::terminal(Terminal::Sha256(hash));
+            insert_undominated(&mut ret, ext, constraints);
+        }
+        Concrete::Hash256(hash) => {
+            let ext = AstElemExt::terminal(Terminal::Hash256(hash));
+            insert_undominated(&mut ret, ext, constraints);
+        }
+        Concrete::Ripemd160(hash) => {
+            let ext = AstElemExt::terminal(Terminal::Ripemd160(hash));
+            insert_undominated(&mut ret, ext, constraints);
+        }
+        Concrete::Hash160(hash) => {
+            let ext = AstElemExt::terminal(Terminal::Hash160(hash));
+            insert_undominated(&mut ret, ext, constraints);
+        }
+        Concrete::And(ref subs) => {
+            assert_eq!(subs.len(), 2, "and takes 2 args");
+            let left = all_compilations(&subs[0], constraints)?;
+            let right = all_compilations(&subs[1], constraints)?;
+            let zero = AstElemExt::terminal(Terminal::False);
+            for l in &left {
+                for r in &right {
+                    for &(x, y) in &[(l, r), (r, l)] {
+                        insert_binary!(Terminal::AndB, x, y, [1.0, 1.0]);
+                        insert_binary!(Terminal::AndV, x, y, [1.0, 1.0]);
+                        insert_tern!(x, y, &zero, [1.0, 0.0]);
+                    }
+                }
+            }
+        }
+        Concrete::Or(ref subs) => {
+            let total = (subs[0].0 + subs[1].0) as f64;
+            let lw = subs[0].0 as f64 / total;
+            let rw = subs[1].0 as f64 / total;
+
+            let left = all_compilations(&subs[0].1, constraints)?;
+            let right = all_compilations(&subs[1].1, constraints)?;
+            for l in &left {
+                for r in &right {
+                    for &(x, y, w) in &[(l, r, [lw, rw]), (r, l, [rw, lw])] {
+                        insert_binary!(Terminal::OrB, x, y, w);
+                        insert_binary!(Terminal::OrD, x, y, w);
+                        insert_binary!(Terminal::OrC, x, y, w);
+                        insert_binary!(Terminal::OrI, x, y, w);
+                    }
+                }
+            }
+
+            //and-or
+            for &(and, other, w) in &[
+                (&subs[0].1, &right, [lw, rw]),
+                (&subs[1].1, &left, [rw, lw]),
+            ] {
+                if let Concrete::And(ref x) = *and {
+                    let a_comps = all_compilations(&x[0], constraints)?;
+                    let b_comps = all_compilations(&x[1], constraints)?;
+                    for a in &a_comps {
+                        for b in &b_comps {
+                            for c in other {
+                                insert_tern!(a, b, c, w);
+                                insert_tern!(b, a, c, w);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Concrete::Threshold(k, ref subs) => {
+            let n = subs.len();
+            let sub_comps = subs
+                .iter()
+                .map(|sub| all_compilations(sub, constraints))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // Try each sub-policy as the first, B, argument and all
+            // combinations of compilations of them
+            for first in 0..n {
+                let mut combinations: Vec<Vec<&AstElemExt<Pk>>> = vec![vec![]];
+                for (i, comps) in sub_comps.iter().enumerate() {
+                    let base = if i == first {
+                        types::Base::B
+                    } else {
+                        types::Base::W
+                    };
+                    let comps: Vec<_> = comps
+                        .iter()
+                        .filter(|ext| {
+                            ext.ms.ty.corr.base == base && ext.comp_ext_data.dissat_cost.is_some()
+                        })
+                        .collect();
+                    combinations = combinations
+                        .into_iter()
+                        .flat_map(|comb| {
+                            comps.iter().map(move |ext| {
+                                let mut comb = comb.clone();
+                                comb.push(*ext);
+                                comb
+                            })
+                        })
+                        .collect();
+                }
+
+                for mut comb in combinations {
+                    let first_ext = comb.remove(first);
+                    comb.insert(0, first_ext);
+                    let ast =
+                        Terminal::Thresh(k, comb.iter().map(|ext| Arc::clone(&ext.ms)).collect());
+                    let ty = match types::Type::type_check(&ast, |_| None) {
+                        Ok(ty) => ty,
+                        Err(_) => continue,
+                    };
+                    let ext = match types::ExtData::type_check(&ast, |_| None) {
+                        Ok(ext) => ext,
+                        Err(_) => continue,
+                    };
+                    let comp_ext_data =
+                        CompilerExtData::threshold(k, n, |i| Ok(comb[i].comp_ext_data))
+                            .expect("threshold subs are dissatisfiable");
+                    let ast_ext = AstElemExt {
+                        ms: Arc::new(Miniscript {
+                            ty: ty,
+                            ext: ext,
+                            node: ast,
+                        }),
+                        comp_ext_data: comp_ext_data,
+                    };
+                    insert_undominated(&mut ret, ast_ext, constraints);
+                }
+            }
+
+            let key_vec: Vec<Pk> = subs
+                .iter()
+                .filter_map(|s| {
+                    if let Concrete::Key(ref pk) = *s {
+                        Some(pk.clone())
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            if key_vec.len() == subs.len() && subs.len() <= 20 {
+                let multi = AstElemExt::terminal(Terminal::Multi(k, key_vec));
+                insert_undominated(&mut ret, multi, constraints);
+            }
+        }
+    }
+    Ok(ret)
 }
 
 #[cfg(test)]
@@ -1137,11 +1734,13 @@ mod tests {
 
     use miniscript::satisfy;
     use policy::concrete::PolicyError::{
-        IncorrectThresh, NonBinaryArgAnd, NonBinaryArgOr, TimeTooFar, ZeroTime,
+        self, IncorrectThresh, NonBinaryArgAnd, NonBinaryArgOr, TimeTooFar, ZeroTime,
     };
     use policy::Liftable;
     use BitcoinSig;
+    use Descriptor;
     use DummyKey;
+    use {Legacy, Segwitv0};
 
     type SPolicy = Concrete<String>;
     type DummyPolicy = Concrete<DummyKey>;
@@ -1174,7 +1773,7 @@ mod tests {
 
     fn policy_compile_lift_check(s: &str) -> Result<(), CompilerError> {
         let policy = DummyPolicy::from_str(s).expect("parse");
-        let miniscript = policy.compile()?;
+        let miniscript = policy.compile::<Segwitv0>()?;
 
         assert_eq!(policy.lift().sorted(), miniscript.lift().sorted());
         Ok(())
@@ -1239,10 +1838,283 @@ mod tests {
         );
     }
 
+    #[test]
+    fn compile_context() {
+        // Uncompressed keys are only allowed outside of segwit
+        let policy = BPolicy::from_str(
+            "pk(0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\
+             483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8)",
+        )
+        .unwrap();
+        assert!(policy.compile::<Legacy>().is_ok());
+        assert_eq!(
+            policy.compile::<Segwitv0>(),
+            Err(CompilerError::IncompatibleKey)
+        );
+
+        // 100 signatures in 5 groups of 20 need more witness elements than
+        // are standard in P2WSH, however they are expressed
+        let group = |i: usize| {
+            SPolicy::Threshold(
+                20,
+                (0..20)
+                    .map(|j| SPolicy::Key(format!("K{}_{}", i, j)))
+                    .collect(),
+            )
+        };
+        let policy = (1..5).fold(group(0), |acc, i| SPolicy::And(vec![group(i), acc]));
+        let ms = policy.compile::<Legacy>().unwrap();
+        assert!(ms.within_resource_limits::<Legacy>());
+        assert!(!ms.within_resource_limits::<Segwitv0>());
+        assert_eq!(
+            policy.compile::<Segwitv0>(),
+            Err(CompilerError::ConstraintNotMet(Constraint::WitnessElements))
+        );
+    }
+
+    #[test]
+    fn compile_with_constraints() {
+        let policy = SPolicy::from_str("or(pk(A),and(pk(B),older(100)))").unwrap();
+        let constraints = CompilerConstraints::for_context::<Segwitv0>();
+        let ms = policy.compile_with_constraints::<Segwitv0>(constraints);
+        assert_eq!(ms, policy.compile::<Segwitv0>());
+
+        let sat_size = ms.unwrap().max_satisfaction_size(2);
+        let tight = CompilerConstraints {
+            max_satisfaction_size: Some(sat_size),
+            ..constraints
+        };
+        let ms = policy.compile_with_constraints::<Segwitv0>(tight).unwrap();
+        assert!(ms.max_satisfaction_size(2) <= sat_size);
+
+        // Each violated constraint is reported as such
+        let tight = CompilerConstraints {
+            max_satisfaction_size: Some(50),
+            ..constraints
+        };
+        assert_eq!(
+            policy.compile_with_constraints::<Segwitv0>(tight),
+            Err(CompilerError::ConstraintNotMet(
+                Constraint::SatisfactionSize
+            ))
+        );
+        let tight = CompilerConstraints {
+            max_ops: 1,
+            ..constraints
+        };
+        assert_eq!(
+            policy.compile_with_constraints::<Segwitv0>(tight),
+            Err(CompilerError::ConstraintNotMet(Constraint::Ops))
+        );
+        let tight = CompilerConstraints {
+            max_script_size: 10,
+            max_ops: 1,
+            ..constraints
+        };
+        assert_eq!(
+            policy.compile_with_constraints::<Segwitv0>(tight),
+            Err(CompilerError::ConstraintNotMet(Constraint::ScriptSize))
+        );
+    }
+
+    #[test]
+    fn compile_exhaustive() {
+        for s in &[
+            "pk(A)",
+            "and(pk(A),pk(B))",
+            "or(9@pk(A),1@and(pk(B),older(100)))",
+            "thresh(2,pk(A),pk(B),pk(C))",
+            "or(pk(A),and(pk(B),or(pk(C),after(100))))",
+        ] {
+            let policy = SPolicy::from_str(s).unwrap();
+            let heuristic = best_t(&mut PolicyCache::new(), &policy, 1.0, None).unwrap();
+            let constraints = PolicyCache::<String>::new().constraints;
+            let exhaustive = best_exhaustive(&policy, constraints).unwrap();
+
+            // Never worse than the heuristic compiler, up to rounding
+            assert!(exhaustive.cost_1d(1.0, None) <= heuristic.cost_1d(1.0, None) + 1e-9);
+            assert_eq!(policy.lift().sorted(), exhaustive.ms.lift().sorted());
+            assert_eq!(
+                policy.compile_exhaustive::<Segwitv0>(),
+                Ok((*exhaustive.ms).clone())
+            );
+        }
+
+        let policy =
+            SPolicy::from_str("thresh(4,pk(A),pk(B),pk(C),pk(D),pk(E),pk(F),pk(G))").unwrap();
+        assert!(policy.compile::<Segwitv0>().is_ok());
+        assert_eq!(
+            policy.compile_exhaustive::<Segwitv0>(),
+            Err(CompilerError::PolicyTooLarge)
+        );
+    }
+
+    #[test]
+    fn compile_with_hints() {
+        let policy =
+            SPolicy::from_str("or(thresh(2,pk(A),pk(B),pk(C)),and(pk(D),older(100)))").unwrap();
+        let thresh = SPolicy::from_str("thresh(2,pk(A),pk(B),pk(C))").unwrap();
+        let key_d = SPolicy::Key("D".to_owned());
+
+        let ms = policy
+            .compile_with_hints::<Segwitv0>(&[
+                (thresh.clone(), CompilerHint::Multi),
+                (key_d.clone(), CompilerHint::PkH),
+            ])
+            .unwrap();
+        assert_eq!(policy.lift().sorted(), ms.lift().sorted());
+        let ms_str = ms.to_string();
+        assert!(ms_str.contains("multi(2,A,B,C)"));
+        assert!(ms_str.contains("pk_h(D)"));
+
+        let ms = policy
+            .compile_with_hints::<Segwitv0>(&[(key_d.clone(), CompilerHint::PkK)])
+            .unwrap();
+        assert!(!ms.to_string().contains("pk_h(D)"));
+
+        assert_eq!(
+            policy.compile_with_hints::<Segwitv0>(&[(key_d, CompilerHint::Multi)]),
+            Err(CompilerError::IncompatibleHint(CompilerHint::Multi))
+        );
+        assert_eq!(
+            policy.compile_with_hints::<Segwitv0>(&[(thresh, CompilerHint::PkK)]),
+            Err(CompilerError::IncompatibleHint(CompilerHint::PkK))
+        );
+    }
+
+    #[test]
+    fn compile_private() {
+        let policy =
+            SPolicy::from_str("or(99@thresh(2,pk(A),pk(B),pk(C)),1@and(pk(D),after(100)))")
+                .unwrap();
+        let ms = policy.compile_private::<Segwitv0>().unwrap();
+        assert_eq!(policy.lift().sorted(), ms.lift().sorted());
+        let ms_str = ms.to_string();
+        assert!(ms_str.contains("multi(2,A,B,C)"));
+        assert!(!ms_str.contains("pk_h("));
+    }
+
+    #[test]
+    fn policy_combinators() {
+        let a = SPolicy::Key("A".to_owned());
+        let b = SPolicy::Key("B".to_owned());
+        let timelock = SPolicy::Older(1000);
+
+        let policy = SPolicy::or_with_odds(
+            9,
+            SPolicy::and(a.clone(), b.clone()).unwrap(),
+            1,
+            SPolicy::threshold(2, vec![a.clone(), b.clone(), timelock.clone()]).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            SPolicy::from_str("or(9@and(pk(A),pk(B)),1@thresh(2,pk(A),pk(B),older(1000)))")
+                .unwrap()
+        );
+        assert!(policy.compile::<Segwitv0>().is_ok());
+
+        assert_eq!(
+            SPolicy::or(a.clone(), b.clone()).unwrap(),
+            SPolicy::from_str("or(pk(A),pk(B))").unwrap()
+        );
+        assert_eq!(
+            SPolicy::or_with_odds(0, a.clone(), 1, b.clone()),
+            Err(PolicyError::ZeroOdds)
+        );
+        assert_eq!(
+            SPolicy::threshold(3, vec![a.clone(), b.clone()]),
+            Err(IncorrectThresh)
+        );
+        assert_eq!(SPolicy::and(a, SPolicy::Older(0)), Err(ZeroTime));
+    }
+
+    #[test]
+    fn policy_is_valid() {
+        let policy = SPolicy::from_str("or(pk(A),and(pk(B),older(1000)))").unwrap();
+        assert_eq!(policy.is_valid(), Ok(()));
+
+        // Repeated keys still compile, but are flagged by `is_valid`
+        let policy = SPolicy::from_str("or(pk(A),and(pk(A),older(1000)))").unwrap();
+        assert_eq!(policy.is_valid(), Err(PolicyError::DuplicatePubKeys));
+        assert!(policy.compile::<Segwitv0>().is_ok());
+
+        let policy = SPolicy::from_str("or(0@pk(A),1@pk(B))").unwrap();
+        assert_eq!(policy.is_valid(), Err(PolicyError::ZeroOdds));
+        assert_eq!(
+            policy.compile::<Segwitv0>(),
+            Err(CompilerError::PolicyError(PolicyError::ZeroOdds))
+        );
+
+        // Block heights and timestamps can't both be satisfied
+        let policy = SPolicy::from_str("and(pk(A),and(after(1000),after(500000001)))").unwrap();
+        assert_eq!(
+            policy.is_valid(),
+            Err(PolicyError::HeightTimeLockCombination)
+        );
+        assert_eq!(
+            policy.compile::<Segwitv0>(),
+            Err(CompilerError::PolicyError(
+                PolicyError::HeightTimeLockCombination
+            ))
+        );
+        let policy = SPolicy::from_str("thresh(2,pk(A),older(1000),older(4194305))").unwrap();
+        assert_eq!(
+            policy.is_valid(),
+            Err(PolicyError::HeightTimeLockCombination)
+        );
+        // ..unless they are in different branches
+        let policy =
+            SPolicy::from_str("or(and(pk(A),after(1000)),and(pk(B),after(500000001)))").unwrap();
+        assert_eq!(policy.is_valid(), Ok(()));
+        // ..or of different kinds
+        let policy = SPolicy::from_str("and(pk(A),and(after(1000),older(4194305)))").unwrap();
+        assert_eq!(policy.is_valid(), Ok(()));
+    }
+
+    #[test]
+    fn compile_decaying_multisig() {
+        let keys = vec!["A".to_owned(), "B".to_owned(), "C".to_owned()];
+
+        let policy = SPolicy::decaying_multisig(3, keys.clone(), &[26280, 52560]).unwrap();
+        assert_eq!(
+            policy,
+            SPolicy::from_str("thresh(3,pk(A),pk(B),pk(C),older(26280),older(52560))").unwrap()
+        );
+        let ms = policy.compile::<Segwitv0>().unwrap();
+        assert_eq!(policy.lift().sorted(), ms.lift().sorted());
+        assert_eq!(ms.lift().minimum_n_keys(), 1);
+        assert_eq!(
+            SPolicy::decaying_multisig_wsh(3, keys.clone(), &[26280, 52560]),
+            Ok(Descriptor::Wsh(ms))
+        );
+
+        assert_eq!(
+            SPolicy::decaying_multisig(2, keys.clone(), &[1000, 2000]),
+            Err(PolicyError::InvalidDecay)
+        );
+        assert_eq!(
+            SPolicy::decaying_multisig_wsh(2, keys.clone(), &[1000, 2000]),
+            Err(CompilerError::PolicyError(PolicyError::InvalidDecay))
+        );
+        assert_eq!(
+            SPolicy::decaying_multisig(3, keys.clone(), &[2000, 1000]),
+            Err(PolicyError::InvalidDecay)
+        );
+        assert_eq!(
+            SPolicy::decaying_multisig(4, keys.clone(), &[]),
+            Err(IncorrectThresh)
+        );
+        assert_eq!(
+            SPolicy::decaying_multisig(3, keys, &[0, 1000]),
+            Err(ZeroTime)
+        );
+    }
+
     #[test]
     fn compile_q() {
         let policy = SPolicy::from_str("or(1@and(pk(),pk()),127@pk())").expect("parsing");
-        let compilation = best_t(&mut HashMap::new(), &policy, 1.0, None).unwrap();
+        let compilation = best_t(&mut PolicyCache::new(), &policy, 1.0, None).unwrap();
 
         assert_eq!(compilation.cost_1d(1.0, None), 88.0 + 74.109375);
         assert_eq!(policy.lift().sorted(), compilation.ms.lift().sorted());
@@ -1250,7 +2122,7 @@ mod tests {
         let policy = SPolicy::from_str(
                 "and(and(and(or(127@thresh(2,pk(),pk(),thresh(2,or(127@pk(),1@pk()),after(100),or(and(pk(),after(200)),and(pk(),sha256(66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925))),pk())),1@pk()),sha256(66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925)),or(127@pk(),1@after(300))),or(127@after(400),pk()))"
             ).expect("parsing");
-        let compilation = best_t(&mut HashMap::new(), &policy, 1.0, None).unwrap();
+        let compilation = best_t(&mut PolicyCache::new(), &policy, 1.0, None).unwrap();
 
         assert_eq!(compilation.cost_1d(1.0, None), 437.0 + 299.4003295898438);
         assert_eq!(policy.lift().sorted(), compilation.ms.lift().sorted());
@@ -1262,7 +2134,7 @@ mod tests {
         let key_pol: Vec<BPolicy> = keys.iter().map(|k| Concrete::Key(*k)).collect();
 
         let policy: BPolicy = Concrete::Key(keys[0].clone());
-        let desc = policy.compile().unwrap();
+        let desc = policy.compile::<Segwitv0>().unwrap();
         assert_eq!(
             desc.encode(),
             script::Builder::new()
@@ -1278,7 +2150,7 @@ mod tests {
             keys[6],
             keys[7]
         );
-        let desc = policy.compile().unwrap();
+        let desc = policy.compile::<Segwitv0>().unwrap();
         assert_eq!(
             desc.encode(),
             script::Builder::new()
@@ -1305,7 +2177,7 @@ mod tests {
             ),
         ]);
 
-        let desc = policy.compile().unwrap();
+        let desc = policy.compile::<Segwitv0>().unwrap();
 
         let ms: Miniscript<bitcoin::PublicKey> = ms_str!(
             "or_d(multi(3,{},{},{},{},{}),\
@@ -1357,6 +2229,10 @@ mod tests {
         assert!(desc.satisfy((&right_sat, satisfy::Older(10001))).is_some());
         //timelock not met
         assert!(desc.satisfy((&right_sat, satisfy::Older(9999))).is_none());
+        // nor by a time based sequence
+        assert!(desc
+            .satisfy((&right_sat, satisfy::Older((1 << 22) | 10001)))
+            .is_none());
 
         assert_eq!(
             desc.satisfy((left_sat, satisfy::Older(9999))).unwrap(),
@@ -17,14 +17,20 @@
 
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use std::collections::HashSet;
 use std::{error, fmt, str};
 
+#[cfg(feature = "compiler")]
+use descriptor::Descriptor;
 use errstr;
 use expression::{self, FromTree};
 #[cfg(feature = "compiler")]
+use miniscript::context::{ScriptContext, Segwitv0};
+use miniscript::locktime::{LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_TYPE_FLAG};
+#[cfg(feature = "compiler")]
 use policy::compiler;
 #[cfg(feature = "compiler")]
-use policy::compiler::CompilerError;
+use policy::compiler::{CompilerConstraints, CompilerError, CompilerHint};
 #[cfg(feature = "compiler")]
 use Miniscript;
 use {Error, MiniscriptKey};
@@ -70,6 +76,16 @@ pub enum PolicyError {
     ZeroTime,
     /// `after` fragment can only have ` n < 2^31`
     TimeTooFar,
+    /// `or` branches must have nonzero odds
+    ZeroOdds,
+    /// Decaying multisig timelocks must be strictly increasing, and fewer
+    /// than the initial threshold so that at least one key is always needed
+    InvalidDecay,
+    /// A key is used more than once
+    DuplicatePubKeys,
+    /// Some branch requires both a block height and a time based timelock
+    /// of the same kind, and so can never be satisfied
+    HeightTimeLockCombination,
 }
 
 impl error::Error for PolicyError {
@@ -95,24 +111,234 @@ impl fmt::Display for PolicyError {
                 f.write_str("Relative/Absolute time must be less than 2^31; n < 2^31")
             }
             PolicyError::ZeroTime => f.write_str("Time must be greater than 0; n > 0"),
+            PolicyError::ZeroOdds => f.write_str("Or branch odds must be greater than 0"),
+            PolicyError::InvalidDecay => f.write_str(
+                "Decaying multisig timelocks must be increasing and fewer than the threshold",
+            ),
+            PolicyError::DuplicatePubKeys => f.write_str("Policy contains duplicate keys"),
+            PolicyError::HeightTimeLockCombination => {
+                f.write_str("Cannot mix block height and time based timelocks of the same kind")
+            }
         }
     }
 }
 
 impl<Pk: MiniscriptKey> Policy<Pk> {
     /// Compile the descriptor into an optimized `Miniscript` representation
+    /// for use in the script context `Ctx`, e.g. `compile::<Segwitv0>()` for
+    /// a P2WSH descriptor. The result is within the consensus and
+    /// standardness limits of `Ctx`, and all keys may be used in it.
     #[cfg(feature = "compiler")]
-    pub fn compile(&self) -> Result<Miniscript<Pk>, CompilerError> {
-        self.is_valid()?;
+    pub fn compile<Ctx: ScriptContext>(&self) -> Result<Miniscript<Pk>, CompilerError> {
+        self.check_well_formed()?;
+        self.check_keys::<Ctx>()?;
         match self.is_safe_nonmalleable() {
             (false, _) => Err(CompilerError::TopLevelNonSafe),
             (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
-            _ => compiler::best_compilation(self),
+            _ => compiler::best_compilation::<Pk, Ctx>(self),
+        }
+    }
+
+    /// Compile the descriptor into an optimized `Miniscript` representation
+    /// for use in the script context `Ctx`, forcing each sub-policy in
+    /// `hints` to be expressed with the fragment given for it. Sub-policies
+    /// are matched by value.
+    #[cfg(feature = "compiler")]
+    pub fn compile_with_hints<Ctx: ScriptContext>(
+        &self,
+        hints: &[(Policy<Pk>, CompilerHint)],
+    ) -> Result<Miniscript<Pk>, CompilerError> {
+        self.check_well_formed()?;
+        self.check_keys::<Ctx>()?;
+        match self.is_safe_nonmalleable() {
+            (false, _) => Err(CompilerError::TopLevelNonSafe),
+            (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
+            _ => compiler::best_compilation_with_hints::<Pk, Ctx>(self, hints),
+        }
+    }
+
+    /// Compile the descriptor into an optimized `Miniscript` representation
+    /// for use in the script context `Ctx` which meets `constraints` instead
+    /// of the limits of `Ctx`, e.g. to bound the size of its satisfactions.
+    /// If the compiler finds no such miniscript, the constraint which cannot
+    /// be met is reported as `CompilerError::ConstraintNotMet`.
+    #[cfg(feature = "compiler")]
+    pub fn compile_with_constraints<Ctx: ScriptContext>(
+        &self,
+        constraints: CompilerConstraints,
+    ) -> Result<Miniscript<Pk>, CompilerError> {
+        self.check_well_formed()?;
+        self.check_keys::<Ctx>()?;
+        match self.is_safe_nonmalleable() {
+            (false, _) => Err(CompilerError::TopLevelNonSafe),
+            (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
+            _ => compiler::best_compilation_with_constraints(self, &[], constraints),
+        }
+    }
+
+    /// Compile the descriptor preferring fragments which are common in
+    /// existing wallets, so that the resulting script is harder to
+    /// fingerprint on-chain, at the cost of a possibly larger witness.
+    /// Every threshold of at most 20 keys is expressed as `multi`, and
+    /// every other key as `pk`, matching the scripts produced by most
+    /// multisig wallets.
+    #[cfg(feature = "compiler")]
+    pub fn compile_private<Ctx: ScriptContext>(&self) -> Result<Miniscript<Pk>, CompilerError> {
+        let mut hints = vec![];
+        self.privacy_hints(&mut hints);
+        self.compile_with_hints::<Ctx>(&hints)
+    }
+
+    /// Compile the descriptor into the `Miniscript` with the lowest expected
+    /// cost under the compiler's cost model, by trying every combination of
+    /// fragments instead of building on the best compilations of each
+    /// sub-policy. The running time grows exponentially with the size of the
+    /// policy, so only policies with at most `MAX_EXHAUSTIVE_LEAVES` keys,
+    /// timelocks and hashes are accepted.
+    #[cfg(feature = "compiler")]
+    pub fn compile_exhaustive<Ctx: ScriptContext>(&self) -> Result<Miniscript<Pk>, CompilerError> {
+        self.check_well_formed()?;
+        self.check_keys::<Ctx>()?;
+        match self.is_safe_nonmalleable() {
+            (false, _) => Err(CompilerError::TopLevelNonSafe),
+            (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
+            _ => compiler::exhaustive_compilation::<Pk, Ctx>(self),
+        }
+    }
+
+    /// Helper function for `compile` to check that every key may be used in
+    /// the context `Ctx`
+    #[cfg(feature = "compiler")]
+    fn check_keys<Ctx: ScriptContext>(&self) -> Result<(), CompilerError> {
+        match *self {
+            Policy::Key(ref pk) => Ctx::check_pk(pk).map_err(|_| CompilerError::IncompatibleKey),
+            Policy::And(ref subs) | Policy::Threshold(_, ref subs) => {
+                subs.iter().map(|sub| sub.check_keys::<Ctx>()).collect()
+            }
+            Policy::Or(ref subs) => subs
+                .iter()
+                .map(|&(_, ref sub)| sub.check_keys::<Ctx>())
+                .collect(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Helper function for `compile_private` to collect compiler hints
+    #[cfg(feature = "compiler")]
+    fn privacy_hints(&self, hints: &mut Vec<(Policy<Pk>, CompilerHint)>) {
+        match *self {
+            Policy::Key(..) => hints.push((self.clone(), CompilerHint::PkK)),
+            Policy::Threshold(_, ref subs)
+                if subs.len() <= 20
+                    && subs.iter().all(|sub| match *sub {
+                        Policy::Key(..) => true,
+                        _ => false,
+                    }) =>
+            {
+                hints.push((self.clone(), CompilerHint::Multi))
+            }
+            Policy::And(ref subs) | Policy::Threshold(_, ref subs) => {
+                for sub in subs {
+                    sub.privacy_hints(hints);
+                }
+            }
+            Policy::Or(ref subs) => {
+                for &(_, ref sub) in subs {
+                    sub.privacy_hints(hints);
+                }
+            }
+            _ => {}
         }
     }
 }
 
 impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Construct a "decaying" multisig policy, which initially requires `k`
+    /// of the given keys, and one key fewer after each of the relative
+    /// timelocks `decay` has passed. For example a 3-of-3 which becomes a
+    /// 2-of-3 after ~6 months and a 1-of-3 after ~1 year is given by
+    /// `decaying_multisig(3, keys, &[26280, 52560])`.
+    ///
+    /// This is expressed as `thresh(k,pk(A),pk(B),..,older(t1),older(t2),..)`
+    /// and the result is checked as by `is_valid`, except that repeated keys
+    /// are allowed.
+    pub fn decaying_multisig(
+        k: usize,
+        keys: Vec<Pk>,
+        decay: &[u32],
+    ) -> Result<Policy<Pk>, PolicyError> {
+        if decay.len() >= k || decay.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(PolicyError::InvalidDecay);
+        }
+        if k > keys.len() {
+            return Err(PolicyError::IncorrectThresh);
+        }
+        let subs = keys
+            .into_iter()
+            .map(Policy::Key)
+            .chain(decay.iter().map(|t| Policy::Older(*t)))
+            .collect();
+        Policy::threshold(k, subs)
+    }
+
+    /// Construct a decaying multisig policy as by `decaying_multisig` and
+    /// compile it into a P2WSH descriptor
+    #[cfg(feature = "compiler")]
+    pub fn decaying_multisig_wsh(
+        k: usize,
+        keys: Vec<Pk>,
+        decay: &[u32],
+    ) -> Result<Descriptor<Pk>, CompilerError> {
+        let policy =
+            Policy::decaying_multisig(k, keys, decay).map_err(CompilerError::PolicyError)?;
+        Ok(Descriptor::Wsh(policy.compile::<Segwitv0>()?))
+    }
+
+    /// Add a timelocked recovery path to the policy, giving
+    /// `or(127@POLICY,1@and(pk(RECOVERY),after(T)))`. The existing policy is
+    /// assumed to be the common case, so the compiler optimizes for it.
+    pub fn with_recovery(self, recovery: Pk, after: u32) -> Result<Policy<Pk>, PolicyError> {
+        let recovery = Policy::and(Policy::Key(recovery), Policy::After(after))?;
+        Policy::or_with_odds(127, self, 1, recovery)
+    }
+
+    /// Construct `and(left,right)`, checking that the result is valid
+    pub fn and(left: Policy<Pk>, right: Policy<Pk>) -> Result<Policy<Pk>, PolicyError> {
+        let ret = Policy::And(vec![left, right]);
+        ret.check_well_formed()?;
+        Ok(ret)
+    }
+
+    /// Construct `or(left,right)` with both branches equally likely,
+    /// checking that the result is valid
+    pub fn or(left: Policy<Pk>, right: Policy<Pk>) -> Result<Policy<Pk>, PolicyError> {
+        Policy::or_with_odds(1, left, 1, right)
+    }
+
+    /// Construct `or(L@left,R@right)`, where the odds `L` and `R` give the
+    /// relative probabilities of each branch being used to satisfy the
+    /// policy, checking that the result is valid
+    pub fn or_with_odds(
+        left_odds: usize,
+        left: Policy<Pk>,
+        right_odds: usize,
+        right: Policy<Pk>,
+    ) -> Result<Policy<Pk>, PolicyError> {
+        if left_odds == 0 || right_odds == 0 {
+            return Err(PolicyError::ZeroOdds);
+        }
+        let ret = Policy::Or(vec![(left_odds, left), (right_odds, right)]);
+        ret.check_well_formed()?;
+        Ok(ret)
+    }
+
+    /// Construct `thresh(k,subs..)`, checking that the result is valid
+    pub fn threshold(k: usize, subs: Vec<Policy<Pk>>) -> Result<Policy<Pk>, PolicyError> {
+        let ret = Policy::Threshold(k, subs);
+        ret.check_well_formed()?;
+        Ok(ret)
+    }
+
     /// Convert a policy using one kind of public key to another
     /// type of public key
     pub fn translate_pk<Fpk, Q, E>(&self, mut translatefpk: Fpk) -> Result<Policy<Q>, E>
@@ -148,16 +374,90 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         }
     }
 
-    /// This returns whether the given policy is valid or not. It maybe possible that the policy
-    /// contains Non-two argument `and`, `or` or a `0` arg thresh.
+    /// Checks that the policy is well-formed: `and`s and `or`s have two
+    /// branches, each with nonzero odds, thresholds have `1 <= k <= n`,
+    /// timelocks are in range and no branch requires timelocks which can
+    /// never be satisfied together. Also checks that no key is used more
+    /// than once, which compilation and the constructors allow.
     pub fn is_valid(&self) -> Result<(), PolicyError> {
+        self.check_well_formed()?;
+        self.check_duplicate_keys()
+    }
+
+    /// Checks everything `is_valid` does except for repeated keys, which
+    /// still give a valid (if unwise) Miniscript
+    fn check_well_formed(&self) -> Result<(), PolicyError> {
+        self.check_structure()?;
+        self.check_timelocks()
+    }
+
+    /// Checks that no key is used more than once
+    pub fn check_duplicate_keys(&self) -> Result<(), PolicyError> {
+        let mut keys = HashSet::new();
+        let mut stack = vec![self];
+        while let Some(policy) = stack.pop() {
+            match *policy {
+                Policy::Key(ref pk) => {
+                    if !keys.insert(pk) {
+                        return Err(PolicyError::DuplicatePubKeys);
+                    }
+                }
+                Policy::And(ref subs) | Policy::Threshold(_, ref subs) => stack.extend(subs),
+                Policy::Or(ref subs) => stack.extend(subs.iter().map(|&(_, ref sub)| sub)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that no branch of the policy requires both a block height and
+    /// a time based timelock of the same kind, as a transaction can only
+    /// satisfy one of them
+    pub fn check_timelocks(&self) -> Result<(), PolicyError> {
+        if self.timelock_info().contains_combination {
+            Err(PolicyError::HeightTimeLockCombination)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Helper function for `check_timelocks` to do the recursion
+    fn timelock_info(&self) -> TimelockInfo {
+        match *self {
+            Policy::After(t) => TimelockInfo {
+                cltv_with_height: t < LOCKTIME_THRESHOLD,
+                cltv_with_time: t >= LOCKTIME_THRESHOLD,
+                ..TimelockInfo::default()
+            },
+            Policy::Older(t) => TimelockInfo {
+                csv_with_height: t & SEQUENCE_LOCKTIME_TYPE_FLAG == 0,
+                csv_with_time: t & SEQUENCE_LOCKTIME_TYPE_FLAG != 0,
+                ..TimelockInfo::default()
+            },
+            Policy::And(ref subs) => {
+                TimelockInfo::combine_threshold(subs.len(), subs.iter().map(Policy::timelock_info))
+            }
+            Policy::Or(ref subs) => TimelockInfo::combine_threshold(
+                1,
+                subs.iter().map(|&(_, ref sub)| sub.timelock_info()),
+            ),
+            Policy::Threshold(k, ref subs) => {
+                TimelockInfo::combine_threshold(k, subs.iter().map(Policy::timelock_info))
+            }
+            _ => TimelockInfo::default(),
+        }
+    }
+
+    /// Helper function for `is_valid` to check the arity of `and`s, `or`s
+    /// and thresholds, the odds of `or`s and the range of timelocks
+    fn check_structure(&self) -> Result<(), PolicyError> {
         match *self {
             Policy::And(ref subs) => {
                 if subs.len() != 2 {
                     Err(PolicyError::NonBinaryArgAnd)
                 } else {
                     subs.iter()
-                        .map(|sub| sub.is_valid())
+                        .map(|sub| sub.check_structure())
                         .collect::<Result<Vec<()>, PolicyError>>()?;
                     Ok(())
                 }
@@ -165,9 +465,11 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             Policy::Or(ref subs) => {
                 if subs.len() != 2 {
                     Err(PolicyError::NonBinaryArgOr)
+                } else if subs.iter().any(|&(prob, _)| prob == 0) {
+                    Err(PolicyError::ZeroOdds)
                 } else {
                     subs.iter()
-                        .map(|&(ref _prob, ref sub)| sub.is_valid())
+                        .map(|&(ref _prob, ref sub)| sub.check_structure())
                         .collect::<Result<Vec<()>, PolicyError>>()?;
                     Ok(())
                 }
@@ -177,7 +479,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                     Err(PolicyError::IncorrectThresh)
                 } else {
                     subs.iter()
-                        .map(|sub| sub.is_valid())
+                        .map(|sub| sub.check_structure())
                         .collect::<Result<Vec<()>, PolicyError>>()?;
                     Ok(())
                 }
@@ -185,7 +487,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             Policy::After(n) | Policy::Older(n) => {
                 if n == 0 {
                     Err(PolicyError::ZeroTime)
-                } else if n > 2u32.pow(31) {
+                } else if n >= 2u32.pow(31) {
                     Err(PolicyError::TimeTooFar)
                 } else {
                     Ok(())
@@ -194,6 +496,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             _ => Ok(()),
         }
     }
+
     /// This returns whether any possible compilation of the policy could be
     /// compiled as non-malleable and safe. Note that this returns a tuple
     /// (safe, non-malleable) to avoid because the non-malleability depends on
@@ -244,6 +547,47 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     }
 }
 
+/// The kinds of timelocks required by a policy, see `Policy::check_timelocks`
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+struct TimelockInfo {
+    /// Some branch has a block height based relative timelock
+    csv_with_height: bool,
+    /// Some branch has a time based relative timelock
+    csv_with_time: bool,
+    /// Some branch has a block height based absolute timelock
+    cltv_with_height: bool,
+    /// Some branch has a time based absolute timelock
+    cltv_with_time: bool,
+    /// Some branch requires both block height and time based timelocks of
+    /// the same kind
+    contains_combination: bool,
+}
+
+impl TimelockInfo {
+    /// Combines the timelocks of the branches of a threshold. If `k > 1`
+    /// any two branches may have to be satisfied together, so conflicting
+    /// timelocks in different branches are a combination.
+    fn combine_threshold<I>(k: usize, subs: I) -> TimelockInfo
+    where
+        I: Iterator<Item = TimelockInfo>,
+    {
+        subs.fold(TimelockInfo::default(), |mut acc, sub| {
+            if k > 1 {
+                acc.contains_combination |= (acc.csv_with_height && sub.csv_with_time)
+                    || (acc.csv_with_time && sub.csv_with_height)
+                    || (acc.cltv_with_height && sub.cltv_with_time)
+                    || (acc.cltv_with_time && sub.cltv_with_height);
+            }
+            acc.csv_with_height |= sub.csv_with_height;
+            acc.csv_with_time |= sub.csv_with_time;
+            acc.cltv_with_height |= sub.cltv_with_height;
+            acc.cltv_with_time |= sub.cltv_with_time;
+            acc.contains_combination |= sub.contains_combination;
+            acc
+        })
+    }
+}
+
 impl<Pk: MiniscriptKey> fmt::Debug for Policy<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
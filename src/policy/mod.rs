@@ -58,8 +58,8 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Terminal<Pk> {
         match *self {
             Terminal::PkK(ref pk) => Semantic::KeyHash(pk.to_pubkeyhash()),
             Terminal::PkH(ref pkh) => Semantic::KeyHash(pkh.clone()),
-            Terminal::After(t) => Semantic::After(t),
-            Terminal::Older(t) => Semantic::Older(t),
+            Terminal::After(t) => Semantic::After(t.to_u32()),
+            Terminal::Older(t) => Semantic::Older(t.to_u32()),
             Terminal::Sha256(h) => Semantic::Sha256(h),
             Terminal::Hash256(h) => Semantic::Hash256(h),
             Terminal::Ripemd160(h) => Semantic::Ripemd160(h),
@@ -111,6 +111,9 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Descriptor<Pk> {
             | Descriptor::Pkh(ref p)
             | Descriptor::Wpkh(ref p)
             | Descriptor::ShWpkh(ref p) => Semantic::KeyHash(p.to_pubkeyhash()),
+            // The spending conditions are unknown, so as far as we can tell
+            // these can't be spent
+            Descriptor::Addr(..) | Descriptor::Raw(..) => Semantic::Unsatisfiable,
         }
     }
 }
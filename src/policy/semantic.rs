@@ -16,9 +16,11 @@
 
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use std::collections::BTreeSet;
 use std::{fmt, str};
 
 use errstr;
+use miniscript::locktime::LOCKTIME_THRESHOLD;
 use std::str::FromStr;
 use Error;
 use {expression, MiniscriptKey};
@@ -276,17 +278,24 @@ where
 
 impl<Pk: MiniscriptKey> Policy<Pk> {
     /// Flatten out trees of `And`s and `Or`s; eliminate `Trivial` and
-    /// `Unsatisfiable`s. Does not reorder any branches; use `.sort`.
+    /// `Unsatisfiable`s and branches repeated within an `And` or `Or`.
+    /// Does not reorder any branches; use `.sorted` so that policies which
+    /// only differ in the order of their branches compare equal.
     pub fn normalized(self) -> Policy<Pk> {
         match self {
             Policy::And(subs) => {
                 let mut ret_subs = Vec::with_capacity(subs.len());
                 for sub in subs {
-                    match sub.normalized() {
-                        Policy::Trivial => {}
+                    let flat_subs = match sub.normalized() {
+                        Policy::Trivial => continue,
                         Policy::Unsatisfiable => return Policy::Unsatisfiable,
-                        Policy::And(and_subs) => ret_subs.extend(and_subs),
-                        x => ret_subs.push(x),
+                        Policy::And(and_subs) => and_subs,
+                        x => vec![x],
+                    };
+                    for x in flat_subs {
+                        if !ret_subs.contains(&x) {
+                            ret_subs.push(x);
+                        }
                     }
                 }
                 match ret_subs.len() {
@@ -298,19 +307,27 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             Policy::Or(subs) => {
                 let mut ret_subs = Vec::with_capacity(subs.len());
                 for sub in subs {
-                    match sub {
+                    let flat_subs = match sub.normalized() {
                         Policy::Trivial => return Policy::Trivial,
-                        Policy::Unsatisfiable => {}
-                        Policy::Or(or_subs) => ret_subs.extend(or_subs),
-                        x => ret_subs.push(x),
+                        Policy::Unsatisfiable => continue,
+                        Policy::Or(or_subs) => or_subs,
+                        x => vec![x],
+                    };
+                    for x in flat_subs {
+                        if !ret_subs.contains(&x) {
+                            ret_subs.push(x);
+                        }
                     }
                 }
                 match ret_subs.len() {
-                    0 => Policy::Trivial,
+                    0 => Policy::Unsatisfiable,
                     1 => ret_subs.pop().unwrap(),
                     _ => Policy::Or(ret_subs),
                 }
             }
+            Policy::Threshold(k, subs) => {
+                Policy::Threshold(k, subs.into_iter().map(|sub| sub.normalized()).collect())
+            }
             x => x,
         }
     }
@@ -331,6 +348,63 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         }
     }
 
+    /// Whether any of the given key hashes appears in the policy
+    pub fn involves_any(&self, pkhs: &[Pk::Hash]) -> bool {
+        match *self {
+            Policy::KeyHash(ref pkh) => pkhs.contains(pkh),
+            Policy::And(ref subs) | Policy::Or(ref subs) | Policy::Threshold(_, ref subs) => {
+                subs.iter().any(|sub| sub.involves_any(pkhs))
+            }
+            _ => false,
+        }
+    }
+
+    /// Reduce the policy to the spending paths which require a signature
+    /// from at least one of the given keys, e.g. to show a participant in
+    /// a multi-party setup only the branches they are involved in.
+    /// Disjunction branches not involving any of the keys are dropped;
+    /// conjunctions and thresholds are kept whole so that the conditions
+    /// which must be met alongside the participant remain visible.
+    /// Returns `Unsatisfiable` if none of the keys appear in the policy.
+    pub fn for_signer(self, pkhs: &[Pk::Hash]) -> Policy<Pk> {
+        if !self.involves_any(pkhs) {
+            return Policy::Unsatisfiable;
+        }
+        match self {
+            Policy::Or(subs) => Policy::Or(
+                subs.into_iter()
+                    .filter(|sub| sub.involves_any(pkhs))
+                    .map(|sub| sub.for_signer(pkhs))
+                    .collect(),
+            )
+            .normalized(),
+            Policy::And(subs) => Policy::And(
+                subs.into_iter()
+                    .map(|sub| {
+                        if sub.involves_any(pkhs) {
+                            sub.for_signer(pkhs)
+                        } else {
+                            sub
+                        }
+                    })
+                    .collect(),
+            ),
+            Policy::Threshold(k, subs) => Policy::Threshold(
+                k,
+                subs.into_iter()
+                    .map(|sub| {
+                        if sub.involves_any(pkhs) {
+                            sub.for_signer(pkhs)
+                        } else {
+                            sub
+                        }
+                    })
+                    .collect(),
+            ),
+            x => x,
+        }
+    }
+
     /// Helper function to do the recursion in `timelocks`.
     fn real_relative_timelocks(&self) -> Vec<u32> {
         match *self {
@@ -388,6 +462,33 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         self.normalized()
     }
 
+    /// Filter a policy by eliminating absolute timelock constraints
+    /// that are not satisfied at the given block height or time. Block
+    /// heights and timestamps never satisfy each other.
+    pub fn at_height(mut self, height: u32) -> Policy<Pk> {
+        self = match self {
+            Policy::After(t) => {
+                if (t < LOCKTIME_THRESHOLD) != (height < LOCKTIME_THRESHOLD) || t > height {
+                    Policy::Unsatisfiable
+                } else {
+                    Policy::After(t)
+                }
+            }
+            Policy::And(subs) => {
+                Policy::And(subs.into_iter().map(|sub| sub.at_height(height)).collect())
+            }
+            Policy::Or(subs) => {
+                Policy::Or(subs.into_iter().map(|sub| sub.at_height(height)).collect())
+            }
+            Policy::Threshold(k, subs) => Policy::Threshold(
+                k,
+                subs.into_iter().map(|sub| sub.at_height(height)).collect(),
+            ),
+            x => x,
+        };
+        self.normalized()
+    }
+
     /// Count the number of public keys and keyhashes referenced in a policy.
     /// Duplicate keys will be double-counted.
     pub fn n_keys(&self) -> usize {
@@ -430,6 +531,119 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     }
 }
 
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Enumerate all minimal satisfying assignments of the policy. Each
+    /// assignment is a sorted list of leaf policies (keys, hash preimages
+    /// and timelocks) which together satisfy the policy, and no assignment
+    /// is a superset of another. This is meant for testing, e.g. to check
+    /// every satisfaction of a script against an external interpreter.
+    ///
+    /// Since the number of assignments grows exponentially with the size of
+    /// the policy, `None` is returned as soon as any intermediate result
+    /// exceeds `max_assignments` assignments.
+    pub fn minimal_satisfactions(&self, max_assignments: usize) -> Option<Vec<Vec<Policy<Pk>>>> {
+        let sets = self.satisfying_sets(max_assignments)?;
+        let mut ret: Vec<Vec<Policy<Pk>>> = sets
+            .iter()
+            .filter(|set| {
+                !sets
+                    .iter()
+                    .any(|other| other != *set && other.is_subset(set))
+            })
+            .map(|set| set.iter().cloned().collect())
+            .collect();
+        ret.sort();
+        ret.dedup();
+        Some(ret)
+    }
+
+    /// Helper function for `minimal_satisfactions`, returning all (not
+    /// necessarily minimal) satisfying sets of leaves
+    fn satisfying_sets(&self, max: usize) -> Option<Vec<BTreeSet<Policy<Pk>>>> {
+        // Cartesian product of the satisfying sets of several sub-policies
+        fn product<Pk: MiniscriptKey>(
+            subs: &[&Policy<Pk>],
+            max: usize,
+        ) -> Option<Vec<BTreeSet<Policy<Pk>>>> {
+            let mut ret = vec![BTreeSet::new()];
+            for sub in subs {
+                let sub_sets = sub.satisfying_sets(max)?;
+                let mut new_ret = Vec::with_capacity(ret.len() * sub_sets.len());
+                for set in &ret {
+                    for sub_set in &sub_sets {
+                        new_ret.push(set.union(sub_set).cloned().collect());
+                    }
+                }
+                if new_ret.len() > max {
+                    return None;
+                }
+                ret = new_ret;
+            }
+            Some(ret)
+        }
+
+        let ret = match *self {
+            Policy::Unsatisfiable => vec![],
+            Policy::Trivial => vec![BTreeSet::new()],
+            Policy::KeyHash(..)
+            | Policy::After(..)
+            | Policy::Older(..)
+            | Policy::Sha256(..)
+            | Policy::Hash256(..)
+            | Policy::Ripemd160(..)
+            | Policy::Hash160(..) => {
+                let mut set = BTreeSet::new();
+                set.insert(self.clone());
+                vec![set]
+            }
+            Policy::And(ref subs) => product(&subs.iter().collect::<Vec<_>>(), max)?,
+            Policy::Or(ref subs) => {
+                let mut ret = vec![];
+                for sub in subs {
+                    ret.extend(sub.satisfying_sets(max)?);
+                }
+                ret
+            }
+            Policy::Threshold(k, ref subs) => {
+                // Iterate over all k-subsets of `subs`, represented by
+                // increasing lists of indices
+                let n = subs.len();
+                if k > n {
+                    return Some(vec![]);
+                }
+                let mut ret = vec![];
+                let mut indices: Vec<usize> = (0..k).collect();
+                loop {
+                    let chosen: Vec<_> = indices.iter().map(|&i| &subs[i]).collect();
+                    ret.extend(product(&chosen, max)?);
+                    if ret.len() > max {
+                        return None;
+                    }
+
+                    // Advance to the next k-subset, if any
+                    let mut i = k;
+                    while i > 0 && indices[i - 1] == n - k + i - 1 {
+                        i -= 1;
+                    }
+                    if i == 0 {
+                        break;
+                    }
+                    indices[i - 1] += 1;
+                    for j in i..k {
+                        indices[j] = indices[j - 1] + 1;
+                    }
+                }
+                ret
+            }
+        };
+        if ret.len() > max {
+            None
+        } else {
+            Some(ret)
+        }
+    }
+}
+
 impl<Pk: MiniscriptKey> Policy<Pk> {
     /// "Sort" a policy to bring it into a canonical form to allow comparisons.
     /// Does **not** allow policies to be compared for functional equivalence;
@@ -504,6 +718,19 @@ mod tests {
         assert_eq!(policy.n_keys(), 0);
         assert_eq!(policy.minimum_n_keys(), 0);
 
+        let policy = StringPolicy::from_str("after(1000)").unwrap();
+        assert_eq!(policy, Policy::After(1000));
+        assert_eq!(policy.relative_timelocks(), vec![]);
+        assert_eq!(policy.clone().at_age(0), policy.clone());
+        assert_eq!(policy.clone().at_height(0), Policy::Unsatisfiable);
+        assert_eq!(policy.clone().at_height(999), Policy::Unsatisfiable);
+        assert_eq!(policy.clone().at_height(1000), policy.clone());
+        assert_eq!(policy.clone().at_height(10000), policy.clone());
+        // Block heights are never satisfied by timestamps
+        assert_eq!(policy.clone().at_height(500_000_001), Policy::Unsatisfiable);
+        assert_eq!(policy.n_keys(), 0);
+        assert_eq!(policy.minimum_n_keys(), 0);
+
         let policy = StringPolicy::from_str("or(pkh(),older(1000))").unwrap();
         assert_eq!(
             policy,
@@ -541,4 +768,88 @@ mod tests {
             vec![1000, 2000, 10000] //sorted and dedup'd
         );
     }
+
+    #[test]
+    fn minimal_satisfactions() {
+        let policy =
+            StringPolicy::from_str("or(and(pkh(A),pkh(B)),thresh(2,pkh(A),pkh(C),older(100)))")
+                .unwrap();
+        let key = |s: &str| Policy::KeyHash(s.to_owned());
+        assert_eq!(
+            policy.minimal_satisfactions(100),
+            Some(vec![
+                vec![key("A"), key("B")],
+                vec![key("A"), key("C")],
+                vec![key("A"), Policy::Older(100)],
+                vec![key("C"), Policy::Older(100)],
+            ])
+        );
+        assert_eq!(policy.minimal_satisfactions(3), None);
+
+        let policy = StringPolicy::from_str("or(pkh(A),and(pkh(A),pkh(B)))").unwrap();
+        assert_eq!(
+            policy.minimal_satisfactions(100),
+            Some(vec![vec![key("A")]])
+        );
+
+        assert_eq!(
+            Policy::<String>::Trivial.minimal_satisfactions(1),
+            Some(vec![vec![]])
+        );
+        assert_eq!(
+            Policy::<String>::Unsatisfiable.minimal_satisfactions(1),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn for_signer() {
+        let policy = StringPolicy::from_str(
+            "or(and(pkh(A),pkh(B)),or(and(pkh(C),older(1000)),thresh(1,pkh(A),sha256(\
+             1111111111111111111111111111111111111111111111111111111111111111))))",
+        )
+        .unwrap();
+
+        assert_eq!(
+            policy.clone().for_signer(&["B".to_owned()]),
+            StringPolicy::from_str("and(pkh(A),pkh(B))").unwrap()
+        );
+        assert_eq!(
+            policy.clone().for_signer(&["A".to_owned()]),
+            StringPolicy::from_str(
+                "or(and(pkh(A),pkh(B)),thresh(1,pkh(A),sha256(\
+                 1111111111111111111111111111111111111111111111111111111111111111)))"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            policy.clone().for_signer(&["D".to_owned()]),
+            Policy::Unsatisfiable
+        );
+        assert!(policy.involves_any(&["D".to_owned(), "C".to_owned()]));
+    }
+
+    #[test]
+    fn normalized() {
+        let policy = StringPolicy::from_str("and(pkh(A),and(pkh(A),or(pkh(B),pkh(C))))").unwrap();
+        let other = StringPolicy::from_str("and(or(pkh(C),or(pkh(B),pkh(C))),pkh(A))").unwrap();
+        assert_ne!(policy, other);
+        assert_eq!(
+            policy.clone().normalized(),
+            StringPolicy::from_str("and(pkh(A),or(pkh(B),pkh(C)))").unwrap()
+        );
+        assert_eq!(policy.normalized().sorted(), other.normalized().sorted());
+
+        // Branches of a threshold count separately, so are not deduplicated
+        let policy = StringPolicy::from_str("thresh(2,pkh(A),and(pkh(A),pkh(A)))").unwrap();
+        assert_eq!(
+            policy.normalized(),
+            StringPolicy::from_str("thresh(2,pkh(A),pkh(A))").unwrap()
+        );
+
+        let policy = StringPolicy::from_str("or(UNSATISFIABLE,and(pkh(A),UNSATISFIABLE))").unwrap();
+        assert_eq!(policy.normalized(), Policy::Unsatisfiable);
+        let policy = StringPolicy::from_str("and(TRIVIAL,or(pkh(A),TRIVIAL))").unwrap();
+        assert_eq!(policy.normalized(), Policy::Trivial);
+    }
 }
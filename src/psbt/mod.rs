@@ -14,19 +14,26 @@
 
 //! # Partially-Signed Bitcoin Transactions
 //!
-//! This module implements the Finalizer and Extractor roles defined in
-//! BIP 173, PSBT, described at
+//! This module implements the Signer, Finalizer and Extractor roles defined
+//! in BIP 173, PSBT, described at
 //! `https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki`
 //!
 
 use std::{error, fmt};
 
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::util::bip32::ChildNumber;
 use bitcoin::util::psbt;
 use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
 use bitcoin::{self, secp256k1};
 
+use descriptor::{DerivationError, DescriptorCache, DescriptorPublicKey, KeyMap};
+use miniscript::locktime::{AbsLockTime, RelLockTime};
 use BitcoinSig;
+use Descriptor;
+use ForEach;
 use Miniscript;
+use MiniscriptKey;
 use Satisfier;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -35,8 +42,13 @@ pub enum Error {
         pubkey: bitcoin::PublicKey,
         index: usize,
     },
+    MissingPubkey(usize),
+    MissingRedeemScript(usize),
+    MissingUtxo(usize),
     MissingWitness(usize),
     MissingWitnessScript(usize),
+    ScriptMismatch(usize),
+    SingleWithoutOutput(usize),
     WrongInputCount {
         in_tx: usize,
         in_map: usize,
@@ -67,12 +79,30 @@ impl fmt::Display for Error {
                 "PSBT: bad signature with key {} on input {}",
                 pubkey.key, index
             ),
+            Error::MissingPubkey(index) => {
+                write!(f, "PSBT is missing the public key spent by input {}", index)
+            }
+            Error::MissingRedeemScript(index) => {
+                write!(f, "PSBT is missing redeem script for input {}", index)
+            }
+            Error::MissingUtxo(index) => write!(f, "PSBT is missing UTXO for input {}", index),
             Error::MissingWitness(index) => {
                 write!(f, "PSBT is missing witness for input {}", index)
             }
             Error::MissingWitnessScript(index) => {
                 write!(f, "PSBT is missing witness script for input {}", index)
             }
+            Error::ScriptMismatch(index) => write!(
+                f,
+                "PSBT: scripts of input {} do not match the spent output",
+                index
+            ),
+            Error::SingleWithoutOutput(index) => write!(
+                f,
+                "PSBT: refusing to sign input {} with SIGHASH_SINGLE, \
+                 which has no matching output",
+                index
+            ),
             Error::WrongInputCount { in_tx, in_map } => write!(
                 f,
                 "PSBT had {} inputs in transaction but {} inputs in map",
@@ -95,21 +125,231 @@ impl fmt::Display for Error {
 
 impl Satisfier<bitcoin::PublicKey> for psbt::Input {
     fn lookup_sig(&self, pk: &bitcoin::PublicKey) -> Option<BitcoinSig> {
-        if let Some(rawsig) = self.partial_sigs.get(pk) {
-            let (flag, sig) = rawsig.split_last().unwrap();
-            let flag = bitcoin::SigHashType::from_u32(*flag as u32);
-            let sig = match secp256k1::Signature::from_der(sig) {
-                Ok(sig) => sig,
-                Err(..) => return None,
-            };
-            Some((sig, flag))
-        } else {
-            None
+        // An empty signature has no sighash flag and is ignored, like an
+        // invalid one
+        let (flag, sig) = self.partial_sigs.get(pk)?.split_last()?;
+        let flag = bitcoin::SigHashType::from_u32(*flag as u32);
+        let sig = match secp256k1::Signature::from_der(sig) {
+            Ok(sig) => sig,
+            Err(..) => return None,
+        };
+        Some((sig, flag))
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        find_pubkey(self, &pkh[..])
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &hash160::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        let pk = find_pubkey(self, &pkh[..])?;
+        self.lookup_sig(&pk).map(|sig| (pk, sig))
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        lookup_preimage(self, PSBT_IN_SHA256, &h[..])
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        lookup_preimage(self, PSBT_IN_HASH256, &h[..])
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        lookup_preimage(self, PSBT_IN_RIPEMD160, &h[..])
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        lookup_preimage(self, PSBT_IN_HASH160, &h[..])
+    }
+}
+
+/// BIP 174 key types of the hash preimage fields of an input. The PSBT
+/// implementation does not know them, so they end up in the input's
+/// unknown fields.
+const PSBT_IN_RIPEMD160: u8 = 0x0a;
+const PSBT_IN_SHA256: u8 = 0x0b;
+const PSBT_IN_HASH160: u8 = 0x0c;
+const PSBT_IN_HASH256: u8 = 0x0d;
+
+/// Looks up the preimage of `hash` in the preimage field `type_value`
+fn lookup_preimage(input: &psbt::Input, type_value: u8, hash: &[u8]) -> Option<[u8; 32]> {
+    let key = psbt::raw::Key {
+        type_value: type_value,
+        key: hash.to_vec(),
+    };
+    match input.unknown.get(&key) {
+        Some(preimage) if preimage.len() == 32 => {
+            let mut ret = [0; 32];
+            ret.copy_from_slice(preimage);
+            Some(ret)
+        }
+        _ => None,
+    }
+}
+
+/// Satisfier for an input of a PSBT. In addition to the signatures and hash
+/// preimages of the input itself, see the `Satisfier` implementation of
+/// `psbt::Input`, it knows about the sequence number of the input and the
+/// lock time of the transaction and so can satisfy timelocks.
+pub struct PsbtInputSatisfier<'psbt> {
+    /// The PSBT
+    pub psbt: &'psbt Psbt,
+    /// Index of the input to satisfy
+    pub index: usize,
+}
+
+impl<'psbt> PsbtInputSatisfier<'psbt> {
+    /// Creates a satisfier for input `index` of `psbt`
+    pub fn new(psbt: &'psbt Psbt, index: usize) -> PsbtInputSatisfier<'psbt> {
+        PsbtInputSatisfier {
+            psbt: psbt,
+            index: index,
+        }
+    }
+
+    fn input(&self) -> &psbt::Input {
+        &self.psbt.inputs[self.index]
+    }
+}
+
+impl<'psbt> Satisfier<bitcoin::PublicKey> for PsbtInputSatisfier<'psbt> {
+    fn lookup_sig(&self, pk: &bitcoin::PublicKey) -> Option<BitcoinSig> {
+        self.input().lookup_sig(pk)
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        self.input().lookup_pkh_pk(pkh)
+    }
+
+    fn lookup_pkh_sig(&self, pkh: &hash160::Hash) -> Option<(bitcoin::PublicKey, BitcoinSig)> {
+        self.input().lookup_pkh_sig(pkh)
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.input().lookup_sha256(h)
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.input().lookup_hash256(h)
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.input().lookup_ripemd160(h)
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.input().lookup_hash160(h)
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        // BIP 68: relative locktimes only apply to version 2 transactions;
+        // the disable flag on the sequence is checked by `is_satisfied_by`
+        let tx = &self.psbt.global.unsigned_tx;
+        if tx.version < 2 {
+            return false;
         }
+        let sequence = tx.input[self.index].sequence;
+        RelLockTime::from_consensus(n).map_or(false, |t| t.is_satisfied_by(sequence))
     }
+
+    fn check_after(&self, n: u32) -> bool {
+        // The lock time is ignored if the input has a final sequence number
+        let tx = &self.psbt.global.unsigned_tx;
+        if tx.input[self.index].sequence == 0xffffffff {
+            return false;
+        }
+        AbsLockTime::from_consensus(n).map_or(false, |t| t.is_satisfied_by(tx.lock_time))
+    }
+}
+
+/// Potential problems with the signature hash types used in a PSBT. These
+/// do not make the transaction invalid, but mean that some signatures do not
+/// commit to the whole transaction, so that a counterparty may be able to
+/// reuse them or change parts of the transaction after they are given out.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SighashWarning {
+    /// A signature (or the input's requested sighash type, if `pubkey` is
+    /// `None`) does not use `SIGHASH_ALL`, so it does not commit to all
+    /// inputs and outputs of the transaction
+    NonAll {
+        pubkey: Option<bitcoin::PublicKey>,
+        index: usize,
+        flag: bitcoin::SigHashType,
+    },
+    /// `SIGHASH_SINGLE` is used on an input with no corresponding output.
+    /// Due to a consensus bug the signature then signs the constant `1`
+    /// and can be replayed on any transaction spending a coin with this key
+    SingleWithoutOutput {
+        pubkey: Option<bitcoin::PublicKey>,
+        index: usize,
+    },
 }
 
-fn sanity_check(psbt: &Psbt) -> Result<(), super::Error> {
+impl fmt::Display for SighashWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SighashWarning::NonAll {
+                pubkey: Some(pubkey),
+                index,
+                flag,
+            } => write!(
+                f,
+                "PSBT: signature on input {} with key {} uses sighash {:?}",
+                index, pubkey.key, flag
+            ),
+            SighashWarning::NonAll {
+                pubkey: None,
+                index,
+                flag,
+            } => write!(f, "PSBT: input {} requests sighash {:?}", index, flag),
+            SighashWarning::SingleWithoutOutput {
+                pubkey: Some(pubkey),
+                index,
+            } => write!(
+                f,
+                "PSBT: SIGHASH_SINGLE signature on input {} with key {} \
+                 has no matching output",
+                index, pubkey.key
+            ),
+            SighashWarning::SingleWithoutOutput {
+                pubkey: None,
+                index,
+            } => write!(
+                f,
+                "PSBT: input {} requests SIGHASH_SINGLE but has no matching output",
+                index
+            ),
+        }
+    }
+}
+
+/// Checks a sighash flag used on input `index` of `psbt`, returning a
+/// warning if it does not commit to the whole transaction
+fn check_sighash(
+    psbt: &Psbt,
+    index: usize,
+    pubkey: Option<bitcoin::PublicKey>,
+    flag: bitcoin::SigHashType,
+) -> Option<SighashWarning> {
+    match flag {
+        bitcoin::SigHashType::All => None,
+        bitcoin::SigHashType::Single | bitcoin::SigHashType::SinglePlusAnyoneCanPay
+            if index >= psbt.global.unsigned_tx.output.len() =>
+        {
+            Some(SighashWarning::SingleWithoutOutput { pubkey, index })
+        }
+        flag => Some(SighashWarning::NonAll {
+            pubkey,
+            index,
+            flag,
+        }),
+    }
+}
+
+/// Checks that the PSBT is well-formed, and returns a list of warnings about
+/// signatures (present or requested) which do not commit to the whole
+/// transaction. Protocols which pre-sign transactions should usually reject
+/// PSBTs for which this list is nonempty.
+pub fn sanity_check(psbt: &Psbt) -> Result<Vec<SighashWarning>, super::Error> {
     if psbt.global.unsigned_tx.input.len() != psbt.inputs.len() {
         return Err(Error::WrongInputCount {
             in_tx: psbt.global.unsigned_tx.input.len(),
@@ -118,25 +358,315 @@ fn sanity_check(psbt: &Psbt) -> Result<(), super::Error> {
         .into());
     }
 
+    let mut warnings = vec![];
+    for (n, input) in psbt.inputs.iter().enumerate() {
+        if let Some(flag) = input.sighash_type {
+            warnings.extend(check_sighash(psbt, n, None, flag));
+        }
+        for (key, rawsig) in &input.partial_sigs {
+            if let Some(flag) = rawsig.last() {
+                let flag = bitcoin::SigHashType::from_u32(*flag as u32);
+                warnings.extend(check_sighash(psbt, n, Some(*key), flag));
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+/// The output spent by input `index`, taken from its witness or
+/// non-witness UTXO
+fn spent_output(psbt: &Psbt, index: usize) -> Result<&bitcoin::TxOut, Error> {
+    let input = &psbt.inputs[index];
+    if let Some(ref utxo) = input.witness_utxo {
+        return Ok(utxo);
+    }
+    if let Some(ref tx) = input.non_witness_utxo {
+        let vout = psbt.global.unsigned_tx.input[index].previous_output.vout;
+        if let Some(utxo) = tx.output.get(vout as usize) {
+            return Ok(utxo);
+        }
+    }
+    Err(Error::MissingUtxo(index))
+}
+
+/// Looks for the key hashing to `hash` among the keys the input has
+/// signatures or BIP32 derivations for
+fn find_pubkey(input: &psbt::Input, hash: &[u8]) -> Option<bitcoin::PublicKey> {
+    input
+        .partial_sigs
+        .keys()
+        .chain(input.hd_keypaths.keys())
+        .find(|pk| pk.to_pubkeyhash()[..] == *hash)
+        .cloned()
+}
+
+/// Signs every input of the PSBT which spends an output of `descriptor`
+/// with the keys from `key_map`, adding the signatures to the inputs'
+/// partial signatures. Inputs are signed with their requested sighash type,
+/// or `SIGHASH_ALL` if there is none, using the legacy or BIP143 signature
+/// hash as the spent output requires.
+///
+/// The derivation index of a ranged descriptor is taken from the BIP32
+/// derivations of the input. Inputs without UTXO, or whose UTXO does not
+/// match the descriptor at any of these indices, are skipped. Returns the
+/// number of signatures added.
+///
+/// Fails without signing anything if one of the inputs to sign requests
+/// `SIGHASH_SINGLE` but has no matching output, see
+/// `SighashWarning::SingleWithoutOutput`.
+pub fn sign<C: secp256k1::Signing>(
+    psbt: &mut Psbt,
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+    secp: &secp256k1::Secp256k1<C>,
+) -> Result<usize, super::Error> {
+    sign_with(psbt, descriptor, key_map, secp, |index| {
+        descriptor.derive_index_with_secrets(secp, key_map, index)
+    })
+}
+
+/// Like `sign`, but takes derived keys from `cache` where possible and adds
+/// the ones it has to derive, see `Descriptor::derive_index_cached`
+pub fn sign_cached<C>(
+    psbt: &mut Psbt,
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+    secp: &secp256k1::Secp256k1<C>,
+    cache: &mut DescriptorCache,
+) -> Result<usize, super::Error>
+where
+    C: secp256k1::Signing + secp256k1::Verification,
+{
+    sign_with(psbt, descriptor, key_map, secp, |index| {
+        descriptor.derive_index_with_secrets_cached(secp, key_map, index, cache)
+    })
+}
+
+/// Helper function for `sign` and `sign_cached`, deriving the descriptor at
+/// each candidate index with `derive`
+fn sign_with<C, F>(
+    psbt: &mut Psbt,
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    key_map: &KeyMap,
+    secp: &secp256k1::Secp256k1<C>,
+    mut derive: F,
+) -> Result<usize, super::Error>
+where
+    C: secp256k1::Signing,
+    F: FnMut(u32) -> Result<Descriptor<bitcoin::PublicKey>, DerivationError>,
+{
+    let _ = sanity_check(psbt)?;
+
+    let mut secret_keys = vec![];
+    descriptor.for_each_key(|key| {
+        if let ForEach::Key(pk) = key {
+            secret_keys.extend(key_map.get(pk));
+        }
+        true
+    });
+
+    let mut to_sign = vec![];
+    for n in 0..psbt.inputs.len() {
+        let utxo = match spent_output(psbt, n) {
+            Ok(utxo) => utxo.clone(),
+            Err(_) => continue,
+        };
+        let input = &psbt.inputs[n];
+        let mut indices: Vec<u32> = input
+            .hd_keypaths
+            .values()
+            .filter_map(|&(_, ref path)| path.into_iter().last())
+            .map(|child| match *child {
+                ChildNumber::Normal { index } | ChildNumber::Hardened { index } => index,
+            })
+            .collect();
+        indices.push(0);
+        let found = indices
+            .into_iter()
+            .filter_map(|index| derive(index).ok().map(|derived| (index, derived)))
+            .find(|&(_, ref derived)| derived.script_pubkey() == utxo.script_pubkey);
+        let (index, derived) = match found {
+            Some(found) => found,
+            None => continue,
+        };
+
+        let sighash_type = input.sighash_type.unwrap_or(bitcoin::SigHashType::All);
+        if let Some(SighashWarning::SingleWithoutOutput { .. }) =
+            check_sighash(psbt, n, None, sighash_type)
+        {
+            return Err(Error::SingleWithoutOutput(n).into());
+        }
+        to_sign.push((n, index, derived, utxo, sighash_type));
+    }
+
+    let mut n_sigs = 0;
+    for (n, index, derived, utxo, sighash_type) in to_sign {
+        let tx = &psbt.global.unsigned_tx;
+        let msg = derived.sighash_message(tx, n, utxo.value, sighash_type)?;
+
+        for sk in &secret_keys {
+            let (pk, sig) = match sk.sign(secp, index, &msg) {
+                Ok(signed) => signed,
+                Err(_) => continue,
+            };
+            let mut rawsig = sig.serialize_der().to_vec();
+            rawsig.push(sighash_type.as_u32() as u8);
+            psbt.inputs[n].partial_sigs.insert(pk, rawsig);
+            n_sigs += 1;
+        }
+    }
+    Ok(n_sigs)
+}
+
+/// Infers the descriptor of the output spent by input `index` from its UTXO
+/// and, for script-hash outputs, the redeem and witness scripts provided in
+/// the PSBT. Keys of `pkh` and `wpkh` outputs are looked up among the keys
+/// the input has signatures or BIP32 derivations for.
+///
+/// Panics if `index` is out of range.
+pub fn infer_descriptor(
+    psbt: &Psbt,
+    index: usize,
+) -> Result<Descriptor<bitcoin::PublicKey>, super::Error> {
+    let input = &psbt.inputs[index];
+    let script_pubkey = &spent_output(psbt, index)?.script_pubkey;
+
+    let witness_script = |program: &bitcoin::Script| -> Result<_, super::Error> {
+        let script = input
+            .witness_script
+            .as_ref()
+            .ok_or(Error::MissingWitnessScript(index))?;
+        if script.to_v0_p2wsh() != *program {
+            return Err(Error::ScriptMismatch(index).into());
+        }
+        Miniscript::parse(script)
+    };
+    let pubkey = |program: &bitcoin::Script| {
+        find_pubkey(input, &program[2..22]).ok_or(Error::MissingPubkey(index))
+    };
+
+    if script_pubkey.is_p2sh() {
+        let redeem_script = input
+            .redeem_script
+            .as_ref()
+            .ok_or(Error::MissingRedeemScript(index))?;
+        if redeem_script.to_p2sh() != *script_pubkey {
+            return Err(Error::ScriptMismatch(index).into());
+        }
+        if redeem_script.is_v0_p2wsh() {
+            Ok(Descriptor::ShWsh(witness_script(redeem_script)?))
+        } else if redeem_script.is_v0_p2wpkh() {
+            Ok(Descriptor::ShWpkh(pubkey(redeem_script)?))
+        } else {
+            Ok(Descriptor::Sh(Miniscript::parse(redeem_script)?))
+        }
+    } else if script_pubkey.is_v0_p2wsh() {
+        Ok(Descriptor::Wsh(witness_script(script_pubkey)?))
+    } else if script_pubkey.is_v0_p2wpkh() {
+        Ok(Descriptor::Wpkh(pubkey(script_pubkey)?))
+    } else if script_pubkey.is_p2pkh() {
+        find_pubkey(input, &script_pubkey[3..23])
+            .map(Descriptor::Pkh)
+            .ok_or_else(|| Error::MissingPubkey(index).into())
+    } else {
+        Ok(Descriptor::Bare(Miniscript::parse(script_pubkey)?))
+    }
+}
+
+/// Finalizes input `index` by satisfying `descriptor` with the signatures,
+/// hash preimages and timelocks of the input, see `PsbtInputSatisfier`,
+/// filling in its final scriptSig and witness. As required by
+/// BIP 174, all other data except for the UTXOs is then removed from the
+/// input.
+///
+/// Panics if `index` is out of range.
+pub fn finalize_input(
+    psbt: &mut Psbt,
+    index: usize,
+    descriptor: &Descriptor<bitcoin::PublicKey>,
+) -> Result<(), super::Error> {
+    let (witness, script_sig) =
+        descriptor.get_satisfaction(PsbtInputSatisfier::new(psbt, index))?;
+
+    let input = &mut psbt.inputs[index];
+    input.final_script_sig = if script_sig.is_empty() {
+        None
+    } else {
+        Some(script_sig)
+    };
+    input.final_script_witness = if witness.is_empty() {
+        None
+    } else {
+        Some(witness)
+    };
+    input.partial_sigs.clear();
+    input.sighash_type = None;
+    input.redeem_script = None;
+    input.witness_script = None;
+    input.hd_keypaths.clear();
+    Ok(())
+}
+
+/// Whether the input already has a final scriptSig or witness
+fn is_finalized(input: &psbt::Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+/// Checks every partial signature of input `index` against its signature
+/// hash, `descriptor` being the descriptor of the spent output
+fn verify_partial_sigs<C: secp256k1::Verification>(
+    psbt: &Psbt,
+    index: usize,
+    descriptor: &Descriptor<bitcoin::PublicKey>,
+    secp: &secp256k1::Secp256k1<C>,
+) -> Result<(), super::Error> {
+    let value = spent_output(psbt, index)?.value;
+    let input = &psbt.inputs[index];
+    for key in input.partial_sigs.keys() {
+        let invalid = Error::InvalidSignature {
+            pubkey: *key,
+            index: index,
+        };
+        let (sig, flag) = input.lookup_sig(key).ok_or(invalid)?;
+        let msg = descriptor.sighash_message(&psbt.global.unsigned_tx, index, value, flag)?;
+        if secp.verify(&msg, &sig, &key.key).is_err() {
+            return Err(invalid.into());
+        }
+    }
     Ok(())
 }
 
-pub fn finalize(psbt: &mut Psbt) -> Result<(), super::Error> {
-    sanity_check(psbt)?;
+/// Finalizes all inputs of the PSBT, inferring the descriptor of each
+/// spent output with `infer_descriptor`. Inputs spending outputs with
+/// known descriptors can be finalized with `finalize_input` instead.
+/// Inputs which are already finalized are left alone.
+///
+/// Every partial signature is verified against the signature hash of its
+/// input before any input is finalized.
+pub fn finalize<C: secp256k1::Verification>(
+    psbt: &mut Psbt,
+    secp: &secp256k1::Secp256k1<C>,
+) -> Result<(), super::Error> {
+    // Non-ALL sighashes are legitimate in many protocols, so only
+    // well-formedness is enforced here
+    let _ = sanity_check(psbt)?;
 
     // Check well-formedness of input data
     for (n, input) in psbt.inputs.iter().enumerate() {
-        if let Some(target) = input.sighash_type {
-            for (key, rawsig) in &input.partial_sigs {
-                if rawsig.is_empty() {
-                    return Err(Error::InvalidSignature {
-                        pubkey: *key,
-                        index: n,
-                    }
-                    .into());
-                }
-                let (flag, sig) = rawsig.split_last().unwrap();
-                let flag = bitcoin::SigHashType::from_u32(*flag as u32);
+        if is_finalized(input) {
+            continue;
+        }
+        for (key, rawsig) in &input.partial_sigs {
+            let invalid = Error::InvalidSignature {
+                pubkey: *key,
+                index: n,
+            };
+            let (flag, sig) = match rawsig.split_last() {
+                Some(x) => x,
+                None => return Err(invalid.into()),
+            };
+            let flag = bitcoin::SigHashType::from_u32(*flag as u32);
+            if let Some(target) = input.sighash_type {
                 if target != flag {
                     return Err(Error::WrongSigHashFlag {
                         required: target,
@@ -146,41 +676,323 @@ pub fn finalize(psbt: &mut Psbt) -> Result<(), super::Error> {
                     }
                     .into());
                 }
-                if let Err(_) = secp256k1::Signature::from_der(sig) {
-                    return Err(Error::InvalidSignature {
-                        pubkey: *key,
-                        index: n,
-                    }
-                    .into());
-                }
-                // TODO check signature
+            }
+            if let Err(_) = secp256k1::Signature::from_der(sig) {
+                return Err(invalid.into());
             }
         }
     }
 
-    // Actually construct the witnesses
-    for (n, input) in psbt.inputs.iter_mut().enumerate() {
-        if let Some(script) = input.witness_script.as_ref() {
-            let miniscript = Miniscript::parse(script)?;
-            input.final_script_witness = miniscript.satisfy(&*input);
-        } else {
-            return Err(Error::MissingWitnessScript(n).into());
+    let mut descriptors = Vec::with_capacity(psbt.inputs.len());
+    for n in 0..psbt.inputs.len() {
+        if is_finalized(&psbt.inputs[n]) {
+            continue;
         }
+        let descriptor = infer_descriptor(psbt, n)?;
+        verify_partial_sigs(psbt, n, &descriptor, secp)?;
+        descriptors.push((n, descriptor));
+    }
+
+    // Actually construct the witnesses
+    trace_span!("finalize", n_inputs = psbt.inputs.len());
+    for (n, descriptor) in descriptors {
+        finalize_input(psbt, n, &descriptor)?;
+        trace_event!(index = n, descriptor = %descriptor, "finalized input");
     }
     Ok(())
 }
 
-pub fn extract(psbt: &mut Psbt) -> Result<bitcoin::Transaction, super::Error> {
-    sanity_check(psbt)?;
+/// Extracts the network-serializable transaction from a finalized PSBT
+pub fn extract(psbt: &Psbt) -> Result<bitcoin::Transaction, super::Error> {
+    let _ = sanity_check(psbt)?;
 
     let mut ret = psbt.global.unsigned_tx.clone();
     for (n, input) in psbt.inputs.iter().enumerate() {
+        if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+            return Err(Error::MissingWitness(n).into());
+        }
+        if let Some(script_sig) = input.final_script_sig.as_ref() {
+            ret.input[n].script_sig = script_sig.clone();
+        }
         if let Some(witness) = input.final_script_witness.as_ref() {
             ret.input[n].witness = witness.clone();
-        } else {
-            return Err(Error::MissingWitness(n).into());
         }
     }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+    use std::str::FromStr;
+
+    #[test]
+    fn sighash_warnings() {
+        let txin = TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(sanity_check(&psbt).unwrap(), vec![]);
+
+        psbt.inputs[0].sighash_type = Some(bitcoin::SigHashType::All);
+        psbt.inputs[1].sighash_type = Some(bitcoin::SigHashType::Single);
+        assert_eq!(
+            sanity_check(&psbt).unwrap(),
+            vec![SighashWarning::SingleWithoutOutput {
+                pubkey: None,
+                index: 1,
+            }]
+        );
+
+        psbt.inputs[0].sighash_type = Some(bitcoin::SigHashType::NonePlusAnyoneCanPay);
+        psbt.inputs[1].sighash_type = None;
+        assert_eq!(
+            sanity_check(&psbt).unwrap(),
+            vec![SighashWarning::NonAll {
+                pubkey: None,
+                index: 0,
+                flag: bitcoin::SigHashType::NonePlusAnyoneCanPay,
+            }]
+        );
+    }
+
+    #[test]
+    fn finalize_inferred() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey {
+            key: secp256k1::PublicKey::from_secret_key(&secp, &sk),
+            compressed: true,
+        };
+
+        let wpkh = Descriptor::<bitcoin::PublicKey>::from_str(&format!("wpkh({})", pk)).unwrap();
+        let pkh = Descriptor::<bitcoin::PublicKey>::from_str(&format!("pkh({})", pk)).unwrap();
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: 1000,
+                    script_pubkey: wpkh.script_pubkey(),
+                },
+                TxOut {
+                    value: 1000,
+                    script_pubkey: pkh.script_pubkey(),
+                },
+            ],
+        };
+        let txin = |vout| TxIn {
+            previous_output: OutPoint {
+                txid: prev_tx.txid(),
+                vout,
+            },
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin(0), txin(1)],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        match finalize(&mut psbt, &secp) {
+            Err(::Error::Psbt(Error::MissingUtxo(0))) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        psbt.inputs[0].witness_utxo = Some(prev_tx.output[0].clone());
+        psbt.inputs[1].non_witness_utxo = Some(prev_tx.clone());
+        match finalize(&mut psbt, &secp) {
+            Err(::Error::Psbt(Error::MissingPubkey(0))) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        // Signatures are checked even without a required sighash type
+        psbt.inputs[0].partial_sigs.insert(pk, vec![]);
+        assert!(psbt.inputs[0].lookup_sig(&pk).is_none());
+        match finalize(&mut psbt, &secp) {
+            Err(::Error::Psbt(Error::InvalidSignature { index: 0, .. })) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+
+        let tx = psbt.global.unsigned_tx.clone();
+        let sign = |desc: &Descriptor<bitcoin::PublicKey>, n| {
+            let msg = desc
+                .sighash_message(&tx, n, 1000, bitcoin::SigHashType::All)
+                .unwrap();
+            let mut rawsig = secp.sign(&msg, &sk).serialize_der().to_vec();
+            rawsig.push(bitcoin::SigHashType::All.as_u32() as u8);
+            rawsig
+        };
+        let (wpkh_sig, pkh_sig) = (sign(&wpkh, 0), sign(&pkh, 1));
+
+        // A valid signature, but of another input
+        psbt.inputs[0].partial_sigs.insert(pk, pkh_sig.clone());
+        psbt.inputs[1].partial_sigs.insert(pk, pkh_sig.clone());
+        match finalize(&mut psbt, &secp) {
+            Err(::Error::Psbt(Error::InvalidSignature { index: 0, .. })) => {}
+            x => panic!("unexpected result {:?}", x),
+        }
+        assert!(!is_finalized(&psbt.inputs[1]));
+
+        psbt.inputs[0].partial_sigs.insert(pk, wpkh_sig.clone());
+        assert_eq!(infer_descriptor(&psbt, 0).unwrap(), wpkh);
+        assert_eq!(infer_descriptor(&psbt, 1).unwrap(), pkh);
+        finalize(&mut psbt, &secp).unwrap();
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+        assert_eq!(
+            psbt.inputs[0].final_script_witness,
+            Some(vec![wpkh_sig, pk.to_bytes()])
+        );
+        assert_eq!(psbt.inputs[1].final_script_witness, None);
+
+        // Finalized inputs are skipped, even though their data is gone
+        let finalized = psbt.clone();
+        finalize(&mut psbt, &secp).unwrap();
+        assert_eq!(psbt, finalized);
+
+        let tx = extract(&psbt).unwrap();
+        assert_eq!(tx.input[0].script_sig, Script::new());
+        assert_eq!(
+            tx.input[1].script_sig,
+            bitcoin::blockdata::script::Builder::new()
+                .push_slice(&pkh_sig)
+                .push_key(&pk)
+                .into_script()
+        );
+    }
+
+    #[test]
+    fn sign_with_key_map() {
+        use bitcoin::util::bip143::SighashComponents;
+        use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
+
+        let secp = secp256k1::Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &[0xcd; 32]).unwrap();
+        let (desc, key_map) = Descriptor::<DescriptorPublicKey>::parse_descriptor(
+            &secp,
+            &format!("wpkh({}/0/*)", master),
+        )
+        .unwrap();
+        let pk = match desc.derive_index(5).unwrap() {
+            Descriptor::Wpkh(pk) => pk,
+            _ => unreachable!(),
+        };
+
+        let spent = |script_pubkey| TxOut {
+            value: 10_000,
+            script_pubkey,
+        };
+        let txin = TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: vec![],
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin.clone(), txin],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(spent(desc.derive_index(5).unwrap().script_pubkey()));
+        psbt.inputs[0].hd_keypaths.insert(
+            pk,
+            (
+                master.fingerprint(&secp),
+                DerivationPath::from_str("m/0/5").unwrap(),
+            ),
+        );
+        // Not ours, at least not at any index we know of
+        psbt.inputs[1].witness_utxo = Some(spent(desc.derive_index(6).unwrap().script_pubkey()));
+
+        assert_eq!(sign(&mut psbt, &desc, &key_map, &secp).unwrap(), 1);
+        assert!(psbt.inputs[1].partial_sigs.is_empty());
+
+        let script_code = Descriptor::Pkh(pk).script_pubkey();
+        let sighash = SighashComponents::new(&tx).sighash_all(&tx.input[0], &script_code, 10_000);
+        let msg = secp256k1::Message::from_slice(&sighash[..]).unwrap();
+        let rawsig = &psbt.inputs[0].partial_sigs[&pk];
+        let (flag, sig) = rawsig.split_last().unwrap();
+        assert_eq!(*flag, bitcoin::SigHashType::All.as_u32() as u8);
+        let sig = secp256k1::Signature::from_der(sig).unwrap();
+        assert!(secp.verify(&msg, &sig, &pk.key).is_ok());
+
+        // SIGHASH_SINGLE without a matching output would sign the constant 1
+        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(spent(desc.derive_index(0).unwrap().script_pubkey()));
+        psbt.inputs[0].sighash_type = Some(bitcoin::SigHashType::Single);
+        match sign(&mut psbt, &desc, &key_map, &secp) {
+            Err(::Error::Psbt(Error::SingleWithoutOutput(0))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(spent(desc.derive_index(0).unwrap().script_pubkey()));
+        let mut cache = DescriptorCache::new();
+        let res = sign_cached(&mut psbt, &desc, &key_map, &secp, &mut cache);
+        assert_eq!(res.unwrap(), 1);
+        assert!(!cache.is_empty());
+    }
 
-    unimplemented!()
+    #[test]
+    fn psbt_input_satisfier() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 600,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 10,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let preimage = [0xab; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        psbt.inputs[0].unknown.insert(
+            psbt::raw::Key {
+                type_value: PSBT_IN_SHA256,
+                key: hash[..].to_vec(),
+            },
+            preimage.to_vec(),
+        );
+
+        let satisfier = PsbtInputSatisfier::new(&psbt, 0);
+        assert_eq!(satisfier.lookup_sha256(hash), Some(preimage));
+        assert_eq!(satisfier.lookup_sha256(sha256::Hash::hash(&[])), None);
+        assert!(satisfier.check_older(10));
+        assert!(!satisfier.check_older(11));
+        // Time-based rather than height-based
+        assert!(!satisfier.check_older((1 << 22) | 5));
+        assert!(satisfier.check_after(600));
+        assert!(!satisfier.check_after(601));
+        assert!(!satisfier.check_after(500_000_001));
+
+        // A final sequence number disables both kinds of timelock
+        psbt.global.unsigned_tx.input[0].sequence = 0xffffffff;
+        let satisfier = PsbtInputSatisfier::new(&psbt, 0);
+        assert!(!satisfier.check_older(10));
+        assert!(!satisfier.check_after(600));
+    }
 }